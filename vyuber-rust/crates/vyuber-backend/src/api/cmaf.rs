@@ -0,0 +1,77 @@
+use axum::{
+    extract::Path,
+    response::{Response, IntoResponse},
+    http::{StatusCode, header},
+};
+
+use crate::media::cmaf::{TrackType, CMAF};
+
+/// GET /cmaf/:stream_key/:file - CMAFのHLSプレイリスト（index.m3u8, video.m3u8, audio.m3u8）、
+/// 初期化セグメント（<トラック>_init.mp4）、メディアセグメント（<トラック>_<番号>.m4s）
+pub async fn serve(Path((stream_key, file)): Path<(String, String)>) -> Response {
+    if file == "index.m3u8" {
+        return playlist_response(CMAF.master_playlist(&stream_key));
+    }
+    if let Some(track) = file.strip_suffix(".m3u8").and_then(TrackType::from_name) {
+        return playlist_response(CMAF.media_playlist(&stream_key, track));
+    }
+
+    let data = match file.split_once('_') {
+        Some((track, "init.mp4")) => TrackType::from_name(track)
+            .and_then(|track| CMAF.init(&stream_key, track)),
+        Some((track, number)) => {
+            let track = TrackType::from_name(track);
+            let number = number.strip_suffix(".m4s").and_then(|n| n.parse().ok());
+            match (track, number) {
+                (Some(track), Some(number)) => CMAF.segment(&stream_key, track, number),
+                _ => None,
+            }
+        }
+        None => None,
+    };
+    match data {
+        Some(data) => (
+            [
+                (header::CONTENT_TYPE, "video/mp4"),
+                (header::CACHE_CONTROL, "max-age=60"),
+            ],
+            data,
+        ).into_response(),
+        None => not_found(),
+    }
+}
+
+/// GET /dash/:stream_key/manifest.mpd - CMAFセグメントを参照するMPEG-DASHマニフェスト
+pub async fn manifest(Path(stream_key): Path<String>) -> Response {
+    match CMAF.mpd(&stream_key) {
+        Some(mpd) => (
+            [
+                (header::CONTENT_TYPE, "application/dash+xml"),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            mpd,
+        ).into_response(),
+        None => not_found(),
+    }
+}
+
+fn playlist_response(playlist: Option<String>) -> Response {
+    match playlist {
+        Some(playlist) => (
+            [
+                (header::CONTENT_TYPE, "application/vnd.apple.mpegurl"),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            playlist,
+        ).into_response(),
+        None => not_found(),
+    }
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        [(header::CONTENT_TYPE, "text/plain")],
+        "Stream not found or offline",
+    ).into_response()
+}
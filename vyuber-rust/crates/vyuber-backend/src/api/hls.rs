@@ -0,0 +1,81 @@
+use axum::{
+    extract::{Path, Query},
+    response::{Response, IntoResponse},
+    http::{StatusCode, header},
+};
+use serde::Deserialize;
+
+use crate::media::hls::{HlsError, HLS};
+
+/// LL-HLSのブロッキングリロード用クエリ
+#[derive(Deserialize)]
+pub struct BlockingReload {
+    #[serde(rename = "_HLS_msn")]
+    msn: Option<u64>,
+    #[serde(rename = "_HLS_part")]
+    part: Option<usize>,
+}
+
+/// GET /hls/:stream_key/:file - HLSのプレイリスト（index.m3u8）、
+/// セグメント（<番号>.ts）、パーシャルセグメント（<番号>.<パート>.ts）
+pub async fn serve(
+    Path((stream_key, file)): Path<(String, String)>,
+    Query(reload): Query<BlockingReload>,
+) -> Response {
+    if file == "index.m3u8" {
+        // _HLS_part だけの指定は仕様上400で拒否する
+        if reload.msn.is_none() && reload.part.is_some() {
+            return (
+                StatusCode::BAD_REQUEST,
+                [(header::CONTENT_TYPE, "text/plain")],
+                "_HLS_part requires _HLS_msn",
+            ).into_response();
+        }
+        let block = reload.msn.map(|msn| (msn, reload.part));
+        return match HLS.playlist(&stream_key, block).await {
+            Ok(playlist) => (
+                [
+                    (header::CONTENT_TYPE, "application/vnd.apple.mpegurl"),
+                    (header::CACHE_CONTROL, "no-cache"),
+                ],
+                playlist,
+            ).into_response(),
+            Err(e) => error_response(e),
+        };
+    }
+
+    let name = file.strip_suffix(".ts").unwrap_or_default();
+    let result = match name.split_once('.') {
+        Some((sequence, part)) => match (sequence.parse(), part.parse()) {
+            (Ok(sequence), Ok(part)) => HLS.part(&stream_key, sequence, part).await,
+            _ => Err(HlsError::NotFound),
+        },
+        None => match name.parse() {
+            Ok(sequence) => HLS.segment(&stream_key, sequence),
+            Err(_) => Err(HlsError::NotFound),
+        },
+    };
+    match result {
+        Ok(data) => (
+            [
+                (header::CONTENT_TYPE, "video/mp2t"),
+                (header::CACHE_CONTROL, "max-age=60"),
+            ],
+            data,
+        ).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+fn error_response(error: HlsError) -> Response {
+    let status = match error {
+        HlsError::NotFound => StatusCode::NOT_FOUND,
+        HlsError::TooFarAhead => StatusCode::BAD_REQUEST,
+        HlsError::Timeout => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (
+        status,
+        [(header::CONTENT_TYPE, "text/plain")],
+        error.to_string(),
+    ).into_response()
+}
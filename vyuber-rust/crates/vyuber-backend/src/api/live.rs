@@ -1,26 +1,227 @@
 use axum::{
-    extract::Path,
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path,
+    },
     response::{Response, IntoResponse},
     http::{StatusCode, header},
 };
+use bytes::Bytes;
+use futures_util::stream::{self, Stream, StreamExt};
+use std::convert::Infallible;
+
+use crate::media::flv;
+use crate::media::hub::{Hub, Subscriber, HUB};
 
 /// HTTP-FLVストリーミングエンドポイント
 ///
-/// MVP版では実装をスキップ
-/// 実際の実装にはFFmpegからのFLVストリームを
-/// HTTPレスポンスとしてストリーミングする必要がある
+/// ハブを購読し、FLVヘッダ → キャッシュ済みのメタデータ/シーケンスヘッダ →
+/// ライブのタグの順にchunkedレスポンスで送り続ける
 pub async fn stream_flv(
     Path(stream_key): Path<String>,
 ) -> Response {
+    serve_flv(&HUB, stream_key)
+}
+
+fn serve_flv(hub: &Hub, stream_key: String) -> Response {
     tracing::info!("FLV stream request for key: {}", stream_key);
 
-    // MVP版: プレースホルダーレスポンス
-    // 実装時はFFmpegプロセスの出力をストリーミング
+    let Some(flv) = subscribe_flv(hub, stream_key) else {
+        return not_found();
+    };
+
+    // レスポンスボディがdropされる（クライアント切断）と購読も解除される
+    let body = flv.map(Ok::<Bytes, Infallible>);
+    (
+        [
+            (header::CONTENT_TYPE, "video/x-flv"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        Body::from_stream(body),
+    ).into_response()
+}
+
+/// WebSocket-FLVストリーミングエンドポイント
+///
+/// chunkedレスポンスをバッファしてしまうプロキシ向けに、HTTP-FLVと同じバイト列をバイナリフレームで送る
+pub async fn stream_ws_flv(
+    Path(stream_key): Path<String>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    serve_ws_flv(&HUB, stream_key, ws)
+}
+
+fn serve_ws_flv(hub: &Hub, stream_key: String, ws: WebSocketUpgrade) -> Response {
+    tracing::info!("WebSocket-FLV stream request for key: {}", stream_key);
+
+    // オフラインならアップグレードせずに404を返す
+    let Some(flv) = subscribe_flv(hub, stream_key) else {
+        return not_found();
+    };
+
+    ws.on_upgrade(move |socket| send_flv(socket, flv))
+}
+
+/// FLVのバイト列をクライアントが切断するか配信が終わるまで送る
+async fn send_flv(mut socket: WebSocket, flv: impl Stream<Item = Bytes>) {
+    let mut flv = std::pin::pin!(flv);
+    loop {
+        tokio::select! {
+            data = flv.next() => {
+                let Some(data) = data else {
+                    break;
+                };
+                if socket.send(Message::Binary(data.to_vec())).await.is_err() {
+                    return;
+                }
+            }
+            // 視聴者からのメッセージは読み捨て、切断だけを検知する
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// ハブを購読し、1視聴者分のFLVバイト列（FLVヘッダ → キャッシュ済みのタグ → ライブのタグ）を作る
+///
+/// 配信されていなければ `None`
+fn subscribe_flv(hub: &Hub, stream_key: String) -> Option<impl Stream<Item = Bytes>> {
+    let subscriber = hub.subscribe(&stream_key)?;
+
+    let (has_audio, has_video) = subscriber.tracks();
+    let flv_header = flv::header(has_audio, has_video);
+    let viewer = FlvViewer { stream_key, subscriber };
 
+    // ストリームがdropされると購読も解除される
+    let tags = stream::unfold(viewer, |mut viewer| async move {
+        let tag = viewer.subscriber.recv().await?;
+        Some((flv::encode_tag(&tag), viewer))
+    });
+    Some(stream::once(async move { flv_header }).chain(tags))
+}
+
+fn not_found() -> Response {
     (
-        StatusCode::NOT_IMPLEMENTED,
+        StatusCode::NOT_FOUND,
         [(header::CONTENT_TYPE, "text/plain")],
-        "HTTP-FLV streaming not yet implemented. \
-         This will stream video from FFmpeg transcoder in production."
+        "Stream not found or offline",
     ).into_response()
 }
+
+/// 1視聴者分の購読（切断をログに残す）
+struct FlvViewer {
+    stream_key: String,
+    subscriber: Subscriber,
+}
+
+impl Drop for FlvViewer {
+    fn drop(&mut self) {
+        tracing::info!("FLV viewer disconnected from: {}", self.stream_key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::hub::Publisher;
+    use crate::media::tag::MediaTag;
+    use crate::media::testing;
+    use axum::{routing::get, Router};
+    use bytes::BytesMut;
+    use tokio_tungstenite::tungstenite;
+
+    /// シーケンスヘッダと2つのGOPを配信し、視聴者が最初に受け取るはずのタグを返す
+    fn publish(hub: &Hub) -> (Publisher, Vec<MediaTag>) {
+        let mut publisher = hub.publish("live").unwrap();
+        let mut tags = vec![
+            testing::avc_sequence_header(0),
+            testing::aac_sequence_header(0),
+        ];
+        for (timestamp, keyframe) in [(0, true), (40, false), (80, true), (120, false)] {
+            tags.push(testing::avc_frame(timestamp, keyframe, 0, &[0x10; 16]));
+            tags.push(testing::aac_frame(timestamp, &[0x20; 8]));
+        }
+        for tag in &tags {
+            publisher.send(tag.clone());
+        }
+        // シーケンスヘッダと最新のGOP
+        tags.drain(2..6);
+        (publisher, tags)
+    }
+
+    /// FLVヘッダに続けてタグを並べたバイト列
+    fn expected_flv(tags: &[MediaTag]) -> BytesMut {
+        let mut expected = BytesMut::from(&flv::header(true, true)[..]);
+        for tag in tags {
+            expected.extend_from_slice(&flv::encode_tag(tag));
+        }
+        expected
+    }
+
+    #[tokio::test]
+    async fn serves_http_flv_from_the_gop_cache() {
+        let hub = Hub::new();
+        let response = serve_flv(&hub, "live".to_string());
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let (publisher, tags) = publish(&hub);
+        let response = serve_flv(&hub, "live".to_string());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "video/x-flv");
+
+        // 配信が終わるとレスポンスも終わる
+        drop(publisher);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, expected_flv(&tags));
+    }
+
+    #[tokio::test]
+    async fn serves_websocket_flv_from_the_gop_cache() {
+        // アップグレードには実際の接続が要る
+        let hub: &'static Hub = Box::leak(Box::new(Hub::new()));
+        let app = Router::new().route(
+            "/ws/live/:stream_key",
+            get(
+                move |Path(stream_key): Path<String>, ws: WebSocketUpgrade| async move {
+                    serve_ws_flv(hub, stream_key, ws)
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws/live/live", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        match tokio_tungstenite::connect_async(&url).await {
+            Err(tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), StatusCode::NOT_FOUND)
+            }
+            _ => panic!("expected 404 for an offline stream"),
+        }
+
+        let (publisher, tags) = publish(hub);
+        let (mut socket, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        // FLVヘッダとタグを1つずつバイナリフレームで送る
+        let mut frames = Vec::new();
+        while frames.len() < tags.len() + 1 {
+            match socket.next().await.unwrap().unwrap() {
+                tungstenite::Message::Binary(data) => frames.push(data),
+                message => panic!("unexpected message: {:?}", message),
+            }
+        }
+        assert_eq!(frames[0], flv::header(true, true));
+        assert_eq!(frames.concat(), expected_flv(&tags));
+
+        // 配信が終わるとクローズする
+        drop(publisher);
+        assert!(matches!(
+            socket.next().await,
+            Some(Ok(tungstenite::Message::Close(_)))
+        ));
+    }
+}
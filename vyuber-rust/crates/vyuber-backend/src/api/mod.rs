@@ -1,3 +1,9 @@
 pub mod chat;
 pub mod stream_key;
 pub mod live;
+pub mod streams;
+pub mod hls;
+pub mod cmaf;
+pub mod whip;
+pub mod whep;
+pub mod recordings;
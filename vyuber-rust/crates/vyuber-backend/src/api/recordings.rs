@@ -0,0 +1,193 @@
+use axum::{
+    body::Body,
+    extract::{Path, Request},
+    response::{Response, IntoResponse},
+    http::{StatusCode, header},
+    Json,
+};
+use tower_http::services::ServeFile;
+
+use crate::media::recording::library::RecordingError;
+use crate::media::recording::{RecordingStore, RECORDINGS};
+
+/// GET /api/recordings - 過去の録画の一覧（新しい順）
+pub async fn list() -> Response {
+    match RECORDINGS.list().await {
+        Ok(recordings) => Json(recordings).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// GET /api/recordings/:id - 録画の情報
+pub async fn get(
+    Path(id): Path<String>,
+) -> Response {
+    match RECORDINGS.recording(&id).await {
+        Ok(recording) => Json(recording).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// DELETE /api/recordings/:id - 録画を削除（FLVとMP4の両方）
+pub async fn delete(
+    Path(id): Path<String>,
+) -> Response {
+    match RECORDINGS.delete(&id).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// GET /api/recordings/:id/file - 録画ファイル（MP4があればMP4、なければFLV）。Rangeリクエストに対応
+pub async fn file(
+    Path(id): Path<String>,
+    request: Request,
+) -> Response {
+    serve_file(&RECORDINGS, &id, request).await
+}
+
+/// `store` の録画ファイルを返す
+async fn serve_file(store: &RecordingStore, id: &str, request: Request) -> Response {
+    let path = match store.playback_file(id).await {
+        Ok(path) => path,
+        Err(e) => return error_response(e),
+    };
+    match ServeFile::new(path).try_call(request).await {
+        Ok(response) => response.map(Body::new),
+        Err(e) => error_response(e.into()),
+    }
+}
+
+/// GET /api/recordings/:id/hls/:file - 録画のVOD HLS（index.m3u8 と <番号>.ts）
+pub async fn hls(
+    Path((id, file)): Path<(String, String)>,
+) -> Response {
+    if file == "index.m3u8" {
+        return match RECORDINGS.hls_playlist(&id).await {
+            Ok(playlist) => (
+                [(header::CONTENT_TYPE, "application/vnd.apple.mpegurl")],
+                playlist,
+            ).into_response(),
+            Err(e) => error_response(e),
+        };
+    }
+
+    let sequence = file.strip_suffix(".ts").and_then(|name| name.parse().ok());
+    let Some(sequence) = sequence else {
+        return error_response(RecordingError::NotFound);
+    };
+    match RECORDINGS.hls_segment(&id, sequence).await {
+        Ok(data) => (
+            [
+                (header::CONTENT_TYPE, "video/mp2t"),
+                (header::CACHE_CONTROL, "max-age=3600"),
+            ],
+            data,
+        ).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+fn error_response(error: RecordingError) -> Response {
+    let status = match error {
+        RecordingError::NotFound => StatusCode::NOT_FOUND,
+        RecordingError::InProgress => StatusCode::CONFLICT,
+        RecordingError::UnsupportedCodec(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        RecordingError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (
+        status,
+        [(header::CONTENT_TYPE, "text/plain")],
+        error.to_string(),
+    ).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RecordingConfig;
+    use std::path::PathBuf;
+
+    /// 一時ディレクトリに録画を置くストア
+    fn temp_store() -> (RecordingStore, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("vyuber-recordings-{}", uuid::Uuid::new_v4()));
+        let store = RecordingStore::new(RecordingConfig {
+            dir: dir.clone(),
+            ..RecordingConfig::default()
+        });
+        (store, dir)
+    }
+
+    async fn get_file(
+        store: &RecordingStore,
+        id: &str,
+        range: Option<&str>,
+    ) -> (StatusCode, Option<String>, Vec<u8>) {
+        let mut request = Request::builder().uri(format!("/api/recordings/{}/file", id));
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range);
+        }
+        let response = serve_file(store, id, request.body(Body::empty()).unwrap()).await;
+        let status = response.status();
+        let content_range = response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, content_range, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn serves_byte_ranges_of_recordings() {
+        let (store, dir) = temp_store();
+        tokio::fs::create_dir_all(dir.join("test")).await.unwrap();
+        let data: Vec<u8> = (0..1024).map(|i| i as u8).collect();
+        tokio::fs::write(dir.join("test").join("20260101-120000.mp4"), &data)
+            .await
+            .unwrap();
+        let id = "test_20260101-120000";
+
+        let (status, _, body) = get_file(&store, id, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, data);
+
+        let (status, content_range, body) = get_file(&store, id, Some("bytes=100-199")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(content_range.as_deref(), Some("bytes 100-199/1024"));
+        assert_eq!(body, &data[100..200]);
+
+        // 末尾から / 終わりを省略
+        let (status, content_range, body) = get_file(&store, id, Some("bytes=-24")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(content_range.as_deref(), Some("bytes 1000-1023/1024"));
+        assert_eq!(body, &data[1000..]);
+        let (status, _, body) = get_file(&store, id, Some("bytes=1000-")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body, &data[1000..]);
+
+        // ファイルの外
+        let (status, content_range, _) = get_file(&store, id, Some("bytes=2048-")).await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(content_range.as_deref(), Some("bytes */1024"));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_ids_outside_the_recording_dir() {
+        let (store, dir) = temp_store();
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        for id in [
+            "missing_20260101-120000",
+            "key_..",
+            "key_a.mp4",
+            "..%2F..%2Fetc_passwd",
+        ] {
+            let (status, _, _) = get_file(&store, id, None).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", id);
+        }
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
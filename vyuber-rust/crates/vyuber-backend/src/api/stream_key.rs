@@ -4,16 +4,42 @@ use std::sync::{Arc, RwLock};
 use uuid::Uuid;
 use vyuber_shared::stream::StreamKeyResponse;
 
+use crate::config;
+
 // グローバルなストリームキー管理（メモリベース）
 static STREAM_KEY: Lazy<Arc<RwLock<Option<String>>>> = Lazy::new(|| {
     Arc::new(RwLock::new(None))
 });
 
+/// RTMP publish時のストリームキー検証
+pub fn is_valid_key(key: &str) -> bool {
+    STREAM_KEY.read().unwrap().as_deref() == Some(key)
+}
+
+/// 発行済みのストリームキー
+pub fn current_key() -> Option<String> {
+    STREAM_KEY.read().unwrap().clone()
+}
+
+/// テスト用に発行したストリームキー
+///
+/// キーは1つしかないので、ガードを持っている間は他のテストがそのキーで配信しない
+#[cfg(test)]
+pub async fn test_key() -> (tokio::sync::MutexGuard<'static, ()>, String) {
+    static LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+    let guard = LOCK.lock().await;
+    let key = STREAM_KEY
+        .write()
+        .unwrap()
+        .get_or_insert_with(|| Uuid::new_v4().to_string().replace("-", ""))
+        .clone();
+    (guard, key)
+}
+
 /// GET /api/stream-key - 既存のストリームキーを取得
 pub async fn get_key() -> Json<StreamKeyResponse> {
     let key = STREAM_KEY.read().unwrap().clone();
-    let rtmp_port = std::env::var("RTMP_PORT").unwrap_or("1935".to_string());
-    let server_url = format!("rtmp://localhost:{}/live", rtmp_port);
+    let server_url = server_url();
     let full_url = key.as_ref().map(|k| format!("{}/{}", server_url, k));
 
     Json(StreamKeyResponse {
@@ -26,8 +52,7 @@ pub async fn get_key() -> Json<StreamKeyResponse> {
 /// POST /api/stream-key - 新しいストリームキーを生成
 pub async fn generate_key() -> Json<StreamKeyResponse> {
     let key = Uuid::new_v4().to_string().replace("-", "");
-    let rtmp_port = std::env::var("RTMP_PORT").unwrap_or("1935".to_string());
-    let server_url = format!("rtmp://localhost:{}/live", rtmp_port);
+    let server_url = server_url();
     let full_url = format!("{}/{}", server_url, key);
 
     *STREAM_KEY.write().unwrap() = Some(key.clone());
@@ -47,3 +72,12 @@ pub async fn delete_key() -> StatusCode {
     tracing::info!("Deleted stream key");
     StatusCode::OK
 }
+
+/// 配信ソフトに設定するサーバーURL（RTMPSが有効ならそちらを案内する）
+fn server_url() -> String {
+    let config = config::get();
+    if config.rtmps.is_enabled() {
+        return format!("rtmps://localhost:{}/live", config.rtmps.port);
+    }
+    format!("rtmp://localhost:{}/live", config.rtmp.port)
+}
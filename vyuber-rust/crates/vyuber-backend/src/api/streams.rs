@@ -0,0 +1,129 @@
+use axum::{
+    Json,
+    extract::Path,
+    response::{Response, IntoResponse},
+    http::{StatusCode, header},
+};
+
+use vyuber_shared::stream::{PublishPolicySetting, RecordingSetting, RelayTargets};
+
+use crate::api::stream_key;
+use crate::media::hub::HUB;
+use crate::media::recording::RECORDINGS;
+use crate::rtmp::relay::RELAYS;
+
+/// GET /api/streams/:stream_key/info - 配信中のストリームの映像/音声情報
+pub async fn get_info(
+    Path(stream_key): Path<String>,
+) -> Response {
+    match HUB.info(&stream_key) {
+        Some(info) => Json(info).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "Stream not found or offline",
+        ).into_response(),
+    }
+}
+
+/// GET /api/streams/:stream_key/stats - 配信中のストリームの受信統計
+pub async fn get_stats(
+    Path(stream_key): Path<String>,
+) -> Response {
+    match HUB.stats(&stream_key) {
+        Some(stats) => Json(stats).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "Stream not found or offline",
+        ).into_response(),
+    }
+}
+
+/// GET /api/streams/:stream_key/relays - プッシュリレー先ごとの状態
+pub async fn get_relays(
+    Path(stream_key): Path<String>,
+) -> Response {
+    Json(RELAYS.status(&stream_key)).into_response()
+}
+
+/// PUT /api/streams/:stream_key/relays - プッシュリレー先を設定（次の配信から反映）
+///
+/// 任意のホストへ接続させられるので、発行済みのストリームキーにだけ設定できる
+pub async fn put_relays(
+    Path(stream_key): Path<String>,
+    Json(body): Json<RelayTargets>,
+) -> Response {
+    if !stream_key::is_valid_key(&stream_key) {
+        return (
+            StatusCode::FORBIDDEN,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "Invalid stream key",
+        ).into_response();
+    }
+    match RELAYS.set_targets(&stream_key, body.targets) {
+        Ok(()) => Json(RELAYS.status(&stream_key)).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            [(header::CONTENT_TYPE, "text/plain")],
+            e.to_string(),
+        ).into_response(),
+    }
+}
+
+/// GET /api/streams/:stream_key/publish-policy - 配信中のキーへ重複してpublishされたときの扱い
+pub async fn get_publish_policy(
+    Path(stream_key): Path<String>,
+) -> Response {
+    Json(PublishPolicySetting {
+        policy: HUB.publish_policy(&stream_key),
+    }).into_response()
+}
+
+/// PUT /api/streams/:stream_key/publish-policy - 重複publishの扱いを設定（次のpublishから反映）
+///
+/// 任意のキーで設定を増やせないよう、発行済みのストリームキーにだけ設定できる
+pub async fn put_publish_policy(
+    Path(stream_key): Path<String>,
+    Json(body): Json<PublishPolicySetting>,
+) -> Response {
+    if !stream_key::is_valid_key(&stream_key) {
+        return (
+            StatusCode::FORBIDDEN,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "Invalid stream key",
+        ).into_response();
+    }
+    HUB.set_publish_policy(&stream_key, body.policy);
+    Json(body).into_response()
+}
+
+/// GET /api/streams/:stream_key/recording - 録画の有効/無効と録画中のファイル
+pub async fn get_recording(
+    Path(stream_key): Path<String>,
+) -> Response {
+    Json(RECORDINGS.setting(&stream_key)).into_response()
+}
+
+/// PUT /api/streams/:stream_key/recording - 録画の有効/無効を設定（配信中なら次のキーフレームから反映）
+///
+/// 録画の保存先のディレクトリ名になるので、発行済みのストリームキーにだけ設定できる
+pub async fn put_recording(
+    Path(stream_key): Path<String>,
+    Json(body): Json<RecordingSetting>,
+) -> Response {
+    if !stream_key::is_valid_key(&stream_key) {
+        return (
+            StatusCode::FORBIDDEN,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "Invalid stream key",
+        ).into_response();
+    }
+    RECORDINGS.set_enabled(&stream_key, body.enabled);
+    Json(RECORDINGS.setting(&stream_key)).into_response()
+}
+
+/// GET /api/remux-jobs - 録画のMP4変換ジョブの一覧（古い順）
+pub async fn get_remux_jobs() -> Response {
+    Json(RECORDINGS.remux_jobs()).into_response()
+}
@@ -0,0 +1,64 @@
+use axum::{
+    extract::Path,
+    response::{Response, IntoResponse},
+    http::{HeaderMap, StatusCode, header},
+};
+
+use super::whip::is_sdp;
+use crate::rtc::error::RtcError;
+use crate::rtc::whep;
+
+/// POST /whep/:stream_key - WHEPのオファーを受けて視聴を開始
+pub async fn play(
+    Path(stream_key): Path<String>,
+    headers: HeaderMap,
+    offer: String,
+) -> Response {
+    if !is_sdp(&headers) {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "Content-Type must be application/sdp",
+        ).into_response();
+    }
+
+    match whep::play(&stream_key, offer).await {
+        Ok((session_id, answer)) => (
+            StatusCode::CREATED,
+            [
+                (header::CONTENT_TYPE, "application/sdp".to_string()),
+                (header::LOCATION, format!("/whep/{}/{}", stream_key, session_id)),
+            ],
+            answer,
+        ).into_response(),
+        Err(e) => {
+            tracing::warn!("WHEP playback rejected for {}: {}", stream_key, e);
+            let status = match e {
+                RtcError::StreamNotFound => StatusCode::NOT_FOUND,
+                RtcError::UnsupportedCodec => StatusCode::NOT_ACCEPTABLE,
+                RtcError::InvalidSdp(_) => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (
+                status,
+                [(header::CONTENT_TYPE, "text/plain")],
+                e.to_string(),
+            ).into_response()
+        }
+    }
+}
+
+/// DELETE /whep/:stream_key/:session_id - WHEPの視聴を終了
+pub async fn delete(
+    Path((stream_key, session_id)): Path<(String, String)>,
+) -> Response {
+    if whep::close(&stream_key, &session_id).await {
+        StatusCode::OK.into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "Session not found",
+        ).into_response()
+    }
+}
@@ -0,0 +1,73 @@
+use axum::{
+    extract::Path,
+    response::{Response, IntoResponse},
+    http::{HeaderMap, StatusCode, header},
+};
+
+use crate::media::hub::HubError;
+use crate::rtc::error::RtcError;
+use crate::rtc::whip;
+
+/// POST /whip/:stream_key - WHIPのオファーを受けて配信を開始
+pub async fn publish(
+    Path(stream_key): Path<String>,
+    headers: HeaderMap,
+    offer: String,
+) -> Response {
+    if !is_sdp(&headers) {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "Content-Type must be application/sdp",
+        ).into_response();
+    }
+
+    match whip::publish(&stream_key, offer).await {
+        Ok((session_id, answer)) => (
+            StatusCode::CREATED,
+            [
+                (header::CONTENT_TYPE, "application/sdp".to_string()),
+                (header::LOCATION, format!("/whip/{}/{}", stream_key, session_id)),
+            ],
+            answer,
+        ).into_response(),
+        Err(e) => {
+            tracing::warn!("WHIP publish rejected for {}: {}", stream_key, e);
+            let status = match e {
+                RtcError::InvalidKey => StatusCode::FORBIDDEN,
+                RtcError::Hub(HubError::AlreadyPublishing(_)) => StatusCode::CONFLICT,
+                RtcError::InvalidSdp(_) => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (
+                status,
+                [(header::CONTENT_TYPE, "text/plain")],
+                e.to_string(),
+            ).into_response()
+        }
+    }
+}
+
+/// DELETE /whip/:stream_key/:session_id - WHIPの配信を終了
+pub async fn delete(
+    Path((stream_key, session_id)): Path<(String, String)>,
+) -> Response {
+    if whip::close(&stream_key, &session_id).await {
+        StatusCode::OK.into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            [(header::CONTENT_TYPE, "text/plain")],
+            "Session not found",
+        ).into_response()
+    }
+}
+
+/// Content-Type が application/sdp か
+pub fn is_sdp(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("application/sdp"))
+}
@@ -1,31 +1,549 @@
-/// 環境変数から設定を読み込む
-#[allow(dead_code)]
+use once_cell::sync::OnceCell;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::time::Duration;
+use vyuber_shared::stream::PublishPolicy;
+
+/// 起動時に一度だけ読み込んだ設定
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// 設定を登録する（`main` の最初で一度だけ呼ぶ）
+pub fn init(config: Config) -> &'static Config {
+    if CONFIG.set(config).is_err() {
+        panic!("config is already initialized");
+    }
+    get()
+}
+
+/// 登録済みの設定（テストなど `init` していなければ既定値）
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// 環境変数から読み込む設定
+#[derive(Debug, Clone)]
 pub struct Config {
-    pub gemini_api_key: String,
-    pub rtmp_port: u16,
-    pub http_flv_port: u16,
+    /// APIサーバーのポート
+    pub http_port: u16,
+    /// 静的ファイル（Leptosのビルド成果物）のディレクトリ
+    pub static_dir: PathBuf,
+    pub rtmp: RtmpConfig,
+    pub rtmps: RtmpsConfig,
+    pub gop_cache: GopCacheConfig,
+    pub hls: HlsConfig,
+    pub relay: RelayConfig,
+    pub publish: PublishConfig,
+    pub srt: SrtConfig,
+    pub recording: RecordingConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            http_port: 3000,
+            static_dir: PathBuf::from("crates/vyuber-backend/static"),
+            rtmp: RtmpConfig::default(),
+            rtmps: RtmpsConfig::default(),
+            gop_cache: GopCacheConfig::default(),
+            hls: HlsConfig::default(),
+            relay: RelayConfig::default(),
+            publish: PublishConfig::default(),
+            srt: SrtConfig::default(),
+            recording: RecordingConfig::default(),
+        }
+    }
 }
 
 impl Config {
-    #[allow(dead_code)]
+    /// 値が不正なら起動時にパニックする
     pub fn from_env() -> Self {
-        let gemini_api_key = std::env::var("GEMINI_API_KEY")
-            .expect("GEMINI_API_KEY must be set");
+        let default = Self::default();
+
+        let http_port = std::env::var("HTTP_PORT")
+            .map(|v| v.parse().expect("HTTP_PORT must be a valid port number"))
+            .unwrap_or(default.http_port);
+
+        let static_dir = std::env::var("STATIC_DIR")
+            .map(PathBuf::from)
+            .unwrap_or(default.static_dir);
+
+        Self {
+            http_port,
+            static_dir,
+            rtmp: RtmpConfig::from_env(),
+            rtmps: RtmpsConfig::from_env(),
+            gop_cache: GopCacheConfig::from_env(),
+            hls: HlsConfig::from_env(),
+            relay: RelayConfig::from_env(),
+            publish: PublishConfig::from_env(),
+            srt: SrtConfig::from_env(),
+            recording: RecordingConfig::from_env(),
+        }
+    }
+}
+
+/// RTMPリスナーの設定
+#[derive(Debug, Clone, Copy)]
+pub struct RtmpConfig {
+    /// 待ち受けるアドレス（LANに公開するなら `0.0.0.0`）
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// 同時接続数の上限（0で無制限、RTMPSと合算）
+    pub max_connections: usize,
+    /// 1つのIPアドレスからの同時接続数の上限（0で無制限）
+    pub max_connections_per_ip: usize,
+    /// ハンドシェイク（RTMPSはTLSハンドシェイクを含む）の制限時間（ミリ秒）
+    pub handshake_timeout_ms: u64,
+    /// 送受信が途絶えてから切断するまでの時間（ミリ秒、0で無制限）
+    pub idle_timeout_ms: u64,
+}
+
+impl Default for RtmpConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 1935,
+            max_connections: 200,
+            max_connections_per_ip: 20,
+            handshake_timeout_ms: 10_000,
+            idle_timeout_ms: 60_000,
+        }
+    }
+}
+
+impl RtmpConfig {
+    fn from_env() -> Self {
+        let default = Self::default();
+
+        let bind_address = std::env::var("RTMP_BIND_ADDRESS")
+            .map(|v| v.parse().expect("RTMP_BIND_ADDRESS must be an IP address"))
+            .unwrap_or(default.bind_address);
+
+        let port = std::env::var("RTMP_PORT")
+            .map(|v| v.parse().expect("RTMP_PORT must be a valid port number"))
+            .unwrap_or(default.port);
+
+        let max_connections = std::env::var("RTMP_MAX_CONNECTIONS")
+            .map(|v| v.parse().expect("RTMP_MAX_CONNECTIONS must be a number"))
+            .unwrap_or(default.max_connections);
+
+        let max_connections_per_ip = std::env::var("RTMP_MAX_CONNECTIONS_PER_IP")
+            .map(|v| v.parse().expect("RTMP_MAX_CONNECTIONS_PER_IP must be a number"))
+            .unwrap_or(default.max_connections_per_ip);
+
+        let handshake_timeout_ms = std::env::var("RTMP_HANDSHAKE_TIMEOUT_MS")
+            .map(|v| v.parse().expect("RTMP_HANDSHAKE_TIMEOUT_MS must be a number"))
+            .unwrap_or(default.handshake_timeout_ms);
+
+        let idle_timeout_ms = std::env::var("RTMP_IDLE_TIMEOUT_MS")
+            .map(|v| v.parse().expect("RTMP_IDLE_TIMEOUT_MS must be a number"))
+            .unwrap_or(default.idle_timeout_ms);
+
+        Self {
+            bind_address,
+            port,
+            max_connections,
+            max_connections_per_ip,
+            handshake_timeout_ms,
+            idle_timeout_ms,
+        }
+    }
+
+    pub fn handshake_timeout(&self) -> Duration {
+        Duration::from_millis(self.handshake_timeout_ms)
+    }
+
+    /// 無通信タイムアウト（無制限なら `None`）
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        (self.idle_timeout_ms > 0).then(|| Duration::from_millis(self.idle_timeout_ms))
+    }
+}
+
+/// GOPキャッシュの上限（どちらかを超えたら次のキーフレームまでキャッシュしない）
+#[derive(Debug, Clone, Copy)]
+pub struct GopCacheConfig {
+    /// キャッシュするバイト数の上限（0で無効）
+    pub max_bytes: usize,
+    /// キャッシュする長さの上限（ミリ秒、0で無効）
+    pub max_duration_ms: u32,
+}
+
+impl Default for GopCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 16 * 1024 * 1024,
+            max_duration_ms: 10_000,
+        }
+    }
+}
+
+impl GopCacheConfig {
+    fn from_env() -> Self {
+        let default = Self::default();
+
+        let max_bytes = std::env::var("GOP_CACHE_MAX_BYTES")
+            .map(|v| v.parse().expect("GOP_CACHE_MAX_BYTES must be a number"))
+            .unwrap_or(default.max_bytes);
+
+        let max_duration_ms = std::env::var("GOP_CACHE_MAX_MS")
+            .map(|v| v.parse().expect("GOP_CACHE_MAX_MS must be a number"))
+            .unwrap_or(default.max_duration_ms);
+
+        Self {
+            max_bytes,
+            max_duration_ms,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0 && self.max_duration_ms > 0
+    }
+}
+
+/// HLSセグメントの設定
+#[derive(Debug, Clone, Copy)]
+pub struct HlsConfig {
+    /// 目標セグメント長（ミリ秒、キーフレームで区切るので実際はこれ以上になる）
+    pub segment_duration_ms: u32,
+    /// プレイリストに載せるセグメント数
+    pub playlist_length: usize,
+    /// LL-HLS（パーシャルセグメントとブロッキングリロード）を有効にする
+    pub low_latency: bool,
+    /// LL-HLSの目標パート長（ミリ秒）
+    pub part_duration_ms: u32,
+}
+
+impl Default for HlsConfig {
+    fn default() -> Self {
+        Self {
+            segment_duration_ms: 2000,
+            playlist_length: 6,
+            low_latency: false,
+            part_duration_ms: 333,
+        }
+    }
+}
+
+impl HlsConfig {
+    fn from_env() -> Self {
+        let default = Self::default();
+
+        let segment_duration_ms = std::env::var("HLS_SEGMENT_DURATION_MS")
+            .map(|v| v.parse().expect("HLS_SEGMENT_DURATION_MS must be a number"))
+            .unwrap_or(default.segment_duration_ms);
+
+        let playlist_length = std::env::var("HLS_PLAYLIST_LENGTH")
+            .map(|v| v.parse().expect("HLS_PLAYLIST_LENGTH must be a number"))
+            .unwrap_or(default.playlist_length);
+
+        let low_latency = std::env::var("HLS_LOW_LATENCY")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(default.low_latency);
+
+        let part_duration_ms = std::env::var("HLS_PART_DURATION_MS")
+            .map(|v| v.parse().expect("HLS_PART_DURATION_MS must be a number"))
+            .unwrap_or(default.part_duration_ms);
+
+        Self {
+            segment_duration_ms,
+            playlist_length,
+            low_latency,
+            part_duration_ms,
+        }
+    }
+}
+
+/// RTMPプッシュリレーの設定
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    /// リレー先が設定されていないストリームキーで使うリレー先
+    pub default_targets: Vec<String>,
+    /// 再接続までの待ち時間の初期値（ミリ秒、失敗するたびに倍にする）
+    pub backoff_min_ms: u64,
+    /// 再接続までの待ち時間の上限（ミリ秒）
+    pub backoff_max_ms: u64,
+    /// `rtmps://` のリレー先の証明書を検証するCA証明書（PEM）のパス
+    pub ca_path: PathBuf,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            default_targets: Vec::new(),
+            backoff_min_ms: 1000,
+            backoff_max_ms: 30_000,
+            ca_path: PathBuf::from("/etc/ssl/certs/ca-certificates.crt"),
+        }
+    }
+}
+
+impl RelayConfig {
+    fn from_env() -> Self {
+        let default = Self::default();
+
+        // カンマ区切りのURL
+        let default_targets = std::env::var("RELAY_TARGETS")
+            .map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or(default.default_targets);
+
+        let backoff_min_ms = std::env::var("RELAY_BACKOFF_MIN_MS")
+            .map(|v| v.parse().expect("RELAY_BACKOFF_MIN_MS must be a number"))
+            .unwrap_or(default.backoff_min_ms);
+
+        let backoff_max_ms = std::env::var("RELAY_BACKOFF_MAX_MS")
+            .map(|v| v.parse().expect("RELAY_BACKOFF_MAX_MS must be a number"))
+            .unwrap_or(default.backoff_max_ms);
 
-        let rtmp_port = std::env::var("RTMP_PORT")
-            .unwrap_or("1935".to_string())
-            .parse()
-            .expect("RTMP_PORT must be a valid port number");
+        let ca_path = std::env::var("RELAY_CA_PATH")
+            .map(PathBuf::from)
+            .unwrap_or(default.ca_path);
 
-        let http_flv_port = std::env::var("HTTP_FLV_PORT")
-            .unwrap_or("8888".to_string())
-            .parse()
-            .expect("HTTP_FLV_PORT must be a valid port number");
+        Self {
+            default_targets,
+            backoff_min_ms,
+            backoff_max_ms,
+            ca_path,
+        }
+    }
+}
+
+/// RTMPS（RTMP over TLS）リスナーの設定
+#[derive(Debug, Clone)]
+pub struct RtmpsConfig {
+    pub port: u16,
+    /// PEM形式の証明書チェーンのパス
+    pub cert_path: Option<String>,
+    /// PEM形式の秘密鍵のパス
+    pub key_path: Option<String>,
+}
 
+impl Default for RtmpsConfig {
+    fn default() -> Self {
         Self {
-            gemini_api_key,
-            rtmp_port,
-            http_flv_port,
+            port: 443,
+            cert_path: None,
+            key_path: None,
         }
     }
 }
+
+impl RtmpsConfig {
+    fn from_env() -> Self {
+        let default = Self::default();
+
+        let port = std::env::var("RTMPS_PORT")
+            .map(|v| v.parse().expect("RTMPS_PORT must be a valid port number"))
+            .unwrap_or(default.port);
+
+        let cert_path = std::env::var("RTMPS_CERT_PATH").ok().or(default.cert_path);
+        let key_path = std::env::var("RTMPS_KEY_PATH").ok().or(default.key_path);
+
+        Self {
+            port,
+            cert_path,
+            key_path,
+        }
+    }
+
+    /// 証明書と秘密鍵の両方が設定されていれば有効
+    pub fn is_enabled(&self) -> bool {
+        self.cert_path.is_some() && self.key_path.is_some()
+    }
+}
+
+/// 重複publishの扱いの設定
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PublishConfig {
+    /// ストリームキーごとに設定されていない場合の扱い
+    pub default_policy: PublishPolicy,
+}
+
+impl PublishConfig {
+    fn from_env() -> Self {
+        let default = Self::default();
+
+        let default_policy = std::env::var("PUBLISH_POLICY")
+            .map(|v| match v.to_ascii_lowercase().as_str() {
+                "reject" => PublishPolicy::Reject,
+                "takeover" => PublishPolicy::Takeover,
+                _ => panic!("PUBLISH_POLICY must be 'reject' or 'takeover'"),
+            })
+            .unwrap_or(default.default_policy);
+
+        Self { default_policy }
+    }
+}
+
+/// SRTリスナー（とcallerモード）の設定
+#[derive(Debug, Clone)]
+pub struct SrtConfig {
+    /// 待ち受けるアドレス（LANに公開するなら `0.0.0.0`）
+    pub bind_address: IpAddr,
+    /// UDPポート（0で無効）
+    pub port: u16,
+    /// 再送を待つ受信遅延（ミリ秒）
+    pub latency_ms: u64,
+    /// 暗号化のパスフレーズ（10〜79文字、設定すると必須になる）
+    pub passphrase: Option<String>,
+    /// callerモードで接続するlistenerの `host:port`（設定するとこちらから接続して受信する）
+    pub caller_address: Option<String>,
+    /// callerモードで送るstreamid
+    pub caller_stream_id: Option<String>,
+    /// callerモードで受信した配信を流すストリームキー（未設定なら発行済みのキー）
+    pub caller_stream_key: Option<String>,
+}
+
+impl Default for SrtConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 9000,
+            latency_ms: 120,
+            passphrase: None,
+            caller_address: None,
+            caller_stream_id: None,
+            caller_stream_key: None,
+        }
+    }
+}
+
+impl SrtConfig {
+    fn from_env() -> Self {
+        let default = Self::default();
+
+        let bind_address = std::env::var("SRT_BIND_ADDRESS")
+            .map(|v| v.parse().expect("SRT_BIND_ADDRESS must be an IP address"))
+            .unwrap_or(default.bind_address);
+
+        let port = std::env::var("SRT_PORT")
+            .map(|v| v.parse().expect("SRT_PORT must be a valid port number"))
+            .unwrap_or(default.port);
+
+        let latency_ms = std::env::var("SRT_LATENCY_MS")
+            .map(|v| v.parse().expect("SRT_LATENCY_MS must be a number"))
+            .unwrap_or(default.latency_ms);
+
+        let passphrase = std::env::var("SRT_PASSPHRASE")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .or(default.passphrase);
+        if let Some(passphrase) = &passphrase {
+            assert!(
+                (10..=79).contains(&passphrase.len()),
+                "SRT_PASSPHRASE must be 10 to 79 characters"
+            );
+        }
+
+        let caller_address = std::env::var("SRT_CALLER_ADDRESS")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .or(default.caller_address);
+        let caller_stream_id = std::env::var("SRT_CALLER_STREAM_ID")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .or(default.caller_stream_id);
+        let caller_stream_key = std::env::var("SRT_CALLER_STREAM_KEY")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .or(default.caller_stream_key);
+
+        Self {
+            bind_address,
+            port,
+            latency_ms,
+            passphrase,
+            caller_address,
+            caller_stream_id,
+            caller_stream_key,
+        }
+    }
+
+    pub fn latency(&self) -> Duration {
+        Duration::from_millis(self.latency_ms)
+    }
+}
+
+/// 配信の録画の設定
+#[derive(Debug, Clone)]
+pub struct RecordingConfig {
+    /// 録画ファイルの保存先（`<dir>/<ストリームキー>/<開始日時>.flv`）
+    pub dir: PathBuf,
+    /// ストリームキーごとに設定されていない場合に録画するか
+    pub enabled: bool,
+    /// 1ファイルの最大サイズ（MB、0で無制限。超えたら次のキーフレームで分割する）
+    pub max_size_mb: u64,
+    /// 1ファイルの最大長（秒、0で無制限。超えたら次のキーフレームで分割する）
+    pub max_duration_secs: u64,
+    /// 録画ファイルを閉じたらMP4に変換するか
+    pub remux_mp4: bool,
+    /// MP4に変換した後も元のFLVを残すか
+    pub keep_flv: bool,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("recordings"),
+            enabled: false,
+            max_size_mb: 0,
+            max_duration_secs: 0,
+            remux_mp4: true,
+            keep_flv: true,
+        }
+    }
+}
+
+impl RecordingConfig {
+    fn from_env() -> Self {
+        let default = Self::default();
+
+        let dir = std::env::var("RECORDING_DIR")
+            .map(PathBuf::from)
+            .unwrap_or(default.dir);
+
+        let enabled = std::env::var("RECORDING_ENABLED")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(default.enabled);
+
+        let max_size_mb = std::env::var("RECORDING_MAX_SIZE_MB")
+            .map(|v| v.parse().expect("RECORDING_MAX_SIZE_MB must be a number"))
+            .unwrap_or(default.max_size_mb);
+
+        let max_duration_secs = std::env::var("RECORDING_MAX_DURATION_SECS")
+            .map(|v| v.parse().expect("RECORDING_MAX_DURATION_SECS must be a number"))
+            .unwrap_or(default.max_duration_secs);
+
+        let remux_mp4 = std::env::var("RECORDING_REMUX_MP4")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(default.remux_mp4);
+
+        let keep_flv = std::env::var("RECORDING_KEEP_FLV")
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(default.keep_flv);
+
+        Self {
+            dir,
+            enabled,
+            max_size_mb,
+            max_duration_secs,
+            remux_mp4,
+            keep_flv,
+        }
+    }
+
+    /// 分割するファイルサイズ（無制限なら `None`）
+    pub fn max_size(&self) -> Option<u64> {
+        (self.max_size_mb > 0).then(|| self.max_size_mb * 1024 * 1024)
+    }
+
+    /// 分割する長さ（無制限なら `None`）
+    pub fn max_duration(&self) -> Option<Duration> {
+        (self.max_duration_secs > 0).then(|| Duration::from_secs(self.max_duration_secs))
+    }
+}
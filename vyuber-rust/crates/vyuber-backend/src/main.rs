@@ -1,6 +1,6 @@
 use axum::{
     Router,
-    routing::{get, post},
+    routing::{delete, get, post},
 };
 use tower_http::{
     cors::CorsLayer,
@@ -10,8 +10,11 @@ use std::net::SocketAddr;
 
 mod api;
 mod config;
+mod media;
 mod services;
 mod rtmp;
+mod rtc;
+mod srt;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -22,16 +25,33 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     tracing::info!("Starting VYuber Rust Backend...");
 
+    // 設定は起動時に一度だけ読み込み、各サブシステムで共有する
+    let config = config::init(config::Config::from_env());
+
     // RTMPサーバーをバックグラウンドで起動
-    if let Err(e) = rtmp::start_rtmp_server().await {
+    if let Err(e) = rtmp::start_rtmp_server(config.rtmp, &config.rtmps).await {
         tracing::error!("Failed to start RTMP server: {}", e);
     }
 
-    // 静的ファイルのパスを決定
-    let static_path = std::env::var("STATIC_DIR")
-        .unwrap_or_else(|_| "crates/vyuber-backend/static".to_string());
+    // SRTインジェストをバックグラウンドで起動
+    if let Err(e) = srt::start_srt_server(config.srt.clone()).await {
+        tracing::error!("Failed to start SRT server: {}", e);
+    }
+
+    // 配信開始を監視してリレー先へ転送する
+    rtmp::start_relay();
+
+    // 配信開始を監視してHLS/CMAFセグメントを作る
+    media::hls::start_hls_segmenter(config.hls);
+    media::cmaf::start_cmaf_packager(config.hls);
+
+    // 録画が有効なストリームキーの配信をファイルに書き出す
+    media::recording::start_recorder();
 
-    tracing::info!("Serving static files from: {}", static_path);
+    // 静的ファイルのパス
+    let static_path = &config.static_dir;
+
+    tracing::info!("Serving static files from: {}", static_path.display());
 
     // Axum APIルーター
     let app = Router::new()
@@ -43,12 +63,42 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         )
         .route("/api/chat", post(api::chat::handle_chat))
         .route("/api/live/:stream_key", get(api::live::stream_flv))
+        .route("/ws/live/:stream_key", get(api::live::stream_ws_flv))
+        .route("/api/streams/:stream_key/info", get(api::streams::get_info))
+        .route("/api/streams/:stream_key/stats", get(api::streams::get_stats))
+        .route("/api/streams/:stream_key/relays",
+            get(api::streams::get_relays)
+            .put(api::streams::put_relays)
+        )
+        .route("/api/streams/:stream_key/publish-policy",
+            get(api::streams::get_publish_policy)
+            .put(api::streams::put_publish_policy)
+        )
+        .route("/api/streams/:stream_key/recording",
+            get(api::streams::get_recording)
+            .put(api::streams::put_recording)
+        )
+        .route("/api/remux-jobs", get(api::streams::get_remux_jobs))
+        .route("/api/recordings", get(api::recordings::list))
+        .route("/api/recordings/:id",
+            get(api::recordings::get)
+            .delete(api::recordings::delete)
+        )
+        .route("/api/recordings/:id/file", get(api::recordings::file))
+        .route("/api/recordings/:id/hls/:file", get(api::recordings::hls))
+        .route("/hls/:stream_key/:file", get(api::hls::serve))
+        .route("/cmaf/:stream_key/:file", get(api::cmaf::serve))
+        .route("/dash/:stream_key/manifest.mpd", get(api::cmaf::manifest))
+        .route("/whip/:stream_key", post(api::whip::publish))
+        .route("/whip/:stream_key/:session_id", delete(api::whip::delete))
+        .route("/whep/:stream_key", post(api::whep::play))
+        .route("/whep/:stream_key/:session_id", delete(api::whep::delete))
         // 静的ファイル配信 (Leptosビルド成果物)
         .nest_service("/", ServeDir::new(static_path))
         // CORS設定
         .layer(CorsLayer::permissive());
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    let addr = SocketAddr::from(([127, 0, 0, 1], config.http_port));
     tracing::info!("Axum server listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
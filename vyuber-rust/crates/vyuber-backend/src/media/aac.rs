@@ -0,0 +1,115 @@
+//! AAC のデコーダ設定とADTSヘッダ
+
+/// CRCなしのADTSヘッダの長さ
+const ADTS_HEADER_LEN: usize = 7;
+
+/// サンプリング周波数インデックスに対応するサンプルレート
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// AudioSpecificConfig の先頭部分
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AacConfig {
+    /// Audio Object Type（2 = AAC LC）
+    pub object_type: u8,
+    pub frequency_index: u8,
+    pub channel_config: u8,
+}
+
+impl AacConfig {
+    /// AudioSpecificConfig を解析する（不正なら `None`）
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let &[b0, b1, ..] = data else {
+            return None;
+        };
+        Some(Self {
+            object_type: b0 >> 3,
+            frequency_index: ((b0 & 0x07) << 1) | (b1 >> 7),
+            channel_config: (b1 >> 3) & 0x0F,
+        })
+    }
+
+    /// ADTSフレームを1つ読み、デコーダ設定と生のAACフレーム、残りのデータを返す（不正なら `None`）
+    pub fn parse_adts(data: &[u8]) -> Option<(Self, &[u8], &[u8])> {
+        let header = data.get(..ADTS_HEADER_LEN)?;
+        if header[0] != 0xFF || header[1] & 0xF0 != 0xF0 {
+            return None;
+        }
+        let protection_absent = header[1] & 0x01 != 0;
+        let frame_len = ((header[3] as usize & 0x03) << 11)
+            | ((header[4] as usize) << 3)
+            | (header[5] as usize >> 5);
+        let header_len = if protection_absent {
+            ADTS_HEADER_LEN
+        } else {
+            ADTS_HEADER_LEN + 2
+        };
+        if frame_len < header_len || data.len() < frame_len {
+            return None;
+        }
+        let config = Self {
+            object_type: (header[2] >> 6) + 1,
+            frequency_index: (header[2] >> 2) & 0x0F,
+            channel_config: ((header[2] & 0x01) << 2) | (header[3] >> 6),
+        };
+        Some((config, &data[header_len..frame_len], &data[frame_len..]))
+    }
+
+    /// AudioSpecificConfig に書き出す
+    pub fn audio_specific_config(self) -> [u8; 2] {
+        [
+            (self.object_type << 3) | (self.frequency_index >> 1),
+            ((self.frequency_index & 0x01) << 7) | (self.channel_config << 3),
+        ]
+    }
+
+    /// `mp4a.40.<AOT>` 形式のコーデック文字列
+    pub fn codec_string(&self) -> String {
+        format!("mp4a.40.{}", self.object_type)
+    }
+
+    pub fn sample_rate(&self) -> Option<u32> {
+        SAMPLE_RATES.get(self.frequency_index as usize).copied()
+    }
+
+    /// チャンネル数（channel_config = 7 は 7.1ch、0 は不明）
+    pub fn channels(&self) -> Option<u8> {
+        match self.channel_config {
+            0 => None,
+            7 => Some(8),
+            n => Some(n),
+        }
+    }
+
+    pub fn profile_name(&self) -> Option<&'static str> {
+        match self.object_type {
+            1 => Some("Main"),
+            2 => Some("LC"),
+            3 => Some("SSR"),
+            4 => Some("LTP"),
+            5 => Some("HE"),
+            29 => Some("HEv2"),
+            _ => None,
+        }
+    }
+
+    /// 生のAACフレームに付けるADTSヘッダ（CRCなし）
+    pub fn adts_header(&self, payload_len: usize) -> [u8; 7] {
+        // ADTSのprofileは Main/LC/SSR/LTP のみ表現できるので、SBR/PS付きはLCとして送る
+        let profile = match self.object_type {
+            1..=4 => self.object_type - 1,
+            _ => 1,
+        };
+        let frame_len = payload_len + ADTS_HEADER_LEN;
+        [
+            0xFF,
+            0xF1,
+            (profile << 6) | (self.frequency_index << 2) | ((self.channel_config >> 2) & 0x01),
+            ((self.channel_config & 0x03) << 6) | ((frame_len >> 11) & 0x03) as u8,
+            (frame_len >> 3) as u8,
+            ((frame_len & 0x07) << 5) as u8 | 0x1F,
+            0xFC,
+        ]
+    }
+}
@@ -0,0 +1,179 @@
+//! AV1 のデコーダ設定
+
+use bytes::Bytes;
+
+use super::bits::BitReader;
+
+/// OBUの種類
+mod obu_type {
+    pub const SEQUENCE_HEADER: u8 = 1;
+}
+
+/// AV1CodecConfigurationRecord (av1C)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Av1Config {
+    pub profile: u8,
+    /// seq_level_idx（0が2.0、以降0.1刻み×4で1つ上のメジャーレベル）
+    pub level: u8,
+    pub tier: bool,
+    pub bit_depth: u8,
+    /// configOBUs（通常はシーケンスヘッダOBU）
+    pub config_obus: Bytes,
+}
+
+impl Av1Config {
+    /// av1C を解析する（不正なら `None`）
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let (header, rest) = data.split_first_chunk::<4>()?;
+        // marker(1) = 1, version(7) = 1
+        if header[0] != 0x81 {
+            return None;
+        }
+        let profile = header[1] >> 5;
+        let level = header[1] & 0x1F;
+        let tier = header[2] & 0x80 != 0;
+        let high_bitdepth = header[2] & 0x40 != 0;
+        let twelve_bit = header[2] & 0x20 != 0;
+        let bit_depth = match (high_bitdepth, twelve_bit) {
+            (false, _) => 8,
+            (true, false) => 10,
+            (true, true) => 12,
+        };
+
+        Some(Self {
+            profile,
+            level,
+            tier,
+            bit_depth,
+            config_obus: Bytes::copy_from_slice(rest),
+        })
+    }
+
+    /// `av01.P.LLT.DD` 形式のコーデック文字列（AV1 Codec ISO Media File Format Binding）
+    pub fn codec_string(&self) -> String {
+        format!(
+            "av01.{}.{:02}{}.{:02}",
+            self.profile,
+            self.level,
+            if self.tier { 'H' } else { 'M' },
+            self.bit_depth
+        )
+    }
+
+    /// `"4.1"` 形式のレベル
+    pub fn level_name(&self) -> String {
+        format!("{}.{}", 2 + (self.level >> 2), self.level & 0x03)
+    }
+
+    /// configOBUs のシーケンスヘッダから最大フレームサイズ（幅, 高さ）を求める
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        let mut data = &self.config_obus[..];
+        while let Some((&header, rest)) = data.split_first() {
+            let kind = (header >> 3) & 0x0F;
+            let has_extension = header & 0x04 != 0;
+            let has_size = header & 0x02 != 0;
+
+            let rest = if has_extension { rest.get(1..)? } else { rest };
+            let (size, rest) = if has_size {
+                read_leb128(rest)?
+            } else {
+                (rest.len(), rest)
+            };
+            let payload = rest.get(..size)?;
+            if kind == obu_type::SEQUENCE_HEADER {
+                return parse_sequence_header_dimensions(payload);
+            }
+            data = &rest[size..];
+        }
+        None
+    }
+}
+
+/// プロファイル名（seq_profile）
+pub fn profile_name(profile: u8) -> String {
+    match profile {
+        0 => "Main",
+        1 => "High",
+        2 => "Professional",
+        other => return format!("Profile {}", other),
+    }
+    .to_string()
+}
+
+/// OBUのサイズフィールド
+fn read_leb128(data: &[u8]) -> Option<(usize, &[u8])> {
+    let mut value = 0usize;
+    for (i, &byte) in data.iter().enumerate().take(8) {
+        value |= ((byte & 0x7F) as usize) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, &data[i + 1..]));
+        }
+    }
+    None
+}
+
+/// シーケンスヘッダOBUの max_frame_width / max_frame_height を読む
+fn parse_sequence_header_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut r = BitReader::new(data);
+    // seq_profile, still_picture
+    r.skip_bits(4)?;
+    let reduced_still_picture_header = r.read_flag()?;
+    if reduced_still_picture_header {
+        // seq_level_idx[0]
+        r.skip_bits(5)?;
+    } else {
+        let mut decoder_model_info_present = false;
+        let mut buffer_delay_length = 0;
+        if r.read_flag()? {
+            // timing_info: num_units_in_display_tick, time_scale
+            r.skip_bits(64)?;
+            if r.read_flag()? {
+                // num_ticks_per_picture_minus_1
+                read_uvlc(&mut r)?;
+            }
+            decoder_model_info_present = r.read_flag()?;
+            if decoder_model_info_present {
+                buffer_delay_length = r.read_bits(5)? as usize + 1;
+                // num_units_in_decoding_tick, buffer_removal_time_length_minus_1,
+                // frame_presentation_time_length_minus_1
+                r.skip_bits(32 + 5 + 5)?;
+            }
+        }
+        let initial_display_delay_present = r.read_flag()?;
+        let operating_points = r.read_bits(5)? + 1;
+        for _ in 0..operating_points {
+            // operating_point_idc
+            r.skip_bits(12)?;
+            if r.read_bits(5)? > 7 {
+                // seq_tier
+                r.skip_bits(1)?;
+            }
+            if decoder_model_info_present && r.read_flag()? {
+                // decoder_buffer_delay, encoder_buffer_delay, low_delay_mode_flag
+                r.skip_bits(buffer_delay_length * 2 + 1)?;
+            }
+            if initial_display_delay_present && r.read_flag()? {
+                // initial_display_delay_minus_1
+                r.skip_bits(4)?;
+            }
+        }
+    }
+
+    let width_bits = r.read_bits(4)? + 1;
+    let height_bits = r.read_bits(4)? + 1;
+    let width = r.read_bits(width_bits)? + 1;
+    let height = r.read_bits(height_bits)? + 1;
+    Some((width, height))
+}
+
+/// uvlc()（AV1の可変長符号）
+fn read_uvlc(r: &mut BitReader) -> Option<u32> {
+    let mut leading_zeros = 0;
+    while !r.read_flag()? {
+        leading_zeros += 1;
+        if leading_zeros >= 32 {
+            return Some(u32::MAX);
+        }
+    }
+    Some((1u32 << leading_zeros) - 1 + r.read_bits(leading_zeros)?)
+}
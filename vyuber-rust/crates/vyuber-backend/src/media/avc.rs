@@ -0,0 +1,363 @@
+//! H.264 (AVC) のデコーダ設定とNALUの変換
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::bits::{rbsp, BitReader};
+use super::tag::{codec, MediaTag, TagKind};
+
+/// Annex-B のスタートコード
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// アクセスユニットデリミタ（primary_pic_type = 7: 任意のスライス）
+const AUD: [u8; 2] = [0x09, 0xF0];
+
+/// NALユニットタイプ
+pub mod nal_type {
+    pub const IDR: u8 = 5;
+    pub const SPS: u8 = 7;
+    pub const PPS: u8 = 8;
+    pub const AUD: u8 = 9;
+}
+
+/// AVCDecoderConfigurationRecord (avcC)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvcConfig {
+    pub profile: u8,
+    pub compatibility: u8,
+    pub level: u8,
+    /// NALU長フィールドのバイト数（1, 2, 4）
+    pub nalu_length_size: usize,
+    pub sps: Vec<Bytes>,
+    pub pps: Vec<Bytes>,
+}
+
+impl AvcConfig {
+    /// avcC を解析する（不正なら `None`）
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let (header, mut rest) = data.split_first_chunk::<5>()?;
+        let [_version, profile, compatibility, level, length_size] = *header;
+
+        let mut read_sets = |count_mask: u8| -> Option<Vec<Bytes>> {
+            let (&count, tail) = rest.split_first()?;
+            rest = tail;
+            let mut sets = Vec::new();
+            for _ in 0..(count & count_mask) {
+                let (len, tail) = rest.split_first_chunk::<2>()?;
+                let len = u16::from_be_bytes(*len) as usize;
+                if tail.len() < len {
+                    return None;
+                }
+                sets.push(Bytes::copy_from_slice(&tail[..len]));
+                rest = &tail[len..];
+            }
+            Some(sets)
+        };
+        let sps = read_sets(0x1F)?;
+        let pps = read_sets(0xFF)?;
+
+        Some(Self {
+            profile,
+            compatibility,
+            level,
+            nalu_length_size: (length_size & 0x03) as usize + 1,
+            sps,
+            pps,
+        })
+    }
+
+    /// Annex-B のSPS/PPSから作る（NALU長フィールドは4バイト）
+    pub fn from_parameter_sets(sps: Vec<Bytes>, pps: Vec<Bytes>) -> Option<Self> {
+        let &[_, profile, compatibility, level, ..] = &sps.first()?[..] else {
+            return None;
+        };
+        if pps.is_empty() {
+            return None;
+        }
+        Some(Self {
+            profile,
+            compatibility,
+            level,
+            nalu_length_size: 4,
+            sps,
+            pps,
+        })
+    }
+
+    /// avcC に書き出す
+    pub fn to_record(&self) -> Bytes {
+        let mut out = BytesMut::new();
+        out.put_slice(&[
+            1,
+            self.profile,
+            self.compatibility,
+            self.level,
+            0xFC | (self.nalu_length_size as u8 - 1),
+            0xE0 | self.sps.len() as u8,
+        ]);
+        for sps in &self.sps {
+            out.put_u16(sps.len() as u16);
+            out.put_slice(sps);
+        }
+        out.put_u8(self.pps.len() as u8);
+        for pps in &self.pps {
+            out.put_u16(pps.len() as u16);
+            out.put_slice(pps);
+        }
+        out.freeze()
+    }
+
+    /// `avc1.PPCCLL` 形式のコーデック文字列
+    pub fn codec_string(&self) -> String {
+        format!(
+            "avc1.{:02x}{:02x}{:02x}",
+            self.profile, self.compatibility, self.level
+        )
+    }
+
+    /// プロファイル名（profile_idc と constraint_set フラグから）
+    pub fn profile_name(&self) -> String {
+        match self.profile {
+            66 if self.compatibility & 0x40 != 0 => "Constrained Baseline",
+            66 => "Baseline",
+            77 => "Main",
+            88 => "Extended",
+            100 => "High",
+            110 => "High 10",
+            122 => "High 4:2:2",
+            244 => "High 4:4:4",
+            other => return format!("Profile {}", other),
+        }
+        .to_string()
+    }
+
+    /// 先頭のSPSから表示サイズ（クロップ後の幅, 高さ）を求める
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        parse_sps_dimensions(&rbsp(self.sps.first()?))
+    }
+
+    /// 長さプレフィックス形式のNALU列を分割する
+    pub fn nalus<'a>(&self, mut data: &'a [u8]) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let size = self.nalu_length_size;
+        std::iter::from_fn(move || {
+            if data.len() < size {
+                return None;
+            }
+            let len = data[..size]
+                .iter()
+                .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+            let nalu = data.get(size..size + len)?;
+            data = &data[size + len..];
+            Some(nalu)
+        })
+    }
+
+    /// 1フレーム分のNALU列をAnnex-B形式で書き出す
+    ///
+    /// 先頭にAUDを付け、キーフレームにSPS/PPSが含まれていなければ補う
+    pub fn write_annexb(&self, data: &[u8], keyframe: bool, out: &mut BytesMut) {
+        out.put_slice(&START_CODE);
+        out.put_slice(&AUD);
+
+        let has_parameter_sets = self
+            .nalus(data)
+            .any(|nalu| nalu.first().is_some_and(|&b| b & 0x1F == nal_type::SPS));
+        if keyframe && !has_parameter_sets {
+            for set in self.sps.iter().chain(&self.pps) {
+                out.put_slice(&START_CODE);
+                out.put_slice(set);
+            }
+        }
+
+        for nalu in self.nalus(data) {
+            if nalu.first().is_some_and(|&b| b & 0x1F == nal_type::AUD) {
+                continue;
+            }
+            out.put_slice(&START_CODE);
+            out.put_slice(nalu);
+        }
+    }
+}
+
+/// Annex-B 形式のアクセスユニットをFLVと同じ形式の映像タグにする
+///
+/// SPS/PPSが変わったらシーケンスヘッダのタグを先に出す。SPS/PPSを受け取るまでのフレームは捨てる
+#[derive(Default)]
+pub struct AvcTagBuilder {
+    config: Option<AvcConfig>,
+}
+
+impl AvcTagBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 1フレーム分のAnnex-Bデータを変換し、できあがったタグを `out` に追加する
+    pub fn push(
+        &mut self,
+        data: &[u8],
+        timestamp: u32,
+        composition_time: i32,
+        out: &mut Vec<MediaTag>,
+    ) {
+        let mut sps = Vec::new();
+        let mut pps = Vec::new();
+        let mut nalus = Vec::new();
+        let mut keyframe = false;
+        for nalu in split_annexb(data) {
+            match nalu[0] & 0x1F {
+                nal_type::SPS => sps.push(Bytes::copy_from_slice(nalu)),
+                nal_type::PPS => pps.push(Bytes::copy_from_slice(nalu)),
+                nal_type::AUD => {}
+                kind => {
+                    keyframe |= kind == nal_type::IDR;
+                    nalus.push(nalu);
+                }
+            }
+        }
+
+        if let Some(config) = AvcConfig::from_parameter_sets(sps, pps) {
+            if self.config.as_ref() != Some(&config) {
+                let mut tag = BytesMut::new();
+                tag.put_slice(&[(1 << 4) | codec::VIDEO_AVC, 0, 0, 0, 0]);
+                tag.put_slice(&config.to_record());
+                out.push(MediaTag::new(TagKind::Video, timestamp, tag.freeze()));
+                self.config = Some(config);
+            }
+        }
+        if self.config.is_none() || nalus.is_empty() {
+            return;
+        }
+
+        let mut tag = BytesMut::new();
+        tag.put_u8(if keyframe { 1 << 4 } else { 2 << 4 } | codec::VIDEO_AVC);
+        tag.put_u8(1);
+        tag.put_slice(&composition_time.to_be_bytes()[1..]);
+        for nalu in nalus {
+            tag.put_u32(nalu.len() as u32);
+            tag.put_slice(nalu);
+        }
+        out.push(MediaTag::new(TagKind::Video, timestamp, tag.freeze()));
+    }
+}
+
+/// Annex-B 形式（スタートコード区切り）のNALU列を分割する
+pub fn split_annexb(data: &[u8]) -> impl Iterator<Item = &[u8]> + '_ {
+    let mut rest = data;
+    std::iter::from_fn(move || loop {
+        // 次のスタートコード（00 00 01）の直後まで読み飛ばす
+        let start = rest.windows(3).position(|w| w == [0, 0, 1])? + 3;
+        rest = &rest[start..];
+        let end = rest
+            .windows(3)
+            .position(|w| w == [0, 0, 1] || w == [0, 0, 0])
+            .unwrap_or(rest.len());
+        let nalu = &rest[..end];
+        rest = &rest[end..];
+        if !nalu.is_empty() {
+            return Some(nalu);
+        }
+    })
+}
+
+/// SPS（RBSP）の幅と高さを読む
+fn parse_sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
+    let mut r = BitReader::new(sps);
+    // NALヘッダ
+    r.skip_bits(8)?;
+    let profile_idc = r.read_bits(8)?;
+    // constraint_set_flags + level_idc
+    r.skip_bits(16)?;
+    // seq_parameter_set_id
+    r.read_ue()?;
+
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = false;
+    if matches!(
+        profile_idc,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format_idc = r.read_ue()?;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = r.read_flag()?;
+        }
+        // bit_depth_luma_minus8, bit_depth_chroma_minus8
+        r.read_ue()?;
+        r.read_ue()?;
+        // qpprime_y_zero_transform_bypass_flag
+        r.skip_bits(1)?;
+        if r.read_flag()? {
+            let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..lists {
+                if r.read_flag()? {
+                    skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    // log2_max_frame_num_minus4
+    r.read_ue()?;
+    match r.read_ue()? {
+        0 => {
+            // log2_max_pic_order_cnt_lsb_minus4
+            r.read_ue()?;
+        }
+        1 => {
+            // delta_pic_order_always_zero_flag, offset_for_non_ref_pic, offset_for_top_to_bottom_field
+            r.skip_bits(1)?;
+            r.read_se()?;
+            r.read_se()?;
+            for _ in 0..r.read_ue()? {
+                r.read_se()?;
+            }
+        }
+        _ => {}
+    }
+    // max_num_ref_frames, gaps_in_frame_num_value_allowed_flag
+    r.read_ue()?;
+    r.skip_bits(1)?;
+
+    let width_in_mbs = r.read_ue()? + 1;
+    let height_in_map_units = r.read_ue()? + 1;
+    let frame_mbs_only = r.read_flag()?;
+    if !frame_mbs_only {
+        // mb_adaptive_frame_field_flag
+        r.skip_bits(1)?;
+    }
+    // direct_8x8_inference_flag
+    r.skip_bits(1)?;
+
+    let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+    if r.read_flag()? {
+        crop_left = r.read_ue()?;
+        crop_right = r.read_ue()?;
+        crop_top = r.read_ue()?;
+        crop_bottom = r.read_ue()?;
+    }
+
+    let field_factor = if frame_mbs_only { 1 } else { 2 };
+    let (crop_unit_x, crop_unit_y) = match (chroma_format_idc, separate_colour_plane) {
+        (0, _) | (3, true) => (1, field_factor),
+        (1, _) => (2, 2 * field_factor),
+        (2, _) => (2, field_factor),
+        _ => (1, field_factor),
+    };
+    let width = (width_in_mbs * 16).checked_sub((crop_left + crop_right) * crop_unit_x)?;
+    let height = (field_factor * height_in_map_units * 16)
+        .checked_sub((crop_top + crop_bottom) * crop_unit_y)?;
+    Some((width, height))
+}
+
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> Option<()> {
+    let mut last = 8i32;
+    let mut next = 8i32;
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + r.read_se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
@@ -0,0 +1,75 @@
+//! SPSなどのビット単位で符号化された構造の読み取り
+
+/// エミュレーション防止バイト（00 00 03 の 03）を取り除いたRBSPを返す
+pub fn rbsp(nalu: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nalu.len());
+    let mut zeros = 0;
+    for &byte in nalu {
+        if zeros >= 2 && byte == 0x03 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+/// MSBから順に読むビットリーダ
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    /// 読み取り位置（ビット）
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn read_bit(&mut self) -> Option<u32> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    pub fn read_flag(&mut self) -> Option<bool> {
+        self.read_bit().map(|bit| bit == 1)
+    }
+
+    /// `n` ビット（32以下）を読む
+    pub fn read_bits(&mut self, n: u32) -> Option<u32> {
+        (0..n).try_fold(0u32, |acc, _| Some((acc << 1) | self.read_bit()?))
+    }
+
+    pub fn skip_bits(&mut self, n: usize) -> Option<()> {
+        if self.pos + n > self.data.len() * 8 {
+            return None;
+        }
+        self.pos += n;
+        Some(())
+    }
+
+    /// 符号なし指数ゴロム符号 ue(v)
+    pub fn read_ue(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while self.read_bit()? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << leading_zeros) - 1 + self.read_bits(leading_zeros)?)
+    }
+
+    /// 符号付き指数ゴロム符号 se(v)
+    pub fn read_se(&mut self) -> Option<i32> {
+        let value = self.read_ue()? as i64;
+        Some(if value % 2 == 1 {
+            ((value + 1) / 2) as i32
+        } else {
+            -(value / 2) as i32
+        })
+    }
+}
@@ -0,0 +1,191 @@
+use vyuber_shared::stream::StreamInfo;
+
+use super::info;
+use super::tag::{MediaTag, TagKind};
+use crate::config::GopCacheConfig;
+
+/// 途中から購読した視聴者に最初に送るヘッダ類と直近GOPのキャッシュ
+#[derive(Default)]
+pub struct StreamCache {
+    metadata: Option<MediaTag>,
+    video_header: Option<MediaTag>,
+    audio_header: Option<MediaTag>,
+    gop: GopCache,
+}
+
+impl StreamCache {
+    pub fn new(config: GopCacheConfig) -> Self {
+        Self {
+            gop: GopCache::new(config),
+            ..Self::default()
+        }
+    }
+
+    /// 配信者から届いたタグでキャッシュを更新する
+    pub fn update(&mut self, tag: &MediaTag) {
+        if tag.is_metadata() {
+            self.metadata = Some(tag.clone());
+        } else if tag.is_sequence_header() {
+            match tag.kind {
+                TagKind::Video => {
+                    // デコーダ設定が変わると以前のフレームは復号できない
+                    self.gop.clear();
+                    self.video_header = Some(tag.clone());
+                }
+                TagKind::Audio => self.audio_header = Some(tag.clone()),
+                TagKind::Script => {}
+            }
+        } else {
+            self.gop.push(tag);
+        }
+    }
+
+    /// 新しい購読者に送るタグ列（メタデータ → 映像/音声シーケンスヘッダ → 直近のGOP）
+    ///
+    /// ヘッダは配信途中で届いたものでもタイムスタンプ0として送る
+    pub fn snapshot(&self) -> Vec<MediaTag> {
+        [&self.metadata, &self.video_header, &self.audio_header]
+            .into_iter()
+            .flatten()
+            .map(|tag| MediaTag::new(tag.kind, 0, tag.data.clone()))
+            .chain(self.gop.tags.iter().cloned())
+            .collect()
+    }
+
+    /// メタデータとシーケンスヘッダから読み取った配信情報
+    pub fn info(&self) -> StreamInfo {
+        info::stream_info(
+            self.metadata.as_ref(),
+            self.video_header.as_ref(),
+            self.audio_header.as_ref(),
+        )
+    }
+
+    /// 映像/音声トラックの有無（ヘッダ未着なら両方ありとみなす）
+    pub fn tracks(&self) -> (bool, bool) {
+        match (&self.audio_header, &self.video_header) {
+            (None, None) => (true, true),
+            (audio, video) => (audio.is_some(), video.is_some()),
+        }
+    }
+}
+
+/// 最新のキーフレームから始まるタグ列（GOP）
+///
+/// 上限を超えたら破棄し、次のキーフレームまで溜めない
+#[derive(Default)]
+struct GopCache {
+    config: Option<GopCacheConfig>,
+    tags: Vec<MediaTag>,
+    bytes: usize,
+}
+
+impl GopCache {
+    fn new(config: GopCacheConfig) -> Self {
+        Self {
+            config: config.is_enabled().then_some(config),
+            ..Self::default()
+        }
+    }
+
+    fn push(&mut self, tag: &MediaTag) {
+        let Some(config) = self.config else {
+            return;
+        };
+
+        if tag.is_keyframe() {
+            self.clear();
+        } else if self.tags.is_empty() {
+            // キーフレーム待ち
+            return;
+        }
+
+        let start = self.tags.first().map_or(tag.timestamp, |first| first.timestamp);
+        // キーフレームより少し前のタイムスタンプの音声は負の経過時間として範囲内に扱う
+        let duration = tag.timestamp.wrapping_sub(start) as i32;
+        if self.bytes + tag.data.len() > config.max_bytes
+            || duration > 0 && duration as u32 > config.max_duration_ms
+        {
+            tracing::debug!(
+                "[Hub] GOP exceeds cache limit ({} bytes, {} ms), dropping",
+                self.bytes + tag.data.len(),
+                duration
+            );
+            self.clear();
+            return;
+        }
+
+        self.bytes += tag.data.len();
+        self.tags.push(tag.clone());
+    }
+
+    fn clear(&mut self) {
+        self.tags.clear();
+        self.bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn video(timestamp: u32, keyframe: bool) -> MediaTag {
+        let frame_type = if keyframe { 0x17 } else { 0x27 };
+        MediaTag::new(
+            TagKind::Video,
+            timestamp,
+            Bytes::from(vec![frame_type, 1, 0, 0, 0, 0xaa]),
+        )
+    }
+
+    fn audio(timestamp: u32) -> MediaTag {
+        MediaTag::new(TagKind::Audio, timestamp, Bytes::from_static(&[0xaf, 1, 0xbb]))
+    }
+
+    fn cache(max_duration_ms: u32) -> GopCache {
+        GopCache::new(GopCacheConfig {
+            max_bytes: 1024,
+            max_duration_ms,
+        })
+    }
+
+    fn timestamps(cache: &GopCache) -> Vec<u32> {
+        cache.tags.iter().map(|tag| tag.timestamp).collect()
+    }
+
+    #[test]
+    fn keeps_audio_stamped_before_keyframe() {
+        let mut gop = cache(10_000);
+        gop.push(&video(1000, true));
+        gop.push(&audio(990));
+        gop.push(&video(1033, false));
+        assert_eq!(timestamps(&gop), vec![1000, 990, 1033]);
+
+        // タイムスタンプの折り返し直後でも同じ
+        let mut gop = cache(10_000);
+        gop.push(&video(5, true));
+        gop.push(&audio(u32::MAX - 10));
+        assert_eq!(timestamps(&gop), vec![5, u32::MAX - 10]);
+    }
+
+    #[test]
+    fn drops_gop_over_limits() {
+        let mut gop = cache(1000);
+        gop.push(&video(0, true));
+        gop.push(&video(1000, false));
+        gop.push(&video(1001, false));
+        assert!(gop.tags.is_empty());
+
+        // 次のキーフレームまでは溜めない
+        gop.push(&audio(1010));
+        assert!(gop.tags.is_empty());
+        gop.push(&video(2000, true));
+        assert_eq!(timestamps(&gop), vec![2000]);
+
+        let mut gop = cache(10_000);
+        gop.push(&video(0, true));
+        gop.push(&MediaTag::new(TagKind::Video, 33, Bytes::from(vec![0x27; 1024])));
+        assert!(gop.tags.is_empty());
+    }
+}
@@ -0,0 +1,170 @@
+use chrono::{SecondsFormat, Utc};
+use std::fmt::Write;
+
+use super::{CmafStream, Track, TrackFormat, TrackType};
+
+/// HLSのマスタープレイリスト（まだ何も書き出していなければ `None`）
+///
+/// 映像があれば映像のメディアプレイリストを主にし、音声は別レンディションとして参照する
+pub fn master_playlist(stream: &CmafStream) -> Option<String> {
+    let tracks: Vec<_> = stream.ready_tracks().collect();
+    let (main, main_track) = *tracks.first()?;
+
+    let codecs: Vec<_> = tracks.iter().map(|(_, track)| track.codec.as_str()).collect();
+    let bandwidth: u64 = tracks.iter().map(|(_, track)| track.bandwidth()).sum();
+
+    let mut m3u8 = String::new();
+    writeln!(m3u8, "#EXTM3U").unwrap();
+    writeln!(m3u8, "#EXT-X-VERSION:7").unwrap();
+    writeln!(m3u8, "#EXT-X-INDEPENDENT-SEGMENTS").unwrap();
+
+    let audio_group = main == TrackType::Video && tracks.len() > 1;
+    if audio_group {
+        writeln!(
+            m3u8,
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"audio\",DEFAULT=YES,AUTOSELECT=YES,URI=\"audio.m3u8\""
+        )
+        .unwrap();
+    }
+
+    write!(
+        m3u8,
+        "#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"{}\"",
+        bandwidth.max(1),
+        codecs.join(",")
+    )
+    .unwrap();
+    if let TrackFormat::Video { width, height } = main_track.format {
+        if width > 0 && height > 0 {
+            write!(m3u8, ",RESOLUTION={}x{}", width, height).unwrap();
+        }
+    }
+    if audio_group {
+        write!(m3u8, ",AUDIO=\"audio\"").unwrap();
+    }
+    writeln!(m3u8).unwrap();
+    writeln!(m3u8, "{}.m3u8", main.name()).unwrap();
+    Some(m3u8)
+}
+
+/// トラックごとのHLSメディアプレイリスト（まだ何も書き出していなければ `None`）
+pub fn media_playlist(stream: &CmafStream, kind: TrackType) -> Option<String> {
+    let track = stream.track(kind)?;
+    let segments: Vec<_> = track.window(stream.window).collect();
+    let first = segments.first()?;
+
+    // EXT-X-TARGETDURATION はどのセグメント長以上でなければならない
+    let target_duration = segments
+        .iter()
+        .map(|segment| track.duration_secs(segment))
+        .fold(stream.target_duration, f64::max)
+        .ceil() as u64;
+
+    let mut m3u8 = String::new();
+    writeln!(m3u8, "#EXTM3U").unwrap();
+    writeln!(m3u8, "#EXT-X-VERSION:7").unwrap();
+    writeln!(m3u8, "#EXT-X-TARGETDURATION:{}", target_duration).unwrap();
+    writeln!(m3u8, "#EXT-X-MEDIA-SEQUENCE:{}", first.number).unwrap();
+    writeln!(m3u8, "#EXT-X-MAP:URI=\"{}_init.mp4\"", kind.name()).unwrap();
+    for segment in segments {
+        writeln!(m3u8, "#EXTINF:{:.3},", track.duration_secs(segment)).unwrap();
+        writeln!(m3u8, "{}_{}.m4s", kind.name(), segment.number).unwrap();
+    }
+    Some(m3u8)
+}
+
+/// MPEG-DASHのMPD（まだ何も書き出していなければ `None`）
+///
+/// セグメントは `/cmaf/:stream_key/` 以下のものをそのまま参照する
+pub fn mpd(stream_key: &str, stream: &CmafStream) -> Option<String> {
+    let tracks: Vec<_> = stream.ready_tracks().collect();
+    if tracks.is_empty() {
+        return None;
+    }
+
+    let target = stream.target_duration;
+    let mut mpd = String::new();
+    writeln!(mpd, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>").unwrap();
+    writeln!(
+        mpd,
+        "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" profiles=\"urn:mpeg:dash:profile:isoff-live:2011\" type=\"dynamic\" \
+         availabilityStartTime=\"{}\" publishTime=\"{}\" minimumUpdatePeriod=\"{}\" minBufferTime=\"{}\" \
+         timeShiftBufferDepth=\"{}\" suggestedPresentationDelay=\"{}\">",
+        stream.started_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        duration(target),
+        duration(target),
+        duration(target * stream.window as f64),
+        duration(target * 3.0),
+    )
+    .unwrap();
+    writeln!(mpd, "  <BaseURL>/cmaf/{}/</BaseURL>", stream_key).unwrap();
+    writeln!(mpd, "  <Period id=\"0\" start=\"PT0S\">").unwrap();
+    for (id, (kind, track)) in tracks.into_iter().enumerate() {
+        write_adaptation_set(&mut mpd, id, kind, track, stream.window);
+    }
+    writeln!(mpd, "  </Period>").unwrap();
+    writeln!(mpd, "</MPD>").unwrap();
+    Some(mpd)
+}
+
+fn write_adaptation_set(mpd: &mut String, id: usize, kind: TrackType, track: &Track, window: usize) {
+    let segments: Vec<_> = track.window(window).collect();
+    let name = kind.name();
+
+    writeln!(
+        mpd,
+        "    <AdaptationSet id=\"{}\" contentType=\"{}\" mimeType=\"{}/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\">",
+        id, name, name
+    )
+    .unwrap();
+    write!(
+        mpd,
+        "      <Representation id=\"{}\" codecs=\"{}\" bandwidth=\"{}\"",
+        name,
+        track.codec,
+        track.bandwidth().max(1)
+    )
+    .unwrap();
+    match track.format {
+        TrackFormat::Video { width, height } => {
+            writeln!(mpd, " width=\"{}\" height=\"{}\">", width, height).unwrap();
+        }
+        TrackFormat::Audio { sample_rate, channels } => {
+            writeln!(mpd, " audioSamplingRate=\"{}\">", sample_rate).unwrap();
+            writeln!(
+                mpd,
+                "        <AudioChannelConfiguration schemeIdUri=\"urn:mpeg:dash:23003:3:audio_channel_configuration:2011\" value=\"{}\"/>",
+                channels
+            )
+            .unwrap();
+        }
+    }
+    writeln!(
+        mpd,
+        "        <SegmentTemplate timescale=\"{}\" initialization=\"{}_init.mp4\" media=\"{}_$Number$.m4s\" startNumber=\"{}\">",
+        track.timescale,
+        name,
+        name,
+        segments.first().map_or(0, |segment| segment.number)
+    )
+    .unwrap();
+    writeln!(mpd, "          <SegmentTimeline>").unwrap();
+    for segment in segments {
+        writeln!(
+            mpd,
+            "            <S t=\"{}\" d=\"{}\"/>",
+            segment.start, segment.duration
+        )
+        .unwrap();
+    }
+    writeln!(mpd, "          </SegmentTimeline>").unwrap();
+    writeln!(mpd, "        </SegmentTemplate>").unwrap();
+    writeln!(mpd, "      </Representation>").unwrap();
+    writeln!(mpd, "    </AdaptationSet>").unwrap();
+}
+
+/// xs:duration（秒）
+fn duration(secs: f64) -> String {
+    format!("PT{:.3}S", secs)
+}
@@ -0,0 +1,232 @@
+//! CMAF（fMP4）配信
+//!
+//! 配信が始まるとハブを購読して映像/音声トラックごとのfMP4セグメントを切り出し、
+//! HLS（`EXT-X-MAP`）とMPEG-DASH（SegmentTimeline）の両方から参照できるようメモリ上に保持する
+
+mod manifest;
+mod packager;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+
+use self::packager::Packager;
+use super::hub::HUB;
+use crate::config::HlsConfig;
+
+/// プレイリストから外れた後も取得できるように残しておくセグメント数
+const RETAINED_SEGMENTS: usize = 2;
+
+/// ストリームごとのCMAFセグメント
+pub static CMAF: Lazy<CmafStore> = Lazy::new(CmafStore::default);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackType {
+    Video,
+    Audio,
+}
+
+impl TrackType {
+    /// ファイル名に使う名前
+    pub fn name(self) -> &'static str {
+        match self {
+            TrackType::Video => "video",
+            TrackType::Audio => "audio",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "video" => Some(TrackType::Video),
+            "audio" => Some(TrackType::Audio),
+            _ => None,
+        }
+    }
+}
+
+/// トラック固有の情報
+pub enum TrackFormat {
+    Video { width: u32, height: u32 },
+    Audio { sample_rate: u32, channels: u8 },
+}
+
+/// 切り出し済みのfMP4セグメント（moof + mdat）
+pub struct Segment {
+    /// トラックごとの連番（HLSのメディアシーケンス番号とDASHの `$Number$`）
+    pub number: u64,
+    /// 先頭サンプルのデコード時刻（トラックのタイムスケール単位）
+    pub start: u64,
+    /// トラックのタイムスケール単位
+    pub duration: u64,
+    pub data: Bytes,
+}
+
+/// 1トラック分の初期化セグメントとメディアセグメント
+pub struct Track {
+    pub format: TrackFormat,
+    /// RFC 6381 のコーデック文字列
+    pub codec: String,
+    pub timescale: u32,
+    pub init: Bytes,
+    pub segments: VecDeque<Segment>,
+}
+
+impl Track {
+    /// プレイリストに載せるセグメント
+    fn window(&self, window: usize) -> impl Iterator<Item = &Segment> {
+        self.segments
+            .iter()
+            .skip(self.segments.len().saturating_sub(window))
+    }
+
+    fn push_segment(&mut self, segment: Segment, window: usize) {
+        self.segments.push_back(segment);
+        while self.segments.len() > window + RETAINED_SEGMENTS {
+            self.segments.pop_front();
+        }
+    }
+
+    fn duration_secs(&self, segment: &Segment) -> f64 {
+        segment.duration as f64 / self.timescale as f64
+    }
+
+    /// 直近のセグメントの最大ビットレート（bps）
+    fn bandwidth(&self) -> u64 {
+        self.segments
+            .iter()
+            .filter(|segment| segment.duration > 0)
+            .map(|segment| {
+                segment.data.len() as u64 * 8 * self.timescale as u64 / segment.duration
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// 1ストリーム分のトラック
+pub struct CmafStream {
+    /// DASHの availabilityStartTime（タイムスタンプ0の時刻）
+    pub started_at: DateTime<Utc>,
+    /// 目標セグメント長（秒）
+    pub target_duration: f64,
+    /// プレイリストに載せるセグメント数
+    pub window: usize,
+    pub video: Option<Track>,
+    pub audio: Option<Track>,
+}
+
+impl CmafStream {
+    fn new(config: &HlsConfig) -> Self {
+        Self {
+            started_at: Utc::now(),
+            target_duration: config.segment_duration_ms as f64 / 1000.0,
+            window: config.playlist_length.max(1),
+            video: None,
+            audio: None,
+        }
+    }
+
+    pub fn track(&self, track: TrackType) -> Option<&Track> {
+        match track {
+            TrackType::Video => self.video.as_ref(),
+            TrackType::Audio => self.audio.as_ref(),
+        }
+    }
+
+    /// セグメントを書き出したトラック（まだ何もなければ空）
+    fn ready_tracks(&self) -> impl Iterator<Item = (TrackType, &Track)> {
+        [TrackType::Video, TrackType::Audio]
+            .into_iter()
+            .filter_map(|kind| self.track(kind).map(|track| (kind, track)))
+            .filter(|(_, track)| !track.segments.is_empty())
+    }
+}
+
+#[derive(Default)]
+pub struct CmafStore {
+    streams: RwLock<HashMap<String, Arc<RwLock<CmafStream>>>>,
+}
+
+impl CmafStore {
+    /// HLSのマスタープレイリスト
+    pub fn master_playlist(&self, stream: &str) -> Option<String> {
+        let cmaf = self.get(stream)?;
+        let cmaf = cmaf.read().unwrap();
+        manifest::master_playlist(&cmaf)
+    }
+
+    /// トラックごとのHLSメディアプレイリスト
+    pub fn media_playlist(&self, stream: &str, track: TrackType) -> Option<String> {
+        let cmaf = self.get(stream)?;
+        let cmaf = cmaf.read().unwrap();
+        manifest::media_playlist(&cmaf, track)
+    }
+
+    /// MPEG-DASHのMPD
+    pub fn mpd(&self, stream: &str) -> Option<String> {
+        let cmaf = self.get(stream)?;
+        let cmaf = cmaf.read().unwrap();
+        manifest::mpd(stream, &cmaf)
+    }
+
+    /// 初期化セグメント（ftyp + moov）
+    pub fn init(&self, stream: &str, track: TrackType) -> Option<Bytes> {
+        let cmaf = self.get(stream)?;
+        let cmaf = cmaf.read().unwrap();
+        cmaf.track(track).map(|track| track.init.clone())
+    }
+
+    /// 番号のメディアセグメント
+    pub fn segment(&self, stream: &str, track: TrackType, number: u64) -> Option<Bytes> {
+        let cmaf = self.get(stream)?;
+        let cmaf = cmaf.read().unwrap();
+        cmaf.track(track)?
+            .segments
+            .iter()
+            .find(|segment| segment.number == number)
+            .map(|segment| segment.data.clone())
+    }
+
+    fn get(&self, stream: &str) -> Option<Arc<RwLock<CmafStream>>> {
+        self.streams.read().unwrap().get(stream).cloned()
+    }
+
+    fn remove(&self, stream: &str, cmaf: &Arc<RwLock<CmafStream>>) {
+        let mut streams = self.streams.write().unwrap();
+        if streams
+            .get(stream)
+            .is_some_and(|current| Arc::ptr_eq(current, cmaf))
+        {
+            streams.remove(stream);
+        }
+    }
+}
+
+/// 配信開始イベントを監視し、ストリームごとにパッケージャを起動する
+pub fn start_cmaf_packager(config: HlsConfig) {
+    HUB.spawn_on_publish("CMAF", move |stream| package_stream(stream, config));
+}
+
+/// 1ストリーム分のセグメントを配信終了まで作り続ける
+async fn package_stream(stream: String, config: HlsConfig) {
+    let Some(mut subscriber) = HUB.subscribe(&stream) else {
+        return;
+    };
+
+    let cmaf = Arc::new(RwLock::new(CmafStream::new(&config)));
+    CMAF.streams
+        .write()
+        .unwrap()
+        .insert(stream.clone(), cmaf.clone());
+    tracing::info!("[CMAF] Packaging stream: {}", stream);
+
+    let mut packager = Packager::new(&config);
+    while let Some(tag) = subscriber.recv().await {
+        packager.push(&tag, &mut cmaf.write().unwrap());
+    }
+
+    CMAF.remove(&stream, &cmaf);
+    tracing::info!("[CMAF] Stopped packaging stream: {}", stream);
+}
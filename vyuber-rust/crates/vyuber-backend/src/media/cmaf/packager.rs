@@ -0,0 +1,362 @@
+use bytes::Bytes;
+use std::collections::VecDeque;
+
+use super::{CmafStream, Segment, Track, TrackFormat};
+use crate::config::HlsConfig;
+use crate::media::aac::AacConfig;
+use crate::media::fmp4::{
+    self, Sample, AAC_FRAME_SAMPLES, AUDIO_TRACK_ID, VIDEO_TIMESCALE, VIDEO_TRACK_ID,
+};
+use crate::media::tag::{codec, MediaTag, TagKind, VideoPacketType};
+use crate::media::video::VideoConfig;
+
+/// 次のセグメントに入れる映像フレーム
+struct VideoFrame {
+    /// ミリ秒
+    dts: u32,
+    /// ミリ秒（PTS - DTS）
+    cts: i32,
+    keyframe: bool,
+    /// 長さプレフィックス形式のNALU列（AV1はOBU列）
+    data: Bytes,
+}
+
+/// 次のセグメントに入れる音声フレーム
+struct AudioFrame {
+    /// デコード時刻（サンプリング周波数単位）
+    time: u64,
+    data: Bytes,
+}
+
+/// ハブのタグ列をキーフレーム単位のfMP4セグメントに切り分ける
+///
+/// 映像と音声は同じタイミングで区切る。セグメント番号はトラックごとの連番で、
+/// 音声のない区間などフレームのないトラックには番号を使わない
+pub struct Packager {
+    target_duration_ms: u32,
+    video_config: Option<VideoConfig>,
+    aac: Option<AacConfig>,
+    /// 書き込み中のセグメントの開始時刻（最初のキーフレームまでは `None`）
+    segment_start: Option<u32>,
+    /// トラックごとの次のセグメント番号
+    next_video_number: u64,
+    next_audio_number: u64,
+    /// moof の通し番号
+    fragment_sequence: u32,
+    video: Vec<VideoFrame>,
+    /// 直前に区切ったときのフレーム間隔（ミリ秒）
+    frame_interval: u32,
+    audio: Vec<AudioFrame>,
+    /// 次の音声フレームのデコード時刻（サンプリング周波数単位）
+    audio_time: Option<u64>,
+}
+
+impl Packager {
+    pub fn new(config: &HlsConfig) -> Self {
+        Self {
+            target_duration_ms: config.segment_duration_ms,
+            video_config: None,
+            aac: None,
+            segment_start: None,
+            next_video_number: 0,
+            next_audio_number: 0,
+            fragment_sequence: 0,
+            video: Vec::new(),
+            frame_interval: 0,
+            audio: Vec::new(),
+            audio_time: None,
+        }
+    }
+
+    pub fn push(&mut self, tag: &MediaTag, stream: &mut CmafStream) {
+        match tag.kind {
+            TagKind::Video => self.push_video(tag, stream),
+            TagKind::Audio => self.push_audio(tag, stream),
+            TagKind::Script => {}
+        }
+    }
+
+    fn push_video(&mut self, tag: &MediaTag, stream: &mut CmafStream) {
+        let Some(packet) = tag.video_packet() else {
+            return;
+        };
+        match packet.packet_type {
+            VideoPacketType::SequenceStart => {
+                let Some(config) = VideoConfig::parse(packet.codec, &packet.data) else {
+                    return;
+                };
+                let (width, height) = config.dimensions().unwrap_or((0, 0));
+                set_track(
+                    &mut stream.video,
+                    Track {
+                        format: TrackFormat::Video { width, height },
+                        codec: config.codec_string(),
+                        timescale: VIDEO_TIMESCALE,
+                        init: fmp4::video_init_segment(&packet.data, &config),
+                        segments: VecDeque::new(),
+                    },
+                );
+                self.video_config = Some(config);
+                return;
+            }
+            VideoPacketType::CodedFrames if self.video_config.is_some() => {}
+            _ => return,
+        }
+
+        if packet.keyframe && self.segment_due(tag.timestamp) {
+            self.cut(tag.timestamp, stream);
+        }
+        if self.segment_start.is_none() {
+            // 最初のキーフレームまでは捨てる
+            return;
+        }
+
+        self.video.push(VideoFrame {
+            dts: tag.timestamp,
+            cts: packet.composition_time,
+            keyframe: packet.keyframe,
+            data: packet.data,
+        });
+    }
+
+    fn push_audio(&mut self, tag: &MediaTag, stream: &mut CmafStream) {
+        let data = &tag.data;
+        if data.len() < 2 || data[0] >> 4 != codec::AUDIO_AAC {
+            return;
+        }
+        if tag.is_sequence_header() {
+            let Some(aac) = AacConfig::parse(&data[2..]) else {
+                return;
+            };
+            let sample_rate = aac.sample_rate().unwrap_or(44_100);
+            set_track(
+                &mut stream.audio,
+                Track {
+                    format: TrackFormat::Audio {
+                        sample_rate,
+                        channels: aac.channels().unwrap_or(2),
+                    },
+                    codec: aac.codec_string(),
+                    timescale: sample_rate,
+                    init: fmp4::audio_init_segment(&data[2..], &aac),
+                    segments: VecDeque::new(),
+                },
+            );
+            self.aac = Some(aac);
+            return;
+        }
+        let Some(aac) = self.aac else {
+            return;
+        };
+
+        // 映像がなければ音声フレームで区切る
+        if self.video_config.is_none() && self.segment_due(tag.timestamp) {
+            self.cut(tag.timestamp, stream);
+        }
+        if self.segment_start.is_none() {
+            return;
+        }
+
+        // 1フレーム1024サンプルずつ進め、タイムスタンプと1フレーム以上ずれたら合わせ直す
+        // （音声の欠落で先に進んだときなど。前のフレームより前には戻さない）
+        let sample_rate = aac.sample_rate().unwrap_or(44_100) as u64;
+        let expected = tag.timestamp as u64 * sample_rate / 1000;
+        let time = match self.audio_time {
+            None => expected,
+            Some(next) if expected.abs_diff(next) <= AAC_FRAME_SAMPLES as u64 => next,
+            Some(next) => expected.max(self.audio.last().map_or(next, |frame| frame.time + 1)),
+        };
+        self.audio_time = Some(time + AAC_FRAME_SAMPLES as u64);
+        self.audio.push(AudioFrame {
+            time,
+            data: data.slice(2..),
+        });
+    }
+
+    fn segment_due(&self, timestamp: u32) -> bool {
+        match self.segment_start {
+            None => true,
+            Some(start) => timestamp.saturating_sub(start) >= self.target_duration_ms,
+        }
+    }
+
+    /// 溜めているフレームを `timestamp` までのセグメントとして書き出す
+    fn cut(&mut self, timestamp: u32, stream: &mut CmafStream) {
+        if self.segment_start.replace(timestamp).is_none() {
+            return;
+        }
+
+        let window = stream.window;
+        let video = self.video_segment(timestamp);
+        if let (Some(segment), Some(track)) = (video, &mut stream.video) {
+            track.push_segment(segment, window);
+        }
+        let audio = self.audio_segment();
+        if let (Some(segment), Some(track)) = (audio, &mut stream.audio) {
+            track.push_segment(segment, window);
+        }
+    }
+
+    fn video_segment(&mut self, end: u32) -> Option<Segment> {
+        let frames = std::mem::take(&mut self.video);
+        let first = frames.first()?;
+        let start = first.dts as u64 * 90;
+
+        let mut samples = Vec::with_capacity(frames.len());
+        for (index, frame) in frames.iter().enumerate() {
+            let next = frames.get(index + 1).map_or(end, |next| next.dts);
+            let interval = next.saturating_sub(frame.dts);
+            // 最後のフレームは区切りの時刻から長さを求め、分からなければ直前の間隔を使う
+            let interval = if interval == 0 { self.frame_interval } else { interval };
+            self.frame_interval = interval;
+            samples.push(Sample {
+                duration: interval * 90,
+                composition_offset: frame.cts * 90,
+                keyframe: frame.keyframe,
+                data: frame.data.clone(),
+            });
+        }
+
+        let duration = samples.iter().map(|sample| sample.duration as u64).sum();
+        self.fragment_sequence += 1;
+        self.next_video_number += 1;
+        Some(Segment {
+            number: self.next_video_number - 1,
+            start,
+            duration,
+            data: fmp4::media_segment(self.fragment_sequence, VIDEO_TRACK_ID, start, &samples),
+        })
+    }
+
+    fn audio_segment(&mut self) -> Option<Segment> {
+        let frames = std::mem::take(&mut self.audio);
+        let start = frames.first()?.time;
+        let end = self.audio_time?;
+
+        let samples: Vec<_> = frames
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| {
+                let next = frames.get(index + 1).map_or(end, |next| next.time);
+                Sample {
+                    duration: (next - frame.time) as u32,
+                    composition_offset: 0,
+                    keyframe: true,
+                    data: frame.data.clone(),
+                }
+            })
+            .collect();
+
+        self.fragment_sequence += 1;
+        self.next_audio_number += 1;
+        Some(Segment {
+            number: self.next_audio_number - 1,
+            start,
+            duration: end - start,
+            data: fmp4::media_segment(self.fragment_sequence, AUDIO_TRACK_ID, start, &samples),
+        })
+    }
+}
+
+/// シーケンスヘッダからトラックを作る（配信途中で変わった場合は初期化セグメントだけ差し替える）
+fn set_track(slot: &mut Option<Track>, track: Track) {
+    match slot {
+        Some(current) => {
+            current.format = track.format;
+            current.codec = track.codec;
+            current.init = track.init;
+        }
+        None => *slot = Some(track),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::cmaf::TrackType;
+    use crate::media::testing::{aac_frame, aac_sequence_header, avc_frame, avc_sequence_header};
+
+    fn numbers(stream: &CmafStream, track: TrackType) -> Vec<u64> {
+        stream
+            .track(track)
+            .map(|track| track.segments.iter().map(|s| s.number).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn numbers_audio_segments_without_gaps() {
+        let config = HlsConfig {
+            segment_duration_ms: 1000,
+            ..HlsConfig::default()
+        };
+        let mut stream = CmafStream::new(&config);
+        let mut packager = Packager::new(&config);
+
+        packager.push(&avc_sequence_header(0), &mut stream);
+        packager.push(&aac_sequence_header(0), &mut stream);
+        for i in 0..150u32 {
+            let timestamp = i * 40;
+            packager.push(&avc_frame(timestamp, i % 25 == 0, 0, &[0; 8]), &mut stream);
+            // 最初の2秒は音声がない（ミュート）
+            if timestamp >= 2000 {
+                packager.push(&aac_frame(timestamp, &[0; 8]), &mut stream);
+            }
+        }
+
+        assert_eq!(numbers(&stream, TrackType::Video), vec![0, 1, 2, 3, 4]);
+        assert_eq!(numbers(&stream, TrackType::Audio), vec![0, 1, 2]);
+
+        // メディアシーケンスはセグメントの番号から始まり、プレイリストに抜けがない
+        let playlist = stream.track(TrackType::Audio).unwrap();
+        let first = playlist.segments.front().unwrap();
+        assert_eq!(first.start, 2000 * 48);
+        let m3u8 = super::super::manifest::media_playlist(&stream, TrackType::Audio).unwrap();
+        assert!(m3u8.contains("#EXT-X-MEDIA-SEQUENCE:0"));
+        assert!(m3u8.contains("audio_0.m4s\n#EXTINF"));
+        assert!(m3u8.ends_with("audio_2.m4s\n"));
+    }
+
+    #[test]
+    fn reanchors_audio_to_timestamps_after_a_gap() {
+        let config = HlsConfig {
+            segment_duration_ms: 1000,
+            ..HlsConfig::default()
+        };
+        let mut stream = CmafStream::new(&config);
+        let mut packager = Packager::new(&config);
+
+        packager.push(&avc_sequence_header(0), &mut stream);
+        packager.push(&aac_sequence_header(0), &mut stream);
+        // 48kHzのAAC（ミリ秒に切り捨てたタイムスタンプ）で、1200〜1700msの音声が欠けている
+        let mut audio = (0..)
+            .map(|n: u64| (n * 1024, (n * 1024 / 48) as u32))
+            .filter(|&(_, ts)| !(1200..1700).contains(&ts))
+            .peekable();
+        for i in 0..100u32 {
+            let timestamp = i * 40;
+            packager.push(&avc_frame(timestamp, i % 25 == 0, 0, &[0; 8]), &mut stream);
+            while let Some((_, ts)) = audio.next_if(|&(_, ts)| ts < timestamp + 40) {
+                packager.push(&aac_frame(ts, &[0; 8]), &mut stream);
+            }
+        }
+
+        let segments: Vec<_> = stream
+            .track(TrackType::Audio)
+            .unwrap()
+            .segments
+            .iter()
+            .map(|segment| (segment.start, segment.duration))
+            .collect();
+        // 欠けた後の最初のフレーム（1706ms）で合わせ直し、セグメントは途切れずに続く
+        // （ミリ秒の丸めによるずれでは合わせ直さない）
+        let resumed = 1706 * 48;
+        assert_eq!(
+            segments,
+            vec![
+                (0, 47 * 1024),
+                (47 * 1024, resumed + 14 * 1024 - 47 * 1024),
+                (resumed + 14 * 1024, 47 * 1024),
+            ]
+        );
+    }
+}
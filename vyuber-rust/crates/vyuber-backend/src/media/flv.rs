@@ -0,0 +1,123 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::tag::{MediaTag, TagKind};
+
+/// FLVタグヘッダ長
+const TAG_HEADER_LEN: usize = 11;
+
+/// FLVファイルヘッダ + PreviousTagSize0
+pub fn header(has_audio: bool, has_video: bool) -> Bytes {
+    let mut buf = BytesMut::with_capacity(13);
+    buf.put_slice(b"FLV");
+    buf.put_u8(1);
+    buf.put_u8(((has_audio as u8) << 2) | has_video as u8);
+    buf.put_u32(9);
+    buf.put_u32(0);
+    buf.freeze()
+}
+
+/// 1タグ分（タグヘッダ + ボディ + PreviousTagSize）を書き出す
+pub fn write_tag(tag: &MediaTag, dst: &mut BytesMut) {
+    let tag_type = match tag.kind {
+        TagKind::Audio => 8,
+        TagKind::Video => 9,
+        TagKind::Script => 18,
+    };
+    let data_len = tag.data.len() as u32;
+
+    dst.reserve(TAG_HEADER_LEN + tag.data.len() + 4);
+    dst.put_u8(tag_type);
+    put_u24(dst, data_len);
+    // 下位24bit + 上位8bit (TimestampExtended)
+    put_u24(dst, tag.timestamp & 0x00FF_FFFF);
+    dst.put_u8((tag.timestamp >> 24) as u8);
+    // StreamID は常に0
+    put_u24(dst, 0);
+    dst.put_slice(&tag.data);
+    dst.put_u32(TAG_HEADER_LEN as u32 + data_len);
+}
+
+/// 1タグ分を `Bytes` として返す
+pub fn encode_tag(tag: &MediaTag) -> Bytes {
+    let mut buf = BytesMut::new();
+    write_tag(tag, &mut buf);
+    buf.freeze()
+}
+
+/// FLVファイルを先頭から1タグずつ読む
+pub struct FlvReader<R> {
+    reader: R,
+    /// 次に読むタグのファイル先頭からの位置
+    position: u64,
+}
+
+impl<R: AsyncRead + Unpin> FlvReader<R> {
+    /// ファイルヘッダを読み飛ばす（FLVでなければ `InvalidData`）
+    pub async fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; 9];
+        reader.read_exact(&mut header).await?;
+        if &header[..3] != b"FLV" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an FLV file",
+            ));
+        }
+        // DataOffset + PreviousTagSize0
+        let data_offset = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as u64;
+        let skip = data_offset.saturating_sub(9) + 4;
+        tokio::io::copy(&mut (&mut reader).take(skip), &mut tokio::io::sink()).await?;
+        Ok(Self {
+            reader,
+            position: data_offset.max(9) + 4,
+        })
+    }
+
+    /// ファイルの途中から読む（`reader` はファイル先頭から `position` のタグの先頭を指していること）
+    pub fn at(reader: R, position: u64) -> Self {
+        Self { reader, position }
+    }
+
+    /// 次のタグを読む（ファイルの終わりや、書き込み途中で切れたタグなら `None`）
+    pub async fn next_tag(&mut self) -> io::Result<Option<MediaTag>> {
+        loop {
+            let mut header = [0u8; TAG_HEADER_LEN];
+            match self.reader.read_exact(&mut header).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            let data_len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+            let timestamp = u32::from_be_bytes([header[7], header[4], header[5], header[6]]);
+            // ボディ + PreviousTagSize
+            let mut body = vec![0u8; data_len + 4];
+            match self.reader.read_exact(&mut body).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            self.position += (TAG_HEADER_LEN + data_len + 4) as u64;
+            body.truncate(data_len);
+
+            let kind = match header[0] & 0x1F {
+                8 => TagKind::Audio,
+                9 => TagKind::Video,
+                18 => TagKind::Script,
+                _ => continue,
+            };
+            return Ok(Some(MediaTag::new(kind, timestamp, Bytes::from(body))));
+        }
+    }
+
+    /// 次に読むタグのファイル先頭からの位置
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+fn put_u24(dst: &mut BytesMut, v: u32) {
+    dst.put_u8((v >> 16) as u8);
+    dst.put_u8((v >> 8) as u8);
+    dst.put_u8(v as u8);
+}
@@ -0,0 +1,461 @@
+//! フラグメント化MP4（CMAF）マルチプレクサ
+//!
+//! トラックごとに初期化セグメント（ftyp + moov）と
+//! メディアセグメント（moof + mdat）を組み立てる
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::aac::AacConfig;
+use super::opus;
+use super::video::VideoConfig;
+
+pub const VIDEO_TRACK_ID: u32 = 1;
+pub const AUDIO_TRACK_ID: u32 = 2;
+
+/// 映像トラックのタイムスケール（90kHz）
+pub const VIDEO_TIMESCALE: u32 = 90_000;
+
+/// AACの1フレームあたりのサンプル数
+pub const AAC_FRAME_SAMPLES: u32 = 1024;
+
+/// mvhd / tkhd のタイムスケール（ミリ秒）
+pub(super) const MOVIE_TIMESCALE: u32 = 1000;
+
+/// 単位行列（tkhd/mvhd）
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// moofに載せる1サンプル
+pub struct Sample {
+    /// トラックのタイムスケール単位
+    pub duration: u32,
+    /// PTS - DTS（タイムスケール単位）
+    pub composition_offset: i32,
+    pub keyframe: bool,
+    /// 映像は長さプレフィックス形式のNALU列（AV1はOBU列）、音声は生のAACフレーム
+    pub data: Bytes,
+}
+
+/// 映像トラック（H.264 / HEVC / AV1）の初期化セグメント
+///
+/// `record` はシーケンスヘッダのデコーダ設定レコード（avcC / hvcC / av1C）そのもの
+pub fn video_init_segment(record: &[u8], config: &VideoConfig) -> Bytes {
+    let track = TrackHeader::video(VIDEO_TRACK_ID, config, 0);
+    init_segment(track, |out| write_video_sample_entry(out, record, config))
+}
+
+/// AAC トラックの初期化セグメント
+///
+/// `asc` はシーケンスヘッダのAudioSpecificConfigそのもの
+pub fn audio_init_segment(asc: &[u8], config: &AacConfig) -> Bytes {
+    let track = TrackHeader::audio(AUDIO_TRACK_ID, config, 0);
+    init_segment(track, |out| write_aac_sample_entry(out, asc, config))
+}
+
+/// メディアセグメント（moof + mdat）
+///
+/// `base_decode_time` は先頭サンプルのDTS（タイムスケール単位）
+pub fn media_segment(sequence: u32, track_id: u32, base_decode_time: u64, samples: &[Sample]) -> Bytes {
+    let mut out = BytesMut::new();
+    let mut data_offset_pos = 0;
+    write_box(&mut out, b"moof", |out| {
+        write_full_box(out, b"mfhd", 0, 0, |out| out.put_u32(sequence));
+        write_box(out, b"traf", |out| {
+            // default-base-is-moof
+            write_full_box(out, b"tfhd", 0, 0x02_0000, |out| out.put_u32(track_id));
+            write_full_box(out, b"tfdt", 1, 0, |out| out.put_u64(base_decode_time));
+            // data-offset, sample-duration, sample-size, sample-flags, sample-composition-time-offset
+            write_full_box(out, b"trun", 1, 0x00_0F01, |out| {
+                out.put_u32(samples.len() as u32);
+                data_offset_pos = out.len();
+                out.put_i32(0);
+                for sample in samples {
+                    out.put_u32(sample.duration);
+                    out.put_u32(sample.data.len() as u32);
+                    out.put_u32(if sample.keyframe {
+                        // sample_depends_on = 2（他に依存しない）
+                        0x0200_0000
+                    } else {
+                        // sample_depends_on = 1, sample_is_non_sync_sample = 1
+                        0x0101_0000
+                    });
+                    out.put_i32(sample.composition_offset);
+                }
+            });
+        });
+    });
+
+    // mdatの中身はmoofの先頭からの相対位置
+    let data_offset = (out.len() + 8) as i32;
+    out[data_offset_pos..data_offset_pos + 4].copy_from_slice(&data_offset.to_be_bytes());
+
+    write_box(&mut out, b"mdat", |out| {
+        for sample in samples {
+            out.put_slice(&sample.data);
+        }
+    });
+    out.freeze()
+}
+
+pub(super) enum Handler {
+    Video { width: u32, height: u32 },
+    Audio,
+}
+
+/// tkhd / mdhd / hdlr に書くトラックの情報
+pub(super) struct TrackHeader {
+    pub id: u32,
+    pub timescale: u32,
+    pub handler: Handler,
+    /// トラックの長さ（タイムスケール単位、フラグメント化MP4では0）
+    pub duration: u64,
+    /// 再生開始までの空き（ミリ秒、0でなければ edts に空のエディットを書く）
+    pub delay: u64,
+}
+
+impl TrackHeader {
+    /// 映像トラック（タイムスケールは `VIDEO_TIMESCALE`）
+    pub fn video(id: u32, config: &VideoConfig, duration: u64) -> Self {
+        let (width, height) = config.dimensions().unwrap_or((0, 0));
+        Self {
+            id,
+            timescale: VIDEO_TIMESCALE,
+            handler: Handler::Video { width, height },
+            duration,
+            delay: 0,
+        }
+    }
+
+    /// AACトラック（タイムスケールはサンプルレート）
+    pub fn audio(id: u32, config: &AacConfig, duration: u64) -> Self {
+        Self {
+            id,
+            timescale: aac_sample_rate(config),
+            handler: Handler::Audio,
+            duration,
+            delay: 0,
+        }
+    }
+
+    /// Opusトラック（タイムスケールは48kHz）
+    pub fn opus(id: u32, duration: u64) -> Self {
+        Self {
+            id,
+            timescale: opus::SAMPLE_RATE,
+            handler: Handler::Audio,
+            duration,
+            delay: 0,
+        }
+    }
+
+    /// mvhd / tkhd に書く長さ（ミリ秒、先頭の空きを含む）
+    pub fn movie_duration(&self) -> u64 {
+        self.delay + self.media_duration()
+    }
+
+    /// サンプルの長さの合計（ミリ秒）
+    fn media_duration(&self) -> u64 {
+        self.duration * MOVIE_TIMESCALE as u64 / self.timescale as u64
+    }
+}
+
+/// ftyp + moov（トラック1本）
+fn init_segment(track: TrackHeader, sample_entry: impl FnOnce(&mut BytesMut)) -> Bytes {
+    let mut out = BytesMut::new();
+    write_box(&mut out, b"ftyp", |out| {
+        out.put_slice(b"iso6");
+        out.put_u32(0);
+        for brand in [b"iso6", b"cmfc", b"mp41", b"dash"] {
+            out.put_slice(brand);
+        }
+    });
+    let track_id = track.id;
+    write_box(&mut out, b"moov", |out| {
+        // フラグメント化しているので長さは0
+        write_mvhd(out, 0, track_id + 1);
+        write_trak(out, &track, sample_entry, |out| {
+            write_full_box(out, b"stts", 0, 0, |out| out.put_u32(0));
+            write_full_box(out, b"stsc", 0, 0, |out| out.put_u32(0));
+            write_full_box(out, b"stsz", 0, 0, |out| {
+                out.put_u32(0);
+                out.put_u32(0);
+            });
+            write_full_box(out, b"stco", 0, 0, |out| out.put_u32(0));
+        });
+        write_box(out, b"mvex", |out| write_trex(out, track_id));
+    });
+    out.freeze()
+}
+
+/// edts ボックス（先頭に `delay` の空のエディット、続けてメディア全体）
+fn write_edts(out: &mut BytesMut, track: &TrackHeader) {
+    let version = time_version(track.movie_duration());
+    write_box(out, b"edts", |out| {
+        write_full_box(out, b"elst", version, 0, |out| {
+            out.put_u32(2);
+            // segment_duration, media_time（-1 は空のエディット）, media_rate 1.0
+            put_time(out, version, track.delay);
+            put_time(out, version, u64::MAX);
+            out.put_u32(0x0001_0000);
+            put_time(out, version, track.media_duration());
+            put_time(out, version, 0);
+            out.put_u32(0x0001_0000);
+        });
+    });
+}
+
+/// mvhd ボックス（`duration` はミリ秒）
+pub(super) fn write_mvhd(out: &mut BytesMut, duration: u64, next_track_id: u32) {
+    let version = time_version(duration);
+    write_full_box(out, b"mvhd", version, 0, |out| {
+        // creation_time, modification_time
+        put_time(out, version, 0);
+        put_time(out, version, 0);
+        out.put_u32(MOVIE_TIMESCALE);
+        put_time(out, version, duration);
+        // rate 1.0, volume 1.0
+        out.put_u32(0x0001_0000);
+        out.put_u16(0x0100);
+        out.put_slice(&[0; 10]);
+        MATRIX.iter().for_each(|&v| out.put_u32(v));
+        out.put_slice(&[0; 24]);
+        out.put_u32(next_track_id);
+    });
+}
+
+/// trak ボックス（`sample_entry` で stsd のエントリを、`sample_tables` で stsd 以降の stbl の中身を書く）
+pub(super) fn write_trak(
+    out: &mut BytesMut,
+    track: &TrackHeader,
+    sample_entry: impl FnOnce(&mut BytesMut),
+    sample_tables: impl FnOnce(&mut BytesMut),
+) {
+    let handler = &track.handler;
+    write_box(out, b"trak", |out| {
+        // track_enabled | track_in_movie
+        let version = time_version(track.movie_duration());
+        write_full_box(out, b"tkhd", version, 0x03, |out| {
+            // creation_time, modification_time
+            put_time(out, version, 0);
+            put_time(out, version, 0);
+            out.put_u32(track.id);
+            out.put_u32(0);
+            put_time(out, version, track.movie_duration());
+            out.put_slice(&[0; 8]);
+            // layer, alternate_group
+            out.put_u32(0);
+            out.put_u16(if matches!(handler, Handler::Audio) { 0x0100 } else { 0 });
+            out.put_u16(0);
+            MATRIX.iter().for_each(|&v| out.put_u32(v));
+            let (width, height) = match *handler {
+                Handler::Video { width, height } => (width, height),
+                Handler::Audio => (0, 0),
+            };
+            out.put_u32(width << 16);
+            out.put_u32(height << 16);
+        });
+        if track.delay > 0 {
+            write_edts(out, track);
+        }
+        write_box(out, b"mdia", |out| {
+            let version = time_version(track.duration);
+            write_full_box(out, b"mdhd", version, 0, |out| {
+                put_time(out, version, 0);
+                put_time(out, version, 0);
+                out.put_u32(track.timescale);
+                put_time(out, version, track.duration);
+                // language = "und"
+                out.put_u16(0x55C4);
+                out.put_u16(0);
+            });
+            write_full_box(out, b"hdlr", 0, 0, |out| {
+                out.put_u32(0);
+                let (handler_type, name): (&[u8; 4], &[u8]) = match handler {
+                    Handler::Video { .. } => (b"vide", b"VideoHandler\0"),
+                    Handler::Audio => (b"soun", b"SoundHandler\0"),
+                };
+                out.put_slice(handler_type);
+                out.put_slice(&[0; 12]);
+                out.put_slice(name);
+            });
+            write_box(out, b"minf", |out| {
+                match handler {
+                    Handler::Video { .. } => write_full_box(out, b"vmhd", 0, 1, |out| {
+                        out.put_slice(&[0; 8]);
+                    }),
+                    Handler::Audio => write_full_box(out, b"smhd", 0, 0, |out| {
+                        out.put_u32(0);
+                    }),
+                }
+                write_box(out, b"dinf", |out| {
+                    write_full_box(out, b"dref", 0, 0, |out| {
+                        out.put_u32(1);
+                        // self-contained
+                        write_full_box(out, b"url ", 0, 1, |_| {});
+                    });
+                });
+                write_box(out, b"stbl", |out| {
+                    write_full_box(out, b"stsd", 0, 0, |out| {
+                        out.put_u32(1);
+                        sample_entry(out);
+                    });
+                    sample_tables(out);
+                });
+            });
+        });
+    });
+}
+
+fn write_trex(out: &mut BytesMut, track_id: u32) {
+    write_full_box(out, b"trex", 0, 0, |out| {
+        out.put_u32(track_id);
+        // default_sample_description_index
+        out.put_u32(1);
+        out.put_u32(0);
+        out.put_u32(0);
+        out.put_u32(0);
+    });
+}
+
+/// 映像のサンプルエントリ（avc1 / hvc1 / av01）
+pub(super) fn write_video_sample_entry(out: &mut BytesMut, record: &[u8], config: &VideoConfig) {
+    let (width, height) = config.dimensions().unwrap_or((0, 0));
+    let (entry_type, config_type) = config.sample_entry();
+    write_box(out, entry_type, |out| {
+        write_visual_sample_entry(out, width, height);
+        write_box(out, config_type, |out| out.put_slice(record));
+    });
+}
+
+/// AACのサンプルエントリ（mp4a）
+pub(super) fn write_aac_sample_entry(out: &mut BytesMut, asc: &[u8], config: &AacConfig) {
+    let channels = config.channels().unwrap_or(2);
+    write_box(out, b"mp4a", |out| {
+        write_audio_sample_entry(out, channels, aac_sample_rate(config));
+        write_esds(out, asc);
+    });
+}
+
+/// Opusのサンプルエントリ（Opus + dOps、`head` はOpusHeadそのもの）
+pub(super) fn write_opus_sample_entry(out: &mut BytesMut, head: &[u8]) {
+    let channels = opus::channels(head).unwrap_or(2);
+    write_box(out, b"Opus", |out| {
+        write_audio_sample_entry(out, channels, opus::SAMPLE_RATE);
+        // OpusHead（リトルエンディアン）をビッグエンディアンに並べ替える
+        write_box(out, b"dOps", |out| {
+            out.put_u8(0);
+            out.put_u8(channels);
+            out.put_u16(u16::from_le_bytes([head[10], head[11]]));
+            out.put_u32(u32::from_le_bytes([head[12], head[13], head[14], head[15]]));
+            out.put_i16(i16::from_le_bytes([head[16], head[17]]));
+            // ChannelMappingFamily（0以外ならマッピングテーブルが続く）
+            out.put_slice(&head[18..]);
+        });
+    });
+}
+
+/// AACのサンプルレート（読めなければ44.1kHz）
+pub(super) fn aac_sample_rate(config: &AacConfig) -> u32 {
+    config.sample_rate().unwrap_or(44_100)
+}
+
+fn write_visual_sample_entry(out: &mut BytesMut, width: u32, height: u32) {
+    out.put_slice(&[0; 6]);
+    // data_reference_index
+    out.put_u16(1);
+    out.put_slice(&[0; 16]);
+    out.put_u16(width as u16);
+    out.put_u16(height as u16);
+    // 72dpi
+    out.put_u32(0x0048_0000);
+    out.put_u32(0x0048_0000);
+    out.put_u32(0);
+    // frame_count
+    out.put_u16(1);
+    // compressorname
+    out.put_slice(&[0; 32]);
+    // depth
+    out.put_u16(0x0018);
+    out.put_i16(-1);
+}
+
+fn write_audio_sample_entry(out: &mut BytesMut, channels: u8, sample_rate: u32) {
+    out.put_slice(&[0; 6]);
+    // data_reference_index
+    out.put_u16(1);
+    out.put_slice(&[0; 8]);
+    out.put_u16(channels as u16);
+    // samplesize
+    out.put_u16(16);
+    out.put_u32(0);
+    // 16.16固定小数点（65535Hzを超える場合は表現できない）
+    out.put_u32(sample_rate.min(0xFFFF) << 16);
+}
+
+/// MPEG-4 ES記述子（esds）
+fn write_esds(out: &mut BytesMut, asc: &[u8]) {
+    write_full_box(out, b"esds", 0, 0, |out| {
+        let decoder_specific_len = asc.len();
+        let decoder_config_len = 13 + 2 + decoder_specific_len;
+        let es_len = 3 + 2 + decoder_config_len + 3;
+
+        // ES_Descriptor
+        out.put_u8(0x03);
+        out.put_u8(es_len as u8);
+        // ES_ID, flags
+        out.put_u16(0);
+        out.put_u8(0);
+        // DecoderConfigDescriptor
+        out.put_u8(0x04);
+        out.put_u8(decoder_config_len as u8);
+        // objectTypeIndication = MPEG-4 Audio, streamType = AudioStream
+        out.put_u8(0x40);
+        out.put_u8(0x15);
+        // bufferSizeDB, maxBitrate, avgBitrate
+        out.put_slice(&[0; 3]);
+        out.put_u32(0);
+        out.put_u32(0);
+        // DecoderSpecificInfo
+        out.put_u8(0x05);
+        out.put_u8(decoder_specific_len as u8);
+        out.put_slice(asc);
+        // SLConfigDescriptor
+        out.put_u8(0x06);
+        out.put_u8(0x01);
+        out.put_u8(0x02);
+    });
+}
+
+/// 時刻/長さが32ビットに収まらなければバージョン1のボックスにする
+fn time_version(duration: u64) -> u8 {
+    u8::from(duration > u32::MAX as u64)
+}
+
+fn put_time(out: &mut BytesMut, version: u8, value: u64) {
+    if version == 1 {
+        out.put_u64(value);
+    } else {
+        out.put_u32(value as u32);
+    }
+}
+
+pub(super) fn write_box(out: &mut BytesMut, box_type: &[u8; 4], f: impl FnOnce(&mut BytesMut)) {
+    let start = out.len();
+    out.put_u32(0);
+    out.put_slice(box_type);
+    f(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+pub(super) fn write_full_box(
+    out: &mut BytesMut,
+    box_type: &[u8; 4],
+    version: u8,
+    flags: u32,
+    f: impl FnOnce(&mut BytesMut),
+) {
+    write_box(out, box_type, |out| {
+        out.put_u32(((version as u32) << 24) | (flags & 0x00FF_FFFF));
+        f(out);
+    });
+}
@@ -0,0 +1,173 @@
+//! H.265 (HEVC) のデコーダ設定
+
+use bytes::Bytes;
+
+use super::bits::{rbsp, BitReader};
+
+/// NALユニットタイプ
+mod nal_type {
+    pub const SPS: u8 = 33;
+}
+
+/// HEVCDecoderConfigurationRecord (hvcC)
+#[derive(Debug, Clone, PartialEq)]
+pub struct HevcConfig {
+    pub profile_space: u8,
+    pub tier: bool,
+    pub profile: u8,
+    pub compatibility: u32,
+    /// general_constraint_indicator_flags（48ビット）
+    pub constraints: [u8; 6],
+    /// level_idc（レベルの30倍）
+    pub level: u8,
+    /// NALU長フィールドのバイト数（1, 2, 4）
+    pub nalu_length_size: usize,
+    pub sps: Vec<Bytes>,
+}
+
+impl HevcConfig {
+    /// hvcC を解析する（不正なら `None`）
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let (header, mut rest) = data.split_first_chunk::<23>()?;
+        let profile_space = header[1] >> 6;
+        let tier = header[1] & 0x20 != 0;
+        let profile = header[1] & 0x1F;
+        let compatibility = u32::from_be_bytes([header[2], header[3], header[4], header[5]]);
+        let constraints = [header[6], header[7], header[8], header[9], header[10], header[11]];
+        let level = header[12];
+        let nalu_length_size = (header[21] & 0x03) as usize + 1;
+
+        // NALユニットの配列（VPS/SPS/PPS/SEI）からSPSだけ取り出す
+        let mut sps = Vec::new();
+        for _ in 0..header[22] {
+            let (array, tail) = rest.split_first_chunk::<3>()?;
+            rest = tail;
+            let nal_unit_type = array[0] & 0x3F;
+            for _ in 0..u16::from_be_bytes([array[1], array[2]]) {
+                let (len, tail) = rest.split_first_chunk::<2>()?;
+                let len = u16::from_be_bytes(*len) as usize;
+                if tail.len() < len {
+                    return None;
+                }
+                if nal_unit_type == nal_type::SPS {
+                    sps.push(Bytes::copy_from_slice(&tail[..len]));
+                }
+                rest = &tail[len..];
+            }
+        }
+
+        Some(Self {
+            profile_space,
+            tier,
+            profile,
+            compatibility,
+            constraints,
+            level,
+            nalu_length_size,
+            sps,
+        })
+    }
+
+    /// `hvc1.P.C.TL.CC` 形式のコーデック文字列（ISO/IEC 14496-15 Annex E）
+    pub fn codec_string(&self) -> String {
+        let space = match self.profile_space {
+            1 => "A",
+            2 => "B",
+            3 => "C",
+            _ => "",
+        };
+        let mut codec = format!(
+            "hvc1.{}{}.{:X}.{}{}",
+            space,
+            self.profile,
+            // 互換フラグはビット順を逆にして16進で書く
+            self.compatibility.reverse_bits(),
+            if self.tier { 'H' } else { 'L' },
+            self.level
+        );
+        // 末尾の0のバイトは省略する
+        let len = self
+            .constraints
+            .iter()
+            .rposition(|&b| b != 0)
+            .map_or(0, |i| i + 1);
+        for byte in &self.constraints[..len] {
+            codec.push_str(&format!(".{:X}", byte));
+        }
+        codec
+    }
+
+    /// 先頭のSPSから表示サイズ（クロップ後の幅, 高さ）を求める
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        parse_sps_dimensions(&rbsp(self.sps.first()?))
+    }
+}
+
+/// プロファイル名（general_profile_idc）
+pub fn profile_name(profile: u8) -> String {
+    match profile {
+        1 => "Main",
+        2 => "Main 10",
+        3 => "Main Still Picture",
+        4 => "Range Extensions",
+        other => return format!("Profile {}", other),
+    }
+    .to_string()
+}
+
+/// SPS（RBSP）の幅と高さを読む
+fn parse_sps_dimensions(sps: &[u8]) -> Option<(u32, u32)> {
+    let mut r = BitReader::new(sps);
+    // NALヘッダ（2バイト）
+    r.skip_bits(16)?;
+    // sps_video_parameter_set_id
+    r.skip_bits(4)?;
+    let max_sub_layers_minus1 = r.read_bits(3)? as usize;
+    // sps_temporal_id_nesting_flag
+    r.skip_bits(1)?;
+
+    // profile_tier_level: general_profile_space 〜 general_level_idc
+    r.skip_bits(96)?;
+    let mut sub_layers = Vec::with_capacity(max_sub_layers_minus1);
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layers.push((r.read_flag()?, r.read_flag()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        r.skip_bits(2 * (8 - max_sub_layers_minus1))?;
+    }
+    for (profile_present, level_present) in sub_layers {
+        if profile_present {
+            r.skip_bits(88)?;
+        }
+        if level_present {
+            r.skip_bits(8)?;
+        }
+    }
+
+    // sps_seq_parameter_set_id
+    r.read_ue()?;
+    let chroma_format_idc = r.read_ue()?;
+    let mut separate_colour_plane = false;
+    if chroma_format_idc == 3 {
+        separate_colour_plane = r.read_flag()?;
+    }
+    let width = r.read_ue()?;
+    let height = r.read_ue()?;
+
+    let (mut crop_left, mut crop_right, mut crop_top, mut crop_bottom) = (0, 0, 0, 0);
+    if r.read_flag()? {
+        crop_left = r.read_ue()?;
+        crop_right = r.read_ue()?;
+        crop_top = r.read_ue()?;
+        crop_bottom = r.read_ue()?;
+    }
+
+    let (sub_width, sub_height) = match (chroma_format_idc, separate_colour_plane) {
+        (1, _) => (2, 2),
+        (2, _) => (2, 1),
+        _ => (1, 1),
+    };
+    let width = width.checked_sub((crop_left + crop_right) * sub_width)?;
+    let height = height.checked_sub((crop_top + crop_bottom) * sub_height)?;
+    Some((width, height))
+}
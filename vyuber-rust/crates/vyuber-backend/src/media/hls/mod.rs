@@ -0,0 +1,353 @@
+//! HLS配信
+//!
+//! 配信が始まるとハブを購読してMPEG-TSセグメントを切り出し、
+//! スライディングウィンドウのプレイリストとしてメモリ上に保持する。
+//! LL-HLSモードではパーシャルセグメントとブロッキングリロードに対応する
+
+mod playlist;
+mod segmenter;
+
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+use self::playlist::Playlist;
+use self::segmenter::Segmenter;
+use super::hub::HUB;
+use super::tag::{MediaTag, TagKind, VideoCodec};
+use crate::config::HlsConfig;
+
+/// ストリームごとのHLSプレイリスト
+pub static HLS: Lazy<HlsStore> = Lazy::new(HlsStore::default);
+
+#[derive(Debug, Error)]
+pub enum HlsError {
+    #[error("stream not found or offline")]
+    NotFound,
+
+    #[error("requested media sequence is too far ahead")]
+    TooFarAhead,
+
+    #[error("timed out waiting for the playlist to update")]
+    Timeout,
+}
+
+/// 1ストリーム分のプレイリストと更新通知
+struct HlsStream {
+    playlist: RwLock<Playlist>,
+    /// プレイリストが更新されるたびに進むバージョン
+    updates: watch::Sender<u64>,
+    /// ブロッキングリクエストの待ち時間の上限
+    block_timeout: Duration,
+}
+
+impl HlsStream {
+    fn update(&self, f: impl FnOnce(&mut Playlist) -> bool) {
+        let updated = f(&mut self.playlist.write().unwrap());
+        if updated {
+            self.updates.send_modify(|version| *version += 1);
+        }
+    }
+
+    /// `f` が値を返すまでプレイリストの更新を待つ
+    async fn wait_for<T>(
+        &self,
+        mut f: impl FnMut(&Playlist) -> Option<Result<T, HlsError>>,
+    ) -> Result<T, HlsError> {
+        let mut updates = self.updates.subscribe();
+        let deadline = Instant::now() + self.block_timeout;
+        loop {
+            let ready = {
+                let playlist = self.playlist.read().unwrap();
+                if playlist.is_ended() {
+                    Some(f(&playlist).unwrap_or(Err(HlsError::NotFound)))
+                } else {
+                    f(&playlist)
+                }
+            };
+            if let Some(result) = ready {
+                return result;
+            }
+            match tokio::time::timeout_at(deadline, updates.changed()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(_)) => return Err(HlsError::NotFound),
+                Err(_) => return Err(HlsError::Timeout),
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HlsStore {
+    streams: RwLock<HashMap<String, Arc<HlsStream>>>,
+}
+
+impl HlsStore {
+    /// メディアプレイリスト
+    ///
+    /// `block` に (`_HLS_msn`, `_HLS_part`) を渡すと、そのセグメント/パートが載るまで待つ
+    pub async fn playlist(
+        &self,
+        stream: &str,
+        block: Option<(u64, Option<usize>)>,
+    ) -> Result<String, HlsError> {
+        let hls = self.get(stream)?;
+        hls.wait_for(|playlist| match block {
+            Some((sequence, _)) if sequence > playlist.next_sequence() + 2 => {
+                Some(Err(HlsError::TooFarAhead))
+            }
+            Some((sequence, part)) if !playlist.contains(sequence, part) => None,
+            _ => Some(playlist.render().ok_or(HlsError::NotFound)),
+        })
+        .await
+    }
+
+    /// シーケンス番号のセグメント
+    pub fn segment(&self, stream: &str, sequence: u64) -> Result<Bytes, HlsError> {
+        let hls = self.get(stream)?;
+        let playlist = hls.playlist.read().unwrap();
+        playlist.segment(sequence).ok_or(HlsError::NotFound)
+    }
+
+    /// パーシャルセグメント（PRELOAD-HINTで予告したパートは完成まで待つ）
+    pub async fn part(&self, stream: &str, sequence: u64, index: usize) -> Result<Bytes, HlsError> {
+        let hls = self.get(stream)?;
+        hls.wait_for(|playlist| match playlist.part(sequence, index) {
+            Some(data) => Some(Ok(data)),
+            // 書き込み中のセグメントか次のセグメントのパートはこれから作られる
+            None if (playlist.next_sequence()..=playlist.next_sequence() + 1)
+                .contains(&sequence) =>
+            {
+                None
+            }
+            None => Some(Err(HlsError::NotFound)),
+        })
+        .await
+    }
+
+    fn get(&self, stream: &str) -> Result<Arc<HlsStream>, HlsError> {
+        self.streams
+            .read()
+            .unwrap()
+            .get(stream)
+            .cloned()
+            .ok_or(HlsError::NotFound)
+    }
+
+    fn remove(&self, stream: &str, hls: &Arc<HlsStream>) {
+        let mut streams = self.streams.write().unwrap();
+        if streams
+            .get(stream)
+            .is_some_and(|current| Arc::ptr_eq(current, hls))
+        {
+            streams.remove(stream);
+        }
+    }
+}
+
+/// キーフレームから始まるタグ列（録画など）を1つのMPEG-TSセグメントにする
+///
+/// シーケンスヘッダは先頭に含めること
+pub fn encode_segment<'a>(tags: impl IntoIterator<Item = &'a MediaTag>) -> Option<Bytes> {
+    // 途中で区切らないようにセグメント長を上限にする
+    let config = HlsConfig {
+        segment_duration_ms: u32::MAX,
+        low_latency: false,
+        ..HlsConfig::default()
+    };
+    let mut playlist = Playlist::new(&config);
+    let mut segmenter = Segmenter::new(&config);
+    for tag in tags {
+        segmenter.push(tag, &mut playlist);
+    }
+    segmenter.finish(&mut playlist);
+    playlist.segment(0)
+}
+
+/// MPEG-TSに入れられないコーデック（H.264/AAC以外）のシーケンスヘッダならコーデック名
+pub fn unsupported_codec(tag: &MediaTag) -> Option<&'static str> {
+    if !tag.is_sequence_header() {
+        return None;
+    }
+    match tag.kind {
+        TagKind::Video => match tag.video_packet()?.codec {
+            VideoCodec::Avc => None,
+            VideoCodec::Hevc => Some("HEVC video"),
+            VideoCodec::Av1 => Some("AV1 video"),
+        },
+        _ => tag.opus_packet().map(|_| "Opus audio"),
+    }
+}
+
+/// 配信開始イベントを監視し、ストリームごとにセグメンタを起動する
+pub fn start_hls_segmenter(config: HlsConfig) {
+    HUB.spawn_on_publish("HLS", move |stream| segment_stream(stream, config));
+}
+
+/// 1ストリーム分のセグメントを配信終了まで作り続ける
+async fn segment_stream(stream: String, config: HlsConfig) {
+    let Some(mut subscriber) = HUB.subscribe(&stream) else {
+        return;
+    };
+
+    let hls = Arc::new(HlsStream {
+        playlist: RwLock::new(Playlist::new(&config)),
+        updates: watch::channel(0).0,
+        // 仕様ではターゲット長の3倍までに応答する
+        block_timeout: Duration::from_millis(config.segment_duration_ms as u64 * 3),
+    });
+    HLS.streams
+        .write()
+        .unwrap()
+        .insert(stream.clone(), hls.clone());
+    tracing::info!("[HLS] Segmenting stream: {}", stream);
+
+    let mut segmenter = Segmenter::new(&config);
+    let mut warned = (false, false);
+    while let Some(tag) = subscriber.recv().await {
+        if let Some(codec) = unsupported_codec(&tag) {
+            let (warned, fallback) = match tag.kind {
+                TagKind::Video => (&mut warned.0, " (served over CMAF/DASH instead)"),
+                _ => (&mut warned.1, ""),
+            };
+            if !std::mem::replace(warned, true) {
+                tracing::warn!(
+                    "[HLS] {} uses {}{}, which MPEG-TS HLS does not carry; the track is left out",
+                    stream,
+                    codec,
+                    fallback
+                );
+            }
+        }
+        hls.update(|playlist| segmenter.push(&tag, playlist));
+    }
+
+    hls.update(|playlist| {
+        segmenter.finish(playlist);
+        playlist.end();
+        true
+    });
+    HLS.remove(&stream, &hls);
+    tracing::info!("[HLS] Stopped segmenting stream: {}", stream);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::playlist::Part;
+    use super::*;
+
+    /// LL-HLSのプレイリストを `store` に登録する
+    fn low_latency_stream(store: &HlsStore, block_timeout: Duration) -> Arc<HlsStream> {
+        let config = HlsConfig {
+            low_latency: true,
+            ..HlsConfig::default()
+        };
+        let hls = Arc::new(HlsStream {
+            playlist: RwLock::new(Playlist::new(&config)),
+            updates: watch::channel(0).0,
+            block_timeout,
+        });
+        store
+            .streams
+            .write()
+            .unwrap()
+            .insert("live".to_string(), hls.clone());
+        hls
+    }
+
+    fn push_part(hls: &HlsStream, data: &'static [u8]) {
+        hls.update(|playlist| {
+            playlist.push_part(Part {
+                duration: 0.333,
+                independent: data.starts_with(b"key"),
+                data: Bytes::from_static(data),
+            });
+            true
+        });
+    }
+
+    fn video(data: &'static [u8]) -> MediaTag {
+        MediaTag::new(TagKind::Video, 0, Bytes::from_static(data))
+    }
+
+    #[test]
+    fn detects_codecs_mpeg_ts_does_not_carry() {
+        // Enhanced RTMP の SequenceStart（hvc1 / av01）
+        assert_eq!(
+            unsupported_codec(&video(b"\x90hvc1\x01")),
+            Some("HEVC video")
+        );
+        assert_eq!(
+            unsupported_codec(&video(b"\x90av01\x81")),
+            Some("AV1 video")
+        );
+        assert_eq!(
+            unsupported_codec(&MediaTag::opus(0, true, b"OpusHead")),
+            Some("Opus audio")
+        );
+
+        // H.264/AACと、シーケンスヘッダ以外のフレームは対象外
+        assert_eq!(unsupported_codec(&video(b"\x17\x00\x00\x00\x00\x01")), None);
+        assert_eq!(unsupported_codec(&video(b"\x91hvc1\x00\x00\x00\x00")), None);
+        let aac = MediaTag::new(TagKind::Audio, 0, Bytes::from_static(b"\xaf\x00\x11\x90"));
+        assert_eq!(unsupported_codec(&aac), None);
+    }
+
+    #[tokio::test]
+    async fn blocks_playlist_reload_until_the_part_is_produced() {
+        let store = Arc::new(HlsStore::default());
+        let hls = low_latency_stream(&store, Duration::from_secs(5));
+        push_part(&hls, b"key0");
+
+        let request = tokio::spawn({
+            let store = store.clone();
+            async move { store.playlist("live", Some((0, Some(1)))).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!request.is_finished());
+
+        push_part(&hls, b"part1");
+        let m3u8 = request.await.unwrap().unwrap();
+        assert!(m3u8.contains("#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=0.999\n"));
+        assert!(m3u8.contains("#EXT-X-PART-INF:PART-TARGET=0.333\n"));
+        assert!(m3u8.contains("#EXT-X-PART:DURATION=0.333,URI=\"0.0.ts\",INDEPENDENT=YES\n"));
+        assert!(m3u8.contains("#EXT-X-PART:DURATION=0.333,URI=\"0.1.ts\"\n"));
+        assert!(m3u8.ends_with("#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"0.2.ts\"\n"));
+
+        // PRELOAD-HINTで予告したパートも完成まで待つ
+        let part = tokio::spawn({
+            let store = store.clone();
+            async move { store.part("live", 0, 2).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!part.is_finished());
+        push_part(&hls, b"part2");
+        assert_eq!(&part.await.unwrap().unwrap()[..], b"part2");
+    }
+
+    #[tokio::test]
+    async fn rejects_or_times_out_blocking_requests() {
+        let store = HlsStore::default();
+        let hls = low_latency_stream(&store, Duration::from_millis(50));
+        push_part(&hls, b"key0");
+
+        // 書き込み中のセグメントより3つ以上先は待たずに断る
+        assert!(matches!(
+            store.playlist("live", Some((3, None))).await,
+            Err(HlsError::TooFarAhead)
+        ));
+        assert!(matches!(
+            store.playlist("live", Some((2, None))).await,
+            Err(HlsError::Timeout)
+        ));
+        assert!(matches!(
+            store.playlist("offline", Some((0, None))).await,
+            Err(HlsError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,256 @@
+use bytes::{Bytes, BytesMut};
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+use crate::config::HlsConfig;
+
+/// プレイリストから外れた後も取得できるように残しておくセグメント数
+const RETAINED_SEGMENTS: usize = 2;
+
+/// パートを掲載する完成済みセグメント数（LL-HLS）
+const PART_SEGMENTS: usize = 2;
+
+/// パーシャルセグメント（LL-HLS）
+pub struct Part {
+    /// 秒
+    pub duration: f64,
+    /// キーフレームから始まり単独で復号できるか
+    pub independent: bool,
+    pub data: Bytes,
+}
+
+/// 切り出し済みのMPEG-TSセグメント
+pub struct Segment {
+    pub sequence: u64,
+    /// 秒
+    pub duration: f64,
+    /// 直近のセグメントのみ保持する
+    pub parts: Vec<Part>,
+    pub data: Bytes,
+}
+
+/// スライディングウィンドウのメディアプレイリスト
+pub struct Playlist {
+    segments: VecDeque<Segment>,
+    /// 書き込み中のセグメントのパート
+    open: Vec<Part>,
+    /// 書き込み中のセグメントのシーケンス番号
+    next_sequence: u64,
+    window: usize,
+    target_duration: f64,
+    /// LL-HLSのパート長（秒、無効なら `None`）
+    part_target: Option<f64>,
+    ended: bool,
+}
+
+impl Playlist {
+    pub fn new(config: &HlsConfig) -> Self {
+        Self {
+            segments: VecDeque::new(),
+            open: Vec::new(),
+            next_sequence: 0,
+            window: config.playlist_length.max(1),
+            target_duration: config.segment_duration_ms as f64 / 1000.0,
+            part_target: config
+                .low_latency
+                .then_some(config.part_duration_ms as f64 / 1000.0),
+            ended: false,
+        }
+    }
+
+    /// 書き込み中のセグメントにパートを追加する
+    pub fn push_part(&mut self, part: Part) {
+        self.open.push(part);
+    }
+
+    /// 書き込み中のセグメントを完成させる
+    pub fn end_segment(&mut self, duration: f64) {
+        let parts = std::mem::take(&mut self.open);
+        let data = match parts.as_slice() {
+            [part] => part.data.clone(),
+            parts => {
+                let mut data = BytesMut::with_capacity(parts.iter().map(|p| p.data.len()).sum());
+                for part in parts {
+                    data.extend_from_slice(&part.data);
+                }
+                data.freeze()
+            }
+        };
+
+        self.segments.push_back(Segment {
+            sequence: self.next_sequence,
+            duration,
+            parts: if self.part_target.is_some() { parts } else { Vec::new() },
+            data,
+        });
+        self.next_sequence += 1;
+
+        while self.segments.len() > self.window + RETAINED_SEGMENTS {
+            self.segments.pop_front();
+        }
+        // 古いセグメントのパートは掲載しないので手放す
+        for segment in self.segments.iter_mut().rev().skip(PART_SEGMENTS) {
+            segment.parts = Vec::new();
+        }
+    }
+
+    /// 配信終了を記録する（待機中のリクエストを打ち切る）
+    pub fn end(&mut self) {
+        self.ended = true;
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    /// 書き込み中のセグメントのシーケンス番号
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    pub fn segment(&self, sequence: u64) -> Option<Bytes> {
+        self.segments
+            .iter()
+            .find(|segment| segment.sequence == sequence)
+            .map(|segment| segment.data.clone())
+    }
+
+    pub fn part(&self, sequence: u64, index: usize) -> Option<Bytes> {
+        let parts = if sequence == self.next_sequence {
+            &self.open
+        } else {
+            &self
+                .segments
+                .iter()
+                .find(|segment| segment.sequence == sequence)?
+                .parts
+        };
+        parts.get(index).map(|part| part.data.clone())
+    }
+
+    /// セグメント `sequence`（とそのパート `part`）がプレイリストに載っているか
+    pub fn contains(&self, sequence: u64, part: Option<usize>) -> bool {
+        match part {
+            None => sequence < self.next_sequence,
+            Some(part) => {
+                sequence < self.next_sequence
+                    || (sequence == self.next_sequence && part < self.open.len())
+            }
+        }
+    }
+
+    /// m3u8 を組み立てる（まだ何も書き出していなければ `None`）
+    pub fn render(&self) -> Option<String> {
+        let skip = self.segments.len().saturating_sub(self.window);
+        let segments: Vec<_> = self.segments.iter().skip(skip).collect();
+        if segments.is_empty() && (self.part_target.is_none() || self.open.is_empty()) {
+            return None;
+        }
+        let media_sequence = segments.first().map_or(self.next_sequence, |s| s.sequence);
+
+        // EXT-X-TARGETDURATION はどのセグメント長以上でなければならない
+        let target_duration = segments
+            .iter()
+            .map(|segment| segment.duration)
+            .fold(self.target_duration, f64::max)
+            .ceil() as u64;
+
+        let mut m3u8 = String::new();
+        writeln!(m3u8, "#EXTM3U").unwrap();
+        match self.part_target {
+            Some(part_target) => {
+                writeln!(m3u8, "#EXT-X-VERSION:6").unwrap();
+                writeln!(m3u8, "#EXT-X-TARGETDURATION:{}", target_duration).unwrap();
+                writeln!(
+                    m3u8,
+                    "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK={:.3}",
+                    part_target * 3.0
+                )
+                .unwrap();
+                writeln!(m3u8, "#EXT-X-PART-INF:PART-TARGET={:.3}", part_target).unwrap();
+            }
+            None => {
+                writeln!(m3u8, "#EXT-X-VERSION:3").unwrap();
+                writeln!(m3u8, "#EXT-X-TARGETDURATION:{}", target_duration).unwrap();
+            }
+        }
+        writeln!(m3u8, "#EXT-X-MEDIA-SEQUENCE:{}", media_sequence).unwrap();
+
+        for segment in segments {
+            write_parts(&mut m3u8, segment.sequence, &segment.parts);
+            writeln!(m3u8, "#EXTINF:{:.3},", segment.duration).unwrap();
+            writeln!(m3u8, "{}.ts", segment.sequence).unwrap();
+        }
+
+        if self.ended {
+            writeln!(m3u8, "#EXT-X-ENDLIST").unwrap();
+        } else if self.part_target.is_some() {
+            write_parts(&mut m3u8, self.next_sequence, &self.open);
+            writeln!(
+                m3u8,
+                "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"{}.{}.ts\"",
+                self.next_sequence,
+                self.open.len()
+            )
+            .unwrap();
+        }
+        Some(m3u8)
+    }
+}
+
+fn write_parts(m3u8: &mut String, sequence: u64, parts: &[Part]) {
+    for (index, part) in parts.iter().enumerate() {
+        write!(
+            m3u8,
+            "#EXT-X-PART:DURATION={:.3},URI=\"{}.{}.ts\"",
+            part.duration, sequence, index
+        )
+        .unwrap();
+        if part.independent {
+            write!(m3u8, ",INDEPENDENT=YES").unwrap();
+        }
+        writeln!(m3u8).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(data: &'static [u8]) -> Part {
+        Part {
+            duration: 0.5,
+            independent: true,
+            data: Bytes::from_static(data),
+        }
+    }
+
+    #[test]
+    fn slides_window_and_advances_media_sequence() {
+        let mut playlist = Playlist::new(&HlsConfig {
+            playlist_length: 3,
+            ..HlsConfig::default()
+        });
+        assert!(playlist.render().is_none());
+        for _ in 0..6 {
+            playlist.push_part(part(b"ts"));
+            playlist.end_segment(2.5);
+        }
+
+        let m3u8 = playlist.render().unwrap();
+        assert!(m3u8.contains("#EXT-X-MEDIA-SEQUENCE:3\n"));
+        // どのセグメントより短くならないよう切り上げる
+        assert!(m3u8.contains("#EXT-X-TARGETDURATION:3\n"));
+        let uris: Vec<&str> = m3u8.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(uris, vec!["3.ts", "4.ts", "5.ts"]);
+        assert!(!m3u8.contains("#EXT-X-PART"));
+
+        // プレイリストから外れた直後のセグメントはまだ取得できる
+        assert!(playlist.segment(0).is_none());
+        assert!(playlist.segment(1).is_some());
+        assert!(playlist.segment(5).is_some());
+
+        playlist.end();
+        assert!(playlist.render().unwrap().ends_with("#EXT-X-ENDLIST\n"));
+    }
+}
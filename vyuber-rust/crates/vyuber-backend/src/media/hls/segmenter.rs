@@ -0,0 +1,331 @@
+use bytes::{BufMut, BytesMut};
+
+use super::playlist::{Part, Playlist};
+use crate::config::HlsConfig;
+use crate::media::aac::AacConfig;
+use crate::media::avc::AvcConfig;
+use crate::media::tag::{codec, MediaTag, TagKind, VideoCodec, VideoPacketType};
+use crate::media::ts::{stream_id, Pes, TsMuxer, AUDIO_PID, VIDEO_PID};
+
+/// PCRに対してPTS/DTSを進めておく量（90kHz、0.7秒）
+const PCR_DELAY: u64 = 63_000;
+
+/// 音声フレームをまとめて1つのPESにする長さ（ミリ秒）
+const AUDIO_PES_DURATION_MS: u32 = 100;
+
+/// ハブのタグ列をキーフレーム単位のMPEG-TSセグメント（LL-HLSではさらにパート）に切り分ける
+pub struct Segmenter {
+    target_duration_ms: u32,
+    /// LL-HLSのパート長（無効なら `None`）
+    part_duration_ms: Option<u32>,
+    muxer: TsMuxer,
+    avc: Option<AvcConfig>,
+    aac: Option<AacConfig>,
+    /// 書き込み中のセグメントの開始時刻（最初のキーフレームまでは `None`）
+    segment_start: Option<u32>,
+    part: Option<OpenPart>,
+    /// 区切りの判定に使うトラック（映像があれば映像）の直前のタイムスタンプと間隔
+    last_timestamp: Option<u32>,
+    frame_interval: u32,
+    audio: AudioBuffer,
+}
+
+/// 書き込み中のパート（LL-HLSでなければセグメント全体）
+struct OpenPart {
+    start: u32,
+    independent: bool,
+    data: BytesMut,
+}
+
+/// PESにまとめる前のADTSフレーム
+#[derive(Default)]
+struct AudioBuffer {
+    start: Option<u32>,
+    data: BytesMut,
+}
+
+impl Segmenter {
+    pub fn new(config: &HlsConfig) -> Self {
+        Self {
+            target_duration_ms: config.segment_duration_ms,
+            part_duration_ms: config.low_latency.then_some(config.part_duration_ms),
+            muxer: TsMuxer::new(true, true),
+            avc: None,
+            aac: None,
+            segment_start: None,
+            part: None,
+            last_timestamp: None,
+            frame_interval: 0,
+            audio: AudioBuffer::default(),
+        }
+    }
+
+    /// タグを書き込む。パートやセグメントが完成してプレイリストが更新されたら `true`
+    pub fn push(&mut self, tag: &MediaTag, playlist: &mut Playlist) -> bool {
+        match tag.kind {
+            TagKind::Video => self.push_video(tag, playlist),
+            TagKind::Audio => self.push_audio(tag, playlist),
+            TagKind::Script => false,
+        }
+    }
+
+    /// 書き込み中のパートを閉じる（配信終了時）
+    pub fn finish(&mut self, playlist: &mut Playlist) -> bool {
+        match self.last_timestamp {
+            Some(timestamp) => {
+                let end = timestamp.saturating_add(self.frame_interval);
+                self.cut(end, true, false, playlist)
+            }
+            None => false,
+        }
+    }
+
+    fn push_video(&mut self, tag: &MediaTag, playlist: &mut Playlist) -> bool {
+        // MPEG-TSにはH.264だけを入れる（それ以外は segment_stream で警告する）
+        let Some(packet) = tag.video_packet().filter(|packet| packet.codec == VideoCodec::Avc)
+        else {
+            return false;
+        };
+        match packet.packet_type {
+            VideoPacketType::SequenceStart => {
+                self.avc = AvcConfig::parse(&packet.data);
+                return false;
+            }
+            VideoPacketType::CodedFrames => {}
+            _ => return false,
+        }
+
+        let keyframe = packet.keyframe;
+        let updated = self.split(tag.timestamp, keyframe, playlist);
+
+        let (Some(avc), Some(part)) = (&self.avc, &mut self.part) else {
+            // 最初のキーフレームまでは捨てる
+            return updated;
+        };
+
+        let cts = packet.composition_time;
+        let dts = tag.timestamp as u64 * 90 + PCR_DELAY;
+        let pts = dts.saturating_add_signed(cts as i64 * 90);
+
+        let mut annexb = BytesMut::with_capacity(packet.data.len() + 64);
+        avc.write_annexb(&packet.data, keyframe, &mut annexb);
+        self.muxer.write_pes(
+            &Pes {
+                pid: VIDEO_PID,
+                stream_id: stream_id::VIDEO,
+                pts,
+                dts: (pts != dts).then_some(dts),
+                pcr: Some(dts - PCR_DELAY),
+                random_access: keyframe,
+                payload: &annexb,
+            },
+            &mut part.data,
+        );
+        updated
+    }
+
+    fn push_audio(&mut self, tag: &MediaTag, playlist: &mut Playlist) -> bool {
+        let data = &tag.data;
+        if data.len() < 2 || data[0] >> 4 != codec::AUDIO_AAC {
+            return false;
+        }
+        if tag.is_sequence_header() {
+            self.aac = AacConfig::parse(&data[2..]);
+            return false;
+        }
+        let Some(aac) = self.aac else {
+            return false;
+        };
+
+        // 映像がなければ音声フレームで区切る
+        let updated = if self.avc.is_none() {
+            self.split(tag.timestamp, true, playlist)
+        } else {
+            false
+        };
+        if self.part.is_none() {
+            return updated;
+        }
+
+        let frame = &data[2..];
+        let start = *self.audio.start.get_or_insert(tag.timestamp);
+        self.audio.data.put_slice(&aac.adts_header(frame.len()));
+        self.audio.data.put_slice(frame);
+        if tag.timestamp.saturating_sub(start) >= AUDIO_PES_DURATION_MS {
+            self.flush_audio();
+        }
+        updated
+    }
+
+    /// 区切りの判定に使うトラックのフレームごとに呼び、必要ならセグメント/パートを区切る
+    ///
+    /// `random_access` はこのフレームから単独で復号を始められるか
+    fn split(&mut self, timestamp: u32, random_access: bool, playlist: &mut Playlist) -> bool {
+        if let Some(last) = self.last_timestamp {
+            if timestamp > last {
+                self.frame_interval = timestamp - last;
+            }
+        }
+        self.last_timestamp = Some(timestamp);
+
+        let segment_due = match self.segment_start {
+            None => true,
+            Some(start) => timestamp.saturating_sub(start) >= self.target_duration_ms,
+        };
+        if random_access && segment_due {
+            return self.cut(timestamp, true, true, playlist);
+        }
+
+        // 次のフレームまで含めるとパート長を超えるならここで区切る
+        let part_due = match (self.part_duration_ms, &self.part) {
+            (Some(part_duration), Some(part)) => {
+                timestamp > part.start
+                    && timestamp - part.start + self.frame_interval > part_duration
+            }
+            _ => false,
+        };
+        if part_due {
+            return self.cut(timestamp, false, random_access, playlist);
+        }
+        false
+    }
+
+    /// 溜めている音声フレームを1つのPESとして書き出す
+    fn flush_audio(&mut self) {
+        let Some(start) = self.audio.start.take() else {
+            return;
+        };
+        let data = self.audio.data.split();
+        let Some(part) = &mut self.part else {
+            return;
+        };
+
+        let pts = start as u64 * 90 + PCR_DELAY;
+        let pcr = (self.muxer.pcr_pid() == AUDIO_PID).then_some(pts - PCR_DELAY);
+        self.muxer.write_pes(
+            &Pes {
+                pid: AUDIO_PID,
+                stream_id: stream_id::AUDIO,
+                pts,
+                dts: None,
+                pcr,
+                random_access: pcr.is_some(),
+                payload: &data,
+            },
+            &mut part.data,
+        );
+    }
+
+    /// 書き込み中のパート（`end_segment` ならセグメントも）を `timestamp` で閉じ、次のパートを始める
+    fn cut(
+        &mut self,
+        timestamp: u32,
+        end_segment: bool,
+        independent: bool,
+        playlist: &mut Playlist,
+    ) -> bool {
+        self.flush_audio();
+
+        let mut updated = false;
+        if let Some(part) = self.part.take() {
+            playlist.push_part(Part {
+                duration: timestamp.saturating_sub(part.start) as f64 / 1000.0,
+                independent: part.independent,
+                data: part.data.freeze(),
+            });
+            updated = true;
+        }
+        if end_segment {
+            if let Some(start) = self.segment_start.take() {
+                playlist.end_segment(timestamp.saturating_sub(start) as f64 / 1000.0);
+                updated = true;
+            }
+            self.segment_start = Some(timestamp);
+        }
+
+        // 単独で復号できるパートは先頭にPAT/PMTを置く
+        let mut data = BytesMut::new();
+        if independent {
+            self.muxer.set_tracks(self.aac.is_some(), self.avc.is_some());
+            self.muxer.write_tables(&mut data);
+        }
+        self.part = Some(OpenPart {
+            start: timestamp,
+            independent,
+            data,
+        });
+        updated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::testing;
+    use crate::media::ts::{PACKET_SIZE, SYNC_BYTE};
+
+    /// 100ms間隔の映像（`keyframes` のタイムスタンプがキーフレーム）と同じ時刻の音声
+    fn push_frames(
+        segmenter: &mut Segmenter,
+        playlist: &mut Playlist,
+        frames: std::ops::Range<u32>,
+        keyframes: &[u32],
+    ) {
+        for timestamp in frames.map(|i| i * 100) {
+            let keyframe = keyframes.contains(&timestamp);
+            segmenter.push(
+                &testing::avc_frame(timestamp, keyframe, 0, &[0x10; 32]),
+                playlist,
+            );
+            segmenter.push(&testing::aac_frame(timestamp, &[0x20; 8]), playlist);
+        }
+    }
+
+    /// `#EXTINF` の秒数
+    fn durations(m3u8: &str) -> Vec<&str> {
+        m3u8.lines()
+            .filter_map(|line| line.strip_prefix("#EXTINF:"))
+            .map(|line| line.trim_end_matches(','))
+            .collect()
+    }
+
+    #[test]
+    fn cuts_segments_only_on_keyframes_after_target_duration() {
+        let config = HlsConfig::default();
+        let mut playlist = Playlist::new(&config);
+        let mut segmenter = Segmenter::new(&config);
+        segmenter.push(&testing::avc_sequence_header(0), &mut playlist);
+        segmenter.push(&testing::aac_sequence_header(0), &mut playlist);
+
+        // 最初のキーフレームより前のフレームは捨てる
+        push_frames(&mut segmenter, &mut playlist, 0..2, &[]);
+        assert!(playlist.render().is_none());
+
+        // 1.5秒後のキーフレームでは区切らず、目標の2秒を過ぎた最初のキーフレームで区切る
+        push_frames(
+            &mut segmenter,
+            &mut playlist,
+            2..50,
+            &[200, 1700, 3200, 4700],
+        );
+        segmenter.finish(&mut playlist);
+        let m3u8 = playlist.render().unwrap();
+        assert_eq!(durations(&m3u8), vec!["3.000", "1.800"]);
+
+        for sequence in 0..2 {
+            let data = playlist.segment(sequence).unwrap();
+            assert_eq!(data.len() % PACKET_SIZE, 0);
+            assert!(data
+                .chunks(PACKET_SIZE)
+                .all(|packet| packet[0] == SYNC_BYTE));
+            // PAT, PMT の後にランダムアクセス可能な映像のPESが続く
+            let pids: Vec<u16> = data
+                .chunks(PACKET_SIZE)
+                .take(3)
+                .map(|packet| u16::from_be_bytes([packet[1], packet[2]]) & 0x1FFF)
+                .collect();
+            assert_eq!(pids, vec![0x0000, 0x1000, VIDEO_PID]);
+            assert_eq!(data[2 * PACKET_SIZE + 5] & 0x40, 0x40);
+        }
+    }
+}
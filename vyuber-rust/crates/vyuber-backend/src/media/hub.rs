@@ -0,0 +1,531 @@
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use thiserror::Error;
+use tokio::sync::{broadcast, watch};
+
+use super::cache::StreamCache;
+use super::stats::IngestStats;
+use super::tag::{MediaTag, TagKind};
+use super::timestamp::{ReorderBuffer, TimestampNormalizer};
+use crate::config::{self, GopCacheConfig, PublishConfig};
+use vyuber_shared::stream::{PublishPolicy, StreamInfo, StreamStats};
+
+/// 購読者ごとに溜められるタグ数（60fps + 音声で約10秒分）
+///
+/// これを超えて遅れた購読者は切断し、配信者側を待たせない
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// ライフサイクルイベントのバッファ
+const EVENT_CAPACITY: usize = 64;
+
+/// グローバルなブロードキャストハブ
+pub static HUB: Lazy<Hub> = Lazy::new(Hub::new);
+
+#[derive(Debug, Error)]
+pub enum HubError {
+    #[error("stream '{0}' is already being published")]
+    AlreadyPublishing(String),
+}
+
+/// 配信の開始/終了/引き継ぎイベント
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HubEvent {
+    Published { stream: String },
+    Unpublished { stream: String },
+    /// 配信中に別の配信者が引き継いだ（チャネルと購読者はそのまま）
+    TakenOver { stream: String },
+}
+
+/// 1ストリーム分の配信チャネル
+struct Channel {
+    sender: broadcast::Sender<MediaTag>,
+    /// 送信と同じロックで更新し、購読開始時のスナップショットと取りこぼし/重複が起きないようにする
+    cache: Mutex<StreamCache>,
+    stats: Mutex<IngestStats>,
+    /// 現在の配信者のID（引き継がれると変わる）
+    owner: watch::Sender<u64>,
+    /// 最後に送ったタグのタイムスタンプ（引き継いだ配信者はここから続ける）
+    last_timestamp: AtomicU32,
+}
+
+struct HubInner {
+    streams: RwLock<HashMap<String, Arc<Channel>>>,
+    events: broadcast::Sender<HubEvent>,
+    gop_cache: GopCacheConfig,
+    publish: PublishConfig,
+    /// ストリームキーごとの重複publishの扱い
+    policies: RwLock<HashMap<String, PublishPolicy>>,
+    next_publisher_id: AtomicU64,
+}
+
+/// ストリーム名（ストリームキー）ごとに1配信者を多数の購読者へ配るハブ
+///
+/// タグは `Bytes` のまま複製されるのでペイロードはコピーされない
+#[derive(Clone)]
+pub struct Hub {
+    inner: Arc<HubInner>,
+}
+
+impl Default for Hub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hub {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        Self {
+            inner: Arc::new(HubInner {
+                streams: RwLock::new(HashMap::new()),
+                events,
+                gop_cache: config::get().gop_cache,
+                publish: config::get().publish,
+                policies: RwLock::new(HashMap::new()),
+                next_publisher_id: AtomicU64::new(1),
+            }),
+        }
+    }
+
+    /// 配信を開始する
+    ///
+    /// 同名のストリームが配信中の場合、重複publishの扱いが `Reject` ならエラー、
+    /// `Takeover` なら既存の配信者からチャネルを引き継ぐ（購読者は切断されない）
+    pub fn publish(&self, stream: &str) -> Result<Publisher, HubError> {
+        let id = self.inner.next_publisher_id.fetch_add(1, Ordering::Relaxed);
+        let mut streams = self.inner.streams.write().unwrap();
+
+        if let Some(channel) = streams.get(stream) {
+            if self.publish_policy(stream) == PublishPolicy::Reject {
+                return Err(HubError::AlreadyPublishing(stream.to_string()));
+            }
+
+            // 旧配信者のunpublishと競合しないよう、ストリーム一覧のロックを保持したまま切り替える
+            let channel = channel.clone();
+            let resume_from = {
+                let _cache = channel.cache.lock().unwrap();
+                channel.owner.send_replace(id);
+                *channel.stats.lock().unwrap() = IngestStats::new();
+                channel.last_timestamp.load(Ordering::Relaxed)
+            };
+            drop(streams);
+
+            tracing::warn!("[Hub] Stream taken over by a new publisher: {}", stream);
+            let _ = self.inner.events.send(HubEvent::TakenOver {
+                stream: stream.to_string(),
+            });
+            return Ok(Publisher::new(self, stream, channel, id, Some(resume_from)));
+        }
+
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let channel = Arc::new(Channel {
+            sender,
+            cache: Mutex::new(StreamCache::new(self.inner.gop_cache)),
+            stats: Mutex::new(IngestStats::new()),
+            owner: watch::Sender::new(id),
+            last_timestamp: AtomicU32::new(0),
+        });
+        streams.insert(stream.to_string(), channel.clone());
+        drop(streams);
+
+        tracing::info!("[Hub] Stream published: {}", stream);
+        let _ = self.inner.events.send(HubEvent::Published {
+            stream: stream.to_string(),
+        });
+
+        Ok(Publisher::new(self, stream, channel, id, None))
+    }
+
+    /// ストリームキーの重複publishの扱い（設定されていなければ既定値）
+    pub fn publish_policy(&self, stream: &str) -> PublishPolicy {
+        let policies = self.inner.policies.read().unwrap();
+        policies
+            .get(stream)
+            .copied()
+            .unwrap_or(self.inner.publish.default_policy)
+    }
+
+    /// ストリームキーの重複publishの扱いを設定する（次のpublishから反映）
+    pub fn set_publish_policy(&self, stream: &str, policy: PublishPolicy) {
+        let mut policies = self.inner.policies.write().unwrap();
+        policies.insert(stream.to_string(), policy);
+    }
+
+    /// 配信中のストリームを購読する（配信されていなければ `None`）
+    ///
+    /// 購読者はまずキャッシュ済みのメタデータ、シーケンスヘッダ、直近のGOPを受け取り、その後ライブのタグを受け取る
+    pub fn subscribe(&self, stream: &str) -> Option<Subscriber> {
+        let streams = self.inner.streams.read().unwrap();
+        let channel = streams.get(stream)?;
+        let cache = channel.cache.lock().unwrap();
+        Some(Subscriber {
+            stream: stream.to_string(),
+            pending: cache.snapshot().into(),
+            tracks: cache.tracks(),
+            receiver: channel.sender.subscribe(),
+        })
+    }
+
+    /// 配信中のストリームの映像/音声情報（配信されていなければ `None`）
+    pub fn info(&self, stream: &str) -> Option<StreamInfo> {
+        let streams = self.inner.streams.read().unwrap();
+        let channel = streams.get(stream)?;
+        let info = channel.cache.lock().unwrap().info();
+        Some(info)
+    }
+
+    /// 配信中のストリームの受信統計（配信されていなければ `None`）
+    pub fn stats(&self, stream: &str) -> Option<StreamStats> {
+        let streams = self.inner.streams.read().unwrap();
+        let channel = streams.get(stream)?;
+        let stats = channel.stats.lock().unwrap().snapshot();
+        Some(stats)
+    }
+
+    /// 配信の開始/終了/引き継ぎイベントを購読する
+    pub fn events(&self) -> broadcast::Receiver<HubEvent> {
+        self.inner.events.subscribe()
+    }
+
+    /// 配信が始まるたびに `task(ストリーム名)` をタスクとして起動する
+    ///
+    /// `name` はログに使う
+    pub fn spawn_on_publish<F, Fut>(&self, name: &'static str, task: F)
+    where
+        F: Fn(String) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut events = self.events();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(HubEvent::Published { stream }) => {
+                        tokio::spawn(task(stream));
+                    }
+                    // 引き継ぎではチャネルがそのまま残るので、起動済みのタスクが処理を続ける
+                    Ok(HubEvent::Unpublished { .. } | HubEvent::TakenOver { .. }) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("[{}] Missed {} hub events", name, skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    fn unpublish(&self, stream: &str, channel: &Arc<Channel>, id: u64) {
+        let mut streams = self.inner.streams.write().unwrap();
+        // 自分が配信しているチャネルの場合のみ削除する（引き継がれた後なら何もしない）
+        if streams
+            .get(stream)
+            .is_some_and(|current| Arc::ptr_eq(current, channel))
+            && *channel.owner.borrow() == id
+        {
+            streams.remove(stream);
+            drop(streams);
+
+            tracing::info!("[Hub] Stream unpublished: {}", stream);
+            let _ = self.inner.events.send(HubEvent::Unpublished {
+                stream: stream.to_string(),
+            });
+        }
+    }
+}
+
+/// 配信者ハンドル（dropすると配信終了）
+pub struct Publisher {
+    hub: Hub,
+    stream: String,
+    channel: Arc<Channel>,
+    id: u64,
+    owner: watch::Receiver<u64>,
+    timestamps: TimestampNormalizer,
+    reorder: ReorderBuffer,
+}
+
+impl Publisher {
+    fn new(
+        hub: &Hub,
+        stream: &str,
+        channel: Arc<Channel>,
+        id: u64,
+        resume_from: Option<u32>,
+    ) -> Self {
+        let owner = channel.owner.subscribe();
+        Self {
+            hub: hub.clone(),
+            stream: stream.to_string(),
+            channel,
+            id,
+            owner,
+            // 引き継いだ場合は旧配信者の最後のタイムスタンプから続ける
+            timestamps: TimestampNormalizer::new(resume_from.unwrap_or(0)),
+            reorder: ReorderBuffer::new(),
+        }
+    }
+
+    /// タグを全購読者へ送る（購読者がいなくても失敗しない）
+    ///
+    /// タイムスタンプは0（引き継いだ場合は旧配信者の続き）から単調に増えるよう正規化し、
+    /// 音声と映像を並べ替えてから送る。引き継がれた後に届いたタグは捨てる
+    pub fn send(&mut self, mut tag: MediaTag) {
+        let mut cache = self.channel.cache.lock().unwrap();
+        if *self.owner.borrow() != self.id {
+            return;
+        }
+        // 受信統計はエンコーダの送ってきた値のまま記録する
+        self.channel.stats.lock().unwrap().record(&tag);
+
+        tag.timestamp = self.timestamps.normalize(tag.timestamp);
+        self.reorder.push(tag);
+        while let Some(tag) = self.reorder.pop() {
+            self.forward(&mut cache, tag);
+        }
+    }
+
+    /// 並べ替えを待っているタグをすべて送る
+    fn flush(&mut self) {
+        let mut cache = self.channel.cache.lock().unwrap();
+        if *self.owner.borrow() != self.id {
+            return;
+        }
+        while let Some(tag) = self.reorder.flush() {
+            self.forward(&mut cache, tag);
+        }
+    }
+
+    fn forward(&self, cache: &mut StreamCache, tag: MediaTag) {
+        self.channel
+            .last_timestamp
+            .store(tag.timestamp, Ordering::Relaxed);
+        cache.update(&tag);
+        let _ = self.channel.sender.send(tag);
+    }
+
+    /// 別の配信者に引き継がれるまで待つ
+    ///
+    /// ハンドルを借用しないので、複数のタスクで共有している配信者でも待てる
+    pub fn taken_over(&self) -> impl Future<Output = ()> + Send + 'static {
+        let id = self.id;
+        let mut owner = self.owner.clone();
+        async move {
+            let _ = owner.wait_for(|owner| *owner != id).await;
+        }
+    }
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        self.flush();
+        self.hub.unpublish(&self.stream, &self.channel, self.id);
+    }
+}
+
+/// 購読者ハンドル
+pub struct Subscriber {
+    stream: String,
+    /// ライブのタグより先に返すキャッシュ済みタグ
+    pending: VecDeque<MediaTag>,
+    tracks: (bool, bool),
+    receiver: broadcast::Receiver<MediaTag>,
+}
+
+impl Subscriber {
+    /// 購読開始時点での (音声あり, 映像あり)
+    pub fn tracks(&self) -> (bool, bool) {
+        self.tracks
+    }
+
+    /// キャッシュ済みのタグ（購読開始前に届いたもの）をまだ返している途中か
+    pub fn replaying(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// まだ返していないキャッシュ済みのシーケンスヘッダ
+    pub fn sequence_header(&self, kind: TagKind) -> Option<&MediaTag> {
+        self.pending
+            .iter()
+            .find(|tag| tag.kind == kind && tag.is_sequence_header())
+    }
+
+    /// 次のタグを受け取る
+    ///
+    /// 配信終了時、または遅れすぎてタグを取りこぼした場合は `None`（切断扱い）
+    pub async fn recv(&mut self) -> Option<MediaTag> {
+        if let Some(tag) = self.pending.pop_front() {
+            return Some(tag);
+        }
+        match self.receiver.recv().await {
+            Ok(tag) => Some(tag),
+            Err(broadcast::error::RecvError::Closed) => None,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!(
+                    "[Hub] Subscriber of {} lagged behind by {} tags, disconnecting",
+                    self.stream,
+                    skipped
+                );
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::testing;
+
+    /// (種類, タイムスタンプ, シーケンスヘッダか)
+    fn describe(tag: &MediaTag) -> (TagKind, u32, bool) {
+        (tag.kind, tag.timestamp, tag.is_sequence_header())
+    }
+
+    /// 映像と同じタイムスタンプの音声を続けて送る（並べ替えで待たされないように）
+    fn send_frame(publisher: &mut Publisher, timestamp: u32, keyframe: bool) {
+        publisher.send(testing::avc_frame(timestamp, keyframe, 0, &[0x10; 16]));
+        publisher.send(testing::aac_frame(timestamp, &[0x20; 8]));
+    }
+
+    #[tokio::test]
+    async fn subscriber_starts_with_sequence_headers_and_latest_gop() {
+        let hub = Hub::new();
+        let mut publisher = hub.publish("live").unwrap();
+        publisher.send(testing::avc_sequence_header(0));
+        publisher.send(testing::aac_sequence_header(0));
+        for (timestamp, keyframe) in [(0, true), (40, false), (80, true), (120, false)] {
+            send_frame(&mut publisher, timestamp, keyframe);
+        }
+
+        let mut subscriber = hub.subscribe("live").unwrap();
+        assert_eq!(subscriber.tracks(), (true, true));
+        assert!(subscriber.sequence_header(TagKind::Video).is_some());
+        let mut received = Vec::new();
+        while subscriber.replaying() {
+            received.push(describe(&subscriber.recv().await.unwrap()));
+        }
+        assert_eq!(
+            received,
+            vec![
+                (TagKind::Video, 0, true),
+                (TagKind::Audio, 0, true),
+                (TagKind::Video, 80, false),
+                (TagKind::Audio, 80, false),
+                (TagKind::Video, 120, false),
+                (TagKind::Audio, 120, false),
+            ]
+        );
+
+        // キャッシュの後はライブのタグが続く
+        send_frame(&mut publisher, 160, false);
+        assert_eq!(
+            describe(&subscriber.recv().await.unwrap()),
+            (TagKind::Video, 160, false)
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnects_subscriber_that_lags_behind() {
+        let hub = Hub::new();
+        let mut publisher = hub.publish("live").unwrap();
+        publisher.send(testing::avc_sequence_header(0));
+        let mut subscriber = hub.subscribe("live").unwrap();
+
+        // 映像だけなので並べ替えで待たされずにそのまま溜まる
+        for i in 0..CHANNEL_CAPACITY as u32 + 10 {
+            publisher.send(testing::avc_frame(i * 40, i == 0, 0, &[0x10; 16]));
+        }
+        // キャッシュ済みのシーケンスヘッダは返してから切断する
+        assert!(subscriber.recv().await.unwrap().is_sequence_header());
+        assert!(subscriber.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn announces_unpublish_when_publisher_is_dropped() {
+        let hub = Hub::new();
+        let mut events = hub.events();
+        let publisher = hub.publish("live").unwrap();
+        assert_eq!(
+            events.recv().await.unwrap(),
+            HubEvent::Published {
+                stream: "live".into()
+            }
+        );
+        let mut subscriber = hub.subscribe("live").unwrap();
+
+        drop(publisher);
+        assert_eq!(
+            events.recv().await.unwrap(),
+            HubEvent::Unpublished {
+                stream: "live".into()
+            }
+        );
+        assert!(subscriber.recv().await.is_none());
+        assert!(hub.subscribe("live").is_none());
+    }
+
+    #[tokio::test]
+    async fn takeover_keeps_subscribers_and_releases_old_publisher() {
+        let hub = Hub::new();
+        hub.set_publish_policy("live", PublishPolicy::Takeover);
+        let mut events = hub.events();
+        let mut old = hub.publish("live").unwrap();
+        events.recv().await.unwrap();
+        old.send(testing::avc_sequence_header(0));
+        send_frame(&mut old, 0, true);
+        send_frame(&mut old, 40, false);
+        let mut subscriber = hub.subscribe("live").unwrap();
+        while subscriber.replaying() {
+            subscriber.recv().await.unwrap();
+        }
+
+        let taken_over = old.taken_over();
+        let mut new = hub.publish("live").unwrap();
+        assert_eq!(
+            events.recv().await.unwrap(),
+            HubEvent::TakenOver {
+                stream: "live".into()
+            }
+        );
+        tokio::time::timeout(std::time::Duration::from_secs(1), taken_over)
+            .await
+            .unwrap();
+
+        // 旧配信者のタグは捨て、新しい配信者のタグは旧配信者のタイムスタンプの続きで届く
+        send_frame(&mut old, 80, false);
+        send_frame(&mut new, 0, true);
+        // 旧配信者のdropでは配信は終わらない
+        drop(old);
+        send_frame(&mut new, 40, false);
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(describe(&subscriber.recv().await.unwrap()));
+        }
+        assert_eq!(
+            received,
+            vec![
+                (TagKind::Video, 40, false),
+                (TagKind::Audio, 40, false),
+                (TagKind::Video, 80, false),
+            ]
+        );
+        assert!(hub.subscribe("live").is_some());
+        assert!(matches!(
+            events.try_recv(),
+            Err(broadcast::error::TryRecvError::Empty)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_duplicate_publish() {
+        let hub = Hub::new();
+        hub.set_publish_policy("live", PublishPolicy::Reject);
+        let _publisher = hub.publish("live").unwrap();
+        assert!(matches!(
+            hub.publish("live"),
+            Err(HubError::AlreadyPublishing(stream)) if stream == "live"
+        ));
+    }
+}
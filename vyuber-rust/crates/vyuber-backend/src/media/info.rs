@@ -0,0 +1,157 @@
+//! onMetaData とシーケンスヘッダから配信情報を読み取る
+
+use vyuber_shared::stream::StreamInfo;
+
+use super::aac::AacConfig;
+use super::opus;
+use super::tag::MediaTag;
+use super::video::VideoConfig;
+use crate::rtmp::amf::{amf0, AmfValue};
+
+/// キャッシュ済みのメタデータとシーケンスヘッダから `StreamInfo` を組み立てる
+///
+/// コーデックの詳細はエンコーダ申告値（onMetaData）よりシーケンスヘッダを優先する
+pub fn stream_info(
+    metadata: Option<&MediaTag>,
+    video_header: Option<&MediaTag>,
+    audio_header: Option<&MediaTag>,
+) -> StreamInfo {
+    let mut info = StreamInfo::default();
+    if let Some(tag) = metadata {
+        apply_metadata(&mut info, &tag.data);
+    }
+    if let Some(tag) = video_header {
+        apply_video_config(&mut info, tag);
+    }
+    if let Some(tag) = audio_header {
+        match tag.opus_packet() {
+            Some((_, head)) => apply_opus_head(&mut info, &head),
+            None => apply_aac_config(&mut info, &tag.data),
+        }
+    }
+    info
+}
+
+/// onMetaData の値を反映する
+fn apply_metadata(info: &mut StreamInfo, data: &[u8]) {
+    let Ok(values) = amf0::decode_all(data) else {
+        return;
+    };
+    let Some(props) = values.get(1) else {
+        return;
+    };
+    let number = |key: &str| props.get(key).and_then(AmfValue::as_f64);
+
+    info.width = number("width").map(|v| v as u32);
+    info.height = number("height").map(|v| v as u32);
+    info.framerate = number("framerate").or_else(|| number("fps"));
+    info.video_bitrate = number("videodatarate");
+    info.audio_bitrate = number("audiodatarate");
+    info.audio_sample_rate = number("audiosamplerate").map(|v| v as u32);
+    info.audio_channels = number("audiochannels").map(|v| v as u8).or_else(|| {
+        props
+            .get("stereo")
+            .and_then(AmfValue::as_bool)
+            .map(|stereo| if stereo { 2 } else { 1 })
+    });
+    info.video_codec = props.get("videocodecid").and_then(video_codec_name);
+    info.audio_codec = props.get("audiocodecid").and_then(audio_codec_name);
+    info.encoder = props
+        .get("encoder")
+        .and_then(AmfValue::as_str)
+        .map(str::to_string);
+}
+
+/// 映像シーケンスヘッダ（avcC / hvcC / av1C）からコーデック/プロファイル/レベルを読む
+fn apply_video_config(info: &mut StreamInfo, tag: &MediaTag) {
+    let Some(config) = tag
+        .video_packet()
+        .and_then(|packet| VideoConfig::parse(packet.codec, &packet.data))
+    else {
+        return;
+    };
+
+    // onMetaData に解像度がなければSPS/シーケンスヘッダOBUから求める
+    if let (None, None, Some((width, height))) = (info.width, info.height, config.dimensions()) {
+        info.width = Some(width);
+        info.height = Some(height);
+    }
+    info.video_codec = Some(config.codec_name().to_string());
+    info.video_profile = Some(config.profile_name());
+    info.video_level = Some(config.level_name());
+}
+
+/// AACシーケンスヘッダ（AudioSpecificConfig）からプロファイル/サンプルレート/チャンネル数を読む
+fn apply_aac_config(info: &mut StreamInfo, data: &[u8]) {
+    // AudioTagHeader(1) + AACPacketType(1) の後に AudioSpecificConfig が続く
+    let Some(config) = data.get(2..).and_then(AacConfig::parse) else {
+        return;
+    };
+
+    info.audio_codec = Some("AAC".to_string());
+    info.audio_profile = config.profile_name().map(str::to_string);
+    if let Some(rate) = config.sample_rate() {
+        info.audio_sample_rate = Some(rate);
+    }
+    if let Some(channels) = config.channels() {
+        info.audio_channels = Some(channels);
+    }
+}
+
+/// Opusシーケンスヘッダ（OpusHead）からサンプルレート/チャンネル数を読む
+fn apply_opus_head(info: &mut StreamInfo, head: &[u8]) {
+    info.audio_codec = Some("Opus".to_string());
+    info.audio_profile = None;
+    info.audio_sample_rate = Some(opus::SAMPLE_RATE);
+    if let Some(channels) = opus::channels(head) {
+        info.audio_channels = Some(channels);
+    }
+}
+
+/// onMetaData の videocodecid（FLVのCodecIDまたはFourCC文字列）
+fn video_codec_name(value: &AmfValue) -> Option<String> {
+    let name = match value {
+        AmfValue::Number(id) => match *id as u8 {
+            2 => "H.263",
+            3 => "Screen Video",
+            4 => "VP6",
+            5 => "VP6 Alpha",
+            6 => "Screen Video 2",
+            7 => "H.264",
+            12 => "HEVC",
+            _ => return Some(format!("codec {}", id)),
+        },
+        AmfValue::String(fourcc) => match fourcc.as_str() {
+            "avc1" => "H.264",
+            "hvc1" | "hev1" => "HEVC",
+            "av01" => "AV1",
+            "vp09" => "VP9",
+            other => other,
+        },
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+/// onMetaData の audiocodecid（FLVのSoundFormatまたはFourCC文字列）
+fn audio_codec_name(value: &AmfValue) -> Option<String> {
+    let name = match value {
+        AmfValue::Number(id) => match *id as u8 {
+            0 | 3 => "PCM",
+            1 => "ADPCM",
+            2 => "MP3",
+            7 | 8 => "G.711",
+            10 => "AAC",
+            11 => "Speex",
+            _ => return Some(format!("codec {}", id)),
+        },
+        AmfValue::String(fourcc) => match fourcc.as_str() {
+            "mp4a" => "AAC",
+            ".mp3" => "MP3",
+            "Opus" | "opus" => "Opus",
+            other => other,
+        },
+        _ => return None,
+    };
+    Some(name.to_string())
+}
@@ -0,0 +1,23 @@
+pub mod aac;
+pub mod av1;
+pub mod avc;
+pub mod bits;
+pub mod cache;
+pub mod cmaf;
+pub mod flv;
+pub mod fmp4;
+pub mod hevc;
+pub mod hls;
+pub mod hub;
+pub mod info;
+pub mod mp4;
+pub mod opus;
+pub mod recording;
+pub mod stats;
+pub mod tag;
+#[cfg(test)]
+pub mod testing;
+pub mod timestamp;
+pub mod ts;
+pub mod ts_demux;
+pub mod video;
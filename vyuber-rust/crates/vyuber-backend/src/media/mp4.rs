@@ -0,0 +1,492 @@
+//! プログレッシブMP4（ftyp + moov + mdat）マルチプレクサ
+//!
+//! サンプルのデータはmdatに順に並べ、moovのサンプルテーブル（stts / ctts / stss / stsz / stco）
+//! からその位置を引く。moovを先頭に置く（faststart）ので、全サンプルが揃ってから組み立てる。
+//! 録画のVOD HLS用に、書き出したMP4のサンプルテーブルを読み戻すこともできる
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::aac::AacConfig;
+use super::fmp4::{self, write_box, write_full_box, TrackHeader};
+use super::video::VideoConfig;
+
+/// サンプルテーブルの1サンプル（データ本体はmdatに置く）
+pub struct SampleEntry {
+    /// トラックのタイムスケール単位
+    pub duration: u32,
+    /// PTS - DTS（タイムスケール単位）
+    pub composition_offset: i32,
+    pub keyframe: bool,
+    pub size: u32,
+    /// mdatの中身の先頭からの位置
+    pub offset: u64,
+}
+
+/// トラックのコーデックとデコーダ設定
+pub enum TrackCodec {
+    /// `record` はデコーダ設定レコード（avcC / hvcC / av1C）そのもの
+    Video { record: Bytes, config: VideoConfig },
+    /// `asc` はAudioSpecificConfigそのもの
+    Aac { asc: Bytes, config: AacConfig },
+    /// `head` はOpusHeadそのもの
+    Opus { head: Bytes },
+}
+
+pub struct Track {
+    pub codec: TrackCodec,
+    pub samples: Vec<SampleEntry>,
+    /// 最初のサンプルまでの空き（ミリ秒）
+    pub delay: u64,
+}
+
+impl Track {
+    fn header(&self, id: u32) -> TrackHeader {
+        let duration = self.samples.iter().map(|s| s.duration as u64).sum();
+        let header = match &self.codec {
+            TrackCodec::Video { config, .. } => TrackHeader::video(id, config, duration),
+            TrackCodec::Aac { config, .. } => TrackHeader::audio(id, config, duration),
+            TrackCodec::Opus { .. } => TrackHeader::opus(id, duration),
+        };
+        TrackHeader {
+            delay: self.delay,
+            ..header
+        }
+    }
+
+    fn write_sample_entry(&self, out: &mut BytesMut) {
+        match &self.codec {
+            TrackCodec::Video { record, config } => {
+                fmp4::write_video_sample_entry(out, record, config)
+            }
+            TrackCodec::Aac { asc, config } => fmp4::write_aac_sample_entry(out, asc, config),
+            TrackCodec::Opus { head } => fmp4::write_opus_sample_entry(out, head),
+        }
+    }
+
+    /// stsd 以降のサンプルテーブル（1チャンク = 1サンプル）
+    fn write_sample_tables(&self, out: &mut BytesMut, base_offset: u64, co64: bool) {
+        let samples = &self.samples;
+
+        let durations = run_lengths(samples.iter().map(|s| s.duration));
+        write_full_box(out, b"stts", 0, 0, |out| {
+            out.put_u32(durations.len() as u32);
+            for (count, duration) in durations {
+                out.put_u32(count);
+                out.put_u32(duration);
+            }
+        });
+
+        if samples.iter().any(|s| s.composition_offset != 0) {
+            let offsets = run_lengths(samples.iter().map(|s| s.composition_offset));
+            // バージョン1（符号付きのオフセット）
+            write_full_box(out, b"ctts", 1, 0, |out| {
+                out.put_u32(offsets.len() as u32);
+                for (count, offset) in offsets {
+                    out.put_u32(count);
+                    out.put_i32(offset);
+                }
+            });
+        }
+
+        // 全サンプルがキーフレームなら stss は省略する
+        if samples.iter().any(|s| !s.keyframe) {
+            let keyframes: Vec<u32> = (1..)
+                .zip(samples)
+                .filter(|(_, s)| s.keyframe)
+                .map(|(number, _)| number)
+                .collect();
+            write_full_box(out, b"stss", 0, 0, |out| {
+                out.put_u32(keyframes.len() as u32);
+                keyframes.iter().for_each(|&number| out.put_u32(number));
+            });
+        }
+
+        write_full_box(out, b"stsc", 0, 0, |out| {
+            out.put_u32(1);
+            // first_chunk, samples_per_chunk, sample_description_index
+            out.put_u32(1);
+            out.put_u32(1);
+            out.put_u32(1);
+        });
+
+        write_full_box(out, b"stsz", 0, 0, |out| {
+            // sample_size = 0（サンプルごとに指定）
+            out.put_u32(0);
+            out.put_u32(samples.len() as u32);
+            samples.iter().for_each(|s| out.put_u32(s.size));
+        });
+
+        if co64 {
+            write_full_box(out, b"co64", 0, 0, |out| {
+                out.put_u32(samples.len() as u32);
+                samples
+                    .iter()
+                    .for_each(|s| out.put_u64(base_offset + s.offset));
+            });
+        } else {
+            write_full_box(out, b"stco", 0, 0, |out| {
+                out.put_u32(samples.len() as u32);
+                samples
+                    .iter()
+                    .for_each(|s| out.put_u32((base_offset + s.offset) as u32));
+            });
+        }
+    }
+}
+
+/// ftyp + moov + mdatのヘッダ（この後にmdatの中身 `mdat_size` バイトをそのまま続ける）
+pub fn header(tracks: &[Track], mdat_size: u64) -> Bytes {
+    let ftyp = ftyp();
+    let mdat_header_len = if mdat_size + 8 > u32::MAX as u64 {
+        16
+    } else {
+        8
+    };
+    // チャンクオフセットの幅でmoovの大きさが変わるので、co64で見積もってから決める
+    let file_size = ftyp.len() as u64 + moov(tracks, 0, true).len() as u64 + 16 + mdat_size;
+    let co64 = file_size > u32::MAX as u64;
+    let moov_len = moov(tracks, 0, co64).len() as u64;
+    let base_offset = ftyp.len() as u64 + moov_len + mdat_header_len;
+
+    let mut out = BytesMut::new();
+    out.put_slice(&ftyp);
+    out.put_slice(&moov(tracks, base_offset, co64));
+    if mdat_header_len == 16 {
+        // largesize
+        out.put_u32(1);
+        out.put_slice(b"mdat");
+        out.put_u64(mdat_size + 16);
+    } else {
+        out.put_u32((mdat_size + 8) as u32);
+        out.put_slice(b"mdat");
+    }
+    out.freeze()
+}
+
+fn ftyp() -> Bytes {
+    let mut out = BytesMut::new();
+    write_box(&mut out, b"ftyp", |out| {
+        out.put_slice(b"isom");
+        out.put_u32(0x200);
+        for brand in [b"isom", b"iso2", b"mp41"] {
+            out.put_slice(brand);
+        }
+    });
+    out.freeze()
+}
+
+fn moov(tracks: &[Track], base_offset: u64, co64: bool) -> Bytes {
+    let headers: Vec<TrackHeader> = (1..).zip(tracks).map(|(id, t)| t.header(id)).collect();
+    let duration = headers
+        .iter()
+        .map(|h| h.movie_duration())
+        .max()
+        .unwrap_or(0);
+
+    let mut out = BytesMut::new();
+    write_box(&mut out, b"moov", |out| {
+        fmp4::write_mvhd(out, duration, tracks.len() as u32 + 1);
+        for (track, header) in tracks.iter().zip(&headers) {
+            fmp4::write_trak(
+                out,
+                header,
+                |out| track.write_sample_entry(out),
+                |out| track.write_sample_tables(out, base_offset, co64),
+            );
+        }
+    });
+    out.freeze()
+}
+
+/// 書き出したMP4の長さとトラック
+pub struct Mp4Info {
+    /// 秒
+    pub duration: f64,
+    /// トラックごとのサンプルエントリの種類（avc1 / mp4a など）
+    pub sample_entries: Vec<[u8; 4]>,
+}
+
+/// moov ボックスの中身から長さとトラックのサンプルエントリを読む
+pub fn probe(moov: &[u8]) -> Option<Mp4Info> {
+    let mvhd = children(moov, b"mvhd").next()?;
+    let (timescale, duration) = match mvhd.first()? {
+        // version(1) + flags(3) + creation_time + modification_time + timescale + duration
+        1 => (be_u32(mvhd, 20)?, be_u64(mvhd, 24)?),
+        _ => (be_u32(mvhd, 12)?, be_u32(mvhd, 16)? as u64),
+    };
+    let sample_entries = children(moov, b"trak")
+        .filter_map(|trak| {
+            let stsd = [b"mdia", b"minf", b"stbl", b"stsd"]
+                .into_iter()
+                .try_fold(trak, |data, box_type| children(data, box_type).next())?;
+            // version/flags + entry_count + 最初のエントリのサイズの後に種類が続く
+            stsd.get(12..16)?.try_into().ok()
+        })
+        .collect();
+    Some(Mp4Info {
+        duration: duration as f64 / timescale.max(1) as f64,
+        sample_entries,
+    })
+}
+
+/// MP4から読み取ったトラックのコーデック（VOD HLSで扱えるのはH.264とAACのみ）
+pub enum Mp4Codec {
+    /// avcC の中身
+    Avc(Bytes),
+    /// AudioSpecificConfig
+    Aac(Bytes),
+    /// 録画しうるがVOD HLSで扱えないコーデック（HEVC/AV1/Opus）の名前
+    Unsupported(&'static str),
+}
+
+/// MP4から読み取ったトラック
+pub struct Mp4Track {
+    pub codec: Mp4Codec,
+    pub timescale: u32,
+    /// デコード順
+    pub samples: Vec<Mp4Sample>,
+}
+
+/// MP4から読み取ったサンプルの位置と時刻（トラックのタイムスケール単位）
+pub struct Mp4Sample {
+    pub dts: u64,
+    /// PTS - DTS
+    pub composition_offset: i32,
+    pub keyframe: bool,
+    /// ファイル先頭からの位置
+    pub offset: u64,
+    pub size: u32,
+}
+
+/// moov ボックスの中身から映像/音声トラックのサンプルテーブルを読む（知らないコーデックのトラックは飛ばす）
+pub fn read_tracks(moov: &[u8]) -> Vec<Mp4Track> {
+    let movie_timescale = children(moov, b"mvhd")
+        .next()
+        .and_then(|mvhd| match mvhd.first()? {
+            1 => be_u32(mvhd, 20),
+            _ => be_u32(mvhd, 12),
+        })
+        .unwrap_or(0);
+    children(moov, b"trak")
+        .filter_map(|trak| read_track(trak, movie_timescale))
+        .collect()
+}
+
+/// edts の先頭にある空のエディットの長さ（mvhd のタイムスケール単位）
+fn read_delay(trak: &[u8]) -> Option<u64> {
+    let elst = children(children(trak, b"edts").next()?, b"elst").next()?;
+    let (duration, media_time) = match elst.first()? {
+        1 => (be_u64(elst, 8)?, be_u64(elst, 16)? as i64),
+        _ => (be_u32(elst, 8)? as u64, be_u32(elst, 12)? as i32 as i64),
+    };
+    (be_u32(elst, 4)? > 0 && media_time == -1).then_some(duration)
+}
+
+fn read_track(trak: &[u8], movie_timescale: u32) -> Option<Mp4Track> {
+    let mdia = children(trak, b"mdia").next()?;
+    let mdhd = children(mdia, b"mdhd").next()?;
+    let timescale = match mdhd.first()? {
+        1 => be_u32(mdhd, 20)?,
+        _ => be_u32(mdhd, 12)?,
+    };
+    // 空のエディットの分だけサンプルの時刻を後ろにずらす
+    let delay = match read_delay(trak) {
+        Some(delay) if movie_timescale > 0 => delay * timescale as u64 / movie_timescale as u64,
+        _ => 0,
+    };
+    let stbl = [b"minf", b"stbl"]
+        .into_iter()
+        .try_fold(mdia, |data, box_type| children(data, box_type).next())?;
+    let codec = read_codec(children(stbl, b"stsd").next()?)?;
+    let table = |box_type| children(stbl, box_type).next();
+
+    // stsz（sample_size が0でなければ全サンプルが同じ大きさ）
+    let stsz = table(b"stsz")?;
+    let (sample_size, count) = (be_u32(stsz, 4)?, be_u32(stsz, 8)? as usize);
+    let sizes = if sample_size == 0 {
+        entries(stsz, 8, 1).map(|entry| entry[0]).collect()
+    } else {
+        vec![sample_size; count]
+    };
+
+    let mut timestamps = Vec::with_capacity(sizes.len());
+    let mut dts = delay;
+    for entry in entries(table(b"stts")?, 4, 2) {
+        for _ in 0..entry[0].min((sizes.len() - timestamps.len()) as u32) {
+            timestamps.push(dts);
+            dts += entry[1] as u64;
+        }
+    }
+
+    // ctts がなければPTS = DTS
+    let mut composition_offsets = Vec::with_capacity(sizes.len());
+    for entry in table(b"ctts")
+        .into_iter()
+        .flat_map(|ctts| entries(ctts, 4, 2))
+    {
+        for _ in 0..entry[0].min((sizes.len() - composition_offsets.len()) as u32) {
+            composition_offsets.push(entry[1] as i32);
+        }
+    }
+
+    // stss がなければ全サンプルがキーフレーム
+    let keyframes: Option<Vec<u32>> =
+        table(b"stss").map(|stss| entries(stss, 4, 1).map(|entry| entry[0]).collect());
+
+    // stsc の (最初のチャンク番号, チャンクあたりのサンプル数) でチャンクの位置をサンプルに分ける
+    let chunks: Vec<(u32, u32)> = entries(table(b"stsc")?, 4, 3)
+        .map(|entry| (entry[0], entry[1]))
+        .collect();
+    let chunk_offsets: Vec<u64> = match table(b"co64") {
+        Some(co64) => entries(co64, 4, 2)
+            .map(|entry| (entry[0] as u64) << 32 | entry[1] as u64)
+            .collect(),
+        None => entries(table(b"stco")?, 4, 1)
+            .map(|entry| entry[0] as u64)
+            .collect(),
+    };
+    let mut offsets = Vec::with_capacity(sizes.len());
+    for (chunk, &chunk_offset) in (1..).zip(&chunk_offsets) {
+        let samples_per_chunk = chunks
+            .iter()
+            .rev()
+            .find(|&&(first_chunk, _)| first_chunk <= chunk)
+            .map_or(0, |&(_, samples)| samples);
+        let mut offset = chunk_offset;
+        for _ in 0..samples_per_chunk {
+            let Some(&size) = sizes.get(offsets.len()) else {
+                break;
+            };
+            offsets.push(offset);
+            offset += size as u64;
+        }
+    }
+
+    let samples = (1..)
+        .zip(sizes.iter().zip(&timestamps).zip(&offsets))
+        .map(|(number, ((&size, &dts), &offset))| Mp4Sample {
+            dts,
+            composition_offset: composition_offsets
+                .get(number as usize - 1)
+                .copied()
+                .unwrap_or(0),
+            keyframe: keyframes
+                .as_ref()
+                .is_none_or(|keyframes| keyframes.binary_search(&number).is_ok()),
+            offset,
+            size,
+        })
+        .collect();
+    Some(Mp4Track {
+        codec,
+        timescale,
+        samples,
+    })
+}
+
+/// stsd の最初のサンプルエントリのデコーダ設定
+fn read_codec(stsd: &[u8]) -> Option<Mp4Codec> {
+    // version/flags + entry_count の後に最初のエントリ
+    let entry = stsd.get(8..)?;
+    let content = entry.get(8..be_u32(entry, 0)? as usize)?;
+    match entry.get(4..8)? {
+        // VisualSampleEntry（78バイト）の後に avcC
+        b"avc1" | b"avc3" => {
+            let avcc = children(content.get(78..)?, b"avcC").next()?;
+            Some(Mp4Codec::Avc(Bytes::copy_from_slice(avcc)))
+        }
+        // AudioSampleEntry（28バイト）の後に esds
+        b"mp4a" => {
+            let esds = children(content.get(28..)?, b"esds").next()?;
+            Some(Mp4Codec::Aac(Bytes::copy_from_slice(esds_asc(esds)?)))
+        }
+        b"hvc1" | b"hev1" => Some(Mp4Codec::Unsupported("HEVC video")),
+        b"av01" => Some(Mp4Codec::Unsupported("AV1 video")),
+        b"Opus" => Some(Mp4Codec::Unsupported("Opus audio")),
+        _ => None,
+    }
+}
+
+/// esds から DecoderSpecificInfo（AudioSpecificConfig）を取り出す
+fn esds_asc(esds: &[u8]) -> Option<&[u8]> {
+    let mut data = esds.get(4..)?;
+    loop {
+        let tag = *data.first()?;
+        // 長さは1バイトに7ビットずつ（最上位ビットが続きを表す、最長4バイト）
+        let mut len = 0usize;
+        let mut header_len = 1;
+        for byte in data.iter().skip(1).take(4) {
+            len = len << 7 | (byte & 0x7f) as usize;
+            header_len += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let body = data.get(header_len..header_len + len)?;
+        data = match tag {
+            // ES_Descriptor: ES_ID + フラグ（に応じたフィールド）の後に DecoderConfigDescriptor
+            0x03 => {
+                let flags = *body.get(2)?;
+                let mut skip = 3;
+                if flags & 0x80 != 0 {
+                    skip += 2;
+                }
+                if flags & 0x40 != 0 {
+                    skip += 1 + *body.get(skip)? as usize;
+                }
+                if flags & 0x20 != 0 {
+                    skip += 2;
+                }
+                body.get(skip..)?
+            }
+            // DecoderConfigDescriptor: 13バイトの後に DecoderSpecificInfo
+            0x04 => body.get(13..)?,
+            0x05 => return Some(body),
+            _ => return None,
+        };
+    }
+}
+
+/// フルボックスの `skip` バイト目にあるエントリ数と、それに続く `width` 個ずつのu32のエントリ
+fn entries(data: &[u8], skip: usize, width: usize) -> impl Iterator<Item = Vec<u32>> + '_ {
+    let count = be_u32(data, skip).unwrap_or(0) as usize;
+    (0..count).map_while(move |index| {
+        (0..width)
+            .map(|field| be_u32(data, skip + 4 + (index * width + field) * 4))
+            .collect()
+    })
+}
+
+/// `data` に並んだボックスのうち `box_type` のものの中身
+fn children<'a>(data: &'a [u8], box_type: &'a [u8; 4]) -> impl Iterator<Item = &'a [u8]> {
+    let mut rest = data;
+    std::iter::from_fn(move || loop {
+        let size = be_u32(rest, 0)? as usize;
+        let found = rest.get(4..8)? == box_type;
+        let content = rest.get(8..size)?;
+        rest = &rest[size..];
+        if found {
+            return Some(content);
+        }
+    })
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn be_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+/// 連続する同じ値をまとめる（(個数, 値) の列）
+fn run_lengths<T: PartialEq>(values: impl Iterator<Item = T>) -> Vec<(u32, T)> {
+    let mut runs: Vec<(u32, T)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((count, last)) if *last == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    runs
+}
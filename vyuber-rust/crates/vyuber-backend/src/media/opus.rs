@@ -0,0 +1,28 @@
+//! Opus のID Header（OpusHead）
+
+/// OpusHead の長さ（チャンネルマッピングファミリー0）
+const OPUS_HEAD_LEN: usize = 19;
+
+/// デコーダの出力は常に48kHz
+pub const SAMPLE_RATE: u32 = 48000;
+
+/// チャンネルマッピングファミリー0（モノラル/ステレオ）の OpusHead を作る
+pub fn opus_head(channels: u8) -> [u8; OPUS_HEAD_LEN] {
+    let mut head = [0u8; OPUS_HEAD_LEN];
+    head[..8].copy_from_slice(b"OpusHead");
+    // version
+    head[8] = 1;
+    head[9] = channels;
+    // pre-skip（libopusの既定値）
+    head[10..12].copy_from_slice(&312u16.to_le_bytes());
+    head[12..16].copy_from_slice(&SAMPLE_RATE.to_le_bytes());
+    head
+}
+
+/// OpusHead のチャンネル数（不正なら `None`）
+pub fn channels(head: &[u8]) -> Option<u8> {
+    if head.len() < OPUS_HEAD_LEN || !head.starts_with(b"OpusHead") {
+        return None;
+    }
+    Some(head[9])
+}
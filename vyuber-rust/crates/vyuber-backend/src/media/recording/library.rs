@@ -0,0 +1,445 @@
+//! 録画ファイルのライブラリ（一覧・情報・削除・VOD再生）
+//!
+//! 録画は `<RECORDING_DIR>/<ストリームキー>/<開始日時>.{flv,mp4}` で、
+//! 同じ開始日時のFLVとMP4を1本の録画として `<ストリームキー>_<開始日時>` のIDで扱う
+
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use std::collections::BTreeSet;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+use tracing::warn;
+use vyuber_shared::stream::RecordingInfo;
+
+use super::{vod, RecordingStore};
+use crate::media::flv::FlvReader;
+use crate::media::info;
+use crate::media::mp4;
+use crate::media::tag::TagKind;
+use crate::rtmp::amf::{amf0, AmfValue};
+
+#[derive(Debug, Error)]
+pub enum RecordingError {
+    #[error("recording not found")]
+    NotFound,
+
+    #[error("recording is still being written or remuxed")]
+    InProgress,
+
+    #[error("recording uses {0}, which VOD HLS does not support")]
+    UnsupportedCodec(&'static str),
+
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// 録画1本分のファイル
+struct RecordingFiles {
+    stream_key: String,
+    /// 開始日時（ファイル名の拡張子を除いた部分）
+    name: String,
+    flv: Option<PathBuf>,
+    mp4: Option<PathBuf>,
+}
+
+/// ファイルの先頭から読み取った長さとコーデック
+struct Probe {
+    duration: f64,
+    video_codec: Option<String>,
+    audio_codec: Option<String>,
+}
+
+impl RecordingStore {
+    /// 録画の一覧（新しい順、録画中のものは含めない）
+    pub async fn list(&self) -> Result<Vec<RecordingInfo>, RecordingError> {
+        let mut recordings = Vec::new();
+        let mut streams = match tokio::fs::read_dir(&self.config.dir).await {
+            Ok(streams) => streams,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(recordings),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(stream) = streams.next_entry().await? {
+            let Some(stream_key) = stream.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if !stream.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let mut names = BTreeSet::new();
+            let mut entries = tokio::fs::read_dir(stream.path()).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if !matches!(
+                    path.extension().and_then(|ext| ext.to_str()),
+                    Some("flv" | "mp4")
+                ) {
+                    continue;
+                }
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    names.insert(name.to_string());
+                }
+            }
+
+            for name in names {
+                let Ok(files) = self.files(&stream_key, &name).await else {
+                    continue;
+                };
+                match recording_info(&files).await {
+                    Ok(info) => recordings.push(info),
+                    Err(e) => warn!("[Recording] Failed to read {}: {}", files.id(), e),
+                }
+            }
+        }
+        recordings.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        Ok(recordings)
+    }
+
+    pub async fn recording(&self, id: &str) -> Result<RecordingInfo, RecordingError> {
+        recording_info(&self.find(id).await?).await
+    }
+
+    /// 録画を削除する（FLVとMP4の両方）
+    pub async fn delete(&self, id: &str) -> Result<(), RecordingError> {
+        let files = self.find(id).await?;
+        if files
+            .flv
+            .as_ref()
+            .is_some_and(|flv| self.remux.is_pending(flv))
+        {
+            return Err(RecordingError::InProgress);
+        }
+        for path in [&files.flv, &files.mp4].into_iter().flatten() {
+            vod::forget(path);
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+
+    /// 再生用のファイル（MP4に変換済みならMP4、なければFLV）
+    pub async fn playback_file(&self, id: &str) -> Result<PathBuf, RecordingError> {
+        let files = self.find(id).await?;
+        files.mp4.or(files.flv).ok_or(RecordingError::NotFound)
+    }
+
+    /// VOD HLSのプレイリスト
+    pub async fn hls_playlist(&self, id: &str) -> Result<String, RecordingError> {
+        let path = self.vod_file(id).await?;
+        Ok(vod::index(&path).await?.playlist())
+    }
+
+    /// VOD HLSのセグメント
+    pub async fn hls_segment(&self, id: &str, sequence: usize) -> Result<Bytes, RecordingError> {
+        let path = self.vod_file(id).await?;
+        vod::index(&path)
+            .await?
+            .segment(&path, sequence)
+            .await?
+            .ok_or(RecordingError::NotFound)
+    }
+
+    /// VOD HLSの元にするファイル（FLVが残っていればFLV、なければMP4）
+    async fn vod_file(&self, id: &str) -> Result<PathBuf, RecordingError> {
+        let files = self.find(id).await?;
+        files.flv.or(files.mp4).ok_or(RecordingError::NotFound)
+    }
+
+    async fn find(&self, id: &str) -> Result<RecordingFiles, RecordingError> {
+        let (stream_key, name) = parse_id(id).ok_or(RecordingError::NotFound)?;
+        self.files(stream_key, name).await
+    }
+
+    async fn files(&self, stream_key: &str, name: &str) -> Result<RecordingFiles, RecordingError> {
+        let dir = self.config.dir.join(stream_key);
+        let flv = existing(dir.join(format!("{}.flv", name))).await;
+        let mp4 = existing(dir.join(format!("{}.mp4", name))).await;
+        if flv.is_none() && mp4.is_none() {
+            return Err(RecordingError::NotFound);
+        }
+        if let Some(flv) = &flv {
+            if self.active.read().unwrap().values().any(|path| path == flv) {
+                return Err(RecordingError::InProgress);
+            }
+        }
+        Ok(RecordingFiles {
+            stream_key: stream_key.to_string(),
+            name: name.to_string(),
+            flv,
+            mp4,
+        })
+    }
+}
+
+impl RecordingFiles {
+    fn id(&self) -> String {
+        format!("{}_{}", self.stream_key, self.name)
+    }
+}
+
+/// IDを (ストリームキー, 開始日時) に分ける（パスとして安全な文字だけを受け付ける）
+fn parse_id(id: &str) -> Option<(&str, &str)> {
+    let (stream_key, name) = id.split_once('_')?;
+    let valid = |part: &str| {
+        !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    (valid(stream_key) && valid(name)).then_some((stream_key, name))
+}
+
+async fn existing(path: PathBuf) -> Option<PathBuf> {
+    tokio::fs::try_exists(&path).await.ok()?.then_some(path)
+}
+
+async fn recording_info(files: &RecordingFiles) -> Result<RecordingInfo, RecordingError> {
+    let probe = match (&files.flv, &files.mp4) {
+        (Some(flv), _) => probe_flv(flv).await?,
+        (None, Some(mp4)) => probe_mp4(mp4).await?,
+        (None, None) => return Err(RecordingError::NotFound),
+    };
+
+    let mut size = 0;
+    let mut modified = None;
+    for path in [&files.flv, &files.mp4].into_iter().flatten() {
+        let metadata = tokio::fs::metadata(path).await?;
+        size += metadata.len();
+        modified = modified.or(metadata.modified().ok());
+    }
+
+    let duration = chrono::Duration::milliseconds((probe.duration * 1000.0) as i64);
+    // ファイル名の開始日時が読めなければ更新日時から逆算する
+    let started_at =
+        NaiveDateTime::parse_from_str(files.name.get(..15).unwrap_or_default(), "%Y%m%d-%H%M%S")
+            .map(|started_at| started_at.and_utc())
+            .unwrap_or_else(|_| modified.map(DateTime::<Utc>::from).unwrap_or_default() - duration);
+
+    Ok(RecordingInfo {
+        id: files.id(),
+        stream_key: files.stream_key.clone(),
+        started_at: started_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        ended_at: (started_at + duration).to_rfc3339_opts(SecondsFormat::Secs, true),
+        duration_secs: probe.duration,
+        size,
+        video_codec: probe.video_codec,
+        audio_codec: probe.audio_codec,
+        has_mp4: files.mp4.is_some(),
+        has_flv: files.flv.is_some(),
+    })
+}
+
+/// FLVの先頭（onMetaData とシーケンスヘッダ）から長さとコーデックを読む
+async fn probe_flv(path: &Path) -> io::Result<Probe> {
+    let mut reader = FlvReader::new(BufReader::new(File::open(path).await?)).await?;
+    let (mut metadata, mut video, mut audio) = (None, None, None);
+    while let Some(tag) = reader.next_tag().await? {
+        if tag.is_metadata() {
+            metadata = Some(tag);
+        } else if tag.is_sequence_header() {
+            match tag.kind {
+                TagKind::Video => video = Some(tag),
+                _ => audio = Some(tag),
+            }
+        } else {
+            break;
+        }
+    }
+
+    let info = info::stream_info(metadata.as_ref(), video.as_ref(), audio.as_ref());
+    // 閉じるときに書き換えた onMetaData の duration
+    let duration = metadata
+        .and_then(|tag| amf0::decode_all(&tag.data).ok())
+        .and_then(|values| values.get(1)?.get("duration").and_then(AmfValue::as_f64))
+        .unwrap_or(0.0);
+    Ok(Probe {
+        duration,
+        video_codec: info.video_codec,
+        audio_codec: info.audio_codec,
+    })
+}
+
+/// MP4のmoovから長さとコーデックを読む
+async fn probe_mp4(path: &Path) -> io::Result<Probe> {
+    let moov = read_moov(path).await?;
+    let info = mp4::probe(&moov)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid moov box"))?;
+
+    let mut probe = Probe {
+        duration: info.duration,
+        video_codec: None,
+        audio_codec: None,
+    };
+    for entry in &info.sample_entries {
+        match entry {
+            b"avc1" | b"avc3" => probe.video_codec = Some("H.264".to_string()),
+            b"hvc1" | b"hev1" => probe.video_codec = Some("HEVC".to_string()),
+            b"av01" => probe.video_codec = Some("AV1".to_string()),
+            b"mp4a" => probe.audio_codec = Some("AAC".to_string()),
+            b"Opus" => probe.audio_codec = Some("Opus".to_string()),
+            _ => {}
+        }
+    }
+    Ok(probe)
+}
+
+/// トップレベルのボックスを順にたどり、moovの中身を読む
+pub(super) async fn read_moov(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path).await?;
+    loop {
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header).await?;
+        let (size, header_len) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            // largesize
+            1 => (file.read_u64().await?, 16),
+            size => (size as u64, 8),
+        };
+        if size < header_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid box size",
+            ));
+        }
+        if &header[4..] == b"moov" {
+            let mut moov = vec![0u8; (size - header_len) as usize];
+            file.read_exact(&mut moov).await?;
+            return Ok(moov);
+        }
+        file.seek(SeekFrom::Current((size - header_len) as i64))
+            .await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RecordingConfig;
+    use crate::media::recording::remux;
+    use crate::media::tag::MediaTag;
+    use crate::media::{flv, opus, testing};
+    use bytes::BytesMut;
+
+    #[test]
+    fn rejects_ids_that_are_not_safe_paths() {
+        assert_eq!(
+            parse_id("live-abc_20260101-120000"),
+            Some(("live-abc", "20260101-120000"))
+        );
+        for id in [
+            "",
+            "no-separator",
+            "_20260101-120000",
+            "key_",
+            "../etc_passwd",
+            "key_../../secret",
+            "key_2026/01",
+            "key_name.flv",
+            "key_a_b",
+            "ke y_name",
+        ] {
+            assert_eq!(parse_id(id), None, "{:?}", id);
+        }
+    }
+
+    /// 7秒分（2秒ごとにキーフレーム）のH.264とAACの録画
+    fn recording() -> BytesMut {
+        let mut tags = vec![
+            testing::avc_sequence_header(0),
+            testing::aac_sequence_header(0),
+        ];
+        let mut audio = (0..).map(|n: u32| n * 1024 / 48).peekable();
+        for frame in 0..175u32 {
+            let timestamp = frame * 40;
+            while let Some(audio_timestamp) = audio.next_if(|&ts| ts < timestamp) {
+                tags.push(testing::aac_frame(audio_timestamp, &[0x21; 12]));
+            }
+            let payload = vec![frame as u8; 30];
+            tags.push(testing::avc_frame(timestamp, frame % 50 == 0, 0, &payload));
+        }
+        let mut file = BytesMut::from(&flv::header(true, true)[..]);
+        tags.iter().for_each(|tag| flv::write_tag(tag, &mut file));
+        file
+    }
+
+    #[tokio::test]
+    async fn serves_vod_hls_from_mp4_after_flv_is_deleted() {
+        let dir = std::env::temp_dir().join(format!("vyuber-library-{}", uuid::Uuid::new_v4()));
+        let store = RecordingStore::new(RecordingConfig {
+            dir: dir.clone(),
+            ..RecordingConfig::default()
+        });
+        let flv = dir.join("test").join("20260101-120000.flv");
+        tokio::fs::create_dir_all(flv.parent().unwrap())
+            .await
+            .unwrap();
+        tokio::fs::write(&flv, recording()).await.unwrap();
+        assert!(!remux::remux(&flv).await.unwrap().dropped_tracks);
+
+        let id = "test_20260101-120000";
+        let playlist = store.hls_playlist(id).await.unwrap();
+        let mut segments = Vec::new();
+        while let Ok(segment) = store.hls_segment(id, segments.len()).await {
+            segments.push(segment);
+        }
+        assert_eq!(segments.len(), 4, "{}", playlist);
+
+        // FLVを消してもMP4から同じプレイリストとセグメントを作る
+        vod::forget(&flv);
+        tokio::fs::remove_file(&flv).await.unwrap();
+        assert!(!store.recording(id).await.unwrap().has_flv);
+        assert_eq!(store.hls_playlist(id).await.unwrap(), playlist);
+        for (sequence, segment) in segments.iter().enumerate() {
+            assert_eq!(&store.hls_segment(id, sequence).await.unwrap(), segment);
+        }
+        assert!(matches!(
+            store.hls_segment(id, segments.len()).await,
+            Err(RecordingError::NotFound)
+        ));
+
+        store.delete(id).await.unwrap();
+        assert!(matches!(
+            store.hls_playlist(id).await,
+            Err(RecordingError::NotFound)
+        ));
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_vod_hls_of_codecs_mpeg_ts_does_not_carry() {
+        let dir = std::env::temp_dir().join(format!("vyuber-library-{}", uuid::Uuid::new_v4()));
+        let store = RecordingStore::new(RecordingConfig {
+            dir: dir.clone(),
+            ..RecordingConfig::default()
+        });
+        let flv = dir.join("test").join("20260101-120000.flv");
+        tokio::fs::create_dir_all(flv.parent().unwrap())
+            .await
+            .unwrap();
+        let mut tags = vec![
+            testing::avc_sequence_header(0),
+            MediaTag::opus(0, true, &opus::opus_head(2)),
+        ];
+        for frame in 0..50u32 {
+            tags.push(MediaTag::opus(frame * 20, false, &[0xf0; 8]));
+            tags.push(testing::avc_frame(frame * 20, frame == 0, 0, &[0x10; 20]));
+        }
+        let mut file = BytesMut::from(&flv::header(true, true)[..]);
+        tags.iter().for_each(|tag| flv::write_tag(tag, &mut file));
+        tokio::fs::write(&flv, file).await.unwrap();
+
+        // 音声を黙って落とさずにエラーにする（FLVからもMP4からも）
+        let id = "test_20260101-120000";
+        assert!(matches!(
+            store.hls_playlist(id).await,
+            Err(RecordingError::UnsupportedCodec("Opus audio"))
+        ));
+        assert!(!remux::remux(&flv).await.unwrap().dropped_tracks);
+        vod::forget(&flv);
+        tokio::fs::remove_file(&flv).await.unwrap();
+        assert!(matches!(
+            store.hls_segment(id, 0).await,
+            Err(RecordingError::UnsupportedCodec("Opus audio"))
+        ));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,375 @@
+//! 配信の録画
+//!
+//! 配信が始まるとハブを購読し、録画が有効なストリームキーなら
+//! `<RECORDING_DIR>/<ストリームキー>/<開始日時>.flv` に書き出す。
+//! 最大サイズ/最大長を超えたら次のキーフレームで新しいファイルに切り替える。
+//! 閉じたファイルはバックグラウンドでMP4に変換する。
+//! 過去の録画の一覧・再生は [`library`] を参照
+
+pub mod library;
+pub mod remux;
+mod vod;
+mod writer;
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use tracing::{error, info};
+use vyuber_shared::stream::{RecordingSetting, RemuxJob};
+
+use self::remux::RemuxQueue;
+use self::writer::{FlvWriter, Headers};
+use super::hub::{Subscriber, HUB};
+use super::tag::{MediaTag, TagKind};
+use crate::config::{self, RecordingConfig};
+
+/// ストリームキーごとの録画設定と録画中のファイル
+pub static RECORDINGS: Lazy<RecordingStore> =
+    Lazy::new(|| RecordingStore::new(config::get().recording.clone()));
+
+pub struct RecordingStore {
+    config: RecordingConfig,
+    /// ストリームキーごとの有効/無効（未設定なら `config.enabled`）
+    enabled: RwLock<HashMap<String, bool>>,
+    /// 録画中のファイル
+    active: RwLock<HashMap<String, PathBuf>>,
+    remux: RemuxQueue,
+}
+
+impl RecordingStore {
+    pub fn new(config: RecordingConfig) -> Self {
+        Self {
+            config,
+            enabled: RwLock::new(HashMap::new()),
+            active: RwLock::new(HashMap::new()),
+            remux: RemuxQueue::default(),
+        }
+    }
+
+    /// 録画の設定と録画中のファイル
+    pub fn setting(&self, stream: &str) -> RecordingSetting {
+        RecordingSetting {
+            enabled: self.is_enabled(stream),
+            current_file: self
+                .active
+                .read()
+                .unwrap()
+                .get(stream)
+                .map(|path| path.display().to_string()),
+        }
+    }
+
+    /// 録画の有効/無効を設定する（配信中なら次のキーフレームから反映される）
+    pub fn set_enabled(&self, stream: &str, enabled: bool) {
+        self.enabled
+            .write()
+            .unwrap()
+            .insert(stream.to_string(), enabled);
+    }
+
+    /// MP4変換ジョブの一覧（古い順）
+    pub fn remux_jobs(&self) -> Vec<RemuxJob> {
+        self.remux.jobs()
+    }
+
+    fn is_enabled(&self, stream: &str) -> bool {
+        self.enabled
+            .read()
+            .unwrap()
+            .get(stream)
+            .copied()
+            .unwrap_or(self.config.enabled)
+    }
+
+    /// `timestamp` のキーフレームで今のファイルを閉じて次のファイルに切り替えるか
+    fn should_rotate(&self, writer: &FlvWriter, timestamp: u32) -> bool {
+        self.config
+            .max_size()
+            .is_some_and(|max| writer.size() >= max)
+            || self
+                .config
+                .max_duration()
+                .is_some_and(|max| writer.elapsed(timestamp) >= max)
+    }
+
+    /// 新しい録画ファイルを開く
+    async fn open(
+        &self,
+        stream: &str,
+        headers: &Headers,
+        base_timestamp: u32,
+    ) -> std::io::Result<FlvWriter> {
+        let dir = self.config.dir.join(stream);
+        tokio::fs::create_dir_all(&dir).await?;
+
+        // 同じ秒に分割した場合は連番を付ける
+        let started = Utc::now().format("%Y%m%d-%H%M%S").to_string();
+        let mut path = dir.join(format!("{}.flv", started));
+        let mut suffix = 1;
+        while tokio::fs::try_exists(&path).await? {
+            suffix += 1;
+            path = dir.join(format!("{}-{}.flv", started, suffix));
+        }
+
+        let writer = FlvWriter::create(&path, headers, base_timestamp).await?;
+        self.active
+            .write()
+            .unwrap()
+            .insert(stream.to_string(), path.clone());
+        info!("[Recording] Recording {} to {}", stream, path.display());
+        Ok(writer)
+    }
+
+    /// 録画ファイルを閉じる
+    async fn close(&self, stream: &str, writer: FlvWriter) {
+        let size = writer.size();
+        let duration = writer.duration();
+        self.active.write().unwrap().remove(stream);
+        match writer.finish().await {
+            Ok(path) => {
+                info!(
+                    "[Recording] Finished {} ({:.1}s, {} bytes)",
+                    path.display(),
+                    duration.as_secs_f64(),
+                    size
+                );
+                if self.config.remux_mp4 {
+                    self.remux.push(stream, &path);
+                }
+            }
+            Err(e) => error!(
+                "[Recording] Failed to finalize recording of {}: {}",
+                stream, e
+            ),
+        }
+    }
+
+    /// 購読したタグを配信終了まで録画する
+    async fn record(&self, stream: &str, mut subscriber: Subscriber) {
+        let mut headers = Headers::default();
+        let mut writer: Option<FlvWriter> = None;
+        while let Some(tag) = subscriber.recv().await {
+            if tag.is_metadata() {
+                // ファイルの先頭に書くので、途中で届いたメタデータは次のファイルから反映する
+                headers.metadata = Some(tag);
+                continue;
+            }
+            if tag.is_sequence_header() {
+                match tag.kind {
+                    TagKind::Video => headers.video = Some(tag.clone()),
+                    _ => headers.audio = Some(tag.clone()),
+                }
+            } else if is_boundary(&tag, &headers) {
+                // ファイルの切り替えはキーフレームでだけ行う
+                let enabled = self.is_enabled(stream);
+                if let Some(current) =
+                    writer.take_if(|w| !enabled || self.should_rotate(w, tag.timestamp))
+                {
+                    self.close(stream, current).await;
+                }
+                if enabled && writer.is_none() {
+                    match self.open(stream, &headers, tag.timestamp).await {
+                        Ok(opened) => writer = Some(opened),
+                        Err(e) => {
+                            error!("[Recording] Failed to start recording {}: {}", stream, e);
+                            return;
+                        }
+                    }
+                }
+            }
+
+            let Some(current) = &mut writer else {
+                continue;
+            };
+            if let Err(e) = current.write(&tag).await {
+                error!(
+                    "[Recording] Failed to write {}: {}",
+                    current.path().display(),
+                    e
+                );
+                self.close(stream, writer.take().unwrap()).await;
+                return;
+            }
+        }
+
+        if let Some(current) = writer {
+            self.close(stream, current).await;
+        }
+    }
+}
+
+/// 配信開始イベントを監視し、ストリームごとに録画タスクを起動する（MP4変換のワーカーも起動する）
+pub fn start_recorder() {
+    HUB.spawn_on_publish("Recording", record_stream);
+    tokio::spawn(RECORDINGS.remux.run(RECORDINGS.config.keep_flv));
+}
+
+/// 1ストリーム分を配信終了まで録画する
+async fn record_stream(stream: String) {
+    let Some(subscriber) = HUB.subscribe(&stream) else {
+        return;
+    };
+    RECORDINGS.record(&stream, subscriber).await;
+}
+
+/// 録画を始めたりファイルを切り替えたりできるタグか（映像がなければ音声のどのフレームでもよい）
+fn is_boundary(tag: &MediaTag, headers: &Headers) -> bool {
+    match tag.kind {
+        TagKind::Video => tag.is_keyframe(),
+        TagKind::Audio => headers.video.is_none(),
+        TagKind::Script => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::flv::FlvReader;
+    use crate::media::hub::Hub;
+    use crate::media::testing;
+    use crate::rtmp::amf::{amf0, AmfValue};
+
+    /// 録画した1ファイル
+    struct Recorded {
+        /// onMetaData の duration と filesize
+        duration: f64,
+        filesize: f64,
+        width: Option<f64>,
+        len: u64,
+        /// onMetaData より後のタグ
+        tags: Vec<MediaTag>,
+    }
+
+    /// 100ms間隔の映像（`keyframe_interval` フレームごとにキーフレーム、ペイロードの値はフレーム番号）と同じ時刻の音声
+    fn frames(count: u32, keyframe_interval: u32, payload_len: usize) -> Vec<MediaTag> {
+        let mut tags = vec![
+            MediaTag::new(
+                TagKind::Script,
+                0,
+                amf0::encode_all(&[
+                    "onMetaData".into(),
+                    AmfValue::object([("width", 1280.0.into())]),
+                ]),
+            ),
+            testing::avc_sequence_header(0),
+            testing::aac_sequence_header(0),
+        ];
+        for frame in 0..count {
+            let payload = vec![frame as u8; payload_len];
+            tags.push(testing::avc_frame(
+                frame * 100,
+                frame % keyframe_interval == 0,
+                0,
+                &payload,
+            ));
+            tags.push(testing::aac_frame(frame * 100, &[0x20; 8]));
+        }
+        tags
+    }
+
+    /// `tags` を配信して `config` で録画し、ファイルを録画順に読み戻す
+    async fn record(config: RecordingConfig, tags: Vec<MediaTag>) -> Vec<Recorded> {
+        let dir = std::env::temp_dir().join(format!("vyuber-recording-{}", uuid::Uuid::new_v4()));
+        let store = RecordingStore::new(RecordingConfig {
+            dir: dir.clone(),
+            enabled: true,
+            remux_mp4: false,
+            ..config
+        });
+        let hub = Hub::new();
+        let mut publisher = hub.publish("test").unwrap();
+        let subscriber = hub.subscribe("test").unwrap();
+        for tag in tags {
+            publisher.send(tag);
+        }
+        drop(publisher);
+        store.record("test", subscriber).await;
+        assert!(store.setting("test").current_file.is_none());
+
+        let mut files = Vec::new();
+        let mut entries = tokio::fs::read_dir(dir.join("test")).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            let data = tokio::fs::read(entry.path()).await.unwrap();
+            let mut reader = FlvReader::new(&data[..]).await.unwrap();
+            let metadata = reader.next_tag().await.unwrap().unwrap();
+            assert!(metadata.is_metadata());
+            let values = amf0::decode_all(&metadata.data).unwrap();
+            let number = |key| values[1].get(key).and_then(AmfValue::as_f64);
+            let mut tags = Vec::new();
+            while let Some(tag) = reader.next_tag().await.unwrap() {
+                tags.push(tag);
+            }
+            files.push(Recorded {
+                duration: number("duration").unwrap(),
+                filesize: number("filesize").unwrap(),
+                width: number("width"),
+                len: data.len() as u64,
+                tags,
+            });
+        }
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+
+        // 同じ秒に分割したファイル名の順は録画順と限らないので、最初のフレームの番号で並べる
+        files.sort_by_key(|file| *file.tags[2].data.last().unwrap());
+        files
+    }
+
+    /// 各ファイルの最初のフレームの番号（すべてキーフレーム）
+    fn first_frames(files: &[Recorded]) -> Vec<u8> {
+        files
+            .iter()
+            .map(|file| {
+                // シーケンスヘッダの後にキーフレームが続く
+                assert!(file.tags[0].is_sequence_header() && file.tags[0].kind == TagKind::Video);
+                assert!(file.tags[1].is_sequence_header() && file.tags[1].kind == TagKind::Audio);
+                assert!(file.tags[2].is_keyframe());
+                assert_eq!(file.tags[2].timestamp, 0);
+                *file.tags[2].data.last().unwrap()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn rotates_on_keyframes_after_max_duration() {
+        // 1.5秒ごとのキーフレームで、2秒を超えた最初のキーフレームで切り替える
+        let files = record(
+            RecordingConfig {
+                max_duration_secs: 2,
+                ..RecordingConfig::default()
+            },
+            frames(70, 15, 16),
+        )
+        .await;
+        assert_eq!(first_frames(&files), vec![0, 30, 60]);
+
+        // 閉じるときに onMetaData の duration と filesize を実際の値に書き換える
+        for (file, duration) in files.iter().zip([2.9, 2.9, 0.9]) {
+            assert_eq!(file.duration, duration);
+            assert_eq!(file.filesize, file.len as f64);
+            assert_eq!(file.width, Some(1280.0));
+            assert_eq!(
+                file.tags.last().unwrap().timestamp as f64 / 1000.0,
+                duration
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rotates_on_keyframes_after_max_size() {
+        // 1フレーム100KB、0.5秒ごとのキーフレーム。1MBを超えた後の最初のキーフレームで切り替える
+        let files = record(
+            RecordingConfig {
+                max_size_mb: 1,
+                ..RecordingConfig::default()
+            },
+            frames(25, 5, 100_000),
+        )
+        .await;
+        assert_eq!(first_frames(&files), vec![0, 15]);
+        assert!(files[0].len >= 1024 * 1024);
+        assert!(files[0].len - 5 * 100_000 < 1024 * 1024);
+        assert!(files.iter().all(|file| file.filesize == file.len as f64));
+    }
+}
@@ -0,0 +1,727 @@
+//! 録画（FLV）のMP4への変換
+//!
+//! 録画ファイルを閉じるとジョブを積み、バックグラウンドで1つずつ変換する。
+//! サンプルのデータは一時ファイルに書き出しておき、moovを組み立ててから後ろにつなげる
+
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use thiserror::Error;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
+use tokio::sync::Notify;
+use tracing::warn;
+use vyuber_shared::stream::{RemuxJob, RemuxState};
+
+use crate::media::aac::AacConfig;
+use crate::media::flv::FlvReader;
+use crate::media::fmp4::{aac_sample_rate, AAC_FRAME_SAMPLES};
+use crate::media::mp4::{self, SampleEntry, Track, TrackCodec};
+use crate::media::opus;
+use crate::media::tag::{codec, MediaTag, TagKind, VideoPacketType};
+use crate::media::video::VideoConfig;
+
+/// 一覧に残しておく終わったジョブの数
+const MAX_FINISHED_JOBS: usize = 100;
+
+#[derive(Debug, Error)]
+pub enum RemuxError {
+    #[error("recording has no H.264/HEVC/AV1 video or AAC/Opus audio")]
+    NoTracks,
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// MP4変換ジョブの待ち行列（終わったジョブも状態の確認用に残す）
+#[derive(Default)]
+pub struct RemuxQueue {
+    jobs: Mutex<VecDeque<RemuxJob>>,
+    notify: Notify,
+}
+
+impl RemuxQueue {
+    pub fn push(&self, stream_key: &str, source: &Path) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.push_back(RemuxJob {
+            stream_key: stream_key.to_string(),
+            source: source.display().to_string(),
+            ..Default::default()
+        });
+        // 古い順に終わったジョブを捨てる
+        while jobs.iter().filter(|job| is_finished(job)).count() > MAX_FINISHED_JOBS {
+            let Some(index) = jobs.iter().position(is_finished) else {
+                break;
+            };
+            jobs.remove(index);
+        }
+        self.notify.notify_one();
+    }
+
+    /// 全ジョブ（古い順）
+    pub fn jobs(&self) -> Vec<RemuxJob> {
+        self.jobs.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// `source` の変換が待ち中か変換中か
+    pub fn is_pending(&self, source: &Path) -> bool {
+        let source = source.display().to_string();
+        self.jobs.lock().unwrap().iter().any(|job| {
+            job.source == source && matches!(job.state, RemuxState::Queued | RemuxState::Running)
+        })
+    }
+
+    /// 次の変換待ちのジョブを変換中にして、変換元のファイルを返す
+    async fn next(&self) -> PathBuf {
+        loop {
+            {
+                let mut jobs = self.jobs.lock().unwrap();
+                if let Some(job) = jobs.iter_mut().find(|job| job.state == RemuxState::Queued) {
+                    job.state = RemuxState::Running;
+                    return PathBuf::from(&job.source);
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    fn finish(&self, source: &Path, result: &Result<Remuxed, RemuxError>) {
+        let source = source.display().to_string();
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs
+            .iter_mut()
+            .find(|job| job.state == RemuxState::Running && job.source == source)
+        else {
+            return;
+        };
+        match result {
+            Ok(remuxed) => {
+                job.state = RemuxState::Completed;
+                job.output = Some(remuxed.output.display().to_string());
+            }
+            Err(e) => {
+                job.state = RemuxState::Failed;
+                job.error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// 変換待ちのジョブを順に変換し続ける
+    pub async fn run(&self, keep_flv: bool) {
+        loop {
+            let source = self.next().await;
+            let result = remux(&source).await;
+            match &result {
+                Ok(remuxed) => {
+                    tracing::info!(
+                        "[Recording] Remuxed {} to {}",
+                        source.display(),
+                        remuxed.output.display()
+                    );
+                    // MP4に入らなかったトラックがあればFLVを消さない
+                    if !keep_flv && !remuxed.dropped_tracks {
+                        // 以後のVOD HLSはMP4から作る
+                        super::vod::forget(&source);
+                        if let Err(e) = tokio::fs::remove_file(&source).await {
+                            warn!("[Recording] Failed to delete {}: {}", source.display(), e);
+                        }
+                    }
+                }
+                Err(e) => warn!("[Recording] Failed to remux {}: {}", source.display(), e),
+            }
+            self.finish(&source, &result);
+        }
+    }
+}
+
+fn is_finished(job: &RemuxJob) -> bool {
+    matches!(job.state, RemuxState::Completed | RemuxState::Failed)
+}
+
+/// 変換結果
+pub struct Remuxed {
+    pub output: PathBuf,
+    /// 対応していないコーデックのため、MP4に入れられなかったトラックがあるか
+    pub dropped_tracks: bool,
+}
+
+/// FLVの録画を同じ名前のMP4（拡張子 `.mp4`）に変換する
+pub async fn remux(source: &Path) -> Result<Remuxed, RemuxError> {
+    let output = source.with_extension("mp4");
+    let mdat_path = source.with_extension("mp4.mdat");
+    let part_path = source.with_extension("mp4.part");
+
+    let result = remux_to(source, &mdat_path, &part_path).await;
+    let _ = tokio::fs::remove_file(&mdat_path).await;
+    let dropped_tracks = match result {
+        Ok(dropped_tracks) => dropped_tracks,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(e);
+        }
+    };
+    tokio::fs::rename(&part_path, &output).await?;
+    Ok(Remuxed {
+        output,
+        dropped_tracks,
+    })
+}
+
+/// 変換して、捨てたトラックがあったかを返す
+async fn remux_to(source: &Path, mdat_path: &Path, part_path: &Path) -> Result<bool, RemuxError> {
+    let mut reader = FlvReader::new(BufReader::new(File::open(source).await?)).await?;
+    let mut mdat = BufWriter::new(File::create(mdat_path).await?);
+    let mut tracks = TrackCollector::default();
+    while let Some(tag) = reader.next_tag().await? {
+        if let Some(data) = tracks.push(&tag) {
+            mdat.write_all(&data).await?;
+        }
+    }
+    mdat.flush().await?;
+    drop(mdat);
+
+    let mdat_size = tracks.mdat_size;
+    let dropped_tracks = tracks.dropped_tracks();
+    let tracks = tracks.finish(source)?;
+
+    let mut out = BufWriter::new(File::create(part_path).await?);
+    out.write_all(&mp4::header(&tracks, mdat_size)).await?;
+    tokio::io::copy(&mut File::open(mdat_path).await?, &mut out).await?;
+    out.flush().await?;
+    out.into_inner().sync_all().await?;
+    Ok(dropped_tracks)
+}
+
+/// 映像フレーム（デコード時刻はミリ秒）
+struct VideoFrame {
+    dts: u32,
+    /// ミリ秒（PTS - DTS）
+    cts: i32,
+    keyframe: bool,
+    size: u32,
+    offset: u64,
+}
+
+/// 音声フレーム（ミリ秒）
+struct AudioFrame {
+    timestamp: u32,
+    size: u32,
+    offset: u64,
+}
+
+/// 録画のタグからMP4のトラックを組み立てる
+///
+/// デコーダ設定は最初のシーケンスヘッダを使う
+#[derive(Default)]
+struct TrackCollector {
+    video: Option<TrackCodec>,
+    frames: Vec<VideoFrame>,
+    audio: Option<TrackCodec>,
+    audio_frames: Vec<AudioFrame>,
+    /// 対応していない映像コーデックのタグがあったか
+    unsupported_video: bool,
+    /// 対応していない音声コーデックのタグがあったか
+    unsupported_audio: bool,
+    mdat_size: u64,
+}
+
+impl TrackCollector {
+    /// タグを取り込み、mdatに書くサンプルのデータを返す
+    fn push(&mut self, tag: &MediaTag) -> Option<Bytes> {
+        let offset = self.mdat_size;
+        let data = match tag.kind {
+            TagKind::Video => {
+                let Some(packet) = tag.video_packet() else {
+                    self.unsupported_video |= !tag.data.is_empty();
+                    return None;
+                };
+                match packet.packet_type {
+                    VideoPacketType::SequenceStart if self.video.is_none() => {
+                        let Some(config) = VideoConfig::parse(packet.codec, &packet.data) else {
+                            self.unsupported_video = true;
+                            return None;
+                        };
+                        self.video = Some(TrackCodec::Video {
+                            record: packet.data,
+                            config,
+                        });
+                        return None;
+                    }
+                    VideoPacketType::CodedFrames if self.video.is_some() => {}
+                    _ => return None,
+                }
+                // 最初のキーフレームまでは捨てる
+                if self.frames.is_empty() && !packet.keyframe {
+                    return None;
+                }
+                self.frames.push(VideoFrame {
+                    dts: tag.timestamp,
+                    cts: packet.composition_time,
+                    keyframe: packet.keyframe,
+                    size: packet.data.len() as u32,
+                    offset,
+                });
+                packet.data
+            }
+            TagKind::Audio => {
+                let (sequence_header, frame) = self.audio_packet(tag)?;
+                if sequence_header {
+                    return None;
+                }
+                self.audio_frames.push(AudioFrame {
+                    timestamp: tag.timestamp,
+                    size: frame.len() as u32,
+                    offset,
+                });
+                frame
+            }
+            TagKind::Script => return None,
+        };
+        self.mdat_size += data.len() as u64;
+        Some(data)
+    }
+
+    /// 音声タグを (シーケンスヘッダか, データ) にする
+    ///
+    /// 最初のシーケンスヘッダのコーデック（AAC / Opus）以外のタグは `None`
+    fn audio_packet(&mut self, tag: &MediaTag) -> Option<(bool, Bytes)> {
+        let data = &tag.data;
+        let opus_packet = tag.opus_packet();
+        let opus = opus_packet.is_some();
+        let (sequence_header, payload) = match opus_packet {
+            Some(packet) => packet,
+            None if data.len() >= 2 && data[0] >> 4 == codec::AUDIO_AAC => {
+                (tag.is_sequence_header(), data.slice(2..))
+            }
+            None => {
+                self.unsupported_audio |= !data.is_empty();
+                return None;
+            }
+        };
+
+        if sequence_header && self.audio.is_none() {
+            self.audio = if opus {
+                opus::channels(&payload).map(|_| TrackCodec::Opus {
+                    head: payload.clone(),
+                })
+            } else {
+                AacConfig::parse(&payload).map(|config| TrackCodec::Aac {
+                    asc: payload.clone(),
+                    config,
+                })
+            };
+            self.unsupported_audio |= self.audio.is_none();
+        }
+        match self.audio {
+            Some(TrackCodec::Opus { .. }) if opus => Some((sequence_header, payload)),
+            Some(TrackCodec::Aac { .. }) if !opus => Some((sequence_header, payload)),
+            _ => None,
+        }
+    }
+
+    /// 対応していないコーデックのためにMP4に入れられないトラックがあるか
+    fn dropped_tracks(&self) -> bool {
+        (self.unsupported_video && self.video.is_none())
+            || (self.unsupported_audio && self.audio.is_none())
+    }
+
+    fn finish(self, source: &Path) -> Result<Vec<Track>, RemuxError> {
+        if self.unsupported_video && self.video.is_none() {
+            warn!(
+                "[Recording] {} has video in a codec MP4 remuxing does not support; keeping the FLV",
+                source.display()
+            );
+        }
+        if self.unsupported_audio && self.audio.is_none() {
+            warn!(
+                "[Recording] {} has audio in a codec MP4 remuxing does not support; keeping the FLV",
+                source.display()
+            );
+        }
+
+        // 映像は最初のキーフレームから始まるので、それより前の音声は捨てて
+        // 音声の開始が遅れる分は edts の空のエディットで表す
+        let start = self.frames.first().map(|frame| frame.dts);
+        let audio_frames: Vec<&AudioFrame> = self
+            .audio_frames
+            .iter()
+            .filter(|frame| start.is_none_or(|start| frame.timestamp >= start))
+            .collect();
+
+        let mut tracks = Vec::new();
+        if let Some(codec) = self.video.filter(|_| !self.frames.is_empty()) {
+            tracks.push(Track {
+                codec,
+                samples: video_samples(&self.frames),
+                delay: 0,
+            });
+        }
+        if let Some(codec) = self.audio.filter(|_| !audio_frames.is_empty()) {
+            tracks.push(Track {
+                samples: audio_samples(&codec, &audio_frames),
+                codec,
+                delay: start.map_or(0, |start| (audio_frames[0].timestamp - start) as u64),
+            });
+        }
+        if tracks.is_empty() {
+            return Err(RemuxError::NoTracks);
+        }
+        Ok(tracks)
+    }
+}
+
+/// 音声のサンプルの長さを求める
+///
+/// AACは1フレーム1024サンプルずつ進め、タイムスタンプと1フレーム以上ずれたらタイムスタンプに合わせ直す。
+/// Opusはパケットごとに長さが違うのでタイムスタンプの間隔から求める
+fn audio_samples(codec: &TrackCodec, frames: &[&AudioFrame]) -> Vec<SampleEntry> {
+    let durations: Vec<u32> = match codec {
+        TrackCodec::Aac { config, .. } => {
+            let rate = aac_sample_rate(config) as u64;
+            let first = frames[0].timestamp;
+            let mut position = 0u64;
+            let starts: Vec<u64> = frames
+                .iter()
+                .map(|frame| {
+                    let expected = (frame.timestamp.saturating_sub(first)) as u64 * rate / 1000;
+                    if expected.abs_diff(position) > AAC_FRAME_SAMPLES as u64 {
+                        position = expected;
+                    }
+                    let start = position;
+                    position += AAC_FRAME_SAMPLES as u64;
+                    start
+                })
+                .collect();
+            // 最後のフレームは1フレーム分
+            starts
+                .windows(2)
+                .map(|pair| pair[1].saturating_sub(pair[0]).max(1) as u32)
+                .chain([AAC_FRAME_SAMPLES])
+                .collect()
+        }
+        _ => {
+            // 最後のフレームや間隔が0のときは直前の間隔を使う
+            let mut interval = 0;
+            frames
+                .iter()
+                .enumerate()
+                .map(|(index, frame)| {
+                    let next = frames
+                        .get(index + 1)
+                        .map_or(frame.timestamp, |next| next.timestamp);
+                    let elapsed = next.saturating_sub(frame.timestamp);
+                    if elapsed > 0 {
+                        interval = elapsed * (opus::SAMPLE_RATE / 1000);
+                    }
+                    interval
+                })
+                .collect()
+        }
+    };
+    frames
+        .iter()
+        .zip(durations)
+        .map(|(frame, duration)| SampleEntry {
+            duration,
+            composition_offset: 0,
+            keyframe: true,
+            size: frame.size,
+            offset: frame.offset,
+        })
+        .collect()
+}
+
+/// フレーム間隔からサンプルの長さ（90kHz）を求める
+fn video_samples(frames: &[VideoFrame]) -> Vec<SampleEntry> {
+    let mut interval = 0;
+    frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            // 最後のフレームや間隔が0のときは直前の間隔を使う
+            let next = frames.get(index + 1).map_or(frame.dts, |next| next.dts);
+            let elapsed = next.saturating_sub(frame.dts);
+            if elapsed > 0 {
+                interval = elapsed;
+            }
+            SampleEntry {
+                duration: interval * 90,
+                composition_offset: frame.cts * 90,
+                keyframe: frame.keyframe,
+                size: frame.size,
+                offset: frame.offset,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::{flv, testing};
+    use bytes::BytesMut;
+
+    /// `data` に並んだボックスの (種類, 中身)
+    fn boxes(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut boxes = Vec::new();
+        let mut rest = data;
+        while rest.len() >= 8 {
+            let size = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            boxes.push((&rest[4..8], &rest[8..size]));
+            rest = &rest[size..];
+        }
+        boxes
+    }
+
+    /// `path` をたどった先のボックスの中身
+    fn find<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+        path.iter().try_fold(data, |data, box_type| {
+            boxes(data)
+                .into_iter()
+                .find(|(found, _)| found == box_type)
+                .map(|(_, content)| content)
+        })
+    }
+
+    /// フルボックス（version/flags の後にエントリ数とエントリが続く）のu32の列
+    fn entries(full_box: &[u8], skip: usize) -> Vec<u32> {
+        full_box[4 + skip..]
+            .chunks(4)
+            .map(|chunk| u32::from_be_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    /// タグをFLVに書いてMP4に変換し、(MP4, 捨てたトラックがあったか) を返す
+    async fn remux_tags(tags: &[MediaTag]) -> (Vec<u8>, bool) {
+        let mut file = BytesMut::from(&flv::header(true, true)[..]);
+        tags.iter().for_each(|tag| flv::write_tag(tag, &mut file));
+
+        let dir = std::env::temp_dir().join(format!("vyuber-remux-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let source = dir.join("test.flv");
+        tokio::fs::write(&source, &file).await.unwrap();
+        let remuxed = remux(&source).await.unwrap();
+        let mp4 = tokio::fs::read(&remuxed.output).await.unwrap();
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+        (mp4, remuxed.dropped_tracks)
+    }
+
+    fn traks(mp4: &[u8]) -> Vec<&[u8]> {
+        boxes(find(mp4, &[b"moov"]).unwrap())
+            .into_iter()
+            .filter(|(box_type, _)| box_type == b"trak")
+            .map(|(_, content)| content)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn remuxes_to_faststart_mp4() {
+        // 映像6フレーム（40ms間隔、キーフレームは1枚目と4枚目）と音声6フレーム
+        let video: Vec<MediaTag> = (0..6u8)
+            .map(|i| {
+                testing::avc_frame(
+                    i as u32 * 40,
+                    i % 3 == 0,
+                    0,
+                    &vec![0x10 + i; 20 + i as usize],
+                )
+            })
+            .collect();
+        let audio: Vec<MediaTag> = (0..6u8)
+            .map(|i| testing::aac_frame(i as u32 * 21, &vec![0xa0 + i; 10 + i as usize]))
+            .collect();
+        let mut tags = vec![
+            testing::avc_sequence_header(0),
+            testing::aac_sequence_header(0),
+        ];
+        for (video, audio) in video.iter().zip(&audio) {
+            tags.push(audio.clone());
+            tags.push(video.clone());
+        }
+        let (mp4, dropped_tracks) = remux_tags(&tags).await;
+        assert!(!dropped_tracks);
+
+        // moovが先頭にある
+        let top: Vec<&[u8]> = boxes(&mp4)
+            .into_iter()
+            .map(|(box_type, _)| box_type)
+            .collect();
+        assert_eq!(top, vec![&b"ftyp"[..], b"moov", b"mdat"]);
+        let traks = traks(&mp4);
+        assert_eq!(traks.len(), 2);
+
+        let samples = [
+            video
+                .iter()
+                .map(|tag| tag.video_packet().unwrap().data)
+                .collect::<Vec<_>>(),
+            audio.iter().map(|tag| tag.data.slice(2..)).collect(),
+        ];
+        for ((trak, samples), (duration, keyframes)) in traks
+            .iter()
+            .zip(&samples)
+            .zip([(40 * 90, Some(vec![1, 4])), (AAC_FRAME_SAMPLES, None)])
+        {
+            let stbl = find(trak, &[b"mdia", b"minf", b"stbl"]).unwrap();
+            // 長さが同じサンプルは1エントリにまとまる
+            assert_eq!(
+                entries(find(stbl, &[b"stts"]).unwrap(), 0),
+                vec![1, 6, duration]
+            );
+            // 全サンプルがキーフレームなら stss はない
+            assert_eq!(
+                find(stbl, &[b"stss"]).map(|stss| entries(stss, 4)),
+                keyframes
+            );
+            let sizes: Vec<u32> = samples.iter().map(|sample| sample.len() as u32).collect();
+            assert_eq!(entries(find(stbl, &[b"stsz"]).unwrap(), 8), sizes);
+
+            // 小さいファイルなので32ビットのstco
+            assert!(find(stbl, &[b"co64"]).is_none());
+            let offsets = entries(find(stbl, &[b"stco"]).unwrap(), 4);
+            assert_eq!(offsets.len(), samples.len());
+            for (offset, sample) in offsets.iter().zip(samples) {
+                let offset = *offset as usize;
+                assert_eq!(&mp4[offset..offset + sample.len()], &sample[..]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn remuxes_opus_audio() {
+        // 20msのパケットの中に40msのパケットが1つある
+        let timestamps = [0, 20, 40, 60, 100, 120];
+        let mut tags = vec![
+            testing::avc_sequence_header(0),
+            MediaTag::opus(0, true, &opus::opus_head(2)),
+        ];
+        for (i, &timestamp) in timestamps.iter().enumerate() {
+            tags.push(MediaTag::opus(timestamp, false, &[0xf0 + i as u8; 8]));
+            tags.push(testing::avc_frame(timestamp, i == 0, 0, &[0x10; 20]));
+        }
+        let (mp4, dropped_tracks) = remux_tags(&tags).await;
+        assert!(!dropped_tracks);
+
+        let traks = traks(&mp4);
+        assert_eq!(traks.len(), 2);
+        let stbl = find(traks[1], &[b"mdia", b"minf", b"stbl"]).unwrap();
+        let stsd = find(stbl, &[b"stsd"]).unwrap();
+        assert_eq!(&stsd[12..16], b"Opus");
+        // AudioSampleEntry（28バイト）の後の dOps（ビッグエンディアン）
+        let dops = find(&stsd[16 + 28..], &[b"dOps"]).unwrap();
+        assert_eq!(dops, &[0, 2, 0x01, 0x38, 0, 0, 0xbb, 0x80, 0, 0, 0]);
+        assert_eq!(
+            entries(find(stbl, &[b"stts"]).unwrap(), 0),
+            vec![3, 3, 960, 1, 1920, 2, 960]
+        );
+        let offsets = entries(find(stbl, &[b"stco"]).unwrap(), 4);
+        for (i, offset) in offsets.iter().enumerate() {
+            let offset = *offset as usize;
+            assert_eq!(&mp4[offset..offset + 8], &[0xf0 + i as u8; 8]);
+        }
+    }
+
+    #[tokio::test]
+    async fn aligns_audio_to_the_first_keyframe() {
+        // 映像は60msの非キーフレームの後、100msのキーフレームから40ms間隔。
+        // 音声は100msより前の3フレームを捨て、149msと256msの間に欠けがある
+        let mut video = vec![testing::avc_frame(60, false, 0, &[0x01; 20])];
+        video.extend((0..6u32).map(|i| testing::avc_frame(100 + i * 40, i == 0, 0, &[0x10; 20])));
+        let audio: Vec<MediaTag> = [43, 64, 85, 107, 128, 149, 256, 277]
+            .into_iter()
+            .enumerate()
+            .map(|(i, timestamp)| testing::aac_frame(timestamp, &[0xa0 + i as u8; 10]))
+            .collect();
+        let mut tags = vec![
+            testing::avc_sequence_header(0),
+            testing::aac_sequence_header(0),
+        ];
+        tags.extend(video.iter().chain(&audio).cloned());
+        tags.sort_by_key(|tag| tag.timestamp);
+        let (mp4, _) = remux_tags(&tags).await;
+
+        let traks = traks(&mp4);
+        assert_eq!(traks.len(), 2);
+        // 映像はキーフレームから始まり、空きはない
+        assert!(find(traks[0], &[b"edts"]).is_none());
+        let stbl = find(traks[0], &[b"mdia", b"minf", b"stbl"]).unwrap();
+        assert_eq!(
+            entries(find(stbl, &[b"stts"]).unwrap(), 0),
+            vec![1, 6, 40 * 90]
+        );
+
+        // 音声は107msから。キーフレームとの差7msは空のエディットになる
+        let elst = find(traks[1], &[b"edts", b"elst"]).unwrap();
+        assert_eq!(
+            entries(elst, 0),
+            vec![2, 7, u32::MAX, 0x0001_0000, 191, 0, 0x0001_0000]
+        );
+        // 欠けの後はタイムスタンプに合わせ直す（149msのフレームは256msまでの5104サンプル）
+        let stbl = find(traks[1], &[b"mdia", b"minf", b"stbl"]).unwrap();
+        assert_eq!(
+            entries(find(stbl, &[b"stts"]).unwrap(), 0),
+            vec![3, 2, 1024, 1, 5104, 2, 1024]
+        );
+        let offsets = entries(find(stbl, &[b"stco"]).unwrap(), 4);
+        assert_eq!(offsets.len(), 5);
+        let offset = offsets[0] as usize;
+        assert_eq!(&mp4[offset..offset + 10], &[0xa3; 10]);
+
+        // 読み戻すと空のエディットの分だけ音声が遅れる
+        let tracks = mp4::read_tracks(find(&mp4, &[b"moov"]).unwrap());
+        assert_eq!(tracks[0].samples[0].dts, 0);
+        let dts: Vec<u64> = tracks[1].samples.iter().map(|s| s.dts).collect();
+        assert_eq!(dts, vec![336, 1360, 2384, 7488, 8512]);
+    }
+
+    #[tokio::test]
+    async fn keeps_flv_when_a_track_cannot_be_remuxed() {
+        let dir = std::env::temp_dir().join(format!("vyuber-remux-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let mut tags = vec![testing::avc_sequence_header(0)];
+        for i in 0..6u32 {
+            // MP3（SoundFormat = 2）
+            tags.push(MediaTag::new(
+                TagKind::Audio,
+                i * 26,
+                Bytes::from_static(&[0x2f, 0xff, 0xfb, 0x90]),
+            ));
+            tags.push(testing::avc_frame(i * 40, i == 0, 0, &[0x10; 20]));
+        }
+        let mut file = BytesMut::from(&flv::header(true, true)[..]);
+        tags.iter().for_each(|tag| flv::write_tag(tag, &mut file));
+        let (lossy, complete) = (dir.join("lossy.flv"), dir.join("complete.flv"));
+        tokio::fs::write(&lossy, &file).await.unwrap();
+        let mut file = BytesMut::from(&flv::header(true, true)[..]);
+        tags.iter()
+            .filter(|tag| tag.kind == TagKind::Video)
+            .for_each(|tag| flv::write_tag(tag, &mut file));
+        tokio::fs::write(&complete, &file).await.unwrap();
+
+        let queue = std::sync::Arc::new(RemuxQueue::default());
+        queue.push("test", &lossy);
+        queue.push("test", &complete);
+        let runner = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.run(false).await }
+        });
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while !queue.jobs().iter().all(is_finished) {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        runner.abort();
+
+        assert!(queue
+            .jobs()
+            .iter()
+            .all(|job| job.state == RemuxState::Completed));
+        // MP3を捨てた録画だけFLVを残す
+        assert!(lossy.exists() && lossy.with_extension("mp4").exists());
+        assert!(!complete.exists() && complete.with_extension("mp4").exists());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
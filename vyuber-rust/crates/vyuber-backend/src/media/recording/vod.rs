@@ -0,0 +1,298 @@
+//! 録画のVOD HLS
+//!
+//! キーフレームのファイル上の位置（FLVならタグ、MP4なら stss / stco から引いたサンプル）を
+//! 索引にしてプレイリストを作り、セグメントは要求されたときにその範囲のタグからMPEG-TSを作る
+
+use bytes::{BufMut, Bytes, BytesMut};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+
+use super::library::{read_moov, RecordingError};
+use crate::config;
+use crate::media::flv::FlvReader;
+use crate::media::hls;
+use crate::media::mp4::{self, Mp4Codec};
+use crate::media::tag::{codec, MediaTag, TagKind};
+
+/// 録画ファイルごとの索引（閉じた録画は変わらないので一度作ったら使い回す）
+static INDEXES: Lazy<Mutex<HashMap<PathBuf, Arc<VodIndex>>>> = Lazy::new(Default::default);
+
+pub struct VodIndex {
+    segments: Vec<VodSegment>,
+}
+
+/// キーフレームから次のセグメントのキーフレームまで
+struct VodSegment {
+    /// ミリ秒
+    start: u32,
+    duration: u32,
+    /// セグメントの開始時点のシーケンスヘッダ
+    headers: Vec<MediaTag>,
+    data: SegmentData,
+}
+
+/// セグメントのタグのファイル上の位置
+enum SegmentData {
+    /// セグメントの先頭のタグと次のセグメントの先頭のタグの位置
+    Flv { offset: u64, end: u64 },
+    /// MP4のサンプル（タイムスタンプ順）
+    Mp4(Vec<VodSample>),
+}
+
+/// MP4のサンプル1つ分（読むときにFLVのタグにする）
+struct VodSample {
+    kind: TagKind,
+    /// ミリ秒
+    timestamp: u32,
+    /// ミリ秒（PTS - DTS）
+    composition_time: i32,
+    keyframe: bool,
+    offset: u64,
+    size: u32,
+}
+
+/// 録画の索引（なければFLVかMP4（拡張子で判断）を読んで作る）
+///
+/// MPEG-TSに入れられないコーデックのトラックがあれば `UnsupportedCodec`
+pub async fn index(path: &Path) -> Result<Arc<VodIndex>, RecordingError> {
+    if let Some(index) = INDEXES.lock().unwrap().get(path) {
+        return Ok(index.clone());
+    }
+    let target_duration_ms = config::get().hls.segment_duration_ms;
+    let index = if path.extension().is_some_and(|ext| ext == "mp4") {
+        VodIndex::build_mp4(path, target_duration_ms).await?
+    } else {
+        VodIndex::build_flv(path, target_duration_ms).await?
+    };
+    let index = Arc::new(index);
+    INDEXES
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), index.clone());
+    Ok(index)
+}
+
+/// 削除した（MP4に変換して消したものを含む）録画の索引を捨てる
+pub fn forget(path: &Path) {
+    INDEXES.lock().unwrap().remove(path);
+}
+
+impl VodIndex {
+    /// キーフレーム（映像がなければ音声フレーム）で `target_duration_ms` 以上ごとに区切る
+    async fn build_flv(path: &Path, target_duration_ms: u32) -> Result<Self, RecordingError> {
+        let mut reader = FlvReader::new(BufReader::new(File::open(path).await?)).await?;
+        let mut video_header: Option<MediaTag> = None;
+        let mut audio_header: Option<MediaTag> = None;
+        let mut segments: Vec<VodSegment> = Vec::new();
+        let mut last_timestamp = 0;
+
+        loop {
+            let offset = reader.position();
+            let Some(tag) = reader.next_tag().await? else {
+                break;
+            };
+            if let Some(codec) = hls::unsupported_codec(&tag) {
+                return Err(RecordingError::UnsupportedCodec(codec));
+            }
+            if tag.is_sequence_header() {
+                match tag.kind {
+                    TagKind::Video => video_header = Some(tag),
+                    _ => audio_header = Some(tag),
+                }
+                continue;
+            }
+            let boundary = match tag.kind {
+                TagKind::Video => tag.is_keyframe(),
+                TagKind::Audio => video_header.is_none(),
+                TagKind::Script => continue,
+            };
+            last_timestamp = last_timestamp.max(tag.timestamp);
+            if !boundary || !starts_segment(&mut segments, tag.timestamp, target_duration_ms) {
+                continue;
+            }
+
+            if let Some(SegmentData::Flv { end, .. }) = segments.last_mut().map(|s| &mut s.data) {
+                *end = offset;
+            }
+            segments.push(VodSegment {
+                start: tag.timestamp,
+                duration: 0,
+                headers: [&video_header, &audio_header]
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect(),
+                data: SegmentData::Flv { offset, end: 0 },
+            });
+        }
+
+        if let Some(last) = segments.last_mut() {
+            last.duration = last_timestamp.saturating_sub(last.start).max(1);
+            if let SegmentData::Flv { end, .. } = &mut last.data {
+                *end = reader.position();
+            }
+        }
+        Ok(Self { segments })
+    }
+
+    /// MP4のサンプルテーブルから、FLVと同じようにキーフレームで区切る
+    ///
+    /// FLVを消した録画用
+    async fn build_mp4(path: &Path, target_duration_ms: u32) -> Result<Self, RecordingError> {
+        let tracks = mp4::read_tracks(&read_moov(path).await?);
+        let has_video = tracks
+            .iter()
+            .any(|track| matches!(track.codec, Mp4Codec::Avc(_)));
+        let mut headers = Vec::new();
+        let mut samples = Vec::new();
+        for track in &tracks {
+            let (kind, header) = match &track.codec {
+                Mp4Codec::Avc(record) => (TagKind::Video, avc_tag(true, 0, 0, record)),
+                Mp4Codec::Aac(asc) => (TagKind::Audio, aac_tag(0, asc)),
+                // 黙って音声だけ/映像だけのVODにしない
+                &Mp4Codec::Unsupported(codec) => {
+                    return Err(RecordingError::UnsupportedCodec(codec))
+                }
+            };
+            headers.push(MediaTag::new(kind, 0, header));
+
+            let timescale = track.timescale.max(1) as i64;
+            let millis = |value: i64| value * 1000 / timescale;
+            samples.extend(track.samples.iter().map(|sample| VodSample {
+                kind,
+                timestamp: millis(sample.dts as i64) as u32,
+                composition_time: millis(sample.composition_offset as i64) as i32,
+                keyframe: sample.keyframe,
+                offset: sample.offset,
+                size: sample.size,
+            }));
+        }
+        // 同じタイムスタンプならファイル上の順（録画したときの順）
+        samples.sort_by_key(|sample| (sample.timestamp, sample.offset));
+
+        let mut segments: Vec<VodSegment> = Vec::new();
+        let mut last_timestamp = 0;
+        for sample in samples {
+            let boundary = match sample.kind {
+                TagKind::Video => sample.keyframe,
+                _ => !has_video,
+            };
+            last_timestamp = last_timestamp.max(sample.timestamp);
+            if boundary && starts_segment(&mut segments, sample.timestamp, target_duration_ms) {
+                segments.push(VodSegment {
+                    start: sample.timestamp,
+                    duration: 0,
+                    headers: headers.clone(),
+                    data: SegmentData::Mp4(Vec::new()),
+                });
+            }
+            // 最初のキーフレームより前のサンプルは捨てる
+            if let Some(SegmentData::Mp4(samples)) = segments.last_mut().map(|s| &mut s.data) {
+                samples.push(sample);
+            }
+        }
+
+        if let Some(last) = segments.last_mut() {
+            last.duration = last_timestamp.saturating_sub(last.start).max(1);
+        }
+        Ok(Self { segments })
+    }
+
+    /// VODのメディアプレイリスト（セグメントは `<番号>.ts`）
+    pub fn playlist(&self) -> String {
+        // EXT-X-TARGETDURATION はどのセグメント長以上でなければならない
+        let target_duration = self
+            .segments
+            .iter()
+            .map(|segment| segment.duration)
+            .max()
+            .unwrap_or(0)
+            .div_ceil(1000);
+
+        let mut m3u8 = String::new();
+        writeln!(m3u8, "#EXTM3U").unwrap();
+        writeln!(m3u8, "#EXT-X-VERSION:3").unwrap();
+        writeln!(m3u8, "#EXT-X-TARGETDURATION:{}", target_duration).unwrap();
+        writeln!(m3u8, "#EXT-X-MEDIA-SEQUENCE:0").unwrap();
+        writeln!(m3u8, "#EXT-X-PLAYLIST-TYPE:VOD").unwrap();
+        for (sequence, segment) in self.segments.iter().enumerate() {
+            writeln!(m3u8, "#EXTINF:{:.3},", segment.duration as f64 / 1000.0).unwrap();
+            writeln!(m3u8, "{}.ts", sequence).unwrap();
+        }
+        writeln!(m3u8, "#EXT-X-ENDLIST").unwrap();
+        m3u8
+    }
+
+    /// セグメント `sequence` のMPEG-TS（範囲外なら `None`）
+    pub async fn segment(&self, path: &Path, sequence: usize) -> io::Result<Option<Bytes>> {
+        let Some(segment) = self.segments.get(sequence) else {
+            return Ok(None);
+        };
+        let mut file = File::open(path).await?;
+        let mut tags = segment.headers.clone();
+        match &segment.data {
+            &SegmentData::Flv { offset, end } => {
+                file.seek(SeekFrom::Start(offset)).await?;
+                let mut reader = FlvReader::at(BufReader::new(file), offset);
+                while reader.position() < end {
+                    let Some(tag) = reader.next_tag().await? else {
+                        break;
+                    };
+                    tags.push(tag);
+                }
+            }
+            SegmentData::Mp4(samples) => {
+                for sample in samples {
+                    file.seek(SeekFrom::Start(sample.offset)).await?;
+                    let mut data = vec![0u8; sample.size as usize];
+                    file.read_exact(&mut data).await?;
+                    let data = match sample.kind {
+                        TagKind::Video => {
+                            avc_tag(sample.keyframe, 1, sample.composition_time, &data)
+                        }
+                        _ => aac_tag(1, &data),
+                    };
+                    tags.push(MediaTag::new(sample.kind, sample.timestamp, data));
+                }
+            }
+        }
+        Ok(hls::encode_segment(&tags))
+    }
+}
+
+/// `timestamp` の区切りで新しいセグメントを始めるか（始めるなら直前のセグメントの長さを決める）
+fn starts_segment(segments: &mut [VodSegment], timestamp: u32, target_duration_ms: u32) -> bool {
+    let Some(last) = segments.last_mut() else {
+        return true;
+    };
+    let elapsed = timestamp.saturating_sub(last.start);
+    if elapsed < target_duration_ms {
+        return false;
+    }
+    last.duration = elapsed;
+    true
+}
+
+/// H.264の映像タグのボディ（AVCPacketType 0: シーケンスヘッダ、1: フレーム）
+fn avc_tag(keyframe: bool, packet_type: u8, composition_time: i32, data: &[u8]) -> Bytes {
+    let mut tag = BytesMut::with_capacity(data.len() + 5);
+    tag.put_u8(if keyframe { 1 << 4 } else { 2 << 4 } | codec::VIDEO_AVC);
+    tag.put_u8(packet_type);
+    tag.put_slice(&composition_time.to_be_bytes()[1..]);
+    tag.put_slice(data);
+    tag.freeze()
+}
+
+/// AACの音声タグのボディ（AACPacketType 0: シーケンスヘッダ、1: フレーム）
+fn aac_tag(packet_type: u8, data: &[u8]) -> Bytes {
+    let mut tag = BytesMut::with_capacity(data.len() + 2);
+    tag.put_slice(&[(codec::AUDIO_AAC << 4) | 0x0F, packet_type]);
+    tag.put_slice(data);
+    tag.freeze()
+}
@@ -0,0 +1,163 @@
+//! 録画ファイル（FLV）の書き込み
+
+use bytes::BytesMut;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufWriter};
+
+use crate::media::flv;
+use crate::media::tag::{MediaTag, TagKind};
+use crate::rtmp::amf::{amf0, AmfValue};
+
+/// FLVファイルヘッダ + PreviousTagSize0 の長さ
+const FILE_HEADER_LEN: u64 = 13;
+
+/// FLVタグヘッダの長さ
+const TAG_HEADER_LEN: u64 = 11;
+
+/// 録画の先頭に書くタグ（直近のメタデータとシーケンスヘッダ）
+#[derive(Default)]
+pub struct Headers {
+    pub metadata: Option<MediaTag>,
+    pub video: Option<MediaTag>,
+    pub audio: Option<MediaTag>,
+}
+
+/// 1ファイル分の録画
+///
+/// タイムスタンプはファイルの最初のタグを0として書き、閉じるときに
+/// onMetaData の duration と filesize を実際の値に書き換える
+pub struct FlvWriter {
+    file: BufWriter<File>,
+    path: PathBuf,
+    size: u64,
+    base_timestamp: u32,
+    last_timestamp: u32,
+    /// onMetaData の duration / filesize の値のファイル先頭からの位置
+    duration_offset: u64,
+    filesize_offset: u64,
+}
+
+impl FlvWriter {
+    /// ファイルを作り、ヘッダ・メタデータ・シーケンスヘッダを書く
+    ///
+    /// `base_timestamp` のタグがファイルの0ミリ秒になる
+    pub async fn create(
+        path: &Path,
+        headers: &Headers,
+        base_timestamp: u32,
+    ) -> std::io::Result<Self> {
+        let metadata = metadata_tag(headers.metadata.as_ref());
+        let duration_offset = number_offset(&metadata, "duration");
+        let filesize_offset = number_offset(&metadata, "filesize");
+
+        let mut writer = Self {
+            file: BufWriter::new(File::create(path).await?),
+            path: path.to_path_buf(),
+            size: 0,
+            base_timestamp,
+            last_timestamp: 0,
+            duration_offset: FILE_HEADER_LEN + TAG_HEADER_LEN + duration_offset,
+            filesize_offset: FILE_HEADER_LEN + TAG_HEADER_LEN + filesize_offset,
+        };
+        let header = flv::header(headers.audio.is_some(), headers.video.is_some());
+        writer.write_bytes(&header).await?;
+        writer
+            .write_bytes(&flv::encode_tag(&MediaTag::new(
+                TagKind::Script,
+                0,
+                metadata,
+            )))
+            .await?;
+        for tag in [&headers.video, &headers.audio].into_iter().flatten() {
+            writer.write(tag).await?;
+        }
+        Ok(writer)
+    }
+
+    /// タグを書く（タイムスタンプはファイルの先頭からの値にする）
+    pub async fn write(&mut self, tag: &MediaTag) -> std::io::Result<()> {
+        let mut tag = tag.clone();
+        // 音声が映像のキーフレームより少し前のことがあるので、負にならないようにする
+        tag.timestamp = (tag.timestamp.wrapping_sub(self.base_timestamp) as i32).max(0) as u32;
+        self.last_timestamp = self.last_timestamp.max(tag.timestamp);
+
+        let mut buf = BytesMut::new();
+        flv::write_tag(&tag, &mut buf);
+        self.write_bytes(&buf).await
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.last_timestamp as u64)
+    }
+
+    /// ファイルの先頭から `timestamp` のタグまでの長さ
+    pub fn elapsed(&self, timestamp: u32) -> Duration {
+        let elapsed = (timestamp.wrapping_sub(self.base_timestamp) as i32).max(0);
+        Duration::from_millis(elapsed as u64)
+    }
+
+    /// 書き終えて duration と filesize を書き換える
+    pub async fn finish(mut self) -> std::io::Result<PathBuf> {
+        self.file.flush().await?;
+        let mut file = self.file.into_inner();
+        let duration = self.last_timestamp as f64 / 1000.0;
+        for (offset, value) in [
+            (self.duration_offset, duration),
+            (self.filesize_offset, self.size as f64),
+        ] {
+            file.seek(SeekFrom::Start(offset)).await?;
+            file.write_all(&value.to_be_bytes()).await?;
+        }
+        file.sync_all().await?;
+        Ok(self.path)
+    }
+
+    async fn write_bytes(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(data).await?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+}
+
+/// 録画用の onMetaData（配信者のメタデータの先頭に duration と filesize を置く）
+fn metadata_tag(source: Option<&MediaTag>) -> bytes::Bytes {
+    let mut props = vec![
+        ("duration".to_string(), AmfValue::Number(0.0)),
+        ("filesize".to_string(), AmfValue::Number(0.0)),
+    ];
+    let source = source
+        .and_then(|tag| amf0::decode_all(&tag.data).ok())
+        .and_then(|values| values.into_iter().nth(1));
+    if let Some(AmfValue::Object(source) | AmfValue::EcmaArray(source)) = source {
+        props.extend(
+            source
+                .into_iter()
+                .filter(|(key, _)| key != "duration" && key != "filesize"),
+        );
+    }
+    amf0::encode_all(&[AmfValue::from("onMetaData"), AmfValue::EcmaArray(props)])
+}
+
+/// AMF0でエンコードしたプロパティ `key` の数値の位置
+fn number_offset(data: &[u8], key: &str) -> u64 {
+    // キー（u16長 + 文字列）+ Numberのマーカー
+    let mut pattern = (key.len() as u16).to_be_bytes().to_vec();
+    pattern.extend_from_slice(key.as_bytes());
+    pattern.push(0x00);
+    let position = data
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+        .expect("metadata property must be encoded");
+    (position + pattern.len()) as u64
+}
@@ -0,0 +1,316 @@
+//! 配信者から届いたタグの受信統計
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use vyuber_shared::stream::{BitrateWindows, StreamStats, TrackStats};
+
+use super::tag::{MediaTag, TagKind};
+
+/// ビットレートを集計する最長の時間窓
+const BITRATE_WINDOW: Duration = Duration::from_secs(30);
+
+/// フレームレートを計測するタイムスタンプの範囲（ミリ秒）
+const FPS_WINDOW_MS: u32 = 5000;
+
+/// これ以上タイムスタンプが飛んだら不連続とみなす（ミリ秒）
+const TIMESTAMP_GAP_MS: u32 = 1000;
+
+/// 想定フレーム間隔のこの倍率を超えたらフレーム欠落とみなす
+const DROP_THRESHOLD: f64 = 1.5;
+
+/// 1ストリーム分の受信統計
+pub struct IngestStats {
+    started: Instant,
+    script_bytes: u64,
+    video: TrackCounter,
+    audio: TrackCounter,
+    /// 直近 `FPS_WINDOW_MS` 分の映像フレームのタイムスタンプ
+    frame_timestamps: VecDeque<u32>,
+    keyframes: u64,
+    last_keyframe: Option<u32>,
+    keyframe_interval: Option<u32>,
+    dropped_frames: u64,
+    jitter: f64,
+}
+
+impl Default for IngestStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IngestStats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            script_bytes: 0,
+            video: TrackCounter::default(),
+            audio: TrackCounter::default(),
+            frame_timestamps: VecDeque::new(),
+            keyframes: 0,
+            last_keyframe: None,
+            keyframe_interval: None,
+            dropped_frames: 0,
+            jitter: 0.0,
+        }
+    }
+
+    /// 配信者から届いたタグを記録する
+    pub fn record(&mut self, tag: &MediaTag) {
+        let now = Instant::now();
+        match tag.kind {
+            TagKind::Script => self.script_bytes += tag.data.len() as u64,
+            TagKind::Audio => {
+                self.audio.record(tag, now);
+            }
+            TagKind::Video => {
+                let delta = self.video.record(tag, now);
+                if !tag.is_sequence_header() {
+                    self.record_frame(tag, delta);
+                }
+            }
+        }
+    }
+
+    fn record_frame(&mut self, tag: &MediaTag, delta: Option<i64>) {
+        match delta {
+            Some(delta) if delta < 0 => self.frame_timestamps.clear(),
+            Some(delta) => {
+                if let Some(expected) = self.frame_interval() {
+                    let delta = delta as f64;
+                    if delta > expected * DROP_THRESHOLD {
+                        self.dropped_frames += ((delta / expected).round() as u64).saturating_sub(1);
+                    }
+                    // RFC 3550 と同様の平滑化
+                    self.jitter += ((delta - expected).abs() - self.jitter) / 16.0;
+                }
+            }
+            None => {}
+        }
+
+        self.frame_timestamps.push_back(tag.timestamp);
+        while self
+            .frame_timestamps
+            .front()
+            .is_some_and(|&first| tag.timestamp.saturating_sub(first) > FPS_WINDOW_MS)
+        {
+            self.frame_timestamps.pop_front();
+        }
+
+        if tag.is_keyframe() {
+            self.keyframes += 1;
+            if let Some(last) = self.last_keyframe {
+                self.keyframe_interval = tag.timestamp.checked_sub(last);
+            }
+            self.last_keyframe = Some(tag.timestamp);
+        }
+    }
+
+    /// 計測中の平均フレーム間隔（ミリ秒）
+    fn frame_interval(&self) -> Option<f64> {
+        let (first, last) = (self.frame_timestamps.front()?, self.frame_timestamps.back()?);
+        let frames = self.frame_timestamps.len() - 1;
+        (frames > 0 && last > first).then(|| (last - first) as f64 / frames as f64)
+    }
+
+    /// APIで返す統計値
+    pub fn snapshot(&self) -> StreamStats {
+        let now = Instant::now();
+        let uptime = now.duration_since(self.started);
+        StreamStats {
+            uptime_secs: uptime.as_secs_f64(),
+            bytes_received: self.video.bytes + self.audio.bytes + self.script_bytes,
+            video: self.video.snapshot(now, uptime),
+            audio: self.audio.snapshot(now, uptime),
+            fps: self.frame_interval().map(|interval| 1000.0 / interval),
+            keyframes: self.keyframes,
+            keyframe_interval_ms: self.keyframe_interval,
+            dropped_frames: self.dropped_frames,
+            jitter_ms: self.jitter,
+        }
+    }
+}
+
+/// トラックごとのカウンタ
+#[derive(Default)]
+struct TrackCounter {
+    bytes: u64,
+    frames: u64,
+    /// 直近 `BITRATE_WINDOW` 分の (受信時刻, バイト数)
+    arrivals: VecDeque<(Instant, usize)>,
+    last_timestamp: Option<u32>,
+    gaps: u64,
+    regressions: u64,
+}
+
+impl TrackCounter {
+    /// タグを記録し、前のタグからのタイムスタンプ差を返す
+    fn record(&mut self, tag: &MediaTag, now: Instant) -> Option<i64> {
+        let len = tag.data.len();
+        self.bytes += len as u64;
+        self.arrivals.push_back((now, len));
+        while self
+            .arrivals
+            .front()
+            .is_some_and(|&(at, _)| now.duration_since(at) > BITRATE_WINDOW)
+        {
+            self.arrivals.pop_front();
+        }
+
+        if tag.is_sequence_header() {
+            return None;
+        }
+        self.frames += 1;
+
+        let delta = self
+            .last_timestamp
+            .map(|last| tag.timestamp as i64 - last as i64);
+        match delta {
+            Some(delta) if delta < 0 => self.regressions += 1,
+            Some(delta) if delta > TIMESTAMP_GAP_MS as i64 => self.gaps += 1,
+            _ => {}
+        }
+        self.last_timestamp = Some(tag.timestamp);
+        delta
+    }
+
+    fn snapshot(&self, now: Instant, uptime: Duration) -> TrackStats {
+        TrackStats {
+            bytes: self.bytes,
+            frames: self.frames,
+            bitrate: BitrateWindows {
+                last_1s: self.bitrate(now, uptime, Duration::from_secs(1)),
+                last_5s: self.bitrate(now, uptime, Duration::from_secs(5)),
+                last_30s: self.bitrate(now, uptime, BITRATE_WINDOW),
+            },
+            timestamp_gaps: self.gaps,
+            timestamp_regressions: self.regressions,
+        }
+    }
+
+    /// 直近 `window` のビットレート（kbps）。配信開始直後は経過時間で割る
+    fn bitrate(&self, now: Instant, uptime: Duration, window: Duration) -> f64 {
+        let bytes: usize = self
+            .arrivals
+            .iter()
+            .rev()
+            .take_while(|&&(at, _)| now.duration_since(at) <= window)
+            .map(|&(_, len)| len)
+            .sum();
+        let secs = window.min(uptime).as_secs_f64();
+        if secs > 0.0 {
+            bytes as f64 * 8.0 / 1000.0 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::testing::{aac_frame, avc_frame, avc_sequence_header};
+
+    #[test]
+    fn averages_bitrate_over_each_window() {
+        let start = Instant::now();
+        let mut counter = TrackCounter::default();
+        // 1250バイト = 10kbit
+        let payload = [0u8; 1248];
+        for secs in [0, 20, 26, 28, 29] {
+            let tag = aac_frame(secs * 1000, &payload);
+            assert_eq!(tag.data.len(), 1250);
+            counter.record(&tag, start + Duration::from_secs(secs as u64));
+        }
+
+        // 配信開始から1分経っている
+        let now = start + Duration::from_millis(29_500);
+        let stats = counter.snapshot(now, Duration::from_secs(60));
+        assert_eq!(stats.bytes, 5 * 1250);
+        assert_eq!(stats.frames, 5);
+        assert_eq!(stats.bitrate.last_1s, 10.0);
+        assert_eq!(stats.bitrate.last_5s, 30.0 / 5.0);
+        assert_eq!(stats.bitrate.last_30s, 50.0 / 30.0);
+
+        // 30秒より前の受信は捨てる
+        counter.record(
+            &aac_frame(31_000, &payload),
+            start + Duration::from_secs(31),
+        );
+        assert_eq!(counter.arrivals.len(), 5);
+        assert_eq!(
+            counter.arrivals.front().unwrap().0,
+            start + Duration::from_secs(20)
+        );
+
+        // 配信開始直後は経過時間で割る
+        let counter = {
+            let mut counter = TrackCounter::default();
+            counter.record(&aac_frame(0, &payload), start);
+            counter
+        };
+        let now = start + Duration::from_millis(500);
+        let bitrate = counter.snapshot(now, Duration::from_millis(500)).bitrate;
+        assert_eq!(bitrate.last_1s, 20.0);
+        assert_eq!(bitrate.last_30s, 20.0);
+    }
+
+    #[test]
+    fn measures_frame_rate_and_keyframe_interval() {
+        let mut stats = IngestStats::new();
+        stats.record(&avc_sequence_header(0));
+        for i in 0..60u32 {
+            stats.record(&avc_frame(i * 40, i % 25 == 0, 0, &[0; 8]));
+        }
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.fps, Some(25.0));
+        assert_eq!(snapshot.keyframes, 3);
+        assert_eq!(snapshot.keyframe_interval_ms, Some(1000));
+        assert_eq!(snapshot.dropped_frames, 0);
+        assert_eq!(snapshot.jitter_ms, 0.0);
+        // シーケンスヘッダはフレームに数えない
+        assert_eq!(snapshot.video.frames, 60);
+
+        // 5秒より前のフレームは計測から外す
+        for i in 60..200u32 {
+            stats.record(&avc_frame(i * 40, false, 0, &[0; 8]));
+        }
+        assert_eq!(stats.frame_timestamps.len(), 126);
+        assert_eq!(stats.snapshot().fps, Some(25.0));
+    }
+
+    #[test]
+    fn counts_dropped_frames_and_smooths_jitter() {
+        let mut stats = IngestStats::new();
+        for i in 0..10u32 {
+            stats.record(&avc_frame(i * 40, i == 0, 0, &[0; 8]));
+        }
+        // 3フレーム分抜けて160ms後
+        stats.record(&avc_frame(9 * 40 + 160, false, 0, &[0; 8]));
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.dropped_frames, 3);
+        assert_eq!(snapshot.jitter_ms, 120.0 / 16.0);
+
+        // 間隔が戻ると少しずつ小さくなる
+        let expected = stats.frame_interval().unwrap();
+        stats.record(&avc_frame(9 * 40 + 200, false, 0, &[0; 8]));
+        let jitter = 7.5 + ((40.0 - expected).abs() - 7.5) / 16.0;
+        assert_eq!(stats.snapshot().jitter_ms, jitter);
+        assert_eq!(stats.snapshot().dropped_frames, 3);
+    }
+
+    #[test]
+    fn counts_timestamp_gaps_and_regressions() {
+        let mut stats = IngestStats::new();
+        for timestamp in [0, 40, 80, 2000, 2040, 500, 540] {
+            stats.record(&avc_frame(timestamp, false, 0, &[0; 8]));
+        }
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.video.timestamp_gaps, 1);
+        assert_eq!(snapshot.video.timestamp_regressions, 1);
+        // 巻き戻ったらフレームレートを計測し直す
+        assert_eq!(stats.frame_timestamps, [500, 540]);
+        assert_eq!(snapshot.fps, Some(25.0));
+    }
+}
@@ -0,0 +1,242 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// タグの種類（FLVのTagTypeに対応）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagKind {
+    Audio,
+    Video,
+    Script,
+}
+
+/// ハブを流れるメディアタグ
+///
+/// `data` はFLVタグのボディと同じ形式（RTMPのaudio/video/dataメッセージのペイロード）
+#[derive(Debug, Clone)]
+pub struct MediaTag {
+    pub kind: TagKind,
+    pub timestamp: u32,
+    pub data: Bytes,
+}
+
+impl MediaTag {
+    pub fn new(kind: TagKind, timestamp: u32, data: Bytes) -> Self {
+        Self {
+            kind,
+            timestamp,
+            data,
+        }
+    }
+}
+
+/// FLVのコーデックID
+pub mod codec {
+    /// VideoTagHeader の CodecID
+    pub const VIDEO_AVC: u8 = 7;
+    /// VideoTagHeader の CodecID（Enhanced RTMP以前の独自拡張）
+    pub const VIDEO_HEVC: u8 = 12;
+    /// AudioTagHeader の SoundFormat
+    pub const AUDIO_AAC: u8 = 10;
+}
+
+/// Enhanced RTMP の ExVideoTagHeader
+mod ex_video {
+    /// 1バイト目の IsExHeader ビット
+    pub const EX_HEADER: u8 = 0x80;
+
+    /// VideoPacketType
+    pub const SEQUENCE_START: u8 = 0;
+    pub const CODED_FRAMES: u8 = 1;
+    pub const SEQUENCE_END: u8 = 2;
+    /// CompositionTime を省略した CodedFrames
+    pub const CODED_FRAMES_X: u8 = 3;
+    pub const METADATA: u8 = 4;
+}
+
+/// Enhanced RTMP の ExAudioTagHeader
+mod ex_audio {
+    /// SoundFormat = 9 で ExAudioTagHeader を表す
+    pub const EX_HEADER: u8 = 9;
+
+    /// AudioPacketType
+    pub const SEQUENCE_START: u8 = 0;
+    pub const CODED_FRAMES: u8 = 1;
+
+    pub const OPUS: &[u8; 4] = b"Opus";
+}
+
+/// VideoTagHeader の FrameType
+mod frame_type {
+    pub const KEYFRAME: u8 = 1;
+    /// ビデオ情報/コマンドフレーム（映像データを含まない）
+    pub const COMMAND: u8 = 5;
+}
+
+/// 映像コーデック
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    Avc,
+    Hevc,
+    Av1,
+}
+
+impl VideoCodec {
+    /// Enhanced RTMP の FourCC から
+    fn from_fourcc(fourcc: &[u8]) -> Option<Self> {
+        match fourcc {
+            b"avc1" => Some(VideoCodec::Avc),
+            b"hvc1" => Some(VideoCodec::Hevc),
+            b"av01" => Some(VideoCodec::Av1),
+            _ => None,
+        }
+    }
+}
+
+/// 映像パケットの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoPacketType {
+    /// デコーダ設定レコード（avcC / hvcC / av1C）
+    SequenceStart,
+    CodedFrames,
+    SequenceEnd,
+    /// HDRのcolorInfoなどのAMFメタデータ
+    Metadata,
+    /// コマンドフレームや未対応のパケット
+    Other,
+}
+
+/// 従来形式とEnhanced RTMPの映像タグを共通に扱うためのヘッダ解析結果
+#[derive(Debug, Clone)]
+pub struct VideoPacket {
+    pub codec: VideoCodec,
+    pub packet_type: VideoPacketType,
+    pub keyframe: bool,
+    /// ミリ秒（PTS - DTS）
+    pub composition_time: i32,
+    /// デコーダ設定レコードまたはフレームのデータ（AVC/HEVCは長さプレフィックス形式のNALU列、AV1はOBU列）
+    pub data: Bytes,
+}
+
+impl MediaTag {
+    /// Enhanced RTMP のOpus音声タグを作る（`sequence_start` ならデータはOpusHead）
+    pub fn opus(timestamp: u32, sequence_start: bool, data: &[u8]) -> Self {
+        let packet_type = if sequence_start {
+            ex_audio::SEQUENCE_START
+        } else {
+            ex_audio::CODED_FRAMES
+        };
+        let mut tag = BytesMut::with_capacity(data.len() + 5);
+        tag.put_u8((ex_audio::EX_HEADER << 4) | packet_type);
+        tag.put_slice(ex_audio::OPUS);
+        tag.put_slice(data);
+        Self::new(TagKind::Audio, timestamp, tag.freeze())
+    }
+
+    /// Opus音声タグなら (シーケンスヘッダか, OpusHeadまたはOpusパケット)
+    pub fn opus_packet(&self) -> Option<(bool, Bytes)> {
+        let data = &self.data;
+        if self.kind != TagKind::Audio
+            || data.first()? >> 4 != ex_audio::EX_HEADER
+            || data.get(1..5)? != ex_audio::OPUS
+        {
+            return None;
+        }
+        match data[0] & 0x0F {
+            ex_audio::SEQUENCE_START => Some((true, data.slice(5..))),
+            ex_audio::CODED_FRAMES => Some((false, data.slice(5..))),
+            _ => None,
+        }
+    }
+
+    /// 映像/音声のシーケンスヘッダ（デコーダ設定）かどうか
+    pub fn is_sequence_header(&self) -> bool {
+        match self.kind {
+            TagKind::Video => self
+                .video_packet()
+                .is_some_and(|packet| packet.packet_type == VideoPacketType::SequenceStart),
+            TagKind::Audio => {
+                let aac = self.data.len() >= 2
+                    && self.data[0] >> 4 == codec::AUDIO_AAC
+                    && self.data[1] == 0;
+                aac || self.opus_packet().is_some_and(|(sequence_start, _)| sequence_start)
+            }
+            TagKind::Script => false,
+        }
+    }
+
+    /// 映像のキーフレーム（シーケンスヘッダを除く）かどうか
+    pub fn is_keyframe(&self) -> bool {
+        self.video_packet().is_some_and(|packet| {
+            packet.keyframe && packet.packet_type == VideoPacketType::CodedFrames
+        })
+    }
+
+    /// `onMetaData` スクリプトタグかどうか
+    pub fn is_metadata(&self) -> bool {
+        // AMF0 string marker + u16長(10) + "onMetaData"
+        self.kind == TagKind::Script && self.data.starts_with(b"\x02\x00\x0AonMetaData")
+    }
+
+    /// 映像タグのヘッダを解析する（対応していないコーデックやマルチトラックは `None`）
+    pub fn video_packet(&self) -> Option<VideoPacket> {
+        let data = &self.data;
+        if self.kind != TagKind::Video || data.is_empty() {
+            return None;
+        }
+
+        if data[0] & ex_video::EX_HEADER == 0 {
+            // 従来形式: FrameType(4) + CodecID(4) + PacketType(1) + CompositionTime(3)
+            let codec = match data[0] & 0x0F {
+                codec::VIDEO_AVC => VideoCodec::Avc,
+                codec::VIDEO_HEVC => VideoCodec::Hevc,
+                _ => return None,
+            };
+            let frame_type = data[0] >> 4;
+            let packet_type = match data.get(1)? {
+                _ if frame_type == frame_type::COMMAND => VideoPacketType::Other,
+                0 => VideoPacketType::SequenceStart,
+                1 => VideoPacketType::CodedFrames,
+                2 => VideoPacketType::SequenceEnd,
+                _ => VideoPacketType::Other,
+            };
+            return Some(VideoPacket {
+                codec,
+                packet_type,
+                keyframe: frame_type == frame_type::KEYFRAME,
+                composition_time: composition_time(data.get(2..5)?),
+                data: data.slice(5..),
+            });
+        }
+
+        // Enhanced RTMP: IsExHeader(1) + FrameType(3) + PacketType(4) + FourCC(4)
+        let frame_type = (data[0] >> 4) & 0x07;
+        let codec = VideoCodec::from_fourcc(data.get(1..5)?)?;
+        let mut payload = 5;
+        let packet_type = match data[0] & 0x0F {
+            _ if frame_type == frame_type::COMMAND => VideoPacketType::Other,
+            ex_video::SEQUENCE_START => VideoPacketType::SequenceStart,
+            ex_video::CODED_FRAMES | ex_video::CODED_FRAMES_X => VideoPacketType::CodedFrames,
+            ex_video::SEQUENCE_END => VideoPacketType::SequenceEnd,
+            ex_video::METADATA => VideoPacketType::Metadata,
+            _ => VideoPacketType::Other,
+        };
+
+        // AVC/HEVCの CodedFrames だけ CompositionTime が付く
+        let mut cts = 0;
+        if data[0] & 0x0F == ex_video::CODED_FRAMES && codec != VideoCodec::Av1 {
+            cts = composition_time(data.get(5..8)?);
+            payload = 8;
+        }
+        Some(VideoPacket {
+            codec,
+            packet_type,
+            keyframe: frame_type == frame_type::KEYFRAME,
+            composition_time: cts,
+            data: data.slice(payload.min(data.len())..),
+        })
+    }
+}
+
+/// 符号付き24ビットの CompositionTime
+fn composition_time(bytes: &[u8]) -> i32 {
+    i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) >> 8
+}
@@ -0,0 +1,56 @@
+//! テスト用の合成タグ（1280x720 High@4.2 のH.264と48kHzステレオのAAC-LC）
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::tag::{MediaTag, TagKind};
+
+const SPS: &[u8] = &[
+    0x67, 0x64, 0x00, 0x2a, 0xac, 0xd9, 0x40, 0x78, 0x02, 0x27, 0xe5, 0xc0, 0x44, 0x00, 0x00,
+    0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x0f, 0x03, 0xc6, 0x0c, 0x65, 0x80,
+];
+const PPS: &[u8] = &[0x68, 0xeb, 0xe3, 0xcb, 0x22, 0xc0];
+
+/// AudioSpecificConfig（AAC-LC, 48kHz, 2ch）
+pub const ASC: &[u8] = &[0x11, 0x90];
+
+/// AVCDecoderConfigurationRecord
+pub fn avcc() -> Bytes {
+    let mut record = BytesMut::new();
+    record.put_slice(&[1, SPS[1], SPS[2], SPS[3], 0xff, 0xe1]);
+    record.put_u16(SPS.len() as u16);
+    record.put_slice(SPS);
+    record.put_u8(1);
+    record.put_u16(PPS.len() as u16);
+    record.put_slice(PPS);
+    record.freeze()
+}
+
+pub fn avc_sequence_header(timestamp: u32) -> MediaTag {
+    let mut data = BytesMut::from(&[0x17, 0, 0, 0, 0][..]);
+    data.put_slice(&avcc());
+    MediaTag::new(TagKind::Video, timestamp, data.freeze())
+}
+
+/// 1NALUのH.264フレーム（NALUの中身は `payload`）
+pub fn avc_frame(timestamp: u32, keyframe: bool, cts: i32, payload: &[u8]) -> MediaTag {
+    let mut data = BytesMut::new();
+    data.put_u8(if keyframe { 0x17 } else { 0x27 });
+    data.put_u8(1);
+    data.put_slice(&cts.to_be_bytes()[1..]);
+    data.put_u32(payload.len() as u32 + 1);
+    data.put_u8(if keyframe { 0x65 } else { 0x41 });
+    data.put_slice(payload);
+    MediaTag::new(TagKind::Video, timestamp, data.freeze())
+}
+
+pub fn aac_sequence_header(timestamp: u32) -> MediaTag {
+    let mut data = BytesMut::from(&[0xaf, 0][..]);
+    data.put_slice(ASC);
+    MediaTag::new(TagKind::Audio, timestamp, data.freeze())
+}
+
+pub fn aac_frame(timestamp: u32, payload: &[u8]) -> MediaTag {
+    let mut data = BytesMut::from(&[0xaf, 1][..]);
+    data.put_slice(payload);
+    MediaTag::new(TagKind::Audio, timestamp, data.freeze())
+}
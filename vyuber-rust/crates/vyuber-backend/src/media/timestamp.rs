@@ -0,0 +1,302 @@
+//! 配信者から届いたタイムスタンプの正規化と音声/映像の並べ替え
+
+use std::collections::VecDeque;
+
+use super::tag::{MediaTag, TagKind};
+
+/// 直前のタグからこれ以上進んだら不連続とみなす（ミリ秒）
+const MAX_FORWARD_JUMP_MS: i64 = 10_000;
+
+/// 直前のタグからこれ以上戻ったら不連続とみなす（ミリ秒）
+///
+/// 音声と映像の前後関係のずれはこの範囲で許す
+const MAX_BACKWARD_JUMP_MS: i64 = 1000;
+
+/// 不連続の直後を直前の出力からどれだけ進めるかの上限（ミリ秒）
+const MAX_DISCONTINUITY_STEP_MS: i64 = 100;
+
+/// 並べ替えのためにタグを溜めておく最長時間（ミリ秒）
+///
+/// 最新のタグからこれ以上遅れたトラックは途絶えた（ミュートなど）とみなして待たない
+const REORDER_WINDOW_MS: u32 = 500;
+
+/// 配信者のタイムスタンプを、ストリームごとに `base` から始まる連続した値へ変換する
+///
+/// RTMPのタイムスタンプは約49.7日で折り返し、エンコーダの再起動などで0に戻ることもある。
+/// 折り返しはそのまま続きとして扱い、大きく飛んだ場合は直前の出力の続きから数え直す
+pub struct TimestampNormalizer {
+    base: u32,
+    last_input: Option<u32>,
+    /// 最初のタグからの経過時間（ミリ秒）
+    elapsed: i64,
+    /// これまでの `elapsed` の最大値
+    max_elapsed: i64,
+    /// 直近の正の増分（不連続の直後に進める量）
+    last_step: i64,
+}
+
+impl TimestampNormalizer {
+    pub fn new(base: u32) -> Self {
+        Self {
+            base,
+            last_input: None,
+            elapsed: 0,
+            max_elapsed: 0,
+            last_step: 0,
+        }
+    }
+
+    /// 入力のタイムスタンプを出力用に変換する
+    pub fn normalize(&mut self, timestamp: u32) -> u32 {
+        if let Some(last) = self.last_input {
+            // 32ビットの差として見れば、折り返しをまたいでも正しい増分になる
+            let delta = timestamp.wrapping_sub(last) as i32 as i64;
+            if !(-MAX_BACKWARD_JUMP_MS..=MAX_FORWARD_JUMP_MS).contains(&delta) {
+                tracing::debug!(
+                    "[Timestamp] Discontinuity: {} -> {}, rebasing",
+                    last,
+                    timestamp
+                );
+                self.elapsed = self.max_elapsed + self.last_step;
+            } else {
+                self.elapsed += delta;
+                if delta > 0 {
+                    self.last_step = delta.min(MAX_DISCONTINUITY_STEP_MS);
+                }
+            }
+        }
+        self.last_input = Some(timestamp);
+        self.max_elapsed = self.max_elapsed.max(self.elapsed);
+        // 出力も32ビットなので、折り返しは配信開始から約49.7日後になる
+        self.base.wrapping_add(self.elapsed.max(0) as u32)
+    }
+}
+
+/// 音声と映像のタグをタイムスタンプ順に並べ直す小さなバッファ
+///
+/// エンコーダによっては音声と映像が少し前後して届くので、
+/// 両方のトラックがそのタイムスタンプに追いつくまで（最長 `REORDER_WINDOW_MS`）待ってから送り出す。
+/// 片方のトラックが途絶えたら、もう片方は待たずに送り出す
+#[derive(Default)]
+pub struct ReorderBuffer {
+    /// タイムスタンプ順（同じなら到着順）
+    tags: VecDeque<MediaTag>,
+    latest_audio: Option<u32>,
+    latest_video: Option<u32>,
+    last_output: Option<u32>,
+}
+
+impl ReorderBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// タグを追加する
+    pub fn push(&mut self, tag: MediaTag) {
+        let latest = match tag.kind {
+            TagKind::Audio => Some(&mut self.latest_audio),
+            TagKind::Video => Some(&mut self.latest_video),
+            TagKind::Script => None,
+        };
+        if let Some(latest) = latest {
+            if latest.is_none_or(|latest| not_after(latest, tag.timestamp)) {
+                *latest = Some(tag.timestamp);
+            }
+        }
+
+        // 後ろから見て、同じか前のタイムスタンプの直後に入れる
+        let index = self
+            .tags
+            .iter()
+            .rposition(|queued| not_after(queued.timestamp, tag.timestamp))
+            .map_or(0, |i| i + 1);
+        self.tags.insert(index, tag);
+    }
+
+    /// 送り出せるタグを1つ取り出す
+    pub fn pop(&mut self) -> Option<MediaTag> {
+        let head = self.tags.front()?.timestamp;
+        let newest = self.tags.back()?.timestamp;
+        let caught_up = [self.latest_audio, self.latest_video]
+            .into_iter()
+            .flatten()
+            .filter(|&latest| !is_stale(latest, newest))
+            .all(|latest| not_after(head, latest));
+        let expired = newest.wrapping_sub(head) as i32 >= REORDER_WINDOW_MS as i32;
+        if !caught_up && !expired {
+            return None;
+        }
+        self.take()
+    }
+
+    /// 溜まっているタグを待たずに取り出す（配信終了時）
+    pub fn flush(&mut self) -> Option<MediaTag> {
+        self.take()
+    }
+
+    /// 先頭のタグを、出力が戻らないよう直前のタイムスタンプ以上にして取り出す
+    fn take(&mut self) -> Option<MediaTag> {
+        let mut tag = self.tags.pop_front()?;
+        if let Some(last) = self.last_output {
+            if !not_after(last, tag.timestamp) {
+                tag.timestamp = last;
+            }
+        }
+        self.last_output = Some(tag.timestamp);
+        Some(tag)
+    }
+}
+
+/// 折り返しを考慮して `a <= b` か
+fn not_after(a: u32, b: u32) -> bool {
+    b.wrapping_sub(a) as i32 >= 0
+}
+
+/// 最後のタグが `newest` から `REORDER_WINDOW_MS` 以上遅れている（途絶えた）トラックか
+fn is_stale(latest: u32, newest: u32) -> bool {
+    newest.wrapping_sub(latest) as i32 >= REORDER_WINDOW_MS as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn normalize_all(normalizer: &mut TimestampNormalizer, input: &[u32]) -> Vec<u32> {
+        input.iter().map(|&ts| normalizer.normalize(ts)).collect()
+    }
+
+    #[test]
+    fn continues_across_32bit_wrap() {
+        let mut normalizer = TimestampNormalizer::new(0);
+        let start = u32::MAX - 50;
+        let input: Vec<u32> = (0..5).map(|i| start.wrapping_add(i * 33)).collect();
+        assert_eq!(normalize_all(&mut normalizer, &input), vec![0, 33, 66, 99, 132]);
+    }
+
+    #[test]
+    fn rebases_after_reset_and_jumps() {
+        let mut normalizer = TimestampNormalizer::new(1000);
+        // エンコーダの再起動で0に戻る
+        assert_eq!(
+            normalize_all(&mut normalizer, &[50_000, 50_040, 50_080, 0, 40, 80]),
+            vec![1000, 1040, 1080, 1120, 1160, 1200]
+        );
+        // 大きく進んだ場合も直前の続きから数え直す
+        assert_eq!(
+            normalize_all(&mut normalizer, &[3_600_000, 3_600_040]),
+            vec![1240, 1280]
+        );
+        // 直前の増分が大きくても不連続の直後は `MAX_DISCONTINUITY_STEP_MS` しか進めない
+        let mut normalizer = TimestampNormalizer::new(0);
+        assert_eq!(
+            normalize_all(&mut normalizer, &[0, 5000, 1_000_000]),
+            vec![0, 5000, 5100]
+        );
+    }
+
+    #[test]
+    fn keeps_small_backward_steps_between_tracks() {
+        let mut normalizer = TimestampNormalizer::new(0);
+        // 音声が映像より少し前のタイムスタンプで届く
+        assert_eq!(
+            normalize_all(&mut normalizer, &[100, 140, 120, 180, 160]),
+            vec![0, 40, 20, 80, 60]
+        );
+        // 出力は負にならない
+        let mut normalizer = TimestampNormalizer::new(0);
+        assert_eq!(normalize_all(&mut normalizer, &[500, 200]), vec![0, 0]);
+    }
+
+    fn tag(kind: TagKind, timestamp: u32) -> MediaTag {
+        MediaTag::new(kind, timestamp, Bytes::new())
+    }
+
+    fn pop_all(buffer: &mut ReorderBuffer) -> Vec<(TagKind, u32)> {
+        std::iter::from_fn(|| buffer.pop())
+            .map(|tag| (tag.kind, tag.timestamp))
+            .collect()
+    }
+
+    #[test]
+    fn reorders_interleaved_tracks() {
+        let mut buffer = ReorderBuffer::new();
+        let mut output = Vec::new();
+        for (kind, timestamp) in [
+            (TagKind::Video, 0),
+            (TagKind::Audio, 0),
+            (TagKind::Video, 40),
+            (TagKind::Video, 80),
+            (TagKind::Audio, 21),
+            (TagKind::Audio, 42),
+            (TagKind::Audio, 64),
+            (TagKind::Audio, 85),
+        ] {
+            buffer.push(tag(kind, timestamp));
+            output.extend(pop_all(&mut buffer));
+        }
+        assert_eq!(
+            output,
+            vec![
+                (TagKind::Video, 0),
+                (TagKind::Audio, 0),
+                (TagKind::Audio, 21),
+                (TagKind::Video, 40),
+                (TagKind::Audio, 42),
+                (TagKind::Audio, 64),
+                (TagKind::Video, 80),
+            ]
+        );
+        assert_eq!(
+            std::iter::from_fn(|| buffer.flush())
+                .map(|tag| tag.timestamp)
+                .collect::<Vec<_>>(),
+            vec![85]
+        );
+    }
+
+    #[test]
+    fn stops_waiting_for_a_track_that_went_silent() {
+        let mut buffer = ReorderBuffer::new();
+        for timestamp in (0..1000).step_by(20) {
+            buffer.push(tag(TagKind::Video, timestamp));
+            buffer.push(tag(TagKind::Audio, timestamp));
+            pop_all(&mut buffer);
+        }
+
+        // 音声が980で途絶えた後、映像は窓の分だけ待たされるが、その後は溜まらない
+        let mut held = Vec::new();
+        for timestamp in (1000..5000).step_by(40) {
+            buffer.push(tag(TagKind::Video, timestamp));
+            pop_all(&mut buffer);
+            held.push(buffer.tags.len());
+        }
+        assert!(held[..12].iter().all(|&held| held > 0));
+        assert!(held[13..].iter().all(|&held| held == 0), "{:?}", held);
+
+        // 音声が戻ればまた並べ替える
+        buffer.push(tag(TagKind::Audio, 4980));
+        buffer.push(tag(TagKind::Video, 5000));
+        assert_eq!(pop_all(&mut buffer), vec![(TagKind::Audio, 4980)]);
+        buffer.push(tag(TagKind::Audio, 5001));
+        assert_eq!(pop_all(&mut buffer), vec![(TagKind::Video, 5000)]);
+    }
+
+    #[test]
+    fn orders_across_32bit_wrap() {
+        let mut buffer = ReorderBuffer::new();
+        let before = u32::MAX - 10;
+        buffer.push(tag(TagKind::Video, before));
+        buffer.push(tag(TagKind::Video, 30));
+        buffer.push(tag(TagKind::Audio, 5));
+        buffer.push(tag(TagKind::Audio, 40));
+        assert_eq!(
+            pop_all(&mut buffer),
+            vec![
+                (TagKind::Video, before),
+                (TagKind::Audio, 5),
+                (TagKind::Video, 30),
+            ]
+        );
+    }
+}
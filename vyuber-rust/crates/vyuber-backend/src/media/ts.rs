@@ -0,0 +1,401 @@
+//! MPEG-TS マルチプレクサ（H.264 + AAC/ADTS）
+
+use bytes::{BufMut, BytesMut};
+
+pub const PACKET_SIZE: usize = 188;
+
+pub const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0x0000;
+const PMT_PID: u16 = 0x1000;
+pub const VIDEO_PID: u16 = 0x0100;
+pub const AUDIO_PID: u16 = 0x0101;
+const PROGRAM_NUMBER: u16 = 1;
+
+/// PMTの stream_type
+pub mod stream_type {
+    pub const H264: u8 = 0x1B;
+    pub const AAC_ADTS: u8 = 0x0F;
+}
+
+/// PESの stream_id
+pub mod stream_id {
+    pub const VIDEO: u8 = 0xE0;
+    pub const AUDIO: u8 = 0xC0;
+}
+
+/// PTS/DTS は33ビット
+const TIMESTAMP_MASK: u64 = (1 << 33) - 1;
+
+/// PESに載せる1フレーム分のデータ
+pub struct Pes<'a> {
+    pub pid: u16,
+    pub stream_id: u8,
+    /// 90kHz
+    pub pts: u64,
+    /// 90kHz（PTSと同じなら `None`）
+    pub dts: Option<u64>,
+    /// PCRを付ける場合のクロック値（90kHz）
+    pub pcr: Option<u64>,
+    pub random_access: bool,
+    pub payload: &'a [u8],
+}
+
+/// PID毎の連続性カウンタを保持し、PSIとPESをTSパケット列に書き出す
+pub struct TsMuxer {
+    has_audio: bool,
+    has_video: bool,
+    pat_cc: u8,
+    pmt_cc: u8,
+    video_cc: u8,
+    audio_cc: u8,
+}
+
+impl TsMuxer {
+    pub fn new(has_audio: bool, has_video: bool) -> Self {
+        Self {
+            has_audio,
+            has_video,
+            pat_cc: 0,
+            pmt_cc: 0,
+            video_cc: 0,
+            audio_cc: 0,
+        }
+    }
+
+    /// トラック構成を変更する（次の `write_tables` から反映）
+    pub fn set_tracks(&mut self, has_audio: bool, has_video: bool) {
+        self.has_audio = has_audio;
+        self.has_video = has_video;
+    }
+
+    /// PCRを載せるPID（映像があれば映像）
+    pub fn pcr_pid(&self) -> u16 {
+        if self.has_video {
+            VIDEO_PID
+        } else {
+            AUDIO_PID
+        }
+    }
+
+    /// PATとPMTを書き出す（セグメントの先頭に置く）
+    pub fn write_tables(&mut self, out: &mut BytesMut) {
+        let mut pat = Vec::new();
+        pat.put_u16(PROGRAM_NUMBER);
+        pat.put_u16(0xE000 | PMT_PID);
+        let cc = next_cc(&mut self.pat_cc);
+        write_section(out, PAT_PID, cc, 0x00, 0x0001, &pat);
+
+        let mut pmt = Vec::new();
+        pmt.put_u16(0xE000 | self.pcr_pid());
+        // program_info_length = 0
+        pmt.put_u16(0xF000);
+        let streams = [
+            (self.has_video, stream_type::H264, VIDEO_PID),
+            (self.has_audio, stream_type::AAC_ADTS, AUDIO_PID),
+        ];
+        for (_, stream_type, pid) in streams.into_iter().filter(|(enabled, ..)| *enabled) {
+            pmt.put_u8(stream_type);
+            pmt.put_u16(0xE000 | pid);
+            // ES_info_length = 0
+            pmt.put_u16(0xF000);
+        }
+        let cc = next_cc(&mut self.pmt_cc);
+        write_section(out, PMT_PID, cc, 0x02, PROGRAM_NUMBER, &pmt);
+    }
+
+    /// PESパケットをTSパケット列に分割して書き出す
+    pub fn write_pes(&mut self, pes: &Pes, out: &mut BytesMut) {
+        let mut data = Vec::with_capacity(pes.payload.len() + 19);
+        write_pes_header(pes, &mut data);
+        data.extend_from_slice(pes.payload);
+
+        let mut remaining = &data[..];
+        let mut first = true;
+        while !remaining.is_empty() {
+            let mut adaptation = Vec::new();
+            if first && (pes.pcr.is_some() || pes.random_access) {
+                let mut flags = 0u8;
+                if pes.random_access {
+                    flags |= 0x40;
+                }
+                adaptation.push(flags);
+                if let Some(pcr) = pes.pcr {
+                    adaptation[0] |= 0x10;
+                    write_pcr(pcr, &mut adaptation);
+                }
+            }
+
+            let mut has_adaptation = !adaptation.is_empty();
+            let space = payload_space(has_adaptation, &adaptation);
+            if remaining.len() < space {
+                // 最後のパケットはアダプテーションフィールドで詰める
+                let mut stuffing = space - remaining.len();
+                if !has_adaptation {
+                    has_adaptation = true;
+                    stuffing -= 1;
+                    if stuffing > 0 {
+                        adaptation.push(0x00);
+                        stuffing -= 1;
+                    }
+                }
+                adaptation.resize(adaptation.len() + stuffing, 0xFF);
+            }
+
+            let cc = match pes.pid {
+                VIDEO_PID => next_cc(&mut self.video_cc),
+                _ => next_cc(&mut self.audio_cc),
+            };
+            out.put_u8(SYNC_BYTE);
+            out.put_u16(if first { 0x4000 } else { 0 } | pes.pid);
+            out.put_u8(if has_adaptation { 0x30 } else { 0x10 } | cc);
+            if has_adaptation {
+                out.put_u8(adaptation.len() as u8);
+                out.put_slice(&adaptation);
+            }
+
+            let len = remaining.len().min(payload_space(has_adaptation, &adaptation));
+            out.put_slice(&remaining[..len]);
+            remaining = &remaining[len..];
+            first = false;
+        }
+    }
+}
+
+/// アダプテーションフィールドを除いたペイロード部のバイト数
+fn payload_space(has_adaptation: bool, adaptation: &[u8]) -> usize {
+    PACKET_SIZE - 4 - if has_adaptation { 1 + adaptation.len() } else { 0 }
+}
+
+/// 連続性カウンタを進め、現在値を返す
+fn next_cc(cc: &mut u8) -> u8 {
+    let current = *cc;
+    *cc = (*cc + 1) & 0x0F;
+    current
+}
+
+/// PSIセクションを1パケットに書き出す
+fn write_section(out: &mut BytesMut, pid: u16, cc: u8, table_id: u8, id: u16, body: &[u8]) {
+    let mut section = Vec::with_capacity(body.len() + 12);
+    section.put_u8(table_id);
+    // section_syntax_indicator = 1, section_length = 5(ヘッダ残り) + body + 4(CRC)
+    section.put_u16(0xB000 | (5 + body.len() + 4) as u16);
+    section.put_u16(id);
+    // version 0, current_next_indicator = 1
+    section.put_u8(0xC1);
+    // section_number, last_section_number
+    section.put_u16(0);
+    section.put_slice(body);
+    let crc = crc32_mpeg2(&section);
+    section.put_u32(crc);
+
+    let start = out.len();
+    out.put_u8(SYNC_BYTE);
+    out.put_u16(0x4000 | pid);
+    out.put_u8(0x10 | cc);
+    // pointer_field
+    out.put_u8(0);
+    out.put_slice(&section);
+    out.resize(start + PACKET_SIZE, 0xFF);
+}
+
+fn write_pes_header(pes: &Pes, out: &mut Vec<u8>) {
+    let header_data_len: u8 = if pes.dts.is_some() { 10 } else { 5 };
+    out.put_slice(&[0, 0, 1, pes.stream_id]);
+    // 映像や大きなPESは長さ0（無制限）
+    let packet_len = 3 + header_data_len as usize + pes.payload.len();
+    if pes.stream_id == stream_id::VIDEO || packet_len > 0xFFFF {
+        out.put_u16(0);
+    } else {
+        out.put_u16(packet_len as u16);
+    }
+    // marker '10', data_alignment_indicator
+    out.put_u8(0x84);
+    match pes.dts {
+        Some(dts) => {
+            out.put_u8(0xC0);
+            out.put_u8(header_data_len);
+            write_timestamp(0x3, pes.pts, out);
+            write_timestamp(0x1, dts, out);
+        }
+        None => {
+            out.put_u8(0x80);
+            out.put_u8(header_data_len);
+            write_timestamp(0x2, pes.pts, out);
+        }
+    }
+}
+
+/// PTS/DTS の5バイト表現
+fn write_timestamp(prefix: u8, ts: u64, out: &mut Vec<u8>) {
+    let ts = ts & TIMESTAMP_MASK;
+    out.put_u8((prefix << 4) | (((ts >> 30) as u8 & 0x07) << 1) | 1);
+    out.put_u16((((ts >> 15) as u16 & 0x7FFF) << 1) | 1);
+    out.put_u16((((ts as u16) & 0x7FFF) << 1) | 1);
+}
+
+/// PCRの6バイト表現（拡張部は0）
+fn write_pcr(pcr: u64, out: &mut Vec<u8>) {
+    let base = pcr & TIMESTAMP_MASK;
+    out.put_u32((base >> 1) as u32);
+    out.put_u8((((base & 1) as u8) << 7) | 0x7E);
+    out.put_u8(0);
+}
+
+/// MPEG-2 のCRC32（PSIセクション用）
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 188バイトずつに分け、どれも同期バイトで始まることを確かめる
+    fn packets(data: &[u8]) -> Vec<&[u8]> {
+        assert_eq!(data.len() % PACKET_SIZE, 0);
+        let packets: Vec<&[u8]> = data.chunks(PACKET_SIZE).collect();
+        assert!(packets.iter().all(|packet| packet[0] == SYNC_BYTE));
+        packets
+    }
+
+    fn pid(packet: &[u8]) -> u16 {
+        u16::from_be_bytes([packet[1], packet[2]]) & 0x1FFF
+    }
+
+    fn cc(packet: &[u8]) -> u8 {
+        packet[3] & 0x0F
+    }
+
+    /// アダプテーションフィールド（なければ空）
+    fn adaptation(packet: &[u8]) -> &[u8] {
+        if packet[3] & 0x20 != 0 {
+            &packet[5..5 + packet[4] as usize]
+        } else {
+            &[]
+        }
+    }
+
+    fn payload(packet: &[u8]) -> &[u8] {
+        if packet[3] & 0x20 != 0 {
+            &packet[5 + packet[4] as usize..]
+        } else {
+            &packet[4..]
+        }
+    }
+
+    fn pes<'a>(pid: u16, pcr: Option<u64>, payload: &'a [u8]) -> Pes<'a> {
+        Pes {
+            pid,
+            stream_id: if pid == VIDEO_PID {
+                stream_id::VIDEO
+            } else {
+                stream_id::AUDIO
+            },
+            pts: 90_000,
+            dts: None,
+            pcr,
+            random_access: pcr.is_some(),
+            payload,
+        }
+    }
+
+    #[test]
+    fn computes_mpeg2_crc32() {
+        // CRC-32/MPEG-2 のチェック値
+        assert_eq!(crc32_mpeg2(b"123456789"), 0x0376_E6E7);
+    }
+
+    #[test]
+    fn writes_pat_and_pmt_with_valid_crc() {
+        let mut muxer = TsMuxer::new(true, true);
+        let mut out = BytesMut::new();
+        muxer.write_tables(&mut out);
+        let packets = packets(&out);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(pid(packets[0]), PAT_PID);
+        assert_eq!(pid(packets[1]), PMT_PID);
+
+        for packet in &packets {
+            // pointer_field の後のセクション全体（CRCを含む）のCRCは0になる
+            let section = &packet[5..];
+            let section_length = (u16::from_be_bytes([section[1], section[2]]) & 0x0FFF) as usize;
+            assert_eq!(crc32_mpeg2(&section[..3 + section_length]), 0);
+            assert!(section[3 + section_length..].iter().all(|&b| b == 0xFF));
+        }
+
+        // PMT: PCR_PID は映像、映像と音声のストリームが並ぶ
+        let pmt = &packets[1][5..];
+        assert_eq!(u16::from_be_bytes([pmt[8], pmt[9]]) & 0x1FFF, VIDEO_PID);
+        assert_eq!(pmt[12], stream_type::H264);
+        assert_eq!(u16::from_be_bytes([pmt[13], pmt[14]]) & 0x1FFF, VIDEO_PID);
+        assert_eq!(pmt[17], stream_type::AAC_ADTS);
+        assert_eq!(u16::from_be_bytes([pmt[18], pmt[19]]) & 0x1FFF, AUDIO_PID);
+    }
+
+    #[test]
+    fn splits_pes_with_pcr_and_stuffs_the_last_packet() {
+        let mut muxer = TsMuxer::new(true, true);
+        let data: Vec<u8> = (0..400).map(|i| i as u8).collect();
+        let mut out = BytesMut::new();
+        muxer.write_pes(&pes(VIDEO_PID, Some(45_000), &data), &mut out);
+        let packets = packets(&out);
+        assert_eq!(packets.len(), 3);
+
+        // 先頭パケットだけが payload_unit_start と PCR を持つ
+        assert_eq!(packets[0][1] & 0x40, 0x40);
+        assert!(packets[1..].iter().all(|packet| packet[1] & 0x40 == 0));
+        let field = adaptation(packets[0]);
+        assert_eq!(field[0], 0x50);
+        let base = (u32::from_be_bytes(field[1..5].try_into().unwrap()) as u64) << 1
+            | (field[5] >> 7) as u64;
+        assert_eq!(base, 45_000);
+
+        // 中間はアダプテーションフィールドなし、最後は0xFFで詰める
+        assert!(adaptation(packets[1]).is_empty());
+        let last = adaptation(packets[2]);
+        assert_eq!(last[0], 0x00);
+        assert!(last[1..].iter().all(|&b| b == 0xFF));
+
+        let pes: Vec<u8> = packets
+            .iter()
+            .flat_map(|packet| payload(packet))
+            .copied()
+            .collect();
+        assert_eq!(&pes[..4], &[0, 0, 1, stream_id::VIDEO]);
+        // 5バイトのPTSだけを持つヘッダの後にペイロードが続く
+        assert_eq!(&pes[14..], &data[..]);
+    }
+
+    #[test]
+    fn wraps_continuity_counters_per_pid() {
+        let mut muxer = TsMuxer::new(true, true);
+        let mut out = BytesMut::new();
+        for i in 0..20 {
+            muxer.write_pes(&pes(AUDIO_PID, None, &[0xAA; 16]), &mut out);
+            if i % 5 == 0 {
+                muxer.write_pes(&pes(VIDEO_PID, Some(0), &[0xBB; 16]), &mut out);
+            }
+        }
+        let counters = |target| -> Vec<u8> {
+            packets(&out)
+                .into_iter()
+                .filter(|packet| pid(packet) == target)
+                .map(cc)
+                .collect()
+        };
+        let audio: Vec<u8> = (0..20).map(|i| i % 16).collect();
+        assert_eq!(counters(AUDIO_PID), audio);
+        assert_eq!(counters(VIDEO_PID), vec![0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,279 @@
+//! MPEG-TS デマルチプレクサ（H.264 + AAC/ADTS → ハブのタグ）
+
+use bytes::{BufMut, BytesMut};
+use std::collections::HashMap;
+
+use super::aac::AacConfig;
+use super::avc::AvcTagBuilder;
+use super::tag::{codec, MediaTag, TagKind};
+use super::ts::{stream_type, PACKET_SIZE, SYNC_BYTE};
+
+const PAT_PID: u16 = 0x0000;
+
+/// PTS/DTS は33ビット
+const TIMESTAMP_BITS: u32 = 33;
+
+/// AACの1フレームあたりのサンプル数
+const AAC_SAMPLES_PER_FRAME: u64 = 1024;
+
+/// FLVのAudioTagHeader（AAC, 44kHz, 16bit, ステレオ。AACでは常にこの値）
+const AAC_TAG_HEADER: u8 = (codec::AUDIO_AAC << 4) | 0x0F;
+
+/// 組み立て中のPESパケット
+#[derive(Default)]
+struct PesBuffer {
+    data: Vec<u8>,
+}
+
+/// 33ビットのPTS/DTSを折り返しのない値にする
+#[derive(Default)]
+struct TimestampUnwrapper {
+    last: Option<(u64, i64)>,
+}
+
+impl TimestampUnwrapper {
+    fn unwrap(&mut self, timestamp: u64) -> i64 {
+        let extended = match self.last {
+            // 音声と映像の前後で少し戻ることもあるので、差は符号付きで見る
+            Some((last, extended)) => extended + signed_delta(timestamp, last),
+            None => timestamp as i64,
+        };
+        self.last = Some((timestamp, extended));
+        extended
+    }
+}
+
+/// TSパケット列からH.264とAACを取り出し、FLVと同じ形式のタグにする
+///
+/// 映像はAnnex-Bから長さプレフィックス形式へ、音声はADTSから生のAACへ変換し、
+/// SPS/PPSやADTSヘッダが変わったらシーケンスヘッダのタグを先に出す
+#[derive(Default)]
+pub struct TsDemuxer {
+    /// パケット境界に満たない残り
+    remainder: Vec<u8>,
+    pmt_pid: Option<u16>,
+    video_pid: Option<u16>,
+    audio_pid: Option<u16>,
+    pes: HashMap<u16, PesBuffer>,
+    timestamps: TimestampUnwrapper,
+    video: AvcTagBuilder,
+    audio_config: Option<AacConfig>,
+}
+
+impl TsDemuxer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 受信したデータを処理し、できあがったタグを `out` に追加する
+    ///
+    /// データはTSパケットの境界で区切られていなくてもよい
+    pub fn push(&mut self, data: &[u8], out: &mut Vec<MediaTag>) {
+        self.remainder.extend_from_slice(data);
+        let mut offset = 0;
+        while self.remainder.len() - offset >= PACKET_SIZE {
+            if self.remainder[offset] != SYNC_BYTE {
+                // 同期が外れたら次の同期バイトを探す
+                offset += 1;
+                continue;
+            }
+            let packet: [u8; PACKET_SIZE] = self.remainder[offset..offset + PACKET_SIZE]
+                .try_into()
+                .unwrap();
+            self.on_packet(&packet, out);
+            offset += PACKET_SIZE;
+        }
+        self.remainder.drain(..offset);
+    }
+
+    /// 組み立て中のPESを出し切る（入力の終わり）
+    pub fn flush(&mut self, out: &mut Vec<MediaTag>) {
+        let pids: Vec<u16> = self.pes.keys().copied().collect();
+        for pid in pids {
+            self.finish_pes(pid, out);
+        }
+    }
+
+    fn on_packet(&mut self, packet: &[u8; PACKET_SIZE], out: &mut Vec<MediaTag>) {
+        let payload_start = packet[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([packet[1] & 0x1F, packet[2]]);
+        let adaptation_control = (packet[3] >> 4) & 0x03;
+
+        let mut payload = &packet[4..];
+        if adaptation_control & 0x02 != 0 {
+            let Some(rest) = payload.get(1 + payload[0] as usize..) else {
+                return;
+            };
+            payload = rest;
+        }
+        if adaptation_control & 0x01 == 0 || payload.is_empty() {
+            return;
+        }
+
+        if pid == PAT_PID {
+            if payload_start {
+                self.on_pat(payload);
+            }
+        } else if Some(pid) == self.pmt_pid {
+            if payload_start {
+                self.on_pmt(payload);
+            }
+        } else if Some(pid) == self.video_pid || Some(pid) == self.audio_pid {
+            if payload_start {
+                self.finish_pes(pid, out);
+            } else if !self.pes.contains_key(&pid) {
+                // 途中から受信した場合は次のPESの先頭まで待つ
+                return;
+            }
+            self.pes
+                .entry(pid)
+                .or_default()
+                .data
+                .extend_from_slice(payload);
+        }
+    }
+
+    /// PATから最初の番組のPMTのPIDを読む
+    fn on_pat(&mut self, payload: &[u8]) {
+        let Some(section) = psi_section(payload) else {
+            return;
+        };
+        for program in section.chunks_exact(4) {
+            let number = u16::from_be_bytes([program[0], program[1]]);
+            if number != 0 {
+                self.pmt_pid = Some(u16::from_be_bytes([program[2] & 0x1F, program[3]]));
+                return;
+            }
+        }
+    }
+
+    /// PMTからH.264とAACのPIDを読む
+    fn on_pmt(&mut self, payload: &[u8]) {
+        let Some(section) = psi_section(payload) else {
+            return;
+        };
+        let Some(info_len) = section.get(2..4) else {
+            return;
+        };
+        let info_len = u16::from_be_bytes([info_len[0] & 0x0F, info_len[1]]) as usize;
+        let mut streams = section.get(4 + info_len..).unwrap_or_default();
+
+        while let [kind, pid_hi, pid_lo, len_hi, len_lo, rest @ ..] = streams {
+            let pid = u16::from_be_bytes([pid_hi & 0x1F, *pid_lo]);
+            let es_info_len = u16::from_be_bytes([len_hi & 0x0F, *len_lo]) as usize;
+            match *kind {
+                stream_type::H264 if self.video_pid.is_none() => self.video_pid = Some(pid),
+                stream_type::AAC_ADTS if self.audio_pid.is_none() => self.audio_pid = Some(pid),
+                stream_type::H264 | stream_type::AAC_ADTS => {}
+                other => {
+                    tracing::debug!("[TS] Ignoring stream type 0x{:02X} on PID {}", other, pid);
+                }
+            }
+            streams = rest.get(es_info_len..).unwrap_or_default();
+        }
+    }
+
+    /// 組み立て終わったPESをタグに変換する
+    fn finish_pes(&mut self, pid: u16, out: &mut Vec<MediaTag>) {
+        let Some(pes) = self.pes.remove(&pid) else {
+            return;
+        };
+        let Some((pts, dts, payload)) = parse_pes(&pes.data) else {
+            return;
+        };
+        let composition_offset = signed_delta(pts, dts);
+        let dts = self.timestamps.unwrap(dts);
+        let pts = dts + composition_offset;
+
+        if Some(pid) == self.video_pid {
+            self.on_video(pts, dts, payload, out);
+        } else {
+            self.on_audio(pts, payload, out);
+        }
+    }
+
+    fn on_video(&mut self, pts: i64, dts: i64, data: &[u8], out: &mut Vec<MediaTag>) {
+        let composition_time = ((pts - dts) / 90) as i32;
+        self.video
+            .push(data, to_millis(dts), composition_time, out);
+    }
+
+    fn on_audio(&mut self, pts: i64, mut data: &[u8], out: &mut Vec<MediaTag>) {
+        // 1つのPESに複数のADTSフレームが入っていることがある
+        let mut frames = 0;
+        while let Some((config, frame, rest)) = AacConfig::parse_adts(data) {
+            data = rest;
+            let sample_rate = config.sample_rate().unwrap_or(44100) as i64;
+            let timestamp = to_millis(
+                pts + frames * AAC_SAMPLES_PER_FRAME as i64 * 90_000 / sample_rate,
+            );
+            frames += 1;
+
+            if self.audio_config != Some(config) {
+                let mut tag = BytesMut::new();
+                tag.put_slice(&[AAC_TAG_HEADER, 0]);
+                tag.put_slice(&config.audio_specific_config());
+                out.push(MediaTag::new(TagKind::Audio, timestamp, tag.freeze()));
+                self.audio_config = Some(config);
+            }
+
+            let mut tag = BytesMut::with_capacity(frame.len() + 2);
+            tag.put_slice(&[AAC_TAG_HEADER, 1]);
+            tag.put_slice(frame);
+            out.push(MediaTag::new(TagKind::Audio, timestamp, tag.freeze()));
+        }
+    }
+}
+
+/// PSIのセクション本体（ヘッダとCRCを除いた部分）
+fn psi_section(payload: &[u8]) -> Option<&[u8]> {
+    let pointer = *payload.first()? as usize;
+    let section = payload.get(1 + pointer..)?;
+    let len = u16::from_be_bytes([section.get(1)? & 0x0F, *section.get(2)?]) as usize;
+    // table_id〜section_length の3バイト + 拡張ヘッダ5バイト、末尾のCRC 4バイト
+    section.get(8..(3 + len).checked_sub(4)?)
+}
+
+/// PESヘッダから (PTS, DTS, ペイロード) を読む（DTSがなければPTSと同じ）
+fn parse_pes(data: &[u8]) -> Option<(u64, u64, &[u8])> {
+    if data.get(..3)? != [0, 0, 1] {
+        return None;
+    }
+    let flags = *data.get(7)?;
+    let header_len = *data.get(8)? as usize;
+    let payload = data.get(9 + header_len..)?;
+
+    let pts = match flags >> 6 {
+        0b10 | 0b11 => read_timestamp(data.get(9..14)?),
+        _ => return None,
+    };
+    let dts = match flags >> 6 {
+        0b11 => read_timestamp(data.get(14..19)?),
+        _ => pts,
+    };
+    Some((pts, dts, payload))
+}
+
+/// PESヘッダの5バイトのタイムスタンプ
+fn read_timestamp(b: &[u8]) -> u64 {
+    ((b[0] as u64 >> 1) & 0x07) << 30
+        | (b[1] as u64) << 22
+        | (b[2] as u64 >> 1) << 15
+        | (b[3] as u64) << 7
+        | b[4] as u64 >> 1
+}
+
+/// 33ビットのタイムスタンプの差 `a - b`（折り返しを考慮した符号付き）
+fn signed_delta(a: u64, b: u64) -> i64 {
+    let delta = a.wrapping_sub(b) & ((1 << TIMESTAMP_BITS) - 1);
+    if delta >= 1 << (TIMESTAMP_BITS - 1) {
+        delta as i64 - (1 << TIMESTAMP_BITS)
+    } else {
+        delta as i64
+    }
+}
+
+/// 90kHzのクロック値をミリ秒にする（32ビットで折り返す）
+fn to_millis(clock: i64) -> u32 {
+    clock.div_euclid(90) as u32
+}
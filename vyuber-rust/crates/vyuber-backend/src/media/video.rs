@@ -0,0 +1,89 @@
+//! コーデックごとの映像デコーダ設定をまとめて扱う
+
+use super::av1::{self, Av1Config};
+use super::avc::AvcConfig;
+use super::hevc::{self, HevcConfig};
+use super::tag::VideoCodec;
+
+/// シーケンスヘッダのデコーダ設定レコード
+#[derive(Debug, Clone, PartialEq)]
+pub enum VideoConfig {
+    Avc(AvcConfig),
+    Hevc(HevcConfig),
+    Av1(Av1Config),
+}
+
+impl VideoConfig {
+    /// avcC / hvcC / av1C を解析する（不正なら `None`）
+    pub fn parse(codec: VideoCodec, record: &[u8]) -> Option<Self> {
+        match codec {
+            VideoCodec::Avc => AvcConfig::parse(record).map(VideoConfig::Avc),
+            VideoCodec::Hevc => HevcConfig::parse(record).map(VideoConfig::Hevc),
+            VideoCodec::Av1 => Av1Config::parse(record).map(VideoConfig::Av1),
+        }
+    }
+
+    /// RFC 6381 のコーデック文字列
+    pub fn codec_string(&self) -> String {
+        match self {
+            VideoConfig::Avc(config) => config.codec_string(),
+            VideoConfig::Hevc(config) => config.codec_string(),
+            VideoConfig::Av1(config) => config.codec_string(),
+        }
+    }
+
+    /// 表示サイズ（幅, 高さ）
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        match self {
+            VideoConfig::Avc(config) => config.dimensions(),
+            VideoConfig::Hevc(config) => config.dimensions(),
+            VideoConfig::Av1(config) => config.dimensions(),
+        }
+    }
+
+    /// `StreamInfo` に載せるコーデック名
+    pub fn codec_name(&self) -> &'static str {
+        match self {
+            VideoConfig::Avc(_) => "H.264",
+            VideoConfig::Hevc(_) => "HEVC",
+            VideoConfig::Av1(_) => "AV1",
+        }
+    }
+
+    /// `StreamInfo` に載せるプロファイル名
+    pub fn profile_name(&self) -> String {
+        match self {
+            VideoConfig::Avc(config) => config.profile_name(),
+            VideoConfig::Hevc(config) => hevc::profile_name(config.profile),
+            VideoConfig::Av1(config) => av1::profile_name(config.profile),
+        }
+    }
+
+    /// `StreamInfo` に載せるレベル（例: "4.2"）
+    pub fn level_name(&self) -> String {
+        match self {
+            VideoConfig::Avc(config) => match config.level {
+                9 => "1b".to_string(),
+                level => format!("{}.{}", level / 10, level % 10),
+            },
+            VideoConfig::Hevc(config) => {
+                let level = config.level as u32;
+                match level % 30 {
+                    0 => format!("{}", level / 30),
+                    minor => format!("{}.{}", level / 30, minor / 3),
+                }
+            }
+            VideoConfig::Av1(config) => config.level_name(),
+        }
+    }
+
+    /// fMP4のサンプルエントリとデコーダ設定ボックスの種類
+    pub fn sample_entry(&self) -> (&'static [u8; 4], &'static [u8; 4]) {
+        match self {
+            VideoConfig::Avc(_) => (b"avc1", b"avcC"),
+            VideoConfig::Hevc(_) => (b"hvc1", b"hvcC"),
+            VideoConfig::Av1(_) => (b"av01", b"av1C"),
+        }
+    }
+}
+
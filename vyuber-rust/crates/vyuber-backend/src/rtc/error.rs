@@ -0,0 +1,27 @@
+use thiserror::Error;
+
+use crate::media::hub::HubError;
+
+#[derive(Debug, Error)]
+pub enum RtcError {
+    #[error("invalid stream key")]
+    InvalidKey,
+
+    #[error("stream not found or offline")]
+    StreamNotFound,
+
+    #[error("stream has no tracks playable over WebRTC (H.264 or Opus)")]
+    UnsupportedCodec,
+
+    #[error("invalid SDP offer: {0}")]
+    InvalidSdp(webrtc::Error),
+
+    #[error("failed to create SDP answer")]
+    NoAnswer,
+
+    #[error(transparent)]
+    Hub(#[from] HubError),
+
+    #[error("WebRTC error: {0}")]
+    WebRtc(#[from] webrtc::Error),
+}
@@ -0,0 +1,210 @@
+//! WebRTC（WHIP/WHEP）
+
+pub mod error;
+pub mod whep;
+pub mod whip;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::warn;
+use uuid::Uuid;
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::{MediaEngine, MIME_TYPE_H264, MIME_TYPE_OPUS};
+use webrtc::api::APIBuilder;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::{
+    RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType,
+};
+use webrtc::rtp_transceiver::RTCPFeedback;
+
+use self::error::RtcError;
+use crate::media::opus;
+
+/// H.264のクロックレート
+pub const VIDEO_CLOCK_RATE: u32 = 90000;
+
+/// アンサーを返してからICE/DTLSの接続が完了するまで待つ時間
+#[cfg(not(test))]
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+#[cfg(test)]
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 受け付けるH.264のprofile-level-id（packetization-mode=1のみ）
+///
+/// Baseline / Constrained Baseline / Main / High
+const H264_PROFILES: [(&str, u8); 4] = [
+    ("42001f", 102),
+    ("42e01f", 106),
+    ("4d001f", 108),
+    ("64001f", 112),
+];
+
+/// H.264とOpusだけを扱うピア接続を作る
+///
+/// ハブはH.264しか中継できないので、VP8/VP9などがネゴシエートされないようにする
+pub async fn new_peer_connection() -> Result<RTCPeerConnection, RtcError> {
+    let mut media_engine = MediaEngine::default();
+    for (profile_level_id, payload_type) in H264_PROFILES {
+        media_engine.register_codec(
+            RTCRtpCodecParameters {
+                capability: h264_capability(profile_level_id),
+                payload_type,
+                ..Default::default()
+            },
+            RTPCodecType::Video,
+        )?;
+    }
+    media_engine.register_codec(
+        RTCRtpCodecParameters {
+            capability: opus_capability(),
+            payload_type: 111,
+            ..Default::default()
+        },
+        RTPCodecType::Audio,
+    )?;
+
+    let registry = register_default_interceptors(Registry::new(), &mut media_engine)?;
+    let api = APIBuilder::new()
+        .with_media_engine(media_engine)
+        .with_interceptor_registry(registry)
+        .build();
+    Ok(api.new_peer_connection(RTCConfiguration::default()).await?)
+}
+
+/// H.264（packetization-mode=1）のコーデック
+fn h264_capability(profile_level_id: &str) -> RTCRtpCodecCapability {
+    let feedback = |typ: &str, parameter: &str| RTCPFeedback {
+        typ: typ.to_string(),
+        parameter: parameter.to_string(),
+    };
+    RTCRtpCodecCapability {
+        mime_type: MIME_TYPE_H264.to_string(),
+        clock_rate: VIDEO_CLOCK_RATE,
+        channels: 0,
+        sdp_fmtp_line: format!(
+            "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id={}",
+            profile_level_id
+        ),
+        rtcp_feedback: vec![
+            feedback("goog-remb", ""),
+            feedback("ccm", "fir"),
+            feedback("nack", ""),
+            feedback("nack", "pli"),
+        ],
+    }
+}
+
+/// Opus（ステレオ、インバンドFEC）のコーデック
+fn opus_capability() -> RTCRtpCodecCapability {
+    RTCRtpCodecCapability {
+        mime_type: MIME_TYPE_OPUS.to_string(),
+        clock_rate: opus::SAMPLE_RATE,
+        channels: 2,
+        sdp_fmtp_line: "minptime=10;useinbandfec=1".to_string(),
+        rtcp_feedback: Vec::new(),
+    }
+}
+
+/// オファーを適用してアンサーを作る（ICE候補の収集が終わるまで待ち、候補を含めて返す）
+pub async fn answer(peer: &RTCPeerConnection, offer: String) -> Result<String, RtcError> {
+    let offer = RTCSessionDescription::offer(offer).map_err(RtcError::InvalidSdp)?;
+    peer.set_remote_description(offer)
+        .await
+        .map_err(RtcError::InvalidSdp)?;
+
+    let answer = peer.create_answer(None).await?;
+    let mut gathered = peer.gathering_complete_promise().await;
+    peer.set_local_description(answer).await?;
+    let _ = gathered.recv().await;
+
+    let answer = peer.local_description().await.ok_or(RtcError::NoAnswer)?;
+    Ok(answer.sdp)
+}
+
+/// `CONNECT_TIMEOUT` までに接続できなければピア接続を閉じる
+///
+/// 閉じると状態変化のコールバックに `Closed` が届くので、配信者や購読者はそこで手放す
+fn close_unless_connected(peer: &Arc<RTCPeerConnection>, session: String) {
+    let peer = Arc::downgrade(peer);
+    tokio::spawn(async move {
+        tokio::time::sleep(CONNECT_TIMEOUT).await;
+        let Some(peer) = peer.upgrade() else {
+            return;
+        };
+        if matches!(
+            peer.connection_state(),
+            RTCPeerConnectionState::New | RTCPeerConnectionState::Connecting
+        ) {
+            warn!(
+                "{} did not connect within {}s, closing",
+                session,
+                CONNECT_TIMEOUT.as_secs()
+            );
+            let _ = peer.close().await;
+        }
+    });
+}
+
+/// 接続中のWHIP/WHEPセッション（セッションID → ピア接続）
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+struct Session {
+    stream_key: String,
+    peer: Arc<RTCPeerConnection>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn insert(&self, session_id: &str, stream_key: &str, peer: Arc<RTCPeerConnection>) {
+        self.sessions.lock().unwrap().insert(
+            session_id.to_string(),
+            Session {
+                stream_key: stream_key.to_string(),
+                peer,
+            },
+        );
+    }
+
+    /// 接続が切れたセッションを取り除き、ピア接続を閉じる
+    fn remove(&self, session_id: &str) {
+        if let Some(session) = self.sessions.lock().unwrap().remove(session_id) {
+            // 状態変化のコールバックの中では閉じられないので別タスクで閉じる
+            tokio::spawn(async move {
+                let _ = session.peer.close().await;
+            });
+        }
+    }
+
+    /// セッションを終了する（見つからなければ `false`）
+    pub async fn close(&self, stream_key: &str, session_id: &str) -> bool {
+        let session = {
+            let mut sessions = self.sessions.lock().unwrap();
+            match sessions.get(session_id) {
+                Some(session) if session.stream_key == stream_key => sessions.remove(session_id),
+                _ => None,
+            }
+        };
+        let Some(session) = session else {
+            return false;
+        };
+        let _ = session.peer.close().await;
+        true
+    }
+}
+
+/// 新しいセッションID
+fn new_session_id() -> String {
+    Uuid::new_v4().to_string().replace("-", "")
+}
@@ -0,0 +1,446 @@
+//! WHEP（WebRTC-HTTP Egress Protocol）の視聴セッション
+
+use bytes::BytesMut;
+use once_cell::sync::Lazy;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tracing::{info, warn};
+use webrtc::media::Sample;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+use webrtc::rtp_transceiver::rtp_sender::RTCRtpSender;
+use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+use webrtc::track::track_local::TrackLocal;
+
+use super::error::RtcError;
+use super::Sessions;
+use crate::media::avc::AvcConfig;
+use crate::media::hub::{Subscriber, HUB};
+use crate::media::tag::{TagKind, VideoCodec, VideoPacketType};
+
+/// 視聴中のWHEPセッション
+static SESSIONS: Lazy<Sessions> = Lazy::new(Sessions::new);
+
+/// 視聴開始時のキャッシュ済みのGOPを送るときのフレーム間隔
+///
+/// 直近のキーフレームからのフレームを詰めて送り、すぐにライブの位置へ追いつかせる
+const CATCH_UP_INTERVAL: Duration = Duration::from_millis(1);
+
+/// フレーム間隔の上限（タイムスタンプが飛んだとき）
+const MAX_FRAME_INTERVAL: Duration = Duration::from_secs(1);
+
+/// オファーを受けて視聴を始め、(セッションID, アンサーのSDP) を返す
+///
+/// 映像はH.264（視聴者が配信と同じプロファイルに対応している場合）、
+/// 音声はOpus（WHIPで配信されている場合）だけを送る。
+/// 接続できないまま `CONNECT_TIMEOUT` を過ぎたら購読をやめてピア接続を閉じる
+pub async fn play(stream_key: &str, offer: String) -> Result<(String, String), RtcError> {
+    let subscriber = HUB.subscribe(stream_key).ok_or(RtcError::StreamNotFound)?;
+    let info = HUB.info(stream_key).unwrap_or_default();
+    let profile_level_id = h264_profile_level_id(&subscriber);
+    if info.video_codec.is_some() {
+        match &profile_level_id {
+            None => warn!(
+                "WHEP {}: {} {} cannot be sent over WebRTC; sending audio only",
+                stream_key,
+                info.video_codec.as_deref().unwrap_or_default(),
+                info.video_profile.as_deref().unwrap_or_default()
+            ),
+            Some(id) if !offers_h264_profile(&offer, id) => warn!(
+                "WHEP {}: viewer does not accept H.264 profile-level-id {}; sending audio only",
+                stream_key, id
+            ),
+            Some(_) => {}
+        }
+    }
+    let profile_level_id = profile_level_id.filter(|id| offers_h264_profile(&offer, id));
+    let has_audio = info.audio_codec.as_deref() == Some("Opus");
+    if profile_level_id.is_none() && !has_audio {
+        return Err(RtcError::UnsupportedCodec);
+    }
+
+    let peer = Arc::new(super::new_peer_connection().await?);
+    let video = match &profile_level_id {
+        Some(profile_level_id) => {
+            let track = new_track(super::h264_capability(profile_level_id), "video");
+            add_track(&peer, &track).await?;
+            Some(track)
+        }
+        None => None,
+    };
+    let audio = if has_audio {
+        let track = new_track(super::opus_capability(), "audio");
+        add_track(&peer, &track).await?;
+        Some(track)
+    } else {
+        None
+    };
+
+    let session_id = super::new_session_id();
+    let id = session_id.clone();
+    let key = stream_key.to_string();
+    let weak = Arc::downgrade(&peer);
+    // トラックは接続が確立するまで送れないので、それまでは購読したまま待つ
+    let mut pending = Some((subscriber, video, audio));
+    peer.on_peer_connection_state_change(Box::new(move |state| {
+        info!("WHEP session {} ({}): {}", id, key, state);
+        match state {
+            RTCPeerConnectionState::Connected => {
+                if let Some((subscriber, video, audio)) = pending.take() {
+                    tokio::spawn(forward(subscriber, weak.clone(), video, audio));
+                }
+            }
+            RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed => {
+                pending = None;
+                SESSIONS.remove(&id);
+            }
+            _ => {}
+        }
+        Box::pin(async {})
+    }));
+
+    let answer = match super::answer(&peer, offer).await {
+        Ok(answer) => answer,
+        Err(e) => {
+            let _ = peer.close().await;
+            return Err(e);
+        }
+    };
+
+    super::close_unless_connected(
+        &peer,
+        format!("WHEP session {} ({})", session_id, stream_key),
+    );
+    SESSIONS.insert(&session_id, stream_key, peer);
+    info!(
+        "WHEP playback started: {} (session {})",
+        stream_key, session_id
+    );
+    Ok((session_id, answer))
+}
+
+/// セッションを終了する（見つからなければ `false`）
+pub async fn close(stream_key: &str, session_id: &str) -> bool {
+    if !SESSIONS.close(stream_key, session_id).await {
+        return false;
+    }
+    info!(
+        "WHEP playback ended: {} (session {})",
+        stream_key, session_id
+    );
+    true
+}
+
+/// 配信のH.264のprofile-level-id（H.264でないか、WebRTCで送れないプロファイルなら `None`）
+///
+/// 制約フラグはネゴシエーションで照合される値（Constrained Baselineかどうか）にそろえる
+fn h264_profile_level_id(subscriber: &Subscriber) -> Option<String> {
+    let packet = subscriber
+        .sequence_header(TagKind::Video)?
+        .video_packet()
+        .filter(|packet| packet.codec == VideoCodec::Avc)?;
+    let avc = AvcConfig::parse(&packet.data)?;
+    let constraints = match avc.profile {
+        // constraint_set1_flag が立っていればConstrained Baseline
+        0x42 if avc.compatibility & 0x40 != 0 => 0xe0,
+        // Baseline / Main / High
+        0x42 | 0x4d | 0x64 => 0x00,
+        _ => return None,
+    };
+    Some(format!(
+        "{:02x}{:02x}{:02x}",
+        avc.profile, constraints, avc.level
+    ))
+}
+
+/// オファーに同じプロファイル（レベルは問わない）でpacketization-mode=1のH.264があるか
+fn offers_h264_profile(offer: &str, profile_level_id: &str) -> bool {
+    offer
+        .lines()
+        .filter_map(|line| line.strip_prefix("a=fmtp:"))
+        .any(|fmtp| {
+            let parameters = fmtp
+                .split_once(' ')
+                .map_or("", |(_, parameters)| parameters);
+            let (mut packetization_mode, mut profile) = (false, false);
+            for parameter in parameters.split(';') {
+                match parameter.trim().split_once('=') {
+                    Some(("packetization-mode", mode)) => packetization_mode = mode == "1",
+                    Some(("profile-level-id", id)) => {
+                        profile =
+                            id.len() == 6 && id[..4].eq_ignore_ascii_case(&profile_level_id[..4])
+                    }
+                    _ => {}
+                }
+            }
+            packetization_mode && profile
+        })
+}
+
+fn new_track(capability: RTCRtpCodecCapability, id: &str) -> Arc<TrackLocalStaticSample> {
+    Arc::new(TrackLocalStaticSample::new(
+        capability,
+        id.to_string(),
+        "vyuber".to_string(),
+    ))
+}
+
+async fn add_track(
+    peer: &RTCPeerConnection,
+    track: &Arc<TrackLocalStaticSample>,
+) -> Result<(), RtcError> {
+    let sender = peer
+        .add_track(Arc::clone(track) as Arc<dyn TrackLocal + Send + Sync>)
+        .await?;
+    tokio::spawn(drain_rtcp(sender));
+    Ok(())
+}
+
+/// 視聴者からのRTCPを読み捨てる（読まないとNACKによる再送などのインターセプタが動かない）
+async fn drain_rtcp(sender: Arc<RTCRtpSender>) {
+    let mut buf = vec![0u8; 1500];
+    while sender.read(&mut buf).await.is_ok() {}
+}
+
+/// ハブのタグをサンプルにして、接続が閉じるか配信が終わるまで送る
+async fn forward(
+    mut subscriber: Subscriber,
+    peer: Weak<RTCPeerConnection>,
+    video: Option<Arc<TrackLocalStaticSample>>,
+    audio: Option<Arc<TrackLocalStaticSample>>,
+) {
+    let mut avc: Option<AvcConfig> = None;
+    let mut keyframe_received = false;
+    let mut video_clock = SampleClock::new(Duration::from_millis(33));
+    let mut audio_clock = SampleClock::new(Duration::from_millis(20));
+
+    loop {
+        let catching_up = subscriber.replaying();
+        let Some(tag) = subscriber.recv().await else {
+            break;
+        };
+        // セッションが終わればピア接続は解放される
+        if peer.strong_count() == 0 {
+            return;
+        }
+
+        match tag.kind {
+            TagKind::Video => {
+                let Some(track) = &video else {
+                    continue;
+                };
+                let Some(packet) = tag
+                    .video_packet()
+                    .filter(|packet| packet.codec == VideoCodec::Avc)
+                else {
+                    continue;
+                };
+                match packet.packet_type {
+                    VideoPacketType::SequenceStart => {
+                        avc = AvcConfig::parse(&packet.data);
+                        continue;
+                    }
+                    VideoPacketType::CodedFrames => {}
+                    _ => continue,
+                }
+                // 最初のキーフレームまでは捨てる
+                keyframe_received |= packet.keyframe;
+                let (Some(avc), true) = (&avc, keyframe_received) else {
+                    continue;
+                };
+
+                let mut data = BytesMut::with_capacity(packet.data.len() + 64);
+                avc.write_annexb(&packet.data, packet.keyframe, &mut data);
+                let sample = Sample {
+                    data: data.freeze(),
+                    duration: video_clock.next(tag.timestamp, catching_up),
+                    ..Default::default()
+                };
+                let _ = track.write_sample(&sample).await;
+            }
+            TagKind::Audio => {
+                let Some(track) = &audio else {
+                    continue;
+                };
+                let Some((false, packet)) = tag.opus_packet() else {
+                    continue;
+                };
+                let sample = Sample {
+                    data: packet,
+                    duration: audio_clock.next(tag.timestamp, catching_up),
+                    ..Default::default()
+                };
+                let _ = track.write_sample(&sample).await;
+            }
+            TagKind::Script => {}
+        }
+    }
+
+    // 配信が終わったら接続を閉じる
+    if let Some(peer) = peer.upgrade() {
+        let _ = peer.close().await;
+    }
+}
+
+/// サンプルの長さ（RTPタイムスタンプの進め方）を決める
+///
+/// `write_sample` はサンプルを送った後にその長さだけタイムスタンプを進めるが、
+/// 次のフレームまでの間隔は届くまでわからないので、直前のフレーム間隔で代用する
+struct SampleClock {
+    last_timestamp: Option<u32>,
+    interval: Duration,
+}
+
+impl SampleClock {
+    fn new(interval: Duration) -> Self {
+        Self {
+            last_timestamp: None,
+            interval,
+        }
+    }
+
+    fn next(&mut self, timestamp: u32, catching_up: bool) -> Duration {
+        if let Some(last) = self.last_timestamp {
+            let elapsed = timestamp.wrapping_sub(last) as i32;
+            if elapsed > 0 {
+                self.interval = Duration::from_millis(elapsed as u64).min(MAX_FRAME_INTERVAL);
+            }
+        }
+        self.last_timestamp = Some(timestamp);
+        if catching_up {
+            CATCH_UP_INTERVAL
+        } else {
+            self.interval
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::tag::MediaTag;
+    use crate::media::testing;
+    use bytes::Bytes;
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+    use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+    use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+    use webrtc::rtp_transceiver::RTCRtpTransceiverInit;
+
+    /// 映像だけを受け取る視聴者のオファーを作る（ICE候補の収集が終わるまで待つ）
+    async fn offer(viewer: &RTCPeerConnection) -> String {
+        viewer
+            .add_transceiver_from_kind(
+                RTPCodecType::Video,
+                Some(RTCRtpTransceiverInit {
+                    direction: RTCRtpTransceiverDirection::Recvonly,
+                    send_encodings: vec![],
+                }),
+            )
+            .await
+            .unwrap();
+        let offer = viewer.create_offer(None).await.unwrap();
+        let mut gathered = viewer.gathering_complete_promise().await;
+        viewer.set_local_description(offer).await.unwrap();
+        let _ = gathered.recv().await;
+        viewer.local_description().await.unwrap().sdp
+    }
+
+    #[tokio::test]
+    async fn sends_the_stream_profile_to_a_local_viewer() {
+        let mut publisher = HUB.publish("whep-test-high").unwrap();
+        publisher.send(testing::avc_sequence_header(0));
+        publisher.send(testing::avc_frame(0, true, 0, &[0x88; 64]));
+
+        let viewer = super::super::new_peer_connection().await.unwrap();
+        let (track_tx, mut track_rx) = tokio::sync::mpsc::channel(1);
+        viewer.on_track(Box::new(move |track, _, _| {
+            let _ = track_tx.try_send(track);
+            Box::pin(async {})
+        }));
+        let (session_id, answer) = play("whep-test-high", offer(&viewer).await).await.unwrap();
+        viewer
+            .set_remote_description(RTCSessionDescription::answer(answer).unwrap())
+            .await
+            .unwrap();
+
+        // 接続が確立してRTPが届くまでフレームを送り続ける
+        let mut timestamp = 0;
+        let track = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                timestamp += 33;
+                publisher.send(testing::avc_frame(
+                    timestamp,
+                    timestamp % 990 == 0,
+                    0,
+                    &[0x88; 64],
+                ));
+                tokio::select! {
+                    track = track_rx.recv() => break track.unwrap(),
+                    _ = tokio::time::sleep(Duration::from_millis(33)) => {}
+                }
+            }
+        })
+        .await
+        .expect("viewer did not receive video");
+        // キャッシュ済みのシーケンスヘッダのプロファイル（High）で送られてくる
+        assert!(track
+            .codec()
+            .capability
+            .sdp_fmtp_line
+            .contains("profile-level-id=64"));
+
+        // 接続できたセッションは待ち時間を過ぎても続く
+        tokio::time::sleep(super::super::CONNECT_TIMEOUT + Duration::from_millis(500)).await;
+        assert!(close("whep-test-high", &session_id).await);
+        viewer.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn closes_sessions_that_never_connect() {
+        let mut publisher = HUB.publish("whep-test-timeout").unwrap();
+        publisher.send(testing::avc_sequence_header(0));
+
+        // アンサーを適用しない視聴者は接続できない
+        let viewer = super::super::new_peer_connection().await.unwrap();
+        let (session_id, _) = play("whep-test-timeout", offer(&viewer).await)
+            .await
+            .unwrap();
+        tokio::time::sleep(super::super::CONNECT_TIMEOUT + Duration::from_millis(500)).await;
+        assert!(!close("whep-test-timeout", &session_id).await);
+        viewer.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_profiles_webrtc_cannot_carry() {
+        // High 10（profile_idc=110）
+        let mut avcc = testing::avcc().to_vec();
+        avcc[1] = 0x6e;
+        avcc[9] = 0x6e;
+        let mut data = vec![0x17, 0, 0, 0, 0];
+        data.extend_from_slice(&avcc);
+        let mut publisher = HUB.publish("whep-test-high10").unwrap();
+        publisher.send(MediaTag::new(TagKind::Video, 0, Bytes::from(data)));
+
+        let viewer = super::super::new_peer_connection().await.unwrap();
+        assert!(matches!(
+            play("whep-test-high10", offer(&viewer).await).await,
+            Err(RtcError::UnsupportedCodec)
+        ));
+        viewer.close().await.unwrap();
+    }
+
+    #[test]
+    fn matches_offered_h264_profiles() {
+        let offer = "m=video 9 UDP/TLS/RTP/SAVPF 102 106\r\n\
+            a=rtpmap:102 H264/90000\r\n\
+            a=fmtp:102 level-asymmetry-allowed=1;packetization-mode=0;profile-level-id=64001f\r\n\
+            a=rtpmap:106 H264/90000\r\n\
+            a=fmtp:106 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42E01F\r\n";
+        // レベルは問わない
+        assert!(offers_h264_profile(offer, "42e02a"));
+        // packetization-mode=0 しかないプロファイルは送れない
+        assert!(!offers_h264_profile(offer, "64002a"));
+        assert!(!offers_h264_profile(offer, "4d001f"));
+    }
+}
@@ -0,0 +1,323 @@
+//! WHIP（WebRTC-HTTP Ingestion Protocol）の配信セッション
+
+use once_cell::sync::Lazy;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+use tracing::{info, warn};
+use webrtc::media::io::sample_builder::SampleBuilder;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::rtp::codecs::h264::H264Packet;
+use webrtc::rtp_transceiver::rtp_codec::RTPCodecType;
+use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+use webrtc::rtp_transceiver::RTCRtpTransceiverInit;
+use webrtc::track::track_remote::TrackRemote;
+
+use super::error::RtcError;
+use super::{Sessions, VIDEO_CLOCK_RATE};
+use crate::api::stream_key;
+use crate::media::avc::AvcTagBuilder;
+use crate::media::hub::{Publisher, HUB};
+use crate::media::opus;
+use crate::media::tag::MediaTag;
+
+/// フレームの組み立てを諦めるまでに待つパケット数
+const MAX_LATE_PACKETS: u16 = 512;
+
+/// 配信中のWHIPセッション
+static SESSIONS: Lazy<Sessions> = Lazy::new(Sessions::new);
+
+/// 映像と音声のトラックで共有する配信者（接続が切れたら `None` にして配信を終える）
+type SharedPublisher = Arc<Mutex<Option<Publisher>>>;
+
+/// オファーを受けて配信を始め、(セッションID, アンサーのSDP) を返す
+///
+/// 重複publishを先に断れるよう配信者はここで確保し、接続できないまま
+/// `CONNECT_TIMEOUT` を過ぎたら手放す
+pub async fn publish(stream_key: &str, offer: String) -> Result<(String, String), RtcError> {
+    if !stream_key::is_valid_key(stream_key) {
+        return Err(RtcError::InvalidKey);
+    }
+    let publisher = HUB.publish(stream_key)?;
+    let taken_over = publisher.taken_over();
+    let publisher: SharedPublisher = Arc::new(Mutex::new(Some(publisher)));
+
+    let peer = Arc::new(super::new_peer_connection().await?);
+    for kind in [RTPCodecType::Video, RTPCodecType::Audio] {
+        peer.add_transceiver_from_kind(
+            kind,
+            Some(RTCRtpTransceiverInit {
+                direction: RTCRtpTransceiverDirection::Recvonly,
+                send_encodings: Vec::new(),
+            }),
+        )
+        .await?;
+    }
+
+    let session_id = super::new_session_id();
+    let started = Instant::now();
+
+    let weak = Arc::downgrade(&peer);
+    let track_publisher = publisher.clone();
+    peer.on_track(Box::new(move |track, _, _| {
+        let peer = weak.clone();
+        let publisher = track_publisher.clone();
+        Box::pin(async move {
+            match track.kind() {
+                RTPCodecType::Video => {
+                    tokio::spawn(read_video(track, peer, publisher, started));
+                }
+                RTPCodecType::Audio => {
+                    tokio::spawn(read_audio(track, publisher, started));
+                }
+                _ => {}
+            }
+        })
+    }));
+
+    let id = session_id.clone();
+    let key = stream_key.to_string();
+    let state_publisher = publisher.clone();
+    peer.on_peer_connection_state_change(Box::new(move |state| {
+        let id = id.clone();
+        let key = key.clone();
+        let publisher = state_publisher.clone();
+        Box::pin(async move {
+            info!("WHIP session {} ({}): {}", id, key, state);
+            if matches!(
+                state,
+                RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed
+            ) {
+                publisher.lock().unwrap().take();
+                SESSIONS.remove(&id);
+            }
+        })
+    }));
+
+    // 同じキーで別の配信者が引き継いだら、こちらの接続は閉じる
+    let weak = Arc::downgrade(&peer);
+    let key = stream_key.to_string();
+    tokio::spawn(async move {
+        taken_over.await;
+        if let Some(peer) = weak.upgrade() {
+            warn!("WHIP publish taken over: {}", key);
+            let _ = peer.close().await;
+        }
+    });
+
+    let answer = match super::answer(&peer, offer).await {
+        Ok(answer) => answer,
+        Err(e) => {
+            publisher.lock().unwrap().take();
+            let _ = peer.close().await;
+            return Err(e);
+        }
+    };
+
+    super::close_unless_connected(&peer, format!("WHIP session {} ({})", session_id, stream_key));
+    SESSIONS.insert(&session_id, stream_key, peer);
+    info!(
+        "WHIP publish started: {} (session {})",
+        stream_key, session_id
+    );
+    Ok((session_id, answer))
+}
+
+/// セッションを終了する（見つからなければ `false`）
+pub async fn close(stream_key: &str, session_id: &str) -> bool {
+    if !SESSIONS.close(stream_key, session_id).await {
+        return false;
+    }
+    info!(
+        "WHIP publish ended: {} (session {})",
+        stream_key, session_id
+    );
+    true
+}
+
+/// H.264のRTPをフレームにまとめてハブへ流す
+async fn read_video(
+    track: Arc<TrackRemote>,
+    peer: Weak<RTCPeerConnection>,
+    publisher: SharedPublisher,
+    started: Instant,
+) {
+    let mut samples = SampleBuilder::new(MAX_LATE_PACKETS, H264Packet::default(), VIDEO_CLOCK_RATE);
+    let mut timeline = RtpTimeline::new(VIDEO_CLOCK_RATE, started);
+    let mut builder = AvcTagBuilder::new();
+    let mut tags = Vec::new();
+
+    // 途中から受信してもすぐデコードできるよう、キーフレームを要求しておく
+    request_keyframe(&peer, &track).await;
+    while let Ok((packet, _)) = track.read_rtp().await {
+        samples.push(packet);
+        while let Some(sample) = samples.pop() {
+            if sample.prev_dropped_packets > 0 {
+                // 欠けたフレームの後は次のキーフレームまで壊れた映像になる
+                request_keyframe(&peer, &track).await;
+            }
+            let timestamp = timeline.millis(sample.packet_timestamp);
+            builder.push(&sample.data, timestamp, 0, &mut tags);
+        }
+        if !send_tags(&publisher, &mut tags) {
+            return;
+        }
+    }
+}
+
+/// Opusのパケットをそのままハブへ流す（最初にOpusHeadのシーケンスヘッダを送る）
+async fn read_audio(track: Arc<TrackRemote>, publisher: SharedPublisher, started: Instant) {
+    let mut timeline = RtpTimeline::new(opus::SAMPLE_RATE, started);
+    let channels = track.codec().capability.channels.clamp(1, 2) as u8;
+    let mut tags = Vec::new();
+    let mut sequence_header_sent = false;
+
+    while let Ok((packet, _)) = track.read_rtp().await {
+        if packet.payload.is_empty() {
+            continue;
+        }
+        let timestamp = timeline.millis(packet.header.timestamp);
+        if !sequence_header_sent {
+            tags.push(MediaTag::opus(timestamp, true, &opus::opus_head(channels)));
+            sequence_header_sent = true;
+        }
+        tags.push(MediaTag::opus(timestamp, false, &packet.payload));
+        if !send_tags(&publisher, &mut tags) {
+            return;
+        }
+    }
+}
+
+/// 溜まったタグを配信者へ送る（配信が終わっていれば `false`）
+fn send_tags(publisher: &SharedPublisher, tags: &mut Vec<MediaTag>) -> bool {
+    let mut publisher = publisher.lock().unwrap();
+    let Some(publisher) = publisher.as_mut() else {
+        return false;
+    };
+    for tag in tags.drain(..) {
+        publisher.send(tag);
+    }
+    true
+}
+
+async fn request_keyframe(peer: &Weak<RTCPeerConnection>, track: &TrackRemote) {
+    let Some(peer) = peer.upgrade() else {
+        return;
+    };
+    let pli = PictureLossIndication {
+        sender_ssrc: 0,
+        media_ssrc: track.ssrc(),
+    };
+    let _ = peer.write_rtcp(&[Box::new(pli)]).await;
+}
+
+/// RTPタイムスタンプを、セッション開始からのミリ秒にする
+///
+/// 映像と音声でRTPタイムスタンプの起点が違うので、各トラックの最初のパケットを受信した時刻に合わせる
+struct RtpTimeline {
+    clock_rate: u32,
+    started: Instant,
+    /// (直前のRTPタイムスタンプ, 最初のパケットからの経過クロック数)
+    last: Option<(u32, i64)>,
+    /// 最初のパケットのセッション開始からの経過ミリ秒
+    offset_ms: i64,
+}
+
+impl RtpTimeline {
+    fn new(clock_rate: u32, started: Instant) -> Self {
+        Self {
+            clock_rate,
+            started,
+            last: None,
+            offset_ms: 0,
+        }
+    }
+
+    fn millis(&mut self, timestamp: u32) -> u32 {
+        let elapsed = match self.last {
+            // 32ビットの差として見れば、折り返しをまたいでも正しい増分になる
+            Some((last, elapsed)) => elapsed + timestamp.wrapping_sub(last) as i32 as i64,
+            None => {
+                self.offset_ms = self.started.elapsed().as_millis() as i64;
+                0
+            }
+        };
+        self.last = Some((timestamp, elapsed));
+        (self.offset_ms + elapsed * 1000 / self.clock_rate as i64).max(0) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+    use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+    use webrtc::track::track_local::TrackLocal;
+
+    /// H.264とOpusを送るオファーを作る（ICE候補の収集が終わるまで待つ）
+    async fn offer(peer: &RTCPeerConnection) -> String {
+        for (capability, id) in [
+            (super::super::h264_capability("42e01f"), "video"),
+            (super::super::opus_capability(), "audio"),
+        ] {
+            let track = Arc::new(TrackLocalStaticSample::new(
+                capability,
+                id.to_string(),
+                "test".to_string(),
+            ));
+            peer.add_track(track as Arc<dyn TrackLocal + Send + Sync>)
+                .await
+                .unwrap();
+        }
+        let offer = peer.create_offer(None).await.unwrap();
+        let mut gathered = peer.gathering_complete_promise().await;
+        peer.set_local_description(offer).await.unwrap();
+        let _ = gathered.recv().await;
+        peer.local_description().await.unwrap().sdp
+    }
+
+    #[tokio::test]
+    async fn releases_publisher_when_peer_never_connects() {
+        let (_guard, key) = stream_key::test_key().await;
+
+        // アンサーを適用しない配信者は接続できない
+        let client = super::super::new_peer_connection().await.unwrap();
+        let (session_id, _) = publish(&key, offer(&client).await).await.unwrap();
+        assert!(HUB.subscribe(&key).is_some());
+        assert!(matches!(
+            publish(&key, offer(&client).await).await,
+            Err(RtcError::Hub(_))
+        ));
+
+        tokio::time::sleep(super::super::CONNECT_TIMEOUT + Duration::from_millis(500)).await;
+        assert!(HUB.subscribe(&key).is_none());
+        assert!(!close(&key, &session_id).await);
+        client.close().await.unwrap();
+
+        // 接続できた配信者は待ち時間を過ぎても配信を続ける
+        let client = super::super::new_peer_connection().await.unwrap();
+        let (connected_tx, mut connected) = tokio::sync::mpsc::channel(1);
+        client.on_peer_connection_state_change(Box::new(move |state| {
+            if state == RTCPeerConnectionState::Connected {
+                let _ = connected_tx.try_send(());
+            }
+            Box::pin(async {})
+        }));
+        let (session_id, answer) = publish(&key, offer(&client).await).await.unwrap();
+        client
+            .set_remote_description(RTCSessionDescription::answer(answer).unwrap())
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(10), connected.recv())
+            .await
+            .expect("peer did not connect");
+
+        tokio::time::sleep(super::super::CONNECT_TIMEOUT + Duration::from_millis(500)).await;
+        assert!(HUB.subscribe(&key).is_some());
+        assert!(close(&key, &session_id).await);
+        assert!(HUB.subscribe(&key).is_none());
+        client.close().await.unwrap();
+    }
+}
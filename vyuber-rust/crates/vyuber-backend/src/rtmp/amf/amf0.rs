@@ -0,0 +1,354 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::{amf3, AmfError, AmfValue, Reader, MAX_DEPTH};
+
+/// AMF0 型マーカー
+#[allow(dead_code)]
+pub mod marker {
+    pub const NUMBER: u8 = 0x00;
+    pub const BOOLEAN: u8 = 0x01;
+    pub const STRING: u8 = 0x02;
+    pub const OBJECT: u8 = 0x03;
+    pub const MOVIECLIP: u8 = 0x04;
+    pub const NULL: u8 = 0x05;
+    pub const UNDEFINED: u8 = 0x06;
+    pub const REFERENCE: u8 = 0x07;
+    pub const ECMA_ARRAY: u8 = 0x08;
+    pub const OBJECT_END: u8 = 0x09;
+    pub const STRICT_ARRAY: u8 = 0x0A;
+    pub const DATE: u8 = 0x0B;
+    pub const LONG_STRING: u8 = 0x0C;
+    pub const UNSUPPORTED: u8 = 0x0D;
+    pub const RECORDSET: u8 = 0x0E;
+    pub const XML_DOCUMENT: u8 = 0x0F;
+    pub const TYPED_OBJECT: u8 = 0x10;
+    pub const AVMPLUS_OBJECT: u8 = 0x11;
+}
+
+/// ペイロード全体をAMF0値の列としてデコードする
+///
+/// `avmplus-object` マーカー以降の1値はAMF3としてデコードする
+pub fn decode_all(data: &[u8]) -> Result<Vec<AmfValue>, AmfError> {
+    let mut decoder = Decoder::new(data);
+    let mut values = Vec::new();
+    while decoder.reader.remaining() > 0 {
+        values.push(decoder.decode_value(0)?);
+    }
+    Ok(values)
+}
+
+/// 値の列をAMF0でエンコードする
+pub fn encode_all(values: &[AmfValue]) -> Bytes {
+    let mut dst = BytesMut::new();
+    for value in values {
+        encode(value, &mut dst);
+    }
+    dst.freeze()
+}
+
+/// 1つの値をAMF0でエンコードする
+pub fn encode(value: &AmfValue, dst: &mut BytesMut) {
+    match value {
+        AmfValue::Number(n) => {
+            dst.put_u8(marker::NUMBER);
+            dst.put_f64(*n);
+        }
+        AmfValue::Boolean(b) => {
+            dst.put_u8(marker::BOOLEAN);
+            dst.put_u8(*b as u8);
+        }
+        AmfValue::String(s) => {
+            if s.len() <= u16::MAX as usize {
+                dst.put_u8(marker::STRING);
+                put_short_string(dst, s);
+            } else {
+                dst.put_u8(marker::LONG_STRING);
+                dst.put_u32(s.len() as u32);
+                dst.put_slice(s.as_bytes());
+            }
+        }
+        AmfValue::Object(props) => {
+            dst.put_u8(marker::OBJECT);
+            put_properties(dst, props);
+        }
+        AmfValue::EcmaArray(props) => {
+            dst.put_u8(marker::ECMA_ARRAY);
+            dst.put_u32(props.len() as u32);
+            put_properties(dst, props);
+        }
+        AmfValue::StrictArray(items) => {
+            dst.put_u8(marker::STRICT_ARRAY);
+            dst.put_u32(items.len() as u32);
+            for item in items {
+                encode(item, dst);
+            }
+        }
+        AmfValue::Null => dst.put_u8(marker::NULL),
+        AmfValue::Undefined => dst.put_u8(marker::UNDEFINED),
+        AmfValue::Date { millis, timezone } => {
+            dst.put_u8(marker::DATE);
+            dst.put_f64(*millis);
+            dst.put_i16(*timezone);
+        }
+        AmfValue::XmlDocument(s) => {
+            dst.put_u8(marker::XML_DOCUMENT);
+            dst.put_u32(s.len() as u32);
+            dst.put_slice(s.as_bytes());
+        }
+        AmfValue::ByteArray(_) => {
+            // AMF0には対応する型がないためAMF3に切り替える
+            dst.put_u8(marker::AVMPLUS_OBJECT);
+            amf3::encode(value, dst);
+        }
+    }
+}
+
+fn put_properties(dst: &mut BytesMut, props: &[(String, AmfValue)]) {
+    for (key, value) in props {
+        put_short_string(dst, key);
+        encode(value, dst);
+    }
+    dst.put_u16(0);
+    dst.put_u8(marker::OBJECT_END);
+}
+
+/// u16長の文字列を書く（上限を超える分は文字境界で切り詰める）
+fn put_short_string(dst: &mut BytesMut, s: &str) {
+    let mut end = s.len().min(u16::MAX as usize);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    dst.put_u16(end as u16);
+    dst.put_slice(&s.as_bytes()[..end]);
+}
+
+struct Decoder<'a> {
+    reader: Reader<'a>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            reader: Reader::new(data),
+        }
+    }
+
+    fn decode_value(&mut self, depth: usize) -> Result<AmfValue, AmfError> {
+        if depth > MAX_DEPTH {
+            return Err(AmfError::TooDeep);
+        }
+
+        let r = &mut self.reader;
+        let value = match r.read_u8()? {
+            marker::NUMBER => AmfValue::Number(r.read_f64()?),
+            marker::BOOLEAN => AmfValue::Boolean(r.read_u8()? != 0),
+            marker::STRING => {
+                let len = r.read_u16()? as usize;
+                AmfValue::String(r.read_utf8(len)?)
+            }
+            marker::LONG_STRING => {
+                let len = r.read_u32()? as usize;
+                AmfValue::String(r.read_utf8(len)?)
+            }
+            marker::XML_DOCUMENT => {
+                let len = r.read_u32()? as usize;
+                AmfValue::XmlDocument(r.read_utf8(len)?)
+            }
+            marker::NULL => AmfValue::Null,
+            marker::UNDEFINED => AmfValue::Undefined,
+            marker::DATE => AmfValue::Date {
+                millis: r.read_f64()?,
+                timezone: r.read_u16()? as i16,
+            },
+            marker::OBJECT => AmfValue::Object(self.decode_properties(depth)?),
+            marker::TYPED_OBJECT => {
+                // クラス名は捨てて通常のオブジェクトとして扱う
+                let len = r.read_u16()? as usize;
+                r.read_utf8(len)?;
+                AmfValue::Object(self.decode_properties(depth)?)
+            }
+            marker::ECMA_ARRAY => {
+                // 要素数は信用できないエンコーダがあるため終端マーカーまで読む
+                r.read_u32()?;
+                AmfValue::EcmaArray(self.decode_properties(depth)?)
+            }
+            marker::STRICT_ARRAY => {
+                let count = r.read_u32()? as usize;
+                // 1要素は最低1バイトなので残りバイト数で容量を制限する
+                let mut items = Vec::with_capacity(count.min(r.remaining()));
+                for _ in 0..count {
+                    items.push(self.decode_value(depth + 1)?);
+                }
+                AmfValue::StrictArray(items)
+            }
+            marker::AVMPLUS_OBJECT => amf3::decode_from(r, depth + 1)?,
+            marker::OBJECT_END => return Err(AmfError::UnexpectedObjectEnd),
+            // RTMPのエンコーダは参照を使わないため対応しない
+            marker::REFERENCE => return Err(AmfError::Unsupported("reference")),
+            marker::MOVIECLIP => return Err(AmfError::Unsupported("movieclip")),
+            marker::UNSUPPORTED => return Err(AmfError::Unsupported("unsupported")),
+            marker::RECORDSET => return Err(AmfError::Unsupported("recordset")),
+            other => return Err(AmfError::UnknownMarker(other)),
+        };
+        Ok(value)
+    }
+
+    /// キーと値の組を空キー + object-end マーカーまで読む
+    fn decode_properties(&mut self, depth: usize) -> Result<Vec<(String, AmfValue)>, AmfError> {
+        let mut props = Vec::new();
+        loop {
+            let len = self.reader.read_u16()? as usize;
+            if len == 0 && self.reader.peek_u8()? == marker::OBJECT_END {
+                self.reader.read_u8()?;
+                return Ok(props);
+            }
+            let key = self.reader.read_utf8(len)?;
+            let value = self.decode_value(depth + 1)?;
+            props.push((key, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_values() -> Vec<AmfValue> {
+        vec![
+            AmfValue::Number(0.0),
+            AmfValue::Number(-1.5),
+            AmfValue::Number(f64::MAX),
+            AmfValue::Boolean(true),
+            AmfValue::Boolean(false),
+            AmfValue::from("live"),
+            AmfValue::String(String::new()),
+            AmfValue::String("配信".repeat(20_000)),
+            AmfValue::object([
+                ("app", AmfValue::from("live")),
+                ("audioCodecs", AmfValue::Number(3575.0)),
+                (
+                    "nested",
+                    AmfValue::object([("ok", AmfValue::Boolean(true))]),
+                ),
+            ]),
+            AmfValue::EcmaArray(vec![
+                ("duration".to_string(), AmfValue::Number(0.0)),
+                ("encoder".to_string(), AmfValue::from("obs-output module")),
+            ]),
+            AmfValue::StrictArray(vec![AmfValue::Null, AmfValue::Number(1.0)]),
+            AmfValue::Null,
+            AmfValue::Undefined,
+            AmfValue::Date {
+                millis: 1_700_000_000_000.0,
+                timezone: -540,
+            },
+            AmfValue::XmlDocument("<a/>".to_string()),
+            AmfValue::ByteArray(vec![0, 1, 2, 255]),
+        ]
+    }
+
+    #[test]
+    fn round_trips_every_value_type() {
+        let values = all_values();
+        assert_eq!(decode_all(&encode_all(&values)).unwrap(), values);
+    }
+
+    #[test]
+    fn decodes_obs_connect_command() {
+        let data = encode_all(&[
+            AmfValue::from("connect"),
+            AmfValue::Number(1.0),
+            AmfValue::object([
+                ("app", AmfValue::from("live")),
+                ("type", AmfValue::from("nonprivate")),
+                ("tcUrl", AmfValue::from("rtmp://localhost/live")),
+            ]),
+        ]);
+        let values = decode_all(&data).unwrap();
+        assert_eq!(values[0].as_str(), Some("connect"));
+        assert_eq!(values[1].as_f64(), Some(1.0));
+        assert_eq!(
+            values[2].get("app").and_then(AmfValue::as_str),
+            Some("live")
+        );
+        assert_eq!(values[2].get("missing"), None);
+    }
+
+    #[test]
+    fn decodes_typed_object_as_object() {
+        let mut data = BytesMut::new();
+        data.put_u8(marker::TYPED_OBJECT);
+        put_short_string(&mut data, "flex.Message");
+        put_properties(&mut data, &[("flag".to_string(), AmfValue::Boolean(true))]);
+
+        let values = decode_all(&data).unwrap();
+        assert_eq!(
+            values[0].get("flag").and_then(AmfValue::as_bool),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        for value in all_values() {
+            let data = encode_all(std::slice::from_ref(&value));
+            for len in 0..data.len() {
+                // 空の入力は値0個として正しい
+                if len == 0 {
+                    assert_eq!(decode_all(&data[..0]), Ok(Vec::new()));
+                    continue;
+                }
+                assert_eq!(
+                    decode_all(&data[..len]),
+                    Err(AmfError::UnexpectedEof),
+                    "{:?} truncated to {} bytes",
+                    value,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert_eq!(decode_all(&[0x42]), Err(AmfError::UnknownMarker(0x42)));
+        assert_eq!(
+            decode_all(&[marker::OBJECT_END]),
+            Err(AmfError::UnexpectedObjectEnd)
+        );
+        assert_eq!(
+            decode_all(&[marker::REFERENCE, 0, 0]),
+            Err(AmfError::Unsupported("reference"))
+        );
+        assert_eq!(
+            decode_all(&[marker::STRING, 0, 2, 0xc3, 0x28]),
+            Err(AmfError::InvalidUtf8)
+        );
+        // 要素数だけ大きい配列で大きな確保をしない
+        assert_eq!(
+            decode_all(&[marker::STRICT_ARRAY, 0xff, 0xff, 0xff, 0xff]),
+            Err(AmfError::UnexpectedEof)
+        );
+    }
+
+    /// `depth` 段入れ子になった配列（一番内側は Null）
+    fn nested_arrays(depth: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for _ in 0..depth {
+            data.extend_from_slice(&[marker::STRICT_ARRAY, 0, 0, 0, 1]);
+        }
+        data.push(marker::NULL);
+        data
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        assert!(decode_all(&nested_arrays(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            decode_all(&nested_arrays(MAX_DEPTH + 1)),
+            Err(AmfError::TooDeep)
+        );
+        // 深い入れ子でもスタックを溢れさせずにエラーになる
+        assert_eq!(decode_all(&nested_arrays(100_000)), Err(AmfError::TooDeep));
+    }
+}
@@ -0,0 +1,724 @@
+use bytes::{BufMut, BytesMut};
+use std::rc::Rc;
+
+use super::{AmfError, AmfValue, Reader, MAX_DEPTH};
+
+/// AMF3 型マーカー
+#[allow(dead_code)]
+pub mod marker {
+    pub const UNDEFINED: u8 = 0x00;
+    pub const NULL: u8 = 0x01;
+    pub const FALSE: u8 = 0x02;
+    pub const TRUE: u8 = 0x03;
+    pub const INTEGER: u8 = 0x04;
+    pub const DOUBLE: u8 = 0x05;
+    pub const STRING: u8 = 0x06;
+    pub const XML_DOC: u8 = 0x07;
+    pub const DATE: u8 = 0x08;
+    pub const ARRAY: u8 = 0x09;
+    pub const OBJECT: u8 = 0x0A;
+    pub const XML: u8 = 0x0B;
+    pub const BYTE_ARRAY: u8 = 0x0C;
+    pub const VECTOR_INT: u8 = 0x0D;
+    pub const VECTOR_UINT: u8 = 0x0E;
+    pub const VECTOR_DOUBLE: u8 = 0x0F;
+    pub const VECTOR_OBJECT: u8 = 0x10;
+    pub const DICTIONARY: u8 = 0x11;
+}
+
+/// U29で表現できる整数の範囲
+const INT_MIN: i32 = -(1 << 28);
+const INT_MAX: i32 = (1 << 28) - 1;
+const U29_MAX: usize = (1 << 29) - 1;
+
+/// 参照の展開で複製してよい量（要素数 + 文字列バイト数）
+const MAX_EXPANSION: usize = 16 * 1024 * 1024;
+
+/// AMF0の `avmplus-object` から呼ばれる（参照テーブルは値ごとに初期化）
+pub(super) fn decode_from(reader: &mut Reader<'_>, depth: usize) -> Result<AmfValue, AmfError> {
+    let mut decoder = Decoder {
+        reader,
+        strings: Vec::new(),
+        objects: Vec::new(),
+        traits: Vec::new(),
+        expanded: 0,
+    };
+    decoder.decode_value(depth)
+}
+
+/// 1つの値をAMF3でエンコードする（参照は使わず常にインラインで書く）
+pub fn encode(value: &AmfValue, dst: &mut BytesMut) {
+    match value {
+        AmfValue::Undefined => dst.put_u8(marker::UNDEFINED),
+        AmfValue::Null => dst.put_u8(marker::NULL),
+        AmfValue::Boolean(false) => dst.put_u8(marker::FALSE),
+        AmfValue::Boolean(true) => dst.put_u8(marker::TRUE),
+        AmfValue::Number(n) => {
+            let i = *n as i32;
+            if i as f64 == *n
+                && (INT_MIN..=INT_MAX).contains(&i)
+                && !(*n == 0.0 && n.is_sign_negative())
+            {
+                dst.put_u8(marker::INTEGER);
+                put_u29(dst, (i as u32) & 0x1FFF_FFFF);
+            } else {
+                dst.put_u8(marker::DOUBLE);
+                dst.put_f64(*n);
+            }
+        }
+        AmfValue::String(s) => {
+            dst.put_u8(marker::STRING);
+            put_string(dst, s);
+        }
+        AmfValue::XmlDocument(s) => {
+            dst.put_u8(marker::XML_DOC);
+            put_string(dst, s);
+        }
+        AmfValue::Date { millis, .. } => {
+            dst.put_u8(marker::DATE);
+            put_u29(dst, 1);
+            dst.put_f64(*millis);
+        }
+        AmfValue::StrictArray(items) => {
+            dst.put_u8(marker::ARRAY);
+            put_inline_len(dst, items.len());
+            put_string(dst, "");
+            for item in items {
+                encode(item, dst);
+            }
+        }
+        AmfValue::EcmaArray(props) => {
+            dst.put_u8(marker::ARRAY);
+            put_inline_len(dst, 0);
+            for (key, value) in props.iter().filter(|(k, _)| !k.is_empty()) {
+                put_string(dst, key);
+                encode(value, dst);
+            }
+            put_string(dst, "");
+        }
+        AmfValue::Object(props) => {
+            dst.put_u8(marker::OBJECT);
+            // インラインtraits・dynamic・sealedメンバー0個の匿名オブジェクト
+            put_u29(dst, 0x0B);
+            put_string(dst, "");
+            for (key, value) in props.iter().filter(|(k, _)| !k.is_empty()) {
+                put_string(dst, key);
+                encode(value, dst);
+            }
+            put_string(dst, "");
+        }
+        AmfValue::ByteArray(bytes) => {
+            dst.put_u8(marker::BYTE_ARRAY);
+            let len = bytes.len().min(U29_MAX >> 1);
+            put_inline_len(dst, len);
+            dst.put_slice(&bytes[..len]);
+        }
+    }
+}
+
+fn put_u29(dst: &mut BytesMut, v: u32) {
+    let v = v & 0x1FFF_FFFF;
+    if v < 0x80 {
+        dst.put_u8(v as u8);
+    } else if v < 0x4000 {
+        dst.put_u8(((v >> 7) | 0x80) as u8);
+        dst.put_u8((v & 0x7F) as u8);
+    } else if v < 0x20_0000 {
+        dst.put_u8(((v >> 14) | 0x80) as u8);
+        dst.put_u8(((v >> 7) | 0x80) as u8);
+        dst.put_u8((v & 0x7F) as u8);
+    } else {
+        dst.put_u8(((v >> 22) | 0x80) as u8);
+        dst.put_u8(((v >> 15) | 0x80) as u8);
+        dst.put_u8(((v >> 8) | 0x80) as u8);
+        dst.put_u8(v as u8);
+    }
+}
+
+/// インライン値のヘッダ（下位1bitが1）
+fn put_inline_len(dst: &mut BytesMut, len: usize) {
+    put_u29(dst, ((len.min(U29_MAX >> 1) as u32) << 1) | 1);
+}
+
+fn put_string(dst: &mut BytesMut, s: &str) {
+    let mut end = s.len().min(U29_MAX >> 1);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    put_inline_len(dst, end);
+    dst.put_slice(&s.as_bytes()[..end]);
+}
+
+/// クラス定義（traits）
+struct Traits {
+    dynamic: bool,
+    sealed: Vec<String>,
+}
+
+struct Decoder<'r, 'a> {
+    reader: &'r mut Reader<'a>,
+    strings: Vec<String>,
+    /// 複合値と、その入れ子の深さ
+    objects: Vec<(AmfValue, usize)>,
+    traits: Vec<Rc<Traits>>,
+    /// 参照の展開で複製した量
+    expanded: usize,
+}
+
+impl Decoder<'_, '_> {
+    fn decode_value(&mut self, depth: usize) -> Result<AmfValue, AmfError> {
+        if depth > MAX_DEPTH {
+            return Err(AmfError::TooDeep);
+        }
+
+        let value = match self.reader.read_u8()? {
+            marker::UNDEFINED => AmfValue::Undefined,
+            marker::NULL => AmfValue::Null,
+            marker::FALSE => AmfValue::Boolean(false),
+            marker::TRUE => AmfValue::Boolean(true),
+            marker::INTEGER => {
+                // 29bit符号付き整数
+                let v = self.read_u29()?;
+                let v = if v & 0x1000_0000 != 0 {
+                    v as i32 - (1 << 29)
+                } else {
+                    v as i32
+                };
+                AmfValue::Number(v as f64)
+            }
+            marker::DOUBLE => AmfValue::Number(self.reader.read_f64()?),
+            marker::STRING => AmfValue::String(self.read_string()?),
+            marker::XML_DOC | marker::XML => match self.read_header()? {
+                Header::Reference(index) => self.object_reference(index, depth)?,
+                Header::Inline(len) => {
+                    let value = AmfValue::XmlDocument(self.reader.read_utf8(len)?);
+                    self.push_object(&value);
+                    value
+                }
+            },
+            marker::DATE => match self.read_header()? {
+                Header::Reference(index) => self.object_reference(index, depth)?,
+                Header::Inline(_) => {
+                    let value = AmfValue::Date {
+                        millis: self.reader.read_f64()?,
+                        timezone: 0,
+                    };
+                    self.push_object(&value);
+                    value
+                }
+            },
+            marker::BYTE_ARRAY => match self.read_header()? {
+                Header::Reference(index) => self.object_reference(index, depth)?,
+                Header::Inline(len) => {
+                    let value = AmfValue::ByteArray(self.reader.read_bytes(len)?.to_vec());
+                    self.push_object(&value);
+                    value
+                }
+            },
+            marker::ARRAY => self.decode_array(depth)?,
+            marker::OBJECT => self.decode_object(depth)?,
+            kind @ (marker::VECTOR_INT | marker::VECTOR_UINT | marker::VECTOR_DOUBLE) => {
+                self.decode_number_vector(kind, depth)?
+            }
+            marker::VECTOR_OBJECT => self.decode_object_vector(depth)?,
+            marker::DICTIONARY => self.decode_dictionary(depth)?,
+            other => return Err(AmfError::UnknownMarker(other)),
+        };
+        Ok(value)
+    }
+
+    fn read_u29(&mut self) -> Result<u32, AmfError> {
+        let mut v: u32 = 0;
+        for _ in 0..3 {
+            let b = self.reader.read_u8()?;
+            v = (v << 7) | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        // 4バイト目は8bitすべてが値
+        let b = self.reader.read_u8()?;
+        Ok((v << 8) | b as u32)
+    }
+
+    /// 参照 or インラインのヘッダを読む
+    fn read_header(&mut self) -> Result<Header, AmfError> {
+        let h = self.read_u29()? as usize;
+        if h & 1 == 0 {
+            Ok(Header::Reference(h >> 1))
+        } else {
+            Ok(Header::Inline(h >> 1))
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String, AmfError> {
+        match self.read_header()? {
+            Header::Reference(index) => {
+                let s = self
+                    .strings
+                    .get(index)
+                    .ok_or(AmfError::InvalidReference(index))?
+                    .clone();
+                self.charge(s.len() + 1)?;
+                Ok(s)
+            }
+            Header::Inline(len) => {
+                let s = self.reader.read_utf8(len)?;
+                // 空文字列はテーブルに登録しない
+                if !s.is_empty() {
+                    self.strings.push(s.clone());
+                }
+                Ok(s)
+            }
+        }
+    }
+
+    fn object_reference(&mut self, index: usize, depth: usize) -> Result<AmfValue, AmfError> {
+        let (value, height) = self
+            .objects
+            .get(index)
+            .ok_or(AmfError::InvalidReference(index))?;
+        // 参照の埋め込みで入れ子が上限を超えないようにする
+        if depth + height > MAX_DEPTH {
+            return Err(AmfError::TooDeep);
+        }
+        let value = value.clone();
+        self.charge(weight(&value))?;
+        Ok(value)
+    }
+
+    /// 参照展開による増幅を制限する
+    fn charge(&mut self, amount: usize) -> Result<(), AmfError> {
+        self.expanded += amount;
+        if self.expanded > MAX_EXPANSION {
+            return Err(AmfError::Unsupported("reference expansion too large"));
+        }
+        Ok(())
+    }
+
+    /// 子要素より先に参照番号を確保する（自己参照は Null として扱われる）
+    fn reserve_object(&mut self) -> usize {
+        self.objects.push((AmfValue::Null, 0));
+        self.objects.len() - 1
+    }
+
+    fn complete_object(&mut self, slot: usize, value: AmfValue) -> Result<AmfValue, AmfError> {
+        self.charge(weight(&value))?;
+        self.objects[slot] = (value.clone(), height(&value));
+        Ok(value)
+    }
+
+    fn push_object(&mut self, value: &AmfValue) {
+        self.objects.push((value.clone(), 0));
+    }
+
+    fn decode_array(&mut self, depth: usize) -> Result<AmfValue, AmfError> {
+        let dense_len = match self.read_header()? {
+            Header::Reference(index) => return self.object_reference(index, depth),
+            Header::Inline(len) => len,
+        };
+        let slot = self.reserve_object();
+
+        let mut assoc = Vec::new();
+        loop {
+            let key = self.read_string()?;
+            if key.is_empty() {
+                break;
+            }
+            let value = self.decode_value(depth + 1)?;
+            assoc.push((key, value));
+        }
+
+        let mut dense = Vec::with_capacity(dense_len.min(self.reader.remaining()));
+        for _ in 0..dense_len {
+            dense.push(self.decode_value(depth + 1)?);
+        }
+
+        // 連想部分があればECMA配列（密な部分は添字をキーにする）
+        let value = if assoc.is_empty() {
+            AmfValue::StrictArray(dense)
+        } else {
+            let mut props: Vec<_> = dense
+                .into_iter()
+                .enumerate()
+                .map(|(i, v)| (i.to_string(), v))
+                .collect();
+            props.extend(assoc);
+            AmfValue::EcmaArray(props)
+        };
+        self.complete_object(slot, value)
+    }
+
+    fn decode_object(&mut self, depth: usize) -> Result<AmfValue, AmfError> {
+        let header = self.read_u29()? as usize;
+        if header & 1 == 0 {
+            return self.object_reference(header >> 1, depth);
+        }
+
+        let traits = if header & 2 == 0 {
+            let index = header >> 2;
+            self.traits
+                .get(index)
+                .cloned()
+                .ok_or(AmfError::InvalidReference(index))?
+        } else if header & 4 != 0 {
+            return Err(AmfError::Unsupported("externalizable object"));
+        } else {
+            let dynamic = header & 8 != 0;
+            let sealed_count = header >> 4;
+            // クラス名は使わない
+            self.read_string()?;
+            let mut sealed = Vec::with_capacity(sealed_count.min(self.reader.remaining()));
+            for _ in 0..sealed_count {
+                sealed.push(self.read_string()?);
+            }
+            let traits = Rc::new(Traits { dynamic, sealed });
+            self.traits.push(traits.clone());
+            traits
+        };
+
+        let slot = self.reserve_object();
+        let mut props = Vec::with_capacity(traits.sealed.len());
+        for name in &traits.sealed {
+            let value = self.decode_value(depth + 1)?;
+            props.push((name.clone(), value));
+        }
+        if traits.dynamic {
+            loop {
+                let key = self.read_string()?;
+                if key.is_empty() {
+                    break;
+                }
+                let value = self.decode_value(depth + 1)?;
+                props.push((key, value));
+            }
+        }
+        self.complete_object(slot, AmfValue::Object(props))
+    }
+
+    fn decode_number_vector(&mut self, kind: u8, depth: usize) -> Result<AmfValue, AmfError> {
+        let len = match self.read_header()? {
+            Header::Reference(index) => return self.object_reference(index, depth),
+            Header::Inline(len) => len,
+        };
+        // fixed-vector フラグ
+        self.reader.read_u8()?;
+
+        let slot = self.reserve_object();
+        let mut items = Vec::with_capacity(len.min(self.reader.remaining()));
+        for _ in 0..len {
+            let n = match kind {
+                marker::VECTOR_INT => self.reader.read_u32()? as i32 as f64,
+                marker::VECTOR_UINT => self.reader.read_u32()? as f64,
+                _ => self.reader.read_f64()?,
+            };
+            items.push(AmfValue::Number(n));
+        }
+        self.complete_object(slot, AmfValue::StrictArray(items))
+    }
+
+    fn decode_object_vector(&mut self, depth: usize) -> Result<AmfValue, AmfError> {
+        let len = match self.read_header()? {
+            Header::Reference(index) => return self.object_reference(index, depth),
+            Header::Inline(len) => len,
+        };
+        self.reader.read_u8()?;
+        // 要素の型名
+        self.read_string()?;
+
+        let slot = self.reserve_object();
+        let mut items = Vec::with_capacity(len.min(self.reader.remaining()));
+        for _ in 0..len {
+            items.push(self.decode_value(depth + 1)?);
+        }
+        self.complete_object(slot, AmfValue::StrictArray(items))
+    }
+
+    fn decode_dictionary(&mut self, depth: usize) -> Result<AmfValue, AmfError> {
+        let len = match self.read_header()? {
+            Header::Reference(index) => return self.object_reference(index, depth),
+            Header::Inline(len) => len,
+        };
+        // weak-keys フラグ
+        self.reader.read_u8()?;
+
+        let slot = self.reserve_object();
+        let mut props = Vec::with_capacity(len.min(self.reader.remaining()));
+        for _ in 0..len {
+            let key = match self.decode_value(depth + 1)? {
+                AmfValue::String(s) => s,
+                AmfValue::Number(n) => n.to_string(),
+                AmfValue::Boolean(b) => b.to_string(),
+                _ => return Err(AmfError::Unsupported("dictionary key")),
+            };
+            let value = self.decode_value(depth + 1)?;
+            props.push((key, value));
+        }
+        self.complete_object(slot, AmfValue::EcmaArray(props))
+    }
+}
+
+enum Header {
+    Reference(usize),
+    Inline(usize),
+}
+
+/// 入れ子の深さ（スカラーは0）
+fn height(value: &AmfValue) -> usize {
+    match value {
+        AmfValue::Object(props) | AmfValue::EcmaArray(props) => {
+            1 + props.iter().map(|(_, v)| height(v)).max().unwrap_or(0)
+        }
+        AmfValue::StrictArray(items) => 1 + items.iter().map(height).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// 複製コストの見積もり（要素数 + 文字列/バイト列の長さ）
+fn weight(value: &AmfValue) -> usize {
+    match value {
+        AmfValue::String(s) | AmfValue::XmlDocument(s) => 1 + s.len(),
+        AmfValue::ByteArray(b) => 1 + b.len(),
+        AmfValue::Object(props) | AmfValue::EcmaArray(props) => {
+            1 + props
+                .iter()
+                .map(|(k, v)| k.len() + weight(v))
+                .sum::<usize>()
+        }
+        AmfValue::StrictArray(items) => 1 + items.iter().map(weight).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(data: &[u8]) -> Result<AmfValue, AmfError> {
+        decode_from(&mut Reader::new(data), 0)
+    }
+
+    fn encoded(value: &AmfValue) -> BytesMut {
+        let mut data = BytesMut::new();
+        encode(value, &mut data);
+        data
+    }
+
+    fn all_values() -> Vec<AmfValue> {
+        vec![
+            AmfValue::Undefined,
+            AmfValue::Null,
+            AmfValue::Boolean(false),
+            AmfValue::Boolean(true),
+            AmfValue::Number(0.0),
+            AmfValue::Number(-0.0),
+            AmfValue::Number(127.0),
+            AmfValue::Number(16_384.0),
+            AmfValue::Number(INT_MAX as f64),
+            AmfValue::Number(INT_MIN as f64),
+            AmfValue::Number(INT_MAX as f64 + 1.0),
+            AmfValue::Number(0.25),
+            AmfValue::from("onMetaData"),
+            AmfValue::String(String::new()),
+            AmfValue::XmlDocument("<a/>".to_string()),
+            AmfValue::Date {
+                millis: 1_700_000_000_000.0,
+                timezone: 0,
+            },
+            AmfValue::StrictArray(vec![AmfValue::Number(1.0), AmfValue::from("a")]),
+            AmfValue::EcmaArray(vec![("width".to_string(), AmfValue::Number(1920.0))]),
+            AmfValue::object([
+                ("code", AmfValue::from("NetStream.Publish.Start")),
+                ("inner", AmfValue::object([("x", AmfValue::Null)])),
+            ]),
+            AmfValue::ByteArray(vec![9; 300]),
+        ]
+    }
+
+    #[test]
+    fn round_trips_every_value_type() {
+        for value in all_values() {
+            let decoded = decode(&encoded(&value)).unwrap();
+            assert_eq!(decoded, value);
+            // -0.0 は整数にせずdoubleで送る
+            if let AmfValue::Number(n) = decoded {
+                assert_eq!(
+                    n.is_sign_negative(),
+                    value.as_f64().unwrap().is_sign_negative()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_through_amf0_avmplus_marker() {
+        let values = vec![AmfValue::from("x"), AmfValue::ByteArray(vec![1, 2, 3])];
+        let data = super::super::amf0::encode_all(&values);
+        assert_eq!(data[4], super::super::amf0::marker::AVMPLUS_OBJECT);
+        assert_eq!(super::super::amf0::decode_all(&data).unwrap(), values);
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        for value in all_values() {
+            let data = encoded(&value);
+            for len in 0..data.len() {
+                assert_eq!(
+                    decode(&data[..len]),
+                    Err(AmfError::UnexpectedEof),
+                    "{:?} truncated to {} bytes",
+                    value,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn resolves_string_object_and_trait_references() {
+        let data = [
+            // 2要素の配列
+            marker::ARRAY,
+            0x05,
+            0x01,
+            // {name: "a"}（traits: sealed 1個 "name"）
+            marker::OBJECT,
+            0x13,
+            0x01,
+            0x09,
+            b'n',
+            b'a',
+            b'm',
+            b'e',
+            marker::STRING,
+            0x03,
+            b'a',
+            // traits参照0、文字列参照0（"name"）
+            marker::OBJECT,
+            0x01,
+            marker::STRING,
+            0x00,
+        ];
+        let object = |v: &str| AmfValue::object([("name", AmfValue::from(v))]);
+        assert_eq!(
+            decode(&data).unwrap(),
+            AmfValue::StrictArray(vec![object("a"), object("name")])
+        );
+
+        // 組み立て中の自分自身への参照（オブジェクト参照0）は Null になる
+        let data = [marker::ARRAY, 0x05, 0x01, marker::NULL, marker::ARRAY, 0x00];
+        assert_eq!(
+            decode(&data).unwrap(),
+            AmfValue::StrictArray(vec![AmfValue::Null, AmfValue::Null])
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_references() {
+        assert_eq!(
+            decode(&[marker::STRING, 0x02]),
+            Err(AmfError::InvalidReference(1))
+        );
+        assert_eq!(
+            decode(&[marker::ARRAY, 0x06]),
+            Err(AmfError::InvalidReference(3))
+        );
+        assert_eq!(
+            decode(&[marker::OBJECT, 0x05]),
+            Err(AmfError::InvalidReference(1))
+        );
+        assert_eq!(
+            decode(&[marker::OBJECT, 0x07]),
+            Err(AmfError::Unsupported("externalizable object"))
+        );
+        assert_eq!(decode(&[0x20]), Err(AmfError::UnknownMarker(0x20)));
+    }
+
+    #[test]
+    fn limits_reference_expansion() {
+        // 1MBのバイト列を参照で何度も複製する配列
+        let mut data = BytesMut::new();
+        data.put_u8(marker::ARRAY);
+        put_inline_len(&mut data, 64);
+        put_string(&mut data, "");
+        encode(&AmfValue::ByteArray(vec![0; 1024 * 1024]), &mut data);
+        for _ in 1..64 {
+            data.put_u8(marker::BYTE_ARRAY);
+            // オブジェクト参照1（0は外側の配列）
+            put_u29(&mut data, 1 << 1);
+        }
+        assert_eq!(
+            decode(&data),
+            Err(AmfError::Unsupported("reference expansion too large"))
+        );
+
+        // 入れ子ごとに倍になる参照（billion laughs）
+        let mut data = BytesMut::new();
+        let levels = 40;
+        for _ in 0..levels {
+            data.put_u8(marker::ARRAY);
+            put_inline_len(&mut data, 2);
+            put_string(&mut data, "");
+        }
+        put_string_value(&mut data, &"x".repeat(1000));
+        put_string_value(&mut data, "y");
+        for level in (0..levels - 1).rev() {
+            // 直前に閉じた配列（levelの子）への参照
+            data.put_u8(marker::ARRAY);
+            put_u29(&mut data, ((level as u32) + 1) << 1);
+        }
+        assert_eq!(
+            decode(&data),
+            Err(AmfError::Unsupported("reference expansion too large"))
+        );
+
+        // 同じ文字列の参照も複製として数える
+        let mut data = BytesMut::new();
+        data.put_u8(marker::ARRAY);
+        put_inline_len(&mut data, 40);
+        put_string(&mut data, "");
+        put_string_value(&mut data, &"s".repeat(1024 * 1024));
+        for _ in 1..40 {
+            data.put_u8(marker::STRING);
+            put_u29(&mut data, 0);
+        }
+        assert_eq!(
+            decode(&data),
+            Err(AmfError::Unsupported("reference expansion too large"))
+        );
+    }
+
+    fn put_string_value(dst: &mut BytesMut, s: &str) {
+        dst.put_u8(marker::STRING);
+        put_string(dst, s);
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let nested = |depth: usize| {
+            let mut data = BytesMut::new();
+            for _ in 0..depth {
+                data.put_u8(marker::ARRAY);
+                put_inline_len(&mut data, 1);
+                put_string(&mut data, "");
+            }
+            data.put_u8(marker::NULL);
+            data
+        };
+        assert!(decode(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(decode(&nested(MAX_DEPTH + 1)), Err(AmfError::TooDeep));
+
+        // 参照で一段深く埋め込んでも上限を超えられない
+        let mut data = BytesMut::new();
+        data.put_u8(marker::ARRAY);
+        put_inline_len(&mut data, 2);
+        put_string(&mut data, "");
+        data.extend_from_slice(&nested(MAX_DEPTH - 1));
+        data.put_u8(marker::ARRAY);
+        put_inline_len(&mut data, 1);
+        put_string(&mut data, "");
+        data.put_u8(marker::ARRAY);
+        put_u29(&mut data, 1 << 1);
+        assert_eq!(decode(&data), Err(AmfError::TooDeep));
+    }
+}
@@ -0,0 +1,194 @@
+//! AMF0/AMF3 エンコーダ・デコーダ
+//!
+//! RTMPのコマンドメッセージ（connect, publish 等）と
+//! データメッセージ（@setDataFrame, onMetaData 等）のペイロード形式
+
+pub mod amf0;
+pub mod amf3;
+
+use thiserror::Error;
+
+use super::message::msg_type;
+
+/// ネストの上限（不正な入力によるスタック溢れを防ぐ）
+const MAX_DEPTH: usize = 64;
+
+/// AMFのデコードエラー
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AmfError {
+    #[error("unexpected end of AMF data")]
+    UnexpectedEof,
+
+    #[error("unknown AMF marker: 0x{0:02x}")]
+    UnknownMarker(u8),
+
+    #[error("unsupported AMF type: {0}")]
+    Unsupported(&'static str),
+
+    #[error("invalid UTF-8 string")]
+    InvalidUtf8,
+
+    #[error("invalid reference index: {0}")]
+    InvalidReference(usize),
+
+    #[error("unexpected object-end marker")]
+    UnexpectedObjectEnd,
+
+    #[error("AMF nesting too deep")]
+    TooDeep,
+}
+
+/// AMF0/AMF3 共通の値表現
+///
+/// オブジェクトとECMA配列はキーの順序を保持する
+#[derive(Debug, Clone, PartialEq)]
+pub enum AmfValue {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Object(Vec<(String, AmfValue)>),
+    EcmaArray(Vec<(String, AmfValue)>),
+    StrictArray(Vec<AmfValue>),
+    Null,
+    Undefined,
+    Date { millis: f64, timezone: i16 },
+    XmlDocument(String),
+    ByteArray(Vec<u8>),
+}
+
+impl AmfValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// オブジェクト/ECMA配列のプロパティを取得する
+    pub fn get(&self, key: &str) -> Option<&AmfValue> {
+        match self {
+            Self::Object(props) | Self::EcmaArray(props) => {
+                props.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            _ => None,
+        }
+    }
+
+    /// `(&str, AmfValue)` の列からオブジェクトを作る
+    pub fn object<I, K>(props: I) -> Self
+    where
+        I: IntoIterator<Item = (K, AmfValue)>,
+        K: Into<String>,
+    {
+        Self::Object(props.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+}
+
+impl From<f64> for AmfValue {
+    fn from(n: f64) -> Self {
+        Self::Number(n)
+    }
+}
+
+impl From<bool> for AmfValue {
+    fn from(b: bool) -> Self {
+        Self::Boolean(b)
+    }
+}
+
+impl From<&str> for AmfValue {
+    fn from(s: &str) -> Self {
+        Self::String(s.to_string())
+    }
+}
+
+impl From<String> for AmfValue {
+    fn from(s: String) -> Self {
+        Self::String(s)
+    }
+}
+
+/// コマンド/データメッセージのペイロードをAMF値の列にデコードする
+///
+/// AMF3形式 (type 15/17) は先頭1バイトのフォーマット指定子の後にAMF0列が続き、
+/// 個々の値が `avmplus-object` でAMF3に切り替わる
+pub fn decode_payload(type_id: u8, payload: &[u8]) -> Result<Vec<AmfValue>, AmfError> {
+    match type_id {
+        msg_type::COMMAND_AMF3 | msg_type::DATA_AMF3 => match payload.split_first() {
+            Some((0, rest)) => amf0::decode_all(rest),
+            Some((_, _)) => amf0::decode_all(payload),
+            None => Ok(Vec::new()),
+        },
+        _ => amf0::decode_all(payload),
+    }
+}
+
+/// バイト列の読み取りカーソル
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], AmfError> {
+        if self.remaining() < n {
+            return Err(AmfError::UnexpectedEof);
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn peek_u8(&self) -> Result<u8, AmfError> {
+        self.buf
+            .get(self.pos)
+            .copied()
+            .ok_or(AmfError::UnexpectedEof)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, AmfError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, AmfError> {
+        let b = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, AmfError> {
+        let b = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn read_f64(&mut self) -> Result<f64, AmfError> {
+        let b = self.read_bytes(8)?;
+        Ok(f64::from_be_bytes(b.try_into().unwrap()))
+    }
+
+    fn read_utf8(&mut self, len: usize) -> Result<String, AmfError> {
+        let b = self.read_bytes(len)?;
+        String::from_utf8(b.to_vec()).map_err(|_| AmfError::InvalidUtf8)
+    }
+}
@@ -0,0 +1,590 @@
+use bytes::{Buf, BufMut, BytesMut};
+use std::collections::HashMap;
+
+use super::error::RtmpError;
+use super::message::{msg_type, ControlMessage, RtmpMessage};
+
+/// 仕様上の初期チャンクサイズ
+pub const DEFAULT_CHUNK_SIZE: usize = 128;
+
+/// 接続確立後にこちらから送るチャンクサイズ
+pub const OUTGOING_CHUNK_SIZE: u32 = 4096;
+
+/// Set Chunk Size で受け付ける上限 (16MB)
+const MAX_CHUNK_SIZE: u32 = 0x00FF_FFFF;
+
+/// 受け付ける1メッセージの最大長（ヘッダ上は16MBまで宣言できる）
+const MAX_MESSAGE_LEN: usize = 8 * 1024 * 1024;
+
+/// 1接続で組み立て途中のメッセージに保持するバイト数の合計の上限
+const MAX_BUFFERED_BYTES: usize = 16 * 1024 * 1024;
+
+/// 3バイトのタイムスタンプがこの値なら拡張タイムスタンプが続く
+const EXTENDED_TIMESTAMP: u32 = 0x00FF_FFFF;
+
+/// fmt 0〜3 ごとのメッセージヘッダ長
+const MESSAGE_HEADER_LEN: [usize; 4] = [11, 7, 3, 0];
+
+/// チャンクストリームごとの直前ヘッダと組み立て途中のペイロード
+#[derive(Default)]
+struct ChunkStreamState {
+    initialized: bool,
+    timestamp: u32,
+    /// 直前ヘッダのタイムスタンプフィールド（fmt3で新規メッセージを始める際に加算）
+    timestamp_delta: u32,
+    extended: bool,
+    length: usize,
+    type_id: u8,
+    stream_id: u32,
+    payload: BytesMut,
+}
+
+/// 受信バイト列からRTMPメッセージを組み立てるデマルチプレクサ
+///
+/// Set Chunk Size と Abort はデコーダ自身が適用した上でメッセージとしても返す。
+/// 宣言されたメッセージ長ではなく届いたデータの分だけバッファを確保し、
+/// 組み立て途中のデータの合計が上限を超えたらエラーにする
+pub struct ChunkDecoder {
+    chunk_size: usize,
+    streams: HashMap<u32, ChunkStreamState>,
+    /// 全チャンクストリームの組み立て途中のペイロードの合計
+    buffered: usize,
+}
+
+impl Default for ChunkDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChunkDecoder {
+    pub fn new() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            streams: HashMap::new(),
+            buffered: 0,
+        }
+    }
+
+    /// `src` から完成したメッセージを1つ取り出す
+    ///
+    /// データが足りない場合は `Ok(None)` を返し、未完のチャンクは `src` に残す
+    pub fn decode(&mut self, src: &mut BytesMut) -> Result<Option<RtmpMessage>, RtmpError> {
+        loop {
+            match self.decode_chunk(src)? {
+                Chunk::Incomplete => return Ok(None),
+                Chunk::Partial => continue,
+                Chunk::Message(msg) => {
+                    self.apply_control(&msg)?;
+                    return Ok(Some(msg));
+                }
+            }
+        }
+    }
+
+    fn apply_control(&mut self, msg: &RtmpMessage) -> Result<(), RtmpError> {
+        if msg.type_id != msg_type::SET_CHUNK_SIZE && msg.type_id != msg_type::ABORT {
+            return Ok(());
+        }
+        match ControlMessage::parse(msg)? {
+            Some(ControlMessage::SetChunkSize(size)) => {
+                if size == 0 || size > MAX_CHUNK_SIZE {
+                    return Err(RtmpError::InvalidChunkSize(size));
+                }
+                self.chunk_size = size as usize;
+            }
+            Some(ControlMessage::Abort(csid)) => {
+                if let Some(state) = self.streams.get_mut(&csid) {
+                    self.buffered -= state.payload.len();
+                    state.payload = BytesMut::new();
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn decode_chunk(&mut self, src: &mut BytesMut) -> Result<Chunk, RtmpError> {
+        let buf = &src[..];
+        if buf.is_empty() {
+            return Ok(Chunk::Incomplete);
+        }
+
+        // Basic Header (1〜3バイト)
+        let fmt = (buf[0] >> 6) as usize;
+        let (csid, mut pos) = match buf[0] & 0x3F {
+            0 => {
+                if buf.len() < 2 {
+                    return Ok(Chunk::Incomplete);
+                }
+                (64 + buf[1] as u32, 2)
+            }
+            1 => {
+                if buf.len() < 3 {
+                    return Ok(Chunk::Incomplete);
+                }
+                (64 + buf[1] as u32 + ((buf[2] as u32) << 8), 3)
+            }
+            id => (id as u32, 1),
+        };
+
+        // Message Header (fmt 0: 11, 1: 7, 2: 3, 3: 0 バイト)
+        let header_len = MESSAGE_HEADER_LEN[fmt];
+        if buf.len() < pos + header_len {
+            return Ok(Chunk::Incomplete);
+        }
+
+        let state = self.streams.entry(csid).or_default();
+        if fmt != 0 && !state.initialized {
+            return Err(RtmpError::UnknownChunkStream(csid));
+        }
+
+        let h = &buf[pos..pos + header_len];
+        let mut ts_field = state.timestamp_delta;
+        let mut length = state.length;
+        let mut type_id = state.type_id;
+        let mut stream_id = state.stream_id;
+        if fmt <= 2 {
+            ts_field = read_u24(&h[0..3]);
+        }
+        if fmt <= 1 {
+            length = read_u24(&h[3..6]) as usize;
+            type_id = h[6];
+            if length > MAX_MESSAGE_LEN {
+                return Err(RtmpError::MessageTooLarge(length));
+            }
+        }
+        if fmt == 0 {
+            stream_id = u32::from_le_bytes([h[7], h[8], h[9], h[10]]);
+        }
+        pos += header_len;
+
+        // Extended Timestamp（fmt3では直前ヘッダが拡張だった場合に付く）
+        let extended = if fmt <= 2 {
+            ts_field == EXTENDED_TIMESTAMP
+        } else {
+            state.extended
+        };
+        if extended {
+            if buf.len() < pos + 4 {
+                return Ok(Chunk::Incomplete);
+            }
+            ts_field = u32::from_be_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]]);
+            pos += 4;
+        }
+
+        // fmt0〜2で途中のメッセージが上書きされた場合は破棄して新規扱い
+        if fmt != 3 && !state.payload.is_empty() {
+            tracing::warn!(
+                "RTMP chunk stream {} interrupted by fmt {} header",
+                csid,
+                fmt
+            );
+            self.buffered -= state.payload.len();
+            state.payload = BytesMut::new();
+        }
+        let starts_message = state.payload.is_empty();
+
+        let remaining = length - state.payload.len();
+        let chunk_len = remaining.min(self.chunk_size);
+        if buf.len() < pos + chunk_len {
+            return Ok(Chunk::Incomplete);
+        }
+        if self.buffered + chunk_len > MAX_BUFFERED_BYTES {
+            return Err(RtmpError::BufferLimit);
+        }
+
+        // ここまででチャンク全体が揃ったので状態を更新する
+        if starts_message {
+            state.timestamp = if fmt == 0 {
+                ts_field
+            } else {
+                state.timestamp.wrapping_add(ts_field)
+            };
+            state.timestamp_delta = ts_field;
+            state.extended = extended;
+            state.length = length;
+            state.type_id = type_id;
+            state.stream_id = stream_id;
+            state.initialized = true;
+        }
+
+        src.advance(pos);
+        state.payload.extend_from_slice(&src.split_to(chunk_len));
+        self.buffered += chunk_len;
+
+        if state.payload.len() < state.length {
+            return Ok(Chunk::Partial);
+        }
+        self.buffered -= state.payload.len();
+
+        Ok(Chunk::Message(RtmpMessage {
+            csid,
+            timestamp: state.timestamp,
+            type_id: state.type_id,
+            stream_id: state.stream_id,
+            payload: state.payload.split().freeze(),
+        }))
+    }
+}
+
+enum Chunk {
+    Incomplete,
+    Partial,
+    Message(RtmpMessage),
+}
+
+/// RTMPメッセージをチャンクに分割して書き出すエンコーダ
+///
+/// 先頭チャンクは常にfmt0、続きのチャンクはfmt3で送る
+pub struct ChunkEncoder {
+    chunk_size: usize,
+}
+
+impl Default for ChunkEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChunkEncoder {
+    pub fn new() -> Self {
+        Self {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// 送信チャンクサイズを変更する（相手へのSet Chunk Size送信後に呼ぶ）
+    pub fn set_chunk_size(&mut self, size: usize) {
+        self.chunk_size = size.clamp(1, MAX_CHUNK_SIZE as usize);
+    }
+
+    pub fn encode(&self, msg: &RtmpMessage, dst: &mut BytesMut) {
+        let extended = msg.timestamp >= EXTENDED_TIMESTAMP;
+        let ts_field = if extended {
+            EXTENDED_TIMESTAMP
+        } else {
+            msg.timestamp
+        };
+        let len = msg.payload.len();
+
+        dst.reserve(len + 18 + (len / self.chunk_size) * 8);
+
+        write_basic_header(dst, 0, msg.csid);
+        put_u24(dst, ts_field);
+        put_u24(dst, len as u32);
+        dst.put_u8(msg.type_id);
+        dst.put_u32_le(msg.stream_id);
+        if extended {
+            dst.put_u32(msg.timestamp);
+        }
+
+        for (i, chunk) in msg.payload.chunks(self.chunk_size).enumerate() {
+            if i > 0 {
+                write_basic_header(dst, 3, msg.csid);
+                if extended {
+                    dst.put_u32(msg.timestamp);
+                }
+            }
+            dst.extend_from_slice(chunk);
+        }
+    }
+}
+
+fn write_basic_header(dst: &mut BytesMut, fmt: u8, csid: u32) {
+    let fmt = fmt << 6;
+    match csid {
+        2..=63 => dst.put_u8(fmt | csid as u8),
+        64..=319 => {
+            dst.put_u8(fmt);
+            dst.put_u8((csid - 64) as u8);
+        }
+        _ => {
+            let id = csid - 64;
+            dst.put_u8(fmt | 1);
+            dst.put_u8((id & 0xFF) as u8);
+            dst.put_u8((id >> 8) as u8);
+        }
+    }
+}
+
+fn read_u24(b: &[u8]) -> u32 {
+    ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32
+}
+
+fn put_u24(dst: &mut BytesMut, v: u32) {
+    dst.put_u8((v >> 16) as u8);
+    dst.put_u8((v >> 8) as u8);
+    dst.put_u8(v as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// 全バイトを一度に渡して取り出せるメッセージを全部返す
+    fn decode_all(decoder: &mut ChunkDecoder, data: &[u8]) -> Vec<RtmpMessage> {
+        let mut src = BytesMut::from(data);
+        let mut messages = Vec::new();
+        while let Some(msg) = decoder.decode(&mut src).unwrap() {
+            messages.push(msg);
+        }
+        assert!(src.is_empty());
+        messages
+    }
+
+    fn set_chunk_size(size: u32) -> Vec<u8> {
+        let mut data = hex("02 000000 000004 01 00000000");
+        data.extend_from_slice(&size.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn decodes_all_header_formats() {
+        let data = [
+            // fmt0 csid4: ts=16 len=3 type=9 stream=1
+            hex("04 000010 000003 09 01000000 aabbcc"),
+            // fmt1: delta=33 len=2 type=8
+            hex("44 000021 000002 08 dddd"),
+            // fmt2: delta=33
+            hex("84 000021 eeee"),
+            // fmt3: 直前と同じヘッダで新しいメッセージ
+            hex("c4 ffff"),
+        ]
+        .concat();
+        let messages = decode_all(&mut ChunkDecoder::new(), &data);
+
+        let summary: Vec<_> = messages
+            .iter()
+            .map(|m| {
+                (
+                    m.csid,
+                    m.timestamp,
+                    m.type_id,
+                    m.stream_id,
+                    m.payload.to_vec(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (4, 16, 9, 1, vec![0xaa, 0xbb, 0xcc]),
+                (4, 49, 8, 1, vec![0xdd, 0xdd]),
+                (4, 82, 8, 1, vec![0xee, 0xee]),
+                (4, 115, 8, 1, vec![0xff, 0xff]),
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_extended_basic_headers() {
+        let data = [
+            // csid 64 + 1 (2バイト形式)
+            hex("00 01 000000 000001 14 00000000 01"),
+            // csid 64 + 0x0102 (3バイト形式)
+            hex("01 02 01 000000 000001 14 00000000 02"),
+        ]
+        .concat();
+        let messages = decode_all(&mut ChunkDecoder::new(), &data);
+        let csids: Vec<_> = messages.iter().map(|m| m.csid).collect();
+        assert_eq!(csids, vec![65, 64 + 0x0102]);
+    }
+
+    #[test]
+    fn reassembles_interleaved_chunks() {
+        let payload: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let data = [
+            hex("06 000000 00012c 09 01000000"),
+            payload[..128].to_vec(),
+            // 途中に別のチャンクストリームのメッセージが挟まる
+            hex("03 000000 000002 14 00000000 0102"),
+            hex("c6"),
+            payload[128..256].to_vec(),
+            hex("c6"),
+            payload[256..].to_vec(),
+        ]
+        .concat();
+
+        let messages = decode_all(&mut ChunkDecoder::new(), &data);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].csid, 3);
+        assert_eq!(messages[1].csid, 6);
+        assert_eq!(messages[1].payload.to_vec(), payload);
+
+        // 1バイトずつ届いても同じ結果になる
+        let mut decoder = ChunkDecoder::new();
+        let mut src = BytesMut::new();
+        let mut incremental = Vec::new();
+        for &byte in &data {
+            src.put_u8(byte);
+            while let Some(msg) = decoder.decode(&mut src).unwrap() {
+                incremental.push(msg);
+            }
+        }
+        assert_eq!(incremental, messages);
+    }
+
+    #[test]
+    fn decodes_extended_timestamp() {
+        let payload = vec![0x55; 200];
+        let data = [
+            hex("04 ffffff 0000c8 09 01000000 01000000"),
+            payload[..128].to_vec(),
+            // fmt3の続きにも拡張タイムスタンプが付く
+            hex("c4 01000000"),
+            payload[128..].to_vec(),
+        ]
+        .concat();
+
+        let messages = decode_all(&mut ChunkDecoder::new(), &data);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].timestamp, 0x0100_0000);
+        assert_eq!(messages[0].payload.to_vec(), payload);
+    }
+
+    #[test]
+    fn applies_set_chunk_size() {
+        let payload = vec![0x11; 300];
+        let data = [
+            set_chunk_size(4096),
+            hex("04 000000 00012c 09 01000000"),
+            payload.clone(),
+        ]
+        .concat();
+
+        let mut decoder = ChunkDecoder::new();
+        let messages = decode_all(&mut decoder, &data);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            ControlMessage::parse(&messages[0]).unwrap(),
+            Some(ControlMessage::SetChunkSize(4096))
+        );
+        assert_eq!(messages[1].payload.to_vec(), payload);
+        assert_eq!(decoder.chunk_size, 4096);
+
+        let mut src = BytesMut::from(&set_chunk_size(0)[..]);
+        assert!(matches!(
+            ChunkDecoder::new().decode(&mut src),
+            Err(RtmpError::InvalidChunkSize(0))
+        ));
+    }
+
+    #[test]
+    fn abort_discards_partial_message() {
+        let data = [
+            hex("04 000000 00012c 09 01000000"),
+            vec![0x22; 128],
+            // Abort(csid=4)
+            hex("02 000000 000004 02 00000000 00000004"),
+            hex("04 000000 000002 09 01000000 3333"),
+        ]
+        .concat();
+
+        let mut decoder = ChunkDecoder::new();
+        let messages = decode_all(&mut decoder, &data);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            ControlMessage::parse(&messages[0]).unwrap(),
+            Some(ControlMessage::Abort(4))
+        );
+        assert_eq!(messages[1].payload.to_vec(), vec![0x33, 0x33]);
+        assert_eq!(decoder.buffered, 0);
+    }
+
+    #[test]
+    fn rejects_fmt3_without_previous_header() {
+        let mut src = BytesMut::from(&hex("c5 00")[..]);
+        assert!(matches!(
+            ChunkDecoder::new().decode(&mut src),
+            Err(RtmpError::UnknownChunkStream(5))
+        ));
+    }
+
+    #[test]
+    fn rejects_oversized_message() {
+        let mut src = BytesMut::from(&hex("04 000000 ffffff 09 01000000")[..]);
+        assert!(matches!(
+            ChunkDecoder::new().decode(&mut src),
+            Err(RtmpError::MessageTooLarge(0x00FF_FFFF))
+        ));
+    }
+
+    #[test]
+    fn limits_buffered_bytes_across_chunk_streams() {
+        const CHUNK: usize = 4 * 1024 * 1024;
+        let mut decoder = ChunkDecoder::new();
+        decode_all(&mut decoder, &set_chunk_size(CHUNK as u32));
+
+        // 8MBのメッセージの前半だけを別々のチャンクストリームで送る
+        let mut result = Ok(None);
+        for csid in 3..8u8 {
+            let mut src = BytesMut::from(&[csid][..]);
+            src.put_slice(&hex("000000 800000 09 01000000"));
+            src.put_bytes(0, CHUNK);
+            result = decoder.decode(&mut src);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(matches!(result, Err(RtmpError::BufferLimit)));
+        assert_eq!(decoder.buffered, MAX_BUFFERED_BYTES);
+    }
+
+    #[test]
+    fn encoder_round_trip() {
+        let messages = vec![
+            RtmpMessage {
+                csid: 3,
+                timestamp: 0,
+                type_id: msg_type::COMMAND_AMF0,
+                stream_id: 0,
+                payload: vec![1; 10].into(),
+            },
+            RtmpMessage {
+                csid: 6,
+                timestamp: 0x0123_4567,
+                type_id: msg_type::VIDEO,
+                stream_id: 1,
+                payload: (0..10_000).map(|i| i as u8).collect::<Vec<_>>().into(),
+            },
+            RtmpMessage {
+                csid: 400,
+                timestamp: 40,
+                type_id: msg_type::AUDIO,
+                stream_id: 1,
+                payload: vec![2; 129].into(),
+            },
+        ];
+
+        for chunk_size in [DEFAULT_CHUNK_SIZE, OUTGOING_CHUNK_SIZE as usize] {
+            let mut encoder = ChunkEncoder::new();
+            let mut decoder = ChunkDecoder::new();
+            let mut data = BytesMut::new();
+            if chunk_size != DEFAULT_CHUNK_SIZE {
+                encoder.encode(
+                    &ControlMessage::SetChunkSize(chunk_size as u32).to_message(),
+                    &mut data,
+                );
+                encoder.set_chunk_size(chunk_size);
+            }
+            messages
+                .iter()
+                .for_each(|msg| encoder.encode(msg, &mut data));
+
+            let mut decoded = decode_all(&mut decoder, &data);
+            if chunk_size != DEFAULT_CHUNK_SIZE {
+                decoded.remove(0);
+            }
+            assert_eq!(decoded, messages);
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use bytes::{BufMut, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+use tokio_util::either::Either;
+use tracing::debug;
+
+use super::amf::{self, amf0, AmfValue};
+use super::chunk::{ChunkDecoder, ChunkEncoder, OUTGOING_CHUNK_SIZE};
+use super::command::{self, COMMAND_CSID, STREAM_CSID};
+use super::error::RtmpError;
+use super::handshake;
+use super::message::{
+    msg_type, ControlMessage, RtmpMessage, UserControlEvent, AUDIO_CSID, VIDEO_CSID,
+};
+use crate::media::tag::{MediaTag, TagKind};
+
+/// RTMPの標準ポート
+const DEFAULT_PORT: u16 = 1935;
+
+/// RTMPSの標準ポート
+const DEFAULT_TLS_PORT: u16 = 443;
+
+/// サーバーからWindow Ack Sizeが届くまでのAcknowledgement間隔
+const DEFAULT_WINDOW_ACK_SIZE: u32 = 2_500_000;
+
+/// rtmp://host[:port]/app/stream_name（または rtmps://）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtmpUrl {
+    /// `rtmps://` ならTLSで接続する
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    /// アプリケーション名（最後のパス要素より前）
+    pub app: String,
+    /// ストリーム名（最後のパス要素、クエリ文字列を含む）
+    pub stream_name: String,
+}
+
+impl RtmpUrl {
+    pub fn parse(url: &str) -> Result<Self, RtmpError> {
+        let invalid = || RtmpError::InvalidUrl(url.to_string());
+
+        let (tls, rest) = match url.strip_prefix("rtmps://") {
+            Some(rest) => (true, rest),
+            None => (false, url.strip_prefix("rtmp://").ok_or_else(invalid)?),
+        };
+        let (authority, path) = rest.split_once('/').ok_or_else(invalid)?;
+        let default_port = if tls { DEFAULT_TLS_PORT } else { DEFAULT_PORT };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, default_port),
+        };
+        let (app, stream_name) = path.rsplit_once('/').ok_or_else(invalid)?;
+        if host.is_empty() || app.is_empty() || stream_name.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+            app: app.to_string(),
+            stream_name: stream_name.to_string(),
+        })
+    }
+
+    /// connect の tcUrl（ストリーム名を除いたURL）
+    pub fn tc_url(&self) -> String {
+        let scheme = if self.tls { "rtmps" } else { "rtmp" };
+        format!("{}://{}:{}/{}", scheme, self.host, self.port, self.app)
+    }
+}
+
+/// 上流サーバーとの接続（RTMPSならTLS）
+type ClientStream = Either<TcpStream, TlsStream<TcpStream>>;
+
+/// 上流サーバーへ配信するRTMPクライアント（プッシュリレー用）
+pub struct RtmpClient {
+    stream: ClientStream,
+    decoder: ChunkDecoder,
+    encoder: ChunkEncoder,
+    read_buf: BytesMut,
+    /// 次の送信でまとめて書き出すメッセージ
+    write_buf: BytesMut,
+    bytes_received: u64,
+    last_ack: u64,
+    window_ack_size: u32,
+    stream_id: u32,
+}
+
+impl RtmpClient {
+    /// 接続してハンドシェイク、connect、createStream、publish まで行う
+    ///
+    /// `rtmps://` のURLには `tls` が必要
+    pub async fn publish(url: &RtmpUrl, tls: Option<&TlsConnector>) -> Result<Self, RtmpError> {
+        let socket = TcpStream::connect((url.host.as_str(), url.port)).await?;
+        socket.set_nodelay(true)?;
+        let mut stream = if url.tls {
+            let connector = tls.ok_or(RtmpError::TlsUnavailable)?;
+            let server_name = ServerName::try_from(url.host.clone())
+                .map_err(|_| RtmpError::InvalidUrl(url.host.clone()))?;
+            Either::Right(connector.connect(server_name, socket).await?)
+        } else {
+            Either::Left(socket)
+        };
+        handshake::client_handshake(&mut stream).await?;
+
+        let mut client = Self {
+            stream,
+            decoder: ChunkDecoder::new(),
+            encoder: ChunkEncoder::new(),
+            read_buf: BytesMut::with_capacity(16 * 1024),
+            write_buf: BytesMut::with_capacity(64 * 1024),
+            bytes_received: 0,
+            last_ack: 0,
+            window_ack_size: DEFAULT_WINDOW_ACK_SIZE,
+            stream_id: 0,
+        };
+
+        client.queue(&ControlMessage::SetChunkSize(OUTGOING_CHUNK_SIZE).to_message());
+        client.encoder.set_chunk_size(OUTGOING_CHUNK_SIZE as usize);
+        client.queue(&command::command_message(
+            COMMAND_CSID,
+            0,
+            &[
+                "connect".into(),
+                1.0.into(),
+                AmfValue::object([
+                    ("app", url.app.as_str().into()),
+                    ("type", "nonprivate".into()),
+                    ("flashVer", "FMLE/3.0 (compatible; vyuber)".into()),
+                    ("tcUrl", url.tc_url().into()),
+                ]),
+            ],
+        ));
+        client.flush().await?;
+        client.wait_result(1.0).await?;
+
+        let name = url.stream_name.as_str();
+        for (transaction_id, command_name) in [(2.0, "releaseStream"), (3.0, "FCPublish")] {
+            client.queue(&command::command_message(
+                COMMAND_CSID,
+                0,
+                &[command_name.into(), transaction_id.into(), AmfValue::Null, name.into()],
+            ));
+        }
+        client.queue(&command::command_message(
+            COMMAND_CSID,
+            0,
+            &["createStream".into(), 4.0.into(), AmfValue::Null],
+        ));
+        client.flush().await?;
+        let result = client.wait_result(4.0).await?;
+        client.stream_id = result
+            .get(3)
+            .and_then(AmfValue::as_f64)
+            .ok_or(RtmpError::Malformed("createStream result"))? as u32;
+
+        client.queue(&command::command_message(
+            STREAM_CSID,
+            client.stream_id,
+            &[
+                "publish".into(),
+                5.0.into(),
+                AmfValue::Null,
+                name.into(),
+                "live".into(),
+            ],
+        ));
+        client.flush().await?;
+        client.wait_status("NetStream.Publish.Start").await?;
+        Ok(client)
+    }
+
+    /// タグを送る（スクリプトタグは `@setDataFrame` を付けて送る）
+    pub async fn send_tag(&mut self, tag: &MediaTag) -> Result<(), RtmpError> {
+        let msg = match tag.kind {
+            TagKind::Audio => RtmpMessage {
+                csid: AUDIO_CSID,
+                timestamp: tag.timestamp,
+                type_id: msg_type::AUDIO,
+                stream_id: self.stream_id,
+                payload: tag.data.clone(),
+            },
+            TagKind::Video => RtmpMessage {
+                csid: VIDEO_CSID,
+                timestamp: tag.timestamp,
+                type_id: msg_type::VIDEO,
+                stream_id: self.stream_id,
+                payload: tag.data.clone(),
+            },
+            TagKind::Script => {
+                let mut payload = BytesMut::new();
+                payload.put(amf0::encode_all(&["@setDataFrame".into()]));
+                payload.put_slice(&tag.data);
+                command::data_message(self.stream_id, tag.timestamp, payload.freeze())
+            }
+        };
+        self.queue(&msg);
+        self.flush().await
+    }
+
+    /// サーバーからのデータを読んで処理する（キャンセルしても状態は壊れない）
+    ///
+    /// 応答が必要なメッセージは次の送信でまとめて書き出す
+    pub async fn read(&mut self) -> Result<(), RtmpError> {
+        self.fill().await?;
+        while let Some(msg) = self.decoder.decode(&mut self.read_buf)? {
+            self.handle(&msg)?;
+        }
+        Ok(())
+    }
+
+    /// 配信を終える（deleteStream を送る）
+    pub async fn unpublish(mut self) -> Result<(), RtmpError> {
+        self.queue(&command::command_message(
+            COMMAND_CSID,
+            0,
+            &[
+                "deleteStream".into(),
+                0.0.into(),
+                AmfValue::Null,
+                (self.stream_id as f64).into(),
+            ],
+        ));
+        self.flush().await?;
+        self.stream.shutdown().await?;
+        Ok(())
+    }
+
+    /// `_result` を待ってAMF値列を返す
+    async fn wait_result(&mut self, transaction_id: f64) -> Result<Vec<AmfValue>, RtmpError> {
+        loop {
+            let values = self.next_command().await?;
+            let name = values.first().and_then(AmfValue::as_str).unwrap_or_default();
+            let id = values.get(1).and_then(AmfValue::as_f64);
+            match name {
+                "_result" if id == Some(transaction_id) => return Ok(values),
+                "_error" if id == Some(transaction_id) => {
+                    return Err(RtmpError::Rejected(status_code(&values)))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// onStatus の `code` を待つ
+    async fn wait_status(&mut self, code: &str) -> Result<(), RtmpError> {
+        loop {
+            let values = self.next_command().await?;
+            if values.first().and_then(AmfValue::as_str) == Some("onStatus")
+                && status_code(&values) == code
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// 次のコマンドメッセージ（エラーレベルの onStatus はエラーにする）
+    async fn next_command(&mut self) -> Result<Vec<AmfValue>, RtmpError> {
+        loop {
+            let Some(msg) = self.decoder.decode(&mut self.read_buf)? else {
+                self.fill().await?;
+                continue;
+            };
+            if self.handle(&msg)? {
+                self.flush().await?;
+                continue;
+            }
+            if msg.type_id == msg_type::COMMAND_AMF0 || msg.type_id == msg_type::COMMAND_AMF3 {
+                return Ok(amf::decode_payload(msg.type_id, &msg.payload)?);
+            }
+        }
+    }
+
+    /// 制御メッセージとエラーを処理する（処理済みなら `true`）
+    fn handle(&mut self, msg: &RtmpMessage) -> Result<bool, RtmpError> {
+        if let Some(control) = ControlMessage::parse(msg)? {
+            match control {
+                ControlMessage::WindowAckSize(size) => self.window_ack_size = size.max(1),
+                ControlMessage::UserControl(UserControlEvent::PingRequest(ts)) => {
+                    self.queue(
+                        &ControlMessage::UserControl(UserControlEvent::PingResponse(ts))
+                            .to_message(),
+                    );
+                }
+                other => debug!("RTMP control from upstream: {:?}", other),
+            }
+            return Ok(true);
+        }
+
+        if msg.type_id == msg_type::COMMAND_AMF0 || msg.type_id == msg_type::COMMAND_AMF3 {
+            let values = amf::decode_payload(msg.type_id, &msg.payload)?;
+            if values.first().and_then(AmfValue::as_str) == Some("onStatus") {
+                let level = values
+                    .get(3)
+                    .and_then(|info| info.get("level"))
+                    .and_then(AmfValue::as_str);
+                if level == Some("error") {
+                    return Err(RtmpError::Rejected(status_code(&values)));
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// ソケットから読み足す（接続が閉じられたらエラー）
+    async fn fill(&mut self) -> Result<(), RtmpError> {
+        let n = self.stream.read_buf(&mut self.read_buf).await?;
+        if n == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+        self.bytes_received += n as u64;
+        if self.bytes_received - self.last_ack >= self.window_ack_size as u64 {
+            self.last_ack = self.bytes_received;
+            // シーケンス番号は32bitで折り返す
+            self.queue(&ControlMessage::Acknowledgement(self.bytes_received as u32).to_message());
+        }
+        Ok(())
+    }
+
+    fn queue(&mut self, msg: &RtmpMessage) {
+        self.encoder.encode(msg, &mut self.write_buf);
+    }
+
+    async fn flush(&mut self) -> Result<(), RtmpError> {
+        if self.write_buf.is_empty() {
+            return Ok(());
+        }
+        self.stream.write_all(&self.write_buf).await?;
+        self.write_buf.clear();
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// `_error` / onStatus のinfoオブジェクトの `code`
+fn status_code(values: &[AmfValue]) -> String {
+    values
+        .get(3)
+        .and_then(|info| info.get("code"))
+        .and_then(AmfValue::as_str)
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rtmp_and_rtmps_urls() {
+        let url = RtmpUrl::parse("rtmp://live.example.com/app/key?token=1").unwrap();
+        assert!(!url.tls);
+        assert_eq!(url.port, 1935);
+        assert_eq!(url.stream_name, "key?token=1");
+        assert_eq!(url.tc_url(), "rtmp://live.example.com:1935/app");
+
+        let url = RtmpUrl::parse("rtmps://live.example.com/live2/app/key").unwrap();
+        assert!(url.tls);
+        assert_eq!(url.port, 443);
+        assert_eq!(url.app, "live2/app");
+        assert_eq!(url.tc_url(), "rtmps://live.example.com:443/live2/app");
+
+        let url = RtmpUrl::parse("rtmps://127.0.0.1:1936/app/key").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("127.0.0.1", 1936));
+
+        for invalid in [
+            "http://example.com/app/key",
+            "rtmp://example.com/key",
+            "rtmp://example.com:port/app/key",
+            "rtmps:///app/key",
+        ] {
+            assert!(RtmpUrl::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
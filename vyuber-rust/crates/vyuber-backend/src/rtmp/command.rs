@@ -0,0 +1,163 @@
+use bytes::Bytes;
+
+use super::amf::{amf0, AmfValue};
+use super::message::{msg_type, RtmpMessage};
+
+/// NetConnection コマンド応答用のチャンクストリームID
+pub const COMMAND_CSID: u32 = 3;
+
+/// NetStream の onStatus 用のチャンクストリームID
+pub const STREAM_CSID: u32 = 5;
+
+/// クライアントから届くNetConnection/NetStreamコマンド
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Connect {
+        transaction_id: f64,
+        command_object: AmfValue,
+    },
+    CreateStream {
+        transaction_id: f64,
+    },
+    ReleaseStream {
+        transaction_id: f64,
+        stream_name: String,
+    },
+    FcPublish {
+        transaction_id: f64,
+        stream_name: String,
+    },
+    FcUnpublish {
+        transaction_id: f64,
+        stream_name: String,
+    },
+    Publish {
+        transaction_id: f64,
+        stream_name: String,
+        publish_type: String,
+    },
+    Play {
+        transaction_id: f64,
+        stream_name: String,
+    },
+    DeleteStream {
+        stream_id: u32,
+    },
+    Other {
+        name: String,
+        transaction_id: f64,
+    },
+}
+
+impl Command {
+    /// デコード済みのAMF値列からコマンドを組み立てる
+    ///
+    /// 値列の形式: コマンド名, トランザクションID, コマンドオブジェクト, 引数...
+    pub fn parse(values: &[AmfValue]) -> Option<Self> {
+        let name = values.first()?.as_str()?;
+        let transaction_id = values.get(1).and_then(AmfValue::as_f64).unwrap_or(0.0);
+        let arg_str = |i: usize| {
+            values
+                .get(i)
+                .and_then(AmfValue::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        let command = match name {
+            "connect" => Self::Connect {
+                transaction_id,
+                command_object: values.get(2).cloned().unwrap_or(AmfValue::Null),
+            },
+            "createStream" => Self::CreateStream { transaction_id },
+            "releaseStream" => Self::ReleaseStream {
+                transaction_id,
+                stream_name: arg_str(3),
+            },
+            "FCPublish" => Self::FcPublish {
+                transaction_id,
+                stream_name: arg_str(3),
+            },
+            "FCUnpublish" => Self::FcUnpublish {
+                transaction_id,
+                stream_name: arg_str(3),
+            },
+            "publish" => Self::Publish {
+                transaction_id,
+                stream_name: arg_str(3),
+                publish_type: arg_str(4),
+            },
+            "play" => Self::Play {
+                transaction_id,
+                stream_name: arg_str(3),
+            },
+            "deleteStream" => Self::DeleteStream {
+                stream_id: values.get(3).and_then(AmfValue::as_f64).unwrap_or(0.0) as u32,
+            },
+            other => Self::Other {
+                name: other.to_string(),
+                transaction_id,
+            },
+        };
+        Some(command)
+    }
+}
+
+/// AMF0コマンドメッセージを組み立てる
+pub fn command_message(csid: u32, stream_id: u32, values: &[AmfValue]) -> RtmpMessage {
+    RtmpMessage {
+        csid,
+        timestamp: 0,
+        type_id: msg_type::COMMAND_AMF0,
+        stream_id,
+        payload: amf0::encode_all(values),
+    }
+}
+
+/// `_result` / `_error` 応答
+pub fn response(
+    name: &str,
+    transaction_id: f64,
+    properties: AmfValue,
+    info: AmfValue,
+) -> RtmpMessage {
+    command_message(
+        COMMAND_CSID,
+        0,
+        &[name.into(), transaction_id.into(), properties, info],
+    )
+}
+
+/// onStatus のinfoオブジェクト
+pub fn status_info(level: &str, code: &str, description: &str) -> AmfValue {
+    AmfValue::object([
+        ("level", level.into()),
+        ("code", code.into()),
+        ("description", description.into()),
+    ])
+}
+
+/// NetStream のデータメッセージ（onMetaData, |RtmpSampleAccess など）
+pub fn data_message(stream_id: u32, timestamp: u32, payload: Bytes) -> RtmpMessage {
+    RtmpMessage {
+        csid: STREAM_CSID,
+        timestamp,
+        type_id: msg_type::DATA_AMF0,
+        stream_id,
+        payload,
+    }
+}
+
+/// NetStream の onStatus メッセージ
+pub fn on_status(stream_id: u32, level: &str, code: &str, description: &str) -> RtmpMessage {
+    command_message(
+        STREAM_CSID,
+        stream_id,
+        &[
+            "onStatus".into(),
+            0.0.into(),
+            AmfValue::Null,
+            status_info(level, code, description),
+        ],
+    )
+}
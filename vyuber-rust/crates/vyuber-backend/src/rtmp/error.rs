@@ -0,0 +1,49 @@
+use thiserror::Error;
+
+use super::amf::AmfError;
+
+/// RTMPプロトコル処理のエラー
+#[derive(Debug, Error)]
+pub enum RtmpError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("unsupported RTMP version: {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("invalid chunk size: {0}")]
+    InvalidChunkSize(u32),
+
+    #[error("chunk stream {0} has no previous header")]
+    UnknownChunkStream(u32),
+
+    #[error("message too large: {0} bytes")]
+    MessageTooLarge(usize),
+
+    #[error("too many bytes buffered in incomplete messages")]
+    BufferLimit,
+
+    #[error("AMF error: {0}")]
+    Amf(#[from] AmfError),
+
+    #[error("malformed message: {0}")]
+    Malformed(&'static str),
+
+    #[error("invalid RTMP URL: {0}")]
+    InvalidUrl(String),
+
+    #[error("rtmps:// needs TLS, which failed to initialize")]
+    TlsUnavailable,
+
+    #[error("rejected by server: {0}")]
+    Rejected(String),
+
+    #[error("timed out waiting for the server")]
+    Timeout,
+
+    #[error("handshake timed out")]
+    HandshakeTimeout,
+
+    #[error("no data for {0} ms")]
+    IdleTimeout(u64),
+}
@@ -0,0 +1,156 @@
+use rand::RngCore;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::error::RtmpError;
+
+/// RTMPバージョン (C0/S0)
+pub const RTMP_VERSION: u8 = 3;
+
+/// C1/S1/C2/S2 のサイズ
+pub const HANDSHAKE_SIZE: usize = 1536;
+
+/// S0+S1+S2 を組み立てる
+///
+/// シンプルハンドシェイク: S1はtime=0/version=0のランダム列、
+/// S2はC1をそのまま返す（OBS/ffmpeg/librtmpはこれで接続できる）
+pub fn build_s0s1s2(c1: &[u8; HANDSHAKE_SIZE]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + HANDSHAKE_SIZE * 2);
+    out.push(RTMP_VERSION);
+
+    // S1: time(4) + zero(4) + random(1528)
+    let mut s1 = [0u8; HANDSHAKE_SIZE];
+    rand::thread_rng().fill_bytes(&mut s1[8..]);
+    out.extend_from_slice(&s1);
+
+    // S2: C1のエコー
+    out.extend_from_slice(c1);
+    out
+}
+
+/// サーバー側ハンドシェイク (C0+C1 → S0+S1+S2 → C2)
+pub async fn server_handshake<S>(stream: &mut S) -> Result<(), RtmpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut c0 = [0u8; 1];
+    stream.read_exact(&mut c0).await?;
+    if c0[0] != RTMP_VERSION {
+        return Err(RtmpError::UnsupportedVersion(c0[0]));
+    }
+
+    let mut c1 = [0u8; HANDSHAKE_SIZE];
+    stream.read_exact(&mut c1).await?;
+
+    stream.write_all(&build_s0s1s2(&c1)).await?;
+    stream.flush().await?;
+
+    // C2はS1のエコーだが、クライアント実装によって内容が異なるため検証しない
+    let mut c2 = [0u8; HANDSHAKE_SIZE];
+    stream.read_exact(&mut c2).await?;
+
+    Ok(())
+}
+
+/// クライアント側ハンドシェイク (C0+C1 → S0+S1+S2 → C2)
+///
+/// C1はtime=0/version=0のランダム列、C2はS1のエコー
+pub async fn client_handshake<S>(stream: &mut S) -> Result<(), RtmpError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut c0c1 = vec![0u8; 1 + HANDSHAKE_SIZE];
+    c0c1[0] = RTMP_VERSION;
+    rand::thread_rng().fill_bytes(&mut c0c1[9..]);
+    stream.write_all(&c0c1).await?;
+    stream.flush().await?;
+
+    let mut s0 = [0u8; 1];
+    stream.read_exact(&mut s0).await?;
+    if s0[0] != RTMP_VERSION {
+        return Err(RtmpError::UnsupportedVersion(s0[0]));
+    }
+
+    let mut s1 = [0u8; HANDSHAKE_SIZE];
+    stream.read_exact(&mut s1).await?;
+    stream.write_all(&s1).await?;
+    stream.flush().await?;
+
+    // S2はC1のエコーだが、サーバー実装によって内容が異なるため検証しない
+    let mut s2 = [0u8; HANDSHAKE_SIZE];
+    stream.read_exact(&mut s2).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn client_and_server_complete_handshake() {
+        let (mut client, mut server) = tokio::io::duplex(8192);
+        let (client_result, server_result) =
+            tokio::join!(client_handshake(&mut client), server_handshake(&mut server));
+        client_result.unwrap();
+        server_result.unwrap();
+
+        // ハンドシェイクの後はそのままデータを流せる
+        client.write_all(b"chunk").await.unwrap();
+        let mut buf = [0u8; 5];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"chunk");
+    }
+
+    #[tokio::test]
+    async fn server_echoes_c1_as_s2() {
+        let (mut client, mut server) = tokio::io::duplex(8192);
+        let server_task = tokio::spawn(async move { server_handshake(&mut server).await });
+
+        // librtmp形式のC0+C1: time=0, version=0, 残りはランダム
+        let mut c1 = [0u8; HANDSHAKE_SIZE];
+        c1[8..]
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, b)| *b = (i * 7) as u8);
+        client.write_all(&[RTMP_VERSION]).await.unwrap();
+        client.write_all(&c1).await.unwrap();
+
+        let mut s0s1s2 = vec![0u8; 1 + HANDSHAKE_SIZE * 2];
+        client.read_exact(&mut s0s1s2).await.unwrap();
+        assert_eq!(s0s1s2[0], RTMP_VERSION);
+        assert_eq!(&s0s1s2[1 + HANDSHAKE_SIZE..], &c1[..]);
+
+        // C2 = S1のエコー
+        client
+            .write_all(&s0s1s2[1..1 + HANDSHAKE_SIZE])
+            .await
+            .unwrap();
+        server_task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn server_rejects_unsupported_version() {
+        let (mut client, mut server) = tokio::io::duplex(8192);
+        client.write_all(&[6]).await.unwrap();
+        assert!(matches!(
+            server_handshake(&mut server).await,
+            Err(RtmpError::UnsupportedVersion(6))
+        ));
+    }
+
+    #[tokio::test]
+    async fn client_rejects_unsupported_version() {
+        let (mut client, mut server) = tokio::io::duplex(8192);
+        let mut c0c1 = vec![0u8; 1 + HANDSHAKE_SIZE];
+        let server_task = tokio::spawn(async move {
+            server.read_exact(&mut c0c1).await.unwrap();
+            server.write_all(&[9]).await.unwrap();
+            server
+        });
+        assert!(matches!(
+            client_handshake(&mut client).await,
+            Err(RtmpError::UnsupportedVersion(9))
+        ));
+        drop(server_task.await.unwrap());
+    }
+}
@@ -0,0 +1,178 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use super::error::RtmpError;
+
+/// RTMPメッセージタイプID
+#[allow(dead_code)]
+pub mod msg_type {
+    pub const SET_CHUNK_SIZE: u8 = 1;
+    pub const ABORT: u8 = 2;
+    pub const ACKNOWLEDGEMENT: u8 = 3;
+    pub const USER_CONTROL: u8 = 4;
+    pub const WINDOW_ACK_SIZE: u8 = 5;
+    pub const SET_PEER_BANDWIDTH: u8 = 6;
+    pub const AUDIO: u8 = 8;
+    pub const VIDEO: u8 = 9;
+    pub const DATA_AMF3: u8 = 15;
+    pub const SHARED_OBJECT_AMF3: u8 = 16;
+    pub const COMMAND_AMF3: u8 = 17;
+    pub const DATA_AMF0: u8 = 18;
+    pub const SHARED_OBJECT_AMF0: u8 = 19;
+    pub const COMMAND_AMF0: u8 = 20;
+    pub const AGGREGATE: u8 = 22;
+}
+
+/// プロトコル制御メッセージ用のチャンクストリームID
+pub const CONTROL_CSID: u32 = 2;
+
+/// 音声/映像メッセージ用のチャンクストリームID
+pub const AUDIO_CSID: u32 = 6;
+pub const VIDEO_CSID: u32 = 7;
+
+/// チャンクストリームから組み立てられた1つのRTMPメッセージ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RtmpMessage {
+    pub csid: u32,
+    pub timestamp: u32,
+    pub type_id: u8,
+    pub stream_id: u32,
+    pub payload: Bytes,
+}
+
+/// User Control Message のイベント
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserControlEvent {
+    StreamBegin(u32),
+    StreamEof(u32),
+    StreamDry(u32),
+    SetBufferLength { stream_id: u32, buffer_ms: u32 },
+    StreamIsRecorded(u32),
+    PingRequest(u32),
+    PingResponse(u32),
+    Unknown(u16),
+}
+
+/// プロトコル制御メッセージ (type 1〜6)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMessage {
+    SetChunkSize(u32),
+    Abort(u32),
+    Acknowledgement(u32),
+    UserControl(UserControlEvent),
+    WindowAckSize(u32),
+    SetPeerBandwidth { size: u32, limit_type: u8 },
+}
+
+impl ControlMessage {
+    /// 制御メッセージであればパースする（それ以外のタイプは `None`）
+    pub fn parse(msg: &RtmpMessage) -> Result<Option<Self>, RtmpError> {
+        let mut p = msg.payload.clone();
+        let control = match msg.type_id {
+            msg_type::SET_CHUNK_SIZE => {
+                // 最上位ビットは0でなければならない
+                Self::SetChunkSize(read_u32(&mut p)? & 0x7FFF_FFFF)
+            }
+            msg_type::ABORT => Self::Abort(read_u32(&mut p)?),
+            msg_type::ACKNOWLEDGEMENT => Self::Acknowledgement(read_u32(&mut p)?),
+            msg_type::WINDOW_ACK_SIZE => Self::WindowAckSize(read_u32(&mut p)?),
+            msg_type::SET_PEER_BANDWIDTH => {
+                let size = read_u32(&mut p)?;
+                if !p.has_remaining() {
+                    return Err(RtmpError::Malformed("set peer bandwidth"));
+                }
+                Self::SetPeerBandwidth {
+                    size,
+                    limit_type: p.get_u8(),
+                }
+            }
+            msg_type::USER_CONTROL => {
+                if p.remaining() < 2 {
+                    return Err(RtmpError::Malformed("user control"));
+                }
+                let event = match p.get_u16() {
+                    0 => UserControlEvent::StreamBegin(read_u32(&mut p)?),
+                    1 => UserControlEvent::StreamEof(read_u32(&mut p)?),
+                    2 => UserControlEvent::StreamDry(read_u32(&mut p)?),
+                    3 => UserControlEvent::SetBufferLength {
+                        stream_id: read_u32(&mut p)?,
+                        buffer_ms: read_u32(&mut p)?,
+                    },
+                    4 => UserControlEvent::StreamIsRecorded(read_u32(&mut p)?),
+                    6 => UserControlEvent::PingRequest(read_u32(&mut p)?),
+                    7 => UserControlEvent::PingResponse(read_u32(&mut p)?),
+                    other => UserControlEvent::Unknown(other),
+                };
+                Self::UserControl(event)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(control))
+    }
+
+    /// 送信用のRTMPメッセージに変換する
+    pub fn to_message(self) -> RtmpMessage {
+        let mut p = BytesMut::with_capacity(10);
+        let type_id = match self {
+            Self::SetChunkSize(size) => {
+                p.put_u32(size & 0x7FFF_FFFF);
+                msg_type::SET_CHUNK_SIZE
+            }
+            Self::Abort(csid) => {
+                p.put_u32(csid);
+                msg_type::ABORT
+            }
+            Self::Acknowledgement(seq) => {
+                p.put_u32(seq);
+                msg_type::ACKNOWLEDGEMENT
+            }
+            Self::WindowAckSize(size) => {
+                p.put_u32(size);
+                msg_type::WINDOW_ACK_SIZE
+            }
+            Self::SetPeerBandwidth { size, limit_type } => {
+                p.put_u32(size);
+                p.put_u8(limit_type);
+                msg_type::SET_PEER_BANDWIDTH
+            }
+            Self::UserControl(event) => {
+                match event {
+                    UserControlEvent::StreamBegin(id) => put_event(&mut p, 0, id),
+                    UserControlEvent::StreamEof(id) => put_event(&mut p, 1, id),
+                    UserControlEvent::StreamDry(id) => put_event(&mut p, 2, id),
+                    UserControlEvent::SetBufferLength {
+                        stream_id,
+                        buffer_ms,
+                    } => {
+                        put_event(&mut p, 3, stream_id);
+                        p.put_u32(buffer_ms);
+                    }
+                    UserControlEvent::StreamIsRecorded(id) => put_event(&mut p, 4, id),
+                    UserControlEvent::PingRequest(ts) => put_event(&mut p, 6, ts),
+                    UserControlEvent::PingResponse(ts) => put_event(&mut p, 7, ts),
+                    UserControlEvent::Unknown(kind) => p.put_u16(kind),
+                }
+                msg_type::USER_CONTROL
+            }
+        };
+
+        RtmpMessage {
+            csid: CONTROL_CSID,
+            timestamp: 0,
+            type_id,
+            stream_id: 0,
+            payload: p.freeze(),
+        }
+    }
+}
+
+fn put_event(p: &mut BytesMut, kind: u16, value: u32) {
+    p.put_u16(kind);
+    p.put_u32(value);
+}
+
+fn read_u32(p: &mut Bytes) -> Result<u32, RtmpError> {
+    if p.remaining() < 4 {
+        return Err(RtmpError::Malformed("control message too short"));
+    }
+    Ok(p.get_u32())
+}
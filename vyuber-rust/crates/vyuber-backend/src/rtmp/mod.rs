@@ -1,3 +1,13 @@
+pub mod amf;
+pub mod chunk;
+pub mod client;
+pub mod command;
+pub mod error;
+pub mod handshake;
+pub mod message;
+pub mod relay;
 pub mod server;
+pub mod session;
 
+pub use relay::start_relay;
 pub use server::start_rtmp_server;
@@ -0,0 +1,424 @@
+//! RTMPプッシュリレー
+//!
+//! 配信が始まると、ストリームキーに設定されたリレー先ごとにRTMPクライアントで接続し、
+//! ハブのタグをそのまま転送する。切断されたら待ち時間を倍にしながら再接続する。
+//! `rtmps://` のリレー先には `RELAY_CA_PATH` のCA証明書で検証したTLSで接続する
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio_rustls::rustls::{self, pki_types::CertificateDer, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tracing::{info, warn};
+use vyuber_shared::stream::{RelayState, RelayStatus};
+
+use super::client::{RtmpClient, RtmpUrl};
+use super::error::RtmpError;
+use crate::config::{self, RelayConfig};
+use crate::media::hub::{Subscriber, HUB};
+
+/// 接続からpublish完了までの待ち時間の上限
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// ストリームキーごとのリレー先と状態
+pub static RELAYS: Lazy<RelayStore> = Lazy::new(|| RelayStore::new(config::get().relay.clone()));
+
+/// 1回の配信分のリレー状態
+struct RelaySession {
+    /// 配信ごとに振る番号（再配信後に古いリレーが動き続けないようにする）
+    generation: u64,
+    status: Vec<RelayStatus>,
+    /// リレー先ごとの送信バイト数（タグごとにロックを取らないように別に数える）
+    bytes_sent: Vec<Arc<AtomicU64>>,
+}
+
+pub struct RelayStore {
+    config: RelayConfig,
+    /// `rtmps://` 用（CA証明書を読めなければ `None`）
+    tls: Option<TlsConnector>,
+    /// ストリームキーごとのリレー先（未設定なら `config.default_targets`）
+    targets: RwLock<HashMap<String, Vec<String>>>,
+    /// 直近の配信でのリレー先ごとの状態
+    sessions: RwLock<HashMap<String, RelaySession>>,
+    next_generation: AtomicU64,
+}
+
+impl RelayStore {
+    pub fn new(config: RelayConfig) -> Self {
+        // 証明書を読めなくても rtmp:// のリレーは使えるようにする
+        let tls = tls_connector(&config.ca_path)
+            .inspect_err(|e| warn!("rtmps:// relays are unavailable: {:#}", e))
+            .ok();
+        Self {
+            config,
+            tls,
+            targets: RwLock::new(HashMap::new()),
+            sessions: RwLock::new(HashMap::new()),
+            next_generation: AtomicU64::new(0),
+        }
+    }
+
+    /// リレー先を設定する（次の配信から反映される）
+    pub fn set_targets(&self, stream: &str, targets: Vec<String>) -> Result<(), RtmpError> {
+        for target in &targets {
+            RtmpUrl::parse(target)?;
+        }
+        self.targets
+            .write()
+            .unwrap()
+            .insert(stream.to_string(), targets);
+        Ok(())
+    }
+
+    /// リレー先ごとの状態（配信前は設定済みのリレー先を `Idle` で返す）
+    pub fn status(&self, stream: &str) -> Vec<RelayStatus> {
+        if let Some(session) = self.sessions.read().unwrap().get(stream) {
+            return session
+                .status
+                .iter()
+                .zip(&session.bytes_sent)
+                .map(|(status, bytes_sent)| RelayStatus {
+                    bytes_sent: bytes_sent.load(Ordering::Relaxed),
+                    ..status.clone()
+                })
+                .collect();
+        }
+        self.targets(stream)
+            .into_iter()
+            .map(|url| RelayStatus {
+                url,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn targets(&self, stream: &str) -> Vec<String> {
+        self.targets
+            .read()
+            .unwrap()
+            .get(stream)
+            .cloned()
+            .unwrap_or_else(|| self.config.default_targets.clone())
+    }
+
+    /// 配信開始時にリレー状態を作り直し、リレー先ごとの転送先を返す
+    fn begin(&self, stream: &str, targets: Vec<String>) -> Vec<RelayTarget> {
+        let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+        let status = targets
+            .iter()
+            .map(|url| RelayStatus {
+                url: url.clone(),
+                state: RelayState::Connecting,
+                ..Default::default()
+            })
+            .collect();
+        let bytes_sent: Vec<_> = targets.iter().map(|_| Arc::default()).collect();
+        self.sessions.write().unwrap().insert(
+            stream.to_string(),
+            RelaySession {
+                generation,
+                status,
+                bytes_sent: bytes_sent.clone(),
+            },
+        );
+        targets
+            .into_iter()
+            .zip(bytes_sent)
+            .enumerate()
+            .map(|(index, (url, bytes_sent))| RelayTarget {
+                stream: stream.to_string(),
+                generation,
+                index,
+                url,
+                bytes_sent,
+            })
+            .collect()
+    }
+
+    fn is_current(&self, stream: &str, generation: u64) -> bool {
+        self.sessions
+            .read()
+            .unwrap()
+            .get(stream)
+            .is_some_and(|session| session.generation == generation)
+    }
+
+    fn update(&self, target: &RelayTarget, f: impl FnOnce(&mut RelayStatus)) {
+        let mut sessions = self.sessions.write().unwrap();
+        if let Some(session) = sessions.get_mut(&target.stream) {
+            if session.generation == target.generation {
+                if let Some(status) = session.status.get_mut(target.index) {
+                    f(status);
+                }
+            }
+        }
+    }
+}
+
+/// 1つのリレー先
+struct RelayTarget {
+    stream: String,
+    generation: u64,
+    index: usize,
+    url: String,
+    bytes_sent: Arc<AtomicU64>,
+}
+
+/// 配信開始イベントを監視し、リレー先ごとに転送タスクを起動する
+pub fn start_relay() {
+    HUB.spawn_on_publish("Relay", |stream| async move {
+        let targets = RELAYS.targets(&stream);
+        if targets.is_empty() {
+            return;
+        }
+        for target in RELAYS.begin(&stream, targets) {
+            tokio::spawn(relay(target));
+        }
+    });
+}
+
+/// 配信が終わるまでリレー先へ転送し続ける（切断されたら再接続する）
+async fn relay(target: RelayTarget) {
+    let config = &RELAYS.config;
+    let url = match RtmpUrl::parse(&target.url) {
+        Ok(url) => url,
+        Err(e) => {
+            warn!("[Relay] {}: {}", target.url, e);
+            RELAYS.update(&target, |status| {
+                status.state = RelayState::Stopped;
+                status.last_error = Some(e.to_string());
+            });
+            return;
+        }
+    };
+
+    let mut backoff = config.backoff_min_ms;
+    loop {
+        // 再配信されていたら新しい配信のリレーに任せる
+        if !RELAYS.is_current(&target.stream, target.generation) {
+            return;
+        }
+        let Some(subscriber) = HUB.subscribe(&target.stream) else {
+            break;
+        };
+
+        RELAYS.update(&target, |status| status.state = RelayState::Connecting);
+        match push(&target, &url, subscriber, &mut backoff).await {
+            // 配信終了（または遅れて購読が切れたので購読し直す）
+            Ok(()) => continue,
+            Err(e) => {
+                warn!(
+                    "[Relay] {} -> {} failed: {}, retrying in {}ms",
+                    target.stream, target.url, e, backoff
+                );
+                RELAYS.update(&target, |status| {
+                    status.state = RelayState::Reconnecting;
+                    status.reconnects += 1;
+                    status.last_error = Some(e.to_string());
+                });
+            }
+        }
+
+        tokio::time::sleep(Duration::from_millis(backoff)).await;
+        backoff = (backoff * 2).min(config.backoff_max_ms);
+    }
+
+    RELAYS.update(&target, |status| status.state = RelayState::Stopped);
+    info!("[Relay] Stopped relaying {} -> {}", target.stream, target.url);
+}
+
+/// 1回の接続分の転送
+///
+/// 購読が終わったら `Ok`、接続に失敗したり切断されたら `Err`
+async fn push(
+    target: &RelayTarget,
+    url: &RtmpUrl,
+    mut subscriber: Subscriber,
+    backoff: &mut u64,
+) -> Result<(), RtmpError> {
+    let connect = RtmpClient::publish(url, RELAYS.tls.as_ref());
+    let mut client = tokio::time::timeout(CONNECT_TIMEOUT, connect)
+        .await
+        .map_err(|_| RtmpError::Timeout)??;
+
+    info!("[Relay] Relaying {} -> {}", target.stream, target.url);
+    RELAYS.update(target, |status| status.state = RelayState::Publishing);
+    *backoff = RELAYS.config.backoff_min_ms;
+
+    loop {
+        tokio::select! {
+            tag = subscriber.recv() => {
+                let Some(tag) = tag else {
+                    let _ = client.unpublish().await;
+                    return Ok(());
+                };
+                client.send_tag(&tag).await?;
+                target
+                    .bytes_sent
+                    .fetch_add(tag.data.len() as u64, Ordering::Relaxed);
+            }
+            result = client.read() => result?,
+        }
+    }
+}
+
+/// PEMのCA証明書で上流サーバーの証明書を検証するTLSの設定を作る
+fn tls_connector(ca_path: &Path) -> Result<TlsConnector> {
+    let mut reader = BufReader::new(
+        File::open(ca_path).with_context(|| format!("Failed to open {}", ca_path.display()))?,
+    );
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut reader) {
+        let cert: CertificateDer = cert
+            .with_context(|| format!("Failed to read certificates from {}", ca_path.display()))?;
+        // 読めない証明書があっても残りは使う
+        let _ = roots.add(cert);
+    }
+    anyhow::ensure!(!roots.is_empty(), "No CA certificates found in {}", ca_path.display());
+
+    let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(client_config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::stream_key;
+    use crate::config::RtmpsConfig;
+    use crate::media::testing::{aac_frame, aac_sequence_header, avc_frame, avc_sequence_header};
+    use crate::media::tag::MediaTag;
+    use crate::rtmp::server;
+
+    fn tags() -> Vec<MediaTag> {
+        let mut tags = vec![avc_sequence_header(0), aac_sequence_header(0)];
+        for i in 0..10 {
+            tags.push(avc_frame(i * 40 + 1, i == 0, 0, &[i as u8; 100]));
+            tags.push(aac_frame(i * 40 + 21, &[i as u8; 20]));
+        }
+        tags
+    }
+
+    /// 上流のサーバーに配信が始まるまで待って購読する
+    async fn subscribe(stream: &str) -> Subscriber {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(subscriber) = HUB.subscribe(stream) {
+                    return subscriber;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("upstream did not start publishing")
+    }
+
+    /// 配信が終わるまでに届いたタグ（時刻順）
+    async fn received(mut subscriber: Subscriber) -> Vec<(u32, Vec<u8>)> {
+        let mut tags = Vec::new();
+        let recv = async {
+            while let Some(tag) = subscriber.recv().await {
+                tags.push((tag.timestamp, tag.data.to_vec()));
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), recv)
+            .await
+            .expect("upstream did not stop publishing");
+        tags.sort();
+        tags
+    }
+
+    fn expected() -> Vec<(u32, Vec<u8>)> {
+        let mut tags: Vec<_> = tags()
+            .into_iter()
+            .map(|tag| (tag.timestamp, tag.data.to_vec()))
+            .collect();
+        tags.sort();
+        tags
+    }
+
+    /// 自前のRTMP/RTMPSサーバーをもう1つ起動して上流にする
+    #[tokio::test]
+    async fn relays_to_local_server_over_rtmp_and_rtmps() {
+        let (_guard, key) = stream_key::test_key().await;
+
+        // rtmp://: 配信を始めるとリレー先へ接続して転送し、配信が終わると止まる
+        let upstream = server::spawn_local(None).await;
+        start_relay();
+        RELAYS
+            .set_targets(
+                "relay-source",
+                vec![format!("rtmp://{}/live/{}", upstream, key)],
+            )
+            .unwrap();
+        let mut publisher = HUB.publish("relay-source").unwrap();
+        for tag in tags() {
+            publisher.send(tag);
+        }
+        let subscriber = subscribe(&key).await;
+        drop(publisher);
+        assert_eq!(received(subscriber).await, expected());
+
+        let total: u64 = tags().iter().map(|tag| tag.data.len() as u64).sum();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while RELAYS.status("relay-source")[0].state != RelayState::Stopped {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("relay did not stop");
+        assert_eq!(RELAYS.status("relay-source")[0].bytes_sent, total);
+
+        // rtmps://: テスト用のCAで署名した証明書で待ち受ける
+        let dir = std::env::temp_dir().join(format!("vyuber-relay-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let cert = rcgen::CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&key_pair, &ca, &ca_key)
+            .unwrap();
+        std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+        std::fs::write(dir.join("cert.pem"), cert.pem()).unwrap();
+        std::fs::write(dir.join("key.pem"), key_pair.serialize_pem()).unwrap();
+
+        let acceptor = server::tls_acceptor(&RtmpsConfig {
+            cert_path: Some(dir.join("cert.pem").display().to_string()),
+            key_path: Some(dir.join("key.pem").display().to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let connector = tls_connector(&dir.join("ca.pem")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let upstream = server::spawn_local(Some(acceptor)).await;
+        let url = RtmpUrl::parse(&format!("rtmps://localhost:{}/live/{}", upstream.port(), key))
+            .unwrap();
+        assert!(url.tls);
+        assert!(matches!(
+            RtmpClient::publish(&url, None).await,
+            Err(RtmpError::TlsUnavailable)
+        ));
+
+        let mut client = RtmpClient::publish(&url, Some(&connector)).await.unwrap();
+        let subscriber = subscribe(&key).await;
+        for tag in tags() {
+            client.send_tag(&tag).await.unwrap();
+        }
+        client.unpublish().await.unwrap();
+        assert_eq!(received(subscriber).await, expected());
+    }
+}
@@ -1,63 +1,219 @@
-use anyhow::Result;
-use std::net::SocketAddr;
-use tokio::net::TcpListener;
-use tokio::io::AsyncReadExt;
-use tracing::{info, error};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::BufReader;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::{self, pki_types::CertificateDer};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::either::Either;
+use tracing::{error, info, warn};
+
+use super::error::RtmpError;
+use super::handshake;
+use super::session::Session;
+use crate::config::{RtmpConfig, RtmpsConfig};
 
 /// RTMPサーバーを起動
 ///
-/// MVP版: プレースホルダー実装
-/// 実際のRTMP実装にはsheave等のライブラリが必要
-pub async fn start_rtmp_server() -> Result<()> {
-    let addr = SocketAddr::from(([127, 0, 0, 1], 1935));
-
-    info!("RTMP server (placeholder) will listen on {}", addr);
-    info!("Full RTMP implementation pending - sheave integration required");
+/// ハンドシェイク後はチャンクストリームをデコードし、
+/// 接続ごとに `Session` で処理する。RTMPSが設定されていればTLSのリスナーも起動する
+pub async fn start_rtmp_server(config: RtmpConfig, rtmps: &RtmpsConfig) -> Result<()> {
+    let addr = SocketAddr::new(config.bind_address, config.port);
+    // 同時接続数はRTMPとRTMPSで合算する
+    let limiter = Arc::new(ConnectionLimiter::new(&config));
 
     // バックグラウンドでリスナーを起動
-    tokio::spawn(async move {
-        match TcpListener::bind(addr).await {
-            Ok(listener) => {
-                info!("RTMP placeholder server listening on {}", addr);
-
-                loop {
-                    match listener.accept().await {
-                        Ok((mut socket, peer_addr)) => {
-                            info!("New RTMP connection from: {}", peer_addr);
-
-                            tokio::spawn(async move {
-                                let mut buf = vec![0; 1024];
-
-                                // データを受信してログに記録
-                                loop {
-                                    match socket.read(&mut buf).await {
-                                        Ok(0) => {
-                                            info!("RTMP connection closed: {}", peer_addr);
-                                            break;
-                                        }
-                                        Ok(n) => {
-                                            info!("Received {} bytes from RTMP client", n);
-                                            // MVP: データは処理せず、ログのみ
-                                        }
-                                        Err(e) => {
-                                            error!("RTMP read error: {}", e);
-                                            break;
-                                        }
-                                    }
-                                }
-                            });
-                        }
-                        Err(e) => {
-                            error!("Failed to accept RTMP connection: {}", e);
-                        }
+    tokio::spawn(listen(addr, None, config, limiter.clone()));
+
+    if rtmps.is_enabled() {
+        // 証明書を読めなくても平文のRTMPは使えるようにする
+        match tls_acceptor(rtmps) {
+            Ok(acceptor) => {
+                let addr = SocketAddr::new(config.bind_address, rtmps.port);
+                tokio::spawn(listen(addr, Some(acceptor), config, limiter));
+            }
+            Err(e) => error!("Failed to load RTMPS certificate: {:#}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// 接続を受け付け続ける（`tls` があればTLSを終端してからRTMPとして扱う）
+async fn listen(
+    addr: SocketAddr,
+    tls: Option<TlsAcceptor>,
+    config: RtmpConfig,
+    limiter: Arc<ConnectionLimiter>,
+) {
+    let scheme = if tls.is_some() { "RTMPS" } else { "RTMP" };
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to bind {} server: {}", scheme, e);
+            return;
+        }
+    };
+    info!("{} server listening on {}", scheme, addr);
+    serve(listener, tls, config, limiter).await;
+}
+
+async fn serve(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    config: RtmpConfig,
+    limiter: Arc<ConnectionLimiter>,
+) {
+    let scheme = if tls.is_some() { "RTMPS" } else { "RTMP" };
+    loop {
+        match listener.accept().await {
+            Ok((socket, peer_addr)) => {
+                let Some(guard) = limiter.acquire(peer_addr.ip()) else {
+                    warn!("{} connection from {} rejected: too many connections", scheme, peer_addr);
+                    continue;
+                };
+                info!("New {} connection from: {}", scheme, peer_addr);
+
+                let tls = tls.clone();
+                tokio::spawn(async move {
+                    // 接続が終わるまで枠を確保しておく
+                    let _guard = guard;
+                    if let Err(e) = handle_connection(socket, peer_addr, tls, config).await {
+                        error!("{} connection {} error: {}", scheme, peer_addr, e);
                     }
-                }
+                });
             }
             Err(e) => {
-                error!("Failed to bind RTMP server: {}", e);
+                error!("Failed to accept {} connection: {}", scheme, e);
+            }
+        }
+    }
+}
+
+/// ハンドシェイク（RTMPSはTLSを終端してから）してセッションを終わるまで処理する
+async fn handle_connection(
+    socket: TcpStream,
+    peer_addr: SocketAddr,
+    tls: Option<TlsAcceptor>,
+    config: RtmpConfig,
+) -> Result<()> {
+    socket.set_nodelay(true)?;
+    let handshake = async {
+        match tls {
+            Some(acceptor) => {
+                let mut stream = acceptor.accept(socket).await?;
+                handshake::server_handshake(&mut stream).await?;
+                Ok::<_, RtmpError>(Either::Right(stream))
+            }
+            None => {
+                let mut socket = socket;
+                handshake::server_handshake(&mut socket).await?;
+                Ok(Either::Left(socket))
             }
         }
-    });
+    };
+    let stream = tokio::time::timeout(config.handshake_timeout(), handshake)
+        .await
+        .map_err(|_| RtmpError::HandshakeTimeout)??;
+    info!("RTMP handshake completed: {}", peer_addr);
 
+    Session::new(stream, peer_addr.to_string(), config.idle_timeout())
+        .run()
+        .await?;
     Ok(())
 }
+
+/// 同時接続数（全体とIPアドレスごと）の上限
+struct ConnectionLimiter {
+    max_connections: usize,
+    max_connections_per_ip: usize,
+    connections: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ConnectionLimiter {
+    fn new(config: &RtmpConfig) -> Self {
+        Self {
+            max_connections: config.max_connections,
+            max_connections_per_ip: config.max_connections_per_ip,
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 上限に達していなければ1接続分の枠を確保する（ガードをdropすると解放される）
+    fn acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut connections = self.connections.lock().unwrap();
+        let total: usize = connections.values().sum();
+        let per_ip = connections.get(&ip).copied().unwrap_or(0);
+        if exceeds(total, self.max_connections) || exceeds(per_ip, self.max_connections_per_ip) {
+            return None;
+        }
+        *connections.entry(ip).or_insert(0) += 1;
+        Some(ConnectionGuard {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+}
+
+/// 上限（0で無制限）に達しているか
+fn exceeds(count: usize, limit: usize) -> bool {
+    limit > 0 && count >= limit
+}
+
+struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.limiter.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// PEMの証明書チェーンと秘密鍵からTLSの設定を作る
+pub(super) fn tls_acceptor(config: &RtmpsConfig) -> Result<TlsAcceptor> {
+    let cert_path = config.cert_path.as_deref().context("RTMPS_CERT_PATH is not set")?;
+    let key_path = config.key_path.as_deref().context("RTMPS_KEY_PATH is not set")?;
+
+    let mut reader = BufReader::new(
+        File::open(cert_path).with_context(|| format!("Failed to open {}", cert_path))?,
+    );
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<CertificateDer>, _>>()
+        .with_context(|| format!("Failed to read certificates from {}", cert_path))?;
+
+    let mut reader = BufReader::new(
+        File::open(key_path).with_context(|| format!("Failed to open {}", key_path))?,
+    );
+    let key = rustls_pemfile::private_key(&mut reader)
+        .with_context(|| format!("Failed to read private key from {}", key_path))?
+        .with_context(|| format!("No private key found in {}", key_path))?;
+
+    let server_config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// 空いているポートでサーバーを起動してアドレスを返す（リレーのテストの上流）
+#[cfg(test)]
+pub(super) async fn spawn_local(tls: Option<TlsAcceptor>) -> SocketAddr {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let config = RtmpConfig::default();
+    let limiter = Arc::new(ConnectionLimiter::new(&config));
+    tokio::spawn(serve(listener, tls, config, limiter));
+    addr
+}
@@ -0,0 +1,851 @@
+use bytes::BytesMut;
+use std::ops::ControlFlow;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::{debug, info, warn};
+
+use super::amf::{self, amf0, AmfValue};
+use super::chunk::{ChunkDecoder, ChunkEncoder, OUTGOING_CHUNK_SIZE};
+use super::command::{self, Command};
+use super::error::RtmpError;
+use super::message::{
+    msg_type, ControlMessage, RtmpMessage, UserControlEvent, AUDIO_CSID, VIDEO_CSID,
+};
+use crate::api::stream_key;
+use crate::media::hub::{Publisher, Subscriber, HUB};
+use crate::media::tag::{MediaTag, TagKind};
+
+/// クライアントからWindow Ack Sizeが届くまでのAcknowledgement間隔
+const DEFAULT_WINDOW_ACK_SIZE: u32 = 2_500_000;
+
+/// 受け付けるアプリケーション名（rtmp://host:port/live）
+const APP_NAME: &str = "live";
+
+/// セッションの状態遷移: Handshaken → Connected → Publishing / Playing
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    /// connect待ち
+    Handshaken,
+    /// connect済み（createStream/publish/play待ち）
+    Connected,
+    /// 配信中
+    Publishing { stream_id: u32, stream_key: String },
+    /// 再生中
+    Playing { stream_id: u32, stream_key: String },
+}
+
+/// ハンドシェイク完了後の1接続分のRTMPセッション
+pub struct Session<S> {
+    stream: S,
+    peer: String,
+    decoder: ChunkDecoder,
+    encoder: ChunkEncoder,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+    bytes_received: u64,
+    last_ack: u64,
+    window_ack_size: u32,
+    state: State,
+    next_stream_id: u32,
+    publisher: Option<Publisher>,
+    /// 再生中のストリームの購読
+    player: Option<Subscriber>,
+    /// 送受信がこの時間途絶えたら切断する（`None` なら無制限）
+    idle_timeout: Option<Duration>,
+}
+
+impl<S> Session<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new(stream: S, peer: impl Into<String>, idle_timeout: Option<Duration>) -> Self {
+        Self {
+            stream,
+            peer: peer.into(),
+            decoder: ChunkDecoder::new(),
+            encoder: ChunkEncoder::new(),
+            read_buf: BytesMut::with_capacity(64 * 1024),
+            write_buf: BytesMut::with_capacity(64 * 1024),
+            bytes_received: 0,
+            last_ack: 0,
+            window_ack_size: DEFAULT_WINDOW_ACK_SIZE,
+            state: State::Handshaken,
+            next_stream_id: 1,
+            publisher: None,
+            player: None,
+            idle_timeout,
+        }
+    }
+
+    /// 接続が閉じられるまでメッセージを処理する
+    ///
+    /// 再生中はクライアントからの受信と並行して、購読したタグを送り続ける
+    pub async fn run(mut self) -> Result<(), RtmpError> {
+        'session: loop {
+            while let Some(msg) = self.decoder.decode(&mut self.read_buf)? {
+                if self.handle_message(msg).await?.is_break() {
+                    break 'session;
+                }
+            }
+
+            tokio::select! {
+                n = self.stream.read_buf(&mut self.read_buf) => {
+                    let n = n?;
+                    if n == 0 {
+                        break;
+                    }
+                    self.bytes_received += n as u64;
+                    self.maybe_send_ack().await?;
+                }
+                tag = next_tag(&mut self.player) => {
+                    if self.send_tag(tag).await?.is_break() {
+                        break;
+                    }
+                }
+                // 同じキーで別の配信者が引き継いだら、こちらの接続は閉じる
+                _ = taken_over(&self.publisher) => {
+                    if let State::Publishing { stream_key, .. } = &self.state {
+                        warn!("RTMP publish taken over: {} ({})", stream_key, self.peer);
+                    }
+                    self.state = State::Connected;
+                    self.publisher = None;
+                    break;
+                }
+                // 受信も再生中の送信もないまま時間が過ぎたら切断する
+                _ = idle(self.idle_timeout) => {
+                    let timeout = self.idle_timeout.unwrap_or_default();
+                    return Err(RtmpError::IdleTimeout(timeout.as_millis() as u64));
+                }
+            }
+        }
+        match &self.state {
+            State::Publishing { stream_key, .. } => {
+                info!("RTMP publish ended: {} ({})", stream_key, self.peer);
+            }
+            State::Playing { stream_key, .. } => {
+                info!("RTMP play ended: {} ({})", stream_key, self.peer);
+            }
+            _ => {}
+        }
+        info!("RTMP connection closed: {}", self.peer);
+        Ok(())
+    }
+
+    /// 受信量がウィンドウを超えたらAcknowledgementを返す
+    async fn maybe_send_ack(&mut self) -> Result<(), RtmpError> {
+        if self.bytes_received - self.last_ack < self.window_ack_size as u64 {
+            return Ok(());
+        }
+        self.last_ack = self.bytes_received;
+        // シーケンス番号は32bitで折り返す
+        let seq = self.bytes_received as u32;
+        self.send(&ControlMessage::Acknowledgement(seq).to_message())
+            .await
+    }
+
+    async fn handle_message(&mut self, msg: RtmpMessage) -> Result<ControlFlow<()>, RtmpError> {
+        if let Some(control) = ControlMessage::parse(&msg)? {
+            self.handle_control(control).await?;
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        match msg.type_id {
+            // 壊れたAMFのメッセージは読み飛ばし、接続は切らない
+            msg_type::COMMAND_AMF0 | msg_type::COMMAND_AMF3 => {
+                let values = match amf::decode_payload(msg.type_id, &msg.payload) {
+                    Ok(values) => values,
+                    Err(e) => {
+                        warn!("RTMP malformed command from {}: {}", self.peer, e);
+                        return Ok(ControlFlow::Continue(()));
+                    }
+                };
+                match Command::parse(&values) {
+                    Some(cmd) => self.handle_command(cmd, &msg).await,
+                    None => {
+                        warn!("RTMP malformed command from {}: {:?}", self.peer, values);
+                        Ok(ControlFlow::Continue(()))
+                    }
+                }
+            }
+            msg_type::DATA_AMF0 | msg_type::DATA_AMF3 => {
+                let values = match amf::decode_payload(msg.type_id, &msg.payload) {
+                    Ok(values) => values,
+                    Err(e) => {
+                        warn!("RTMP malformed data message from {}: {}", self.peer, e);
+                        return Ok(ControlFlow::Continue(()));
+                    }
+                };
+                self.on_data(msg.timestamp, values);
+                Ok(ControlFlow::Continue(()))
+            }
+            msg_type::AUDIO | msg_type::VIDEO => {
+                if let Some(publisher) = &mut self.publisher {
+                    let kind = if msg.type_id == msg_type::AUDIO {
+                        TagKind::Audio
+                    } else {
+                        TagKind::Video
+                    };
+                    publisher.send(MediaTag::new(kind, msg.timestamp, msg.payload));
+                }
+                Ok(ControlFlow::Continue(()))
+            }
+            _ => {
+                debug!(
+                    "RTMP message from {}: type={} stream={} ts={} len={}",
+                    self.peer,
+                    msg.type_id,
+                    msg.stream_id,
+                    msg.timestamp,
+                    msg.payload.len()
+                );
+                Ok(ControlFlow::Continue(()))
+            }
+        }
+    }
+
+    async fn handle_command(
+        &mut self,
+        cmd: Command,
+        msg: &RtmpMessage,
+    ) -> Result<ControlFlow<()>, RtmpError> {
+        debug!("RTMP command from {}: {:?}", self.peer, cmd);
+
+        match cmd {
+            Command::Connect {
+                transaction_id,
+                command_object,
+            } => return self.on_connect(transaction_id, &command_object).await,
+            Command::CreateStream { transaction_id } => {
+                let stream_id = self.next_stream_id;
+                self.next_stream_id += 1;
+                self.send(&command::response(
+                    "_result",
+                    transaction_id,
+                    AmfValue::Null,
+                    (stream_id as f64).into(),
+                ))
+                .await?;
+            }
+            Command::ReleaseStream { transaction_id, .. }
+            | Command::FcPublish { transaction_id, .. }
+            | Command::FcUnpublish { transaction_id, .. } => {
+                if transaction_id != 0.0 {
+                    self.send(&command::response(
+                        "_result",
+                        transaction_id,
+                        AmfValue::Null,
+                        AmfValue::Undefined,
+                    ))
+                    .await?;
+                }
+            }
+            Command::Publish {
+                stream_name,
+                publish_type,
+                ..
+            } => {
+                return self
+                    .on_publish(msg.stream_id, &stream_name, &publish_type)
+                    .await
+            }
+            Command::Play { stream_name, .. } => {
+                return self.on_play(msg.stream_id, &stream_name).await
+            }
+            Command::DeleteStream { stream_id } => match &self.state {
+                State::Publishing {
+                    stream_id: publishing,
+                    stream_key,
+                } if *publishing == stream_id => {
+                    info!("RTMP publish ended: {} ({})", stream_key, self.peer);
+                    self.state = State::Connected;
+                    self.publisher = None;
+                }
+                State::Playing {
+                    stream_id: playing,
+                    stream_key,
+                } if *playing == stream_id => {
+                    info!("RTMP play ended: {} ({})", stream_key, self.peer);
+                    self.state = State::Connected;
+                    self.player = None;
+                }
+                _ => {}
+            },
+            Command::Other { name, .. } => {
+                debug!("RTMP ignoring command {} from {}", name, self.peer);
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    async fn on_connect(
+        &mut self,
+        transaction_id: f64,
+        command_object: &AmfValue,
+    ) -> Result<ControlFlow<()>, RtmpError> {
+        let app = command_object
+            .get("app")
+            .and_then(AmfValue::as_str)
+            .unwrap_or_default()
+            .trim_matches('/');
+        let object_encoding = command_object
+            .get("objectEncoding")
+            .and_then(AmfValue::as_f64)
+            .unwrap_or(0.0);
+
+        if self.state != State::Handshaken || app != APP_NAME {
+            warn!("RTMP connect rejected for app '{}' from {}", app, self.peer);
+            self.send(&command::response(
+                "_error",
+                transaction_id,
+                AmfValue::Null,
+                command::status_info(
+                    "error",
+                    "NetConnection.Connect.Rejected",
+                    "Unknown application.",
+                ),
+            ))
+            .await?;
+            return Ok(ControlFlow::Break(()));
+        }
+
+        self.send(&ControlMessage::WindowAckSize(DEFAULT_WINDOW_ACK_SIZE).to_message())
+            .await?;
+        self.send(
+            &ControlMessage::SetPeerBandwidth {
+                size: DEFAULT_WINDOW_ACK_SIZE,
+                // 2 = Dynamic
+                limit_type: 2,
+            }
+            .to_message(),
+        )
+        .await?;
+        self.send(&ControlMessage::SetChunkSize(OUTGOING_CHUNK_SIZE).to_message())
+            .await?;
+        self.encoder.set_chunk_size(OUTGOING_CHUNK_SIZE as usize);
+
+        let mut info = command::status_info(
+            "status",
+            "NetConnection.Connect.Success",
+            "Connection succeeded.",
+        );
+        if let AmfValue::Object(props) = &mut info {
+            props.push(("objectEncoding".to_string(), object_encoding.into()));
+        }
+        self.send(&command::response(
+            "_result",
+            transaction_id,
+            AmfValue::object([
+                ("fmsVer", "FMS/3,0,1,123".into()),
+                ("capabilities", 31.0.into()),
+            ]),
+            info,
+        ))
+        .await?;
+
+        info!("RTMP client {} connected to app '{}'", self.peer, app);
+        self.state = State::Connected;
+        Ok(ControlFlow::Continue(()))
+    }
+
+    async fn on_publish(
+        &mut self,
+        stream_id: u32,
+        stream_name: &str,
+        publish_type: &str,
+    ) -> Result<ControlFlow<()>, RtmpError> {
+        // OBSはキーの後ろにクエリ文字列を付けることがある
+        let stream_key = stream_name.split('?').next().unwrap_or_default();
+
+        if self.state != State::Connected || !stream_key::is_valid_key(stream_key) {
+            warn!(
+                "RTMP publish rejected for key '{}' from {}",
+                stream_key, self.peer
+            );
+            self.send(&command::on_status(
+                stream_id,
+                "error",
+                "NetStream.Publish.BadName",
+                "Invalid stream key.",
+            ))
+            .await?;
+            return Ok(ControlFlow::Break(()));
+        }
+
+        let publisher = match HUB.publish(stream_key) {
+            Ok(publisher) => publisher,
+            Err(e) => {
+                warn!("RTMP publish rejected from {}: {}", self.peer, e);
+                self.send(&command::on_status(
+                    stream_id,
+                    "error",
+                    "NetStream.Publish.BadName",
+                    "Stream is already being published.",
+                ))
+                .await?;
+                return Ok(ControlFlow::Break(()));
+            }
+        };
+
+        self.send(
+            &ControlMessage::UserControl(UserControlEvent::StreamBegin(stream_id)).to_message(),
+        )
+        .await?;
+        self.send(&command::on_status(
+            stream_id,
+            "status",
+            "NetStream.Publish.Start",
+            &format!("{} is now published.", stream_key),
+        ))
+        .await?;
+
+        info!(
+            "RTMP publish started: {} ({}, type={})",
+            stream_key, self.peer, publish_type
+        );
+        self.state = State::Publishing {
+            stream_id,
+            stream_key: stream_key.to_string(),
+        };
+        self.publisher = Some(publisher);
+        Ok(ControlFlow::Continue(()))
+    }
+
+    async fn on_play(
+        &mut self,
+        stream_id: u32,
+        stream_name: &str,
+    ) -> Result<ControlFlow<()>, RtmpError> {
+        let stream_key = stream_name.split('?').next().unwrap_or_default();
+
+        let subscriber = match self.state {
+            State::Connected => HUB.subscribe(stream_key),
+            _ => None,
+        };
+        let Some(subscriber) = subscriber else {
+            warn!(
+                "RTMP play rejected for key '{}' from {}",
+                stream_key, self.peer
+            );
+            self.send(&command::on_status(
+                stream_id,
+                "error",
+                "NetStream.Play.StreamNotFound",
+                "Stream not found or offline.",
+            ))
+            .await?;
+            return Ok(ControlFlow::Break(()));
+        };
+
+        self.send(
+            &ControlMessage::UserControl(UserControlEvent::StreamBegin(stream_id)).to_message(),
+        )
+        .await?;
+        self.send(&command::on_status(
+            stream_id,
+            "status",
+            "NetStream.Play.Reset",
+            &format!("Playing and resetting {}.", stream_key),
+        ))
+        .await?;
+        self.send(&command::on_status(
+            stream_id,
+            "status",
+            "NetStream.Play.Start",
+            &format!("Started playing {}.", stream_key),
+        ))
+        .await?;
+        // クライアント側でのサンプルアクセス（音声/映像の取得）を許可する
+        self.send(&command::data_message(
+            stream_id,
+            0,
+            amf0::encode_all(&["|RtmpSampleAccess".into(), true.into(), true.into()]),
+        ))
+        .await?;
+
+        info!("RTMP play started: {} ({})", stream_key, self.peer);
+        // 以降はメタデータ、シーケンスヘッダ、GOPキャッシュ、ライブのタグの順に届く
+        self.state = State::Playing {
+            stream_id,
+            stream_key: stream_key.to_string(),
+        };
+        self.player = Some(subscriber);
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// 購読したタグを再生中のクライアントへ送る
+    ///
+    /// 配信が終わった（`tag` が `None`）ら終了を通知して切断する
+    async fn send_tag(&mut self, tag: Option<MediaTag>) -> Result<ControlFlow<()>, RtmpError> {
+        let State::Playing {
+            stream_id,
+            stream_key,
+        } = &self.state
+        else {
+            return Ok(ControlFlow::Continue(()));
+        };
+        let stream_id = *stream_id;
+
+        let Some(tag) = tag else {
+            let description = format!("{} is now unpublished.", stream_key);
+            self.player = None;
+            self.send(&command::on_status(
+                stream_id,
+                "status",
+                "NetStream.Play.UnpublishNotify",
+                &description,
+            ))
+            .await?;
+            self.send(
+                &ControlMessage::UserControl(UserControlEvent::StreamEof(stream_id)).to_message(),
+            )
+            .await?;
+            return Ok(ControlFlow::Break(()));
+        };
+
+        let msg = match tag.kind {
+            TagKind::Audio => RtmpMessage {
+                csid: AUDIO_CSID,
+                timestamp: tag.timestamp,
+                type_id: msg_type::AUDIO,
+                stream_id,
+                payload: tag.data,
+            },
+            TagKind::Video => RtmpMessage {
+                csid: VIDEO_CSID,
+                timestamp: tag.timestamp,
+                type_id: msg_type::VIDEO,
+                stream_id,
+                payload: tag.data,
+            },
+            TagKind::Script => command::data_message(stream_id, tag.timestamp, tag.data),
+        };
+        self.send(&msg).await?;
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// データメッセージをスクリプトタグとしてハブへ流す
+    ///
+    /// `@setDataFrame` は取り除き、FLVと同じ `onMetaData, {...}` の形にする
+    fn on_data(&mut self, timestamp: u32, mut values: Vec<AmfValue>) {
+        debug!("RTMP data from {}: {:?}", self.peer, values);
+        let Some(publisher) = &mut self.publisher else {
+            return;
+        };
+        if values.first().and_then(AmfValue::as_str) == Some("@setDataFrame") {
+            values.remove(0);
+        }
+        if values.is_empty() {
+            return;
+        }
+        publisher.send(MediaTag::new(
+            TagKind::Script,
+            timestamp,
+            amf0::encode_all(&values),
+        ));
+    }
+
+    async fn handle_control(&mut self, control: ControlMessage) -> Result<(), RtmpError> {
+        match control {
+            ControlMessage::SetChunkSize(size) => {
+                // デコーダ側で適用済み
+                debug!("RTMP peer {} set chunk size to {}", self.peer, size);
+            }
+            ControlMessage::WindowAckSize(size) => {
+                debug!("RTMP peer {} set window ack size to {}", self.peer, size);
+                self.window_ack_size = size.max(1);
+            }
+            ControlMessage::Acknowledgement(seq) => {
+                debug!("RTMP peer {} acknowledged {} bytes", self.peer, seq);
+            }
+            ControlMessage::SetPeerBandwidth { size, .. } => {
+                // ピアの要求に合わせてWindow Ack Sizeを返す
+                self.send(&ControlMessage::WindowAckSize(size).to_message())
+                    .await?;
+            }
+            ControlMessage::UserControl(UserControlEvent::PingRequest(ts)) => {
+                self.send(
+                    &ControlMessage::UserControl(UserControlEvent::PingResponse(ts)).to_message(),
+                )
+                .await?;
+            }
+            other => {
+                debug!("RTMP control from {}: {:?}", self.peer, other);
+            }
+        }
+        Ok(())
+    }
+
+    /// メッセージをチャンク化して送信する
+    async fn send(&mut self, msg: &RtmpMessage) -> Result<(), RtmpError> {
+        self.encoder.encode(msg, &mut self.write_buf);
+        self.stream.write_all(&self.write_buf).await?;
+        self.write_buf.clear();
+        self.stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// 再生中なら次のタグを待つ（再生していなければ完了しない）
+async fn next_tag(player: &mut Option<Subscriber>) -> Option<MediaTag> {
+    match player {
+        Some(subscriber) => subscriber.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// 配信中なら別の配信者に引き継がれるまで待つ（配信していなければ完了しない）
+async fn taken_over(publisher: &Option<Publisher>) {
+    match publisher {
+        Some(publisher) => publisher.taken_over().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// 無通信タイムアウトまで待つ（`None` なら完了しない）
+async fn idle(timeout: Option<Duration>) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep(timeout).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::testing;
+    use crate::rtmp::command::{COMMAND_CSID, STREAM_CSID};
+    use bytes::Bytes;
+    use tokio::io::DuplexStream;
+    use tokio::task::JoinHandle;
+
+    /// ハンドシェイク済みのセッションにメモリ上でつながるクライアント
+    struct TestClient {
+        io: DuplexStream,
+        decoder: ChunkDecoder,
+        encoder: ChunkEncoder,
+        read_buf: BytesMut,
+    }
+
+    impl TestClient {
+        fn start() -> (Self, JoinHandle<Result<(), RtmpError>>) {
+            let (io, server) = tokio::io::duplex(64 * 1024);
+            let session = tokio::spawn(Session::new(server, "test", None).run());
+            let client = Self {
+                io,
+                decoder: ChunkDecoder::new(),
+                encoder: ChunkEncoder::new(),
+                read_buf: BytesMut::new(),
+            };
+            (client, session)
+        }
+
+        async fn send(&mut self, msg: &RtmpMessage) {
+            let mut buf = BytesMut::new();
+            self.encoder.encode(msg, &mut buf);
+            self.io.write_all(&buf).await.unwrap();
+        }
+
+        async fn command(&mut self, stream_id: u32, values: &[AmfValue]) {
+            let csid = if stream_id == 0 {
+                COMMAND_CSID
+            } else {
+                STREAM_CSID
+            };
+            self.send(&command::command_message(csid, stream_id, values))
+                .await;
+        }
+
+        /// 次のメッセージ（接続が閉じられたら `None`）
+        async fn next_message(&mut self) -> Option<RtmpMessage> {
+            loop {
+                if let Some(msg) = self.decoder.decode(&mut self.read_buf).unwrap() {
+                    return Some(msg);
+                }
+                if self.io.read_buf(&mut self.read_buf).await.unwrap() == 0 {
+                    return None;
+                }
+            }
+        }
+
+        /// 次のコマンドメッセージ（制御メッセージなどは飛ばす）
+        async fn next_command(&mut self) -> Vec<AmfValue> {
+            loop {
+                let msg = self.next_message().await.expect("connection closed");
+                if msg.type_id == msg_type::COMMAND_AMF0 {
+                    return amf0::decode_all(&msg.payload).unwrap();
+                }
+            }
+        }
+
+        /// 次の onStatus の `code`
+        async fn next_status(&mut self) -> String {
+            loop {
+                let values = self.next_command().await;
+                if values[0].as_str() == Some("onStatus") {
+                    return status_code(&values);
+                }
+            }
+        }
+
+        /// connect と createStream を済ませてストリームIDを返す
+        async fn connect(&mut self) -> u32 {
+            self.command(
+                0,
+                &[
+                    "connect".into(),
+                    1.0.into(),
+                    AmfValue::object([("app", "live".into())]),
+                ],
+            )
+            .await;
+            let result = self.next_command().await;
+            assert_eq!(result[0].as_str(), Some("_result"));
+            assert_eq!(status_code(&result), "NetConnection.Connect.Success");
+
+            self.command(0, &["createStream".into(), 2.0.into(), AmfValue::Null])
+                .await;
+            let result = self.next_command().await;
+            assert_eq!(result[1].as_f64(), Some(2.0));
+            result[3].as_f64().unwrap() as u32
+        }
+
+        async fn publish(&mut self, stream_id: u32, stream_name: &str) -> String {
+            self.command(
+                stream_id,
+                &[
+                    "publish".into(),
+                    3.0.into(),
+                    AmfValue::Null,
+                    stream_name.into(),
+                    "live".into(),
+                ],
+            )
+            .await;
+            self.next_status().await
+        }
+    }
+
+    fn status_code(values: &[AmfValue]) -> String {
+        values[3]
+            .get("code")
+            .and_then(AmfValue::as_str)
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn publishes_with_valid_stream_key() {
+        let (_guard, key) = stream_key::test_key().await;
+        let (mut client, session) = TestClient::start();
+
+        // 壊れたAMFのコマンドやデータは読み飛ばす
+        for type_id in [msg_type::COMMAND_AMF0, msg_type::DATA_AMF0] {
+            client
+                .send(&RtmpMessage {
+                    csid: COMMAND_CSID,
+                    timestamp: 0,
+                    type_id,
+                    stream_id: 0,
+                    payload: Bytes::from_static(&[0x02, 0x00, 0x10, b'x']),
+                })
+                .await;
+        }
+        let stream_id = client.connect().await;
+        assert_eq!(
+            client.publish(stream_id, &format!("{}?token=1", key)).await,
+            "NetStream.Publish.Start"
+        );
+        assert!(HUB.subscribe(&key).is_some());
+
+        // 切断すると配信が終わる
+        drop(client);
+        session.await.unwrap().unwrap();
+        assert!(HUB.subscribe(&key).is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_publish_with_invalid_stream_key() {
+        let (mut client, session) = TestClient::start();
+        let stream_id = client.connect().await;
+        assert_eq!(
+            client.publish(stream_id, "not-a-key").await,
+            "NetStream.Publish.BadName"
+        );
+        // サーバー側から切断される
+        assert!(client.next_message().await.is_none());
+        session.await.unwrap().unwrap();
+        assert!(HUB.subscribe("not-a-key").is_none());
+    }
+
+    #[tokio::test]
+    async fn plays_cached_tags_then_live_tags_until_unpublished() {
+        let stream = format!("play-{}", uuid::Uuid::new_v4().simple());
+        let mut publisher = HUB.publish(&stream).unwrap();
+        // 映像と同じタイムスタンプの音声を続けて送り、並べ替えで待たされないようにする
+        let frames = |timestamp, keyframe| {
+            [
+                testing::avc_frame(timestamp, keyframe, 0, &[0x10; 16]),
+                testing::aac_frame(timestamp, &[0x20; 8]),
+            ]
+        };
+        publisher.send(testing::avc_sequence_header(0));
+        publisher.send(testing::aac_sequence_header(0));
+        for tag in frames(0, true).into_iter().chain(frames(40, false)) {
+            publisher.send(tag);
+        }
+
+        let (mut client, session) = TestClient::start();
+        let stream_id = client.connect().await;
+        client
+            .command(
+                stream_id,
+                &[
+                    "play".into(),
+                    3.0.into(),
+                    AmfValue::Null,
+                    stream.as_str().into(),
+                ],
+            )
+            .await;
+        assert_eq!(client.next_status().await, "NetStream.Play.Reset");
+        assert_eq!(client.next_status().await, "NetStream.Play.Start");
+
+        // シーケンスヘッダとGOPキャッシュの後にライブのタグが届く
+        for tag in frames(80, false) {
+            publisher.send(tag);
+        }
+        let mut received = Vec::new();
+        while received.len() < 8 {
+            let msg = client.next_message().await.unwrap();
+            if matches!(msg.type_id, msg_type::AUDIO | msg_type::VIDEO) {
+                assert_eq!(msg.stream_id, stream_id);
+                let kind = if msg.type_id == msg_type::AUDIO {
+                    TagKind::Audio
+                } else {
+                    TagKind::Video
+                };
+                let tag = MediaTag::new(kind, msg.timestamp, msg.payload);
+                received.push((kind, tag.timestamp, tag.is_sequence_header()));
+            }
+        }
+        assert_eq!(
+            received,
+            vec![
+                (TagKind::Video, 0, true),
+                (TagKind::Audio, 0, true),
+                (TagKind::Video, 0, false),
+                (TagKind::Audio, 0, false),
+                (TagKind::Video, 40, false),
+                (TagKind::Audio, 40, false),
+                (TagKind::Video, 80, false),
+                (TagKind::Audio, 80, false),
+            ]
+        );
+
+        // 配信が終わると通知して切断する
+        drop(publisher);
+        assert_eq!(client.next_status().await, "NetStream.Play.UnpublishNotify");
+        while client.next_message().await.is_some() {}
+        session.await.unwrap().unwrap();
+    }
+}
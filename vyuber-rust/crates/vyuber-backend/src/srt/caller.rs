@@ -0,0 +1,125 @@
+//! SRTのcallerモード
+//!
+//! エンコーダやゲートウェイがlistenerとして待っている場合に、こちらから接続してMPEG-TSを受信する。
+//! 切断されたら待ち時間を倍にしながら接続し直す
+
+use anyhow::{Context, Result};
+use srt_tokio::SrtSocket;
+use std::time::Duration;
+use tracing::warn;
+
+use super::server;
+use crate::api::stream_key;
+use crate::config::SrtConfig;
+use crate::media::hub::HUB;
+
+/// 再接続までの待ち時間の初期値
+const RETRY_MIN: Duration = Duration::from_secs(1);
+
+/// 再接続までの待ち時間の上限
+const RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// `address` のlistenerへ接続して受信し続ける
+pub(super) async fn run(config: SrtConfig, address: String) {
+    let mut backoff = RETRY_MIN;
+    loop {
+        match call(&config, &address).await {
+            // 受信できていたら、切断後はすぐに接続し直す
+            Ok(()) => backoff = RETRY_MIN,
+            Err(e) => warn!(
+                "SRT caller to {} failed: {:#}, retrying in {}s",
+                address,
+                e,
+                backoff.as_secs()
+            ),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(RETRY_MAX);
+    }
+}
+
+/// 1回の接続分の受信（接続して配信が終わったら `Ok`）
+async fn call(config: &SrtConfig, address: &str) -> Result<()> {
+    let stream_key = config
+        .caller_stream_key
+        .clone()
+        .or_else(stream_key::current_key)
+        .context("no stream key has been issued")?;
+
+    let mut builder = SrtSocket::builder().latency(config.latency());
+    if let Some(passphrase) = &config.passphrase {
+        builder = builder.encryption(0, passphrase.clone());
+    }
+    let socket = builder
+        .call(address, config.caller_stream_id.as_deref())
+        .await
+        .context("failed to connect")?;
+    let publisher = HUB.publish(&stream_key)?;
+    server::receive(socket, publisher, &stream_key, address).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::hls;
+    use crate::media::tag::TagKind;
+    use crate::media::testing::{aac_frame, aac_sequence_header, avc_frame, avc_sequence_header};
+    use futures_util::SinkExt;
+    use std::time::Instant;
+
+    /// listenerとして待つエンコーダから受信してハブへ流す
+    #[tokio::test]
+    async fn receives_from_a_listener() {
+        let port = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = SrtConfig {
+            caller_stream_id: Some("live/encoder".to_string()),
+            caller_stream_key: Some("srt-caller".to_string()),
+            ..Default::default()
+        };
+        let listener = tokio::spawn(SrtSocket::builder().listen_on(port));
+        let address = format!("127.0.0.1:{}", port);
+        let caller = tokio::spawn(async move { call(&config, &address).await });
+
+        let mut encoder = listener.await.unwrap().unwrap();
+        let mut subscriber = loop {
+            if let Some(subscriber) = HUB.subscribe("srt-caller") {
+                break subscriber;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        let mut tags = vec![avc_sequence_header(0), aac_sequence_header(0)];
+        for i in 0..10u32 {
+            tags.push(avc_frame(i * 40, i == 0, 0, &[i as u8; 200]));
+            tags.push(aac_frame(i * 40 + 20, &[i as u8; 20]));
+        }
+        let ts = hls::encode_segment(&tags).unwrap();
+        for chunk in ts.chunks(7 * 188) {
+            encoder
+                .send((Instant::now(), ts.slice_ref(chunk)))
+                .await
+                .unwrap();
+        }
+        encoder.close().await.unwrap();
+
+        let mut frames = (0, 0);
+        let recv = async {
+            while let Some(tag) = subscriber.recv().await {
+                match tag.kind {
+                    TagKind::Video if !tag.is_sequence_header() => frames.0 += 1,
+                    TagKind::Audio if !tag.is_sequence_header() => frames.1 += 1,
+                    _ => {}
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), recv)
+            .await
+            .expect("caller did not stop publishing");
+        assert_eq!(frames, (10, 10));
+        caller.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,4 @@
+mod caller;
+pub mod server;
+
+pub use server::start_srt_server;
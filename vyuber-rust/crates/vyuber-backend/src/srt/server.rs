@@ -0,0 +1,152 @@
+use anyhow::Result;
+use futures_util::StreamExt;
+use srt_tokio::access::{RejectReason, ServerRejectReason};
+use srt_tokio::{ConnectionRequest, SrtListener, SrtSocket};
+use std::fmt::Display;
+use std::net::SocketAddr;
+use tracing::{error, info, warn};
+
+use super::caller;
+use crate::api::stream_key;
+use crate::config::SrtConfig;
+use crate::media::hub::{Publisher, HUB};
+use crate::media::ts_demux::TsDemuxer;
+
+/// SRTサーバーを起動
+///
+/// エンコーダからcallerとして接続を受け付け、streamidのストリームキーで
+/// MPEG-TSを受信してRTMPと同じハブへ流す。
+/// `SRT_CALLER_ADDRESS` が設定されていれば、そのlistenerへこちらから接続して受信する
+pub async fn start_srt_server(config: SrtConfig) -> Result<()> {
+    if let Some(address) = config.caller_address.clone() {
+        tokio::spawn(caller::run(config.clone(), address));
+    }
+    if config.port == 0 {
+        info!("SRT server disabled");
+        return Ok(());
+    }
+
+    // バックグラウンドでリスナーを起動
+    tokio::spawn(listen(config));
+    Ok(())
+}
+
+async fn listen(config: SrtConfig) {
+    let addr = SocketAddr::new(config.bind_address, config.port);
+    let mut builder = SrtListener::builder().latency(config.latency());
+    if let Some(passphrase) = &config.passphrase {
+        builder = builder.encryption(0, passphrase.clone());
+    }
+    // リスナーをdropすると受け付けが止まるので、ループの間は保持しておく
+    let (_listener, mut incoming) = match builder.bind(addr).await {
+        Ok(bound) => bound,
+        Err(e) => {
+            error!("Failed to bind SRT server: {}", e);
+            return;
+        }
+    };
+    info!("SRT server listening on {}", addr);
+
+    while let Some(request) = incoming.incoming().next().await {
+        let peer = request.remote();
+        info!("New SRT connection from: {}", peer);
+        tokio::spawn(async move {
+            if let Err(e) = handle_request(request).await {
+                error!("SRT connection {} error: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// streamidを検証して受け入れ、切断されるまでTSをハブへ流す
+async fn handle_request(request: ConnectionRequest) -> Result<()> {
+    let peer = request.remote();
+    let stream_id = request
+        .stream_id()
+        .map(|id| id.to_string())
+        .unwrap_or_default();
+
+    let Some(stream_key) = parse_stream_id(&stream_id).filter(|key| stream_key::is_valid_key(key))
+    else {
+        warn!(
+            "SRT publish rejected for stream id '{}' from {}",
+            stream_id, peer
+        );
+        request
+            .reject(RejectReason::Server(ServerRejectReason::Forbidden))
+            .await?;
+        return Ok(());
+    };
+
+    let publisher = match HUB.publish(stream_key) {
+        Ok(publisher) => publisher,
+        Err(e) => {
+            warn!("SRT publish rejected from {}: {}", peer, e);
+            request
+                .reject(RejectReason::Server(ServerRejectReason::Conflict))
+                .await?;
+            return Ok(());
+        }
+    };
+    let socket = request.accept(None).await?;
+    receive(socket, publisher, stream_key, peer).await
+}
+
+/// 切断される（または引き継がれる）までTSを受信してハブへ流す
+pub(super) async fn receive(
+    mut socket: SrtSocket,
+    mut publisher: Publisher,
+    stream_key: &str,
+    peer: impl Display,
+) -> Result<()> {
+    info!("SRT publish started: {} ({})", stream_key, peer);
+
+    let mut demuxer = TsDemuxer::new();
+    let mut tags = Vec::new();
+    loop {
+        tokio::select! {
+            packet = socket.next() => match packet {
+                Some(Ok((_, data))) => demuxer.push(&data, &mut tags),
+                Some(Err(e)) => return Err(e.into()),
+                None => break,
+            },
+            // 同じキーで別の配信者が引き継いだら、こちらの接続は閉じる
+            _ = publisher.taken_over() => {
+                warn!("SRT publish taken over: {} ({})", stream_key, peer);
+                return Ok(());
+            }
+        }
+        for tag in tags.drain(..) {
+            publisher.send(tag);
+        }
+    }
+
+    demuxer.flush(&mut tags);
+    for tag in tags {
+        publisher.send(tag);
+    }
+    info!("SRT publish ended: {} ({})", stream_key, peer);
+    Ok(())
+}
+
+/// streamidからストリームキーを取り出す
+///
+/// `KEY`、`live/KEY`、アクセス制御の書式 `#!::r=live/KEY,m=publish` に対応する
+fn parse_stream_id(stream_id: &str) -> Option<&str> {
+    let resource = match stream_id.strip_prefix("#!::") {
+        Some(fields) => {
+            let mut resource = None;
+            for field in fields.split(',') {
+                match field.split_once('=')? {
+                    ("r", value) => resource = Some(value),
+                    // 再生（m=request）には対応しない
+                    ("m", mode) if mode != "publish" => return None,
+                    _ => {}
+                }
+            }
+            resource?
+        }
+        None => stream_id,
+    };
+    resource.rsplit('/').next().filter(|key| !key.is_empty())
+}
@@ -1,8 +1,29 @@
 // mpegts.js wrapper for WASM integration
 // This script expects mpegts to be loaded from CDN first
+//
+// Set window.VYUBER_CONFIG = { flvTransport: 'ws' } before this script to
+// play over WebSocket-FLV (for proxies that buffer chunked HTTP responses)
+//
+// The live preview prefers WHEP (WebRTC, sub-second latency) and falls back to
+// mpegts.js when WHEP is unavailable. Set { preview: 'flv' } to always use mpegts.js
 
 (function() {
     let player = null;
+    let whep = null;
+    // Incremented on every init/destroy so stale async WHEP setups can bail out
+    let generation = 0;
+
+    const config = Object.assign({ flvTransport: 'http', preview: 'whep' }, window.VYUBER_CONFIG || {});
+
+    // Live FLV URL for a stream key (HTTP-FLV by default, WebSocket-FLV when configured)
+    window.liveStreamUrl = function(streamKey) {
+        const key = encodeURIComponent(streamKey);
+        if (config.flvTransport === 'ws') {
+            const scheme = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
+            return scheme + '//' + window.location.host + '/ws/live/' + key;
+        }
+        return '/api/live/' + key;
+    };
 
     window.initMpegtsPlayer = function(videoElementId, streamUrl) {
         if (typeof mpegts === 'undefined') {
@@ -58,4 +79,111 @@
             console.log('mpegts player destroyed');
         }
     };
+
+    // Wait until ICE gathering finishes (the server does not accept trickle ICE)
+    function waitForIceGathering(pc) {
+        if (pc.iceGatheringState === 'complete') {
+            return Promise.resolve();
+        }
+        return new Promise(function(resolve) {
+            pc.addEventListener('icegatheringstatechange', function() {
+                if (pc.iceGatheringState === 'complete') {
+                    resolve();
+                }
+            });
+        });
+    }
+
+    function destroyWhepPlayer() {
+        if (whep) {
+            whep.pc.close();
+            if (whep.resource) {
+                fetch(whep.resource, { method: 'DELETE' }).catch(function() {});
+            }
+            whep.video.srcObject = null;
+            whep = null;
+            console.log('WHEP player destroyed');
+        }
+    }
+
+    // Play a stream over WHEP. Rejects if the server or the browser cannot do it
+    async function initWhepPlayer(videoElement, streamKey, current) {
+        if (typeof RTCPeerConnection === 'undefined') {
+            throw new Error('WebRTC not supported');
+        }
+
+        const pc = new RTCPeerConnection();
+        whep = { pc: pc, video: videoElement, resource: null };
+        pc.addTransceiver('video', { direction: 'recvonly' });
+        pc.addTransceiver('audio', { direction: 'recvonly' });
+
+        const stream = new MediaStream();
+        pc.ontrack = function(event) {
+            stream.addTrack(event.track);
+            videoElement.srcObject = stream;
+        };
+
+        await pc.setLocalDescription(await pc.createOffer());
+        await waitForIceGathering(pc);
+
+        const response = await fetch('/whep/' + encodeURIComponent(streamKey), {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/sdp' },
+            body: pc.localDescription.sdp,
+        });
+        if (!response.ok) {
+            throw new Error('WHEP request failed: ' + response.status + ' ' + await response.text());
+        }
+        if (current !== generation) {
+            // Destroyed while waiting for the answer: release the server session
+            const resource = response.headers.get('Location');
+            if (resource) {
+                fetch(resource, { method: 'DELETE' }).catch(function() {});
+            }
+            return;
+        }
+        whep.resource = response.headers.get('Location');
+        await pc.setRemoteDescription({ type: 'answer', sdp: await response.text() });
+
+        pc.onconnectionstatechange = function() {
+            if (pc.connectionState === 'failed' && current === generation) {
+                console.warn('WHEP connection failed, falling back to FLV');
+                destroyWhepPlayer();
+                window.initMpegtsPlayer(videoElement.id, window.liveStreamUrl(streamKey));
+            }
+        };
+        console.log('WHEP player initialized for:', streamKey);
+    }
+
+    // Start the live preview (WHEP when available, otherwise mpegts.js)
+    window.initLivePlayer = function(videoElementId, streamKey) {
+        window.destroyLivePlayer();
+        const current = generation;
+
+        const videoElement = document.getElementById(videoElementId);
+        if (!videoElement) {
+            console.error('Video element not found:', videoElementId);
+            return false;
+        }
+
+        if (config.preview !== 'whep') {
+            return window.initMpegtsPlayer(videoElementId, window.liveStreamUrl(streamKey));
+        }
+
+        initWhepPlayer(videoElement, streamKey, current).catch(function(error) {
+            if (current !== generation) {
+                return;
+            }
+            console.warn('WHEP unavailable, falling back to FLV:', error);
+            destroyWhepPlayer();
+            window.initMpegtsPlayer(videoElementId, window.liveStreamUrl(streamKey));
+        });
+        return true;
+    };
+
+    window.destroyLivePlayer = function() {
+        generation++;
+        destroyWhepPlayer();
+        window.destroyMpegtsPlayer();
+    };
 })();
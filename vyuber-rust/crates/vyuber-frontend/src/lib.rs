@@ -1,5 +1,7 @@
 use leptos::prelude::*;
+use std::time::Duration;
 use vyuber_shared::chat::ChatMessage;
+use vyuber_shared::stream::{StreamInfo, StreamKeyResponse};
 use wasm_bindgen_futures::spawn_local;
 use wasm_bindgen::prelude::*;
 
@@ -170,25 +172,89 @@ fn StudioLayout(
     }
 }
 
+/// 配信情報の確認間隔
+const STREAM_INFO_POLL_MS: u64 = 2000;
+
+// mpegts-wrapper.js のプレイヤー操作
+#[wasm_bindgen]
+extern "C" {
+    /// ライブプレビューを開始（WHEPが使えなければHTTP-FLV または WebSocket-FLV）
+    #[wasm_bindgen(js_name = initLivePlayer)]
+    fn init_live_player(video_element_id: &str, stream_key: &str) -> bool;
+
+    #[wasm_bindgen(js_name = destroyLivePlayer)]
+    fn destroy_live_player();
+}
+
 #[component]
 fn VideoPreview() -> impl IntoView {
+    let (stream_info, set_stream_info) = signal(None::<(String, StreamInfo)>);
+
+    // ストリームキーが発行されていれば配信状況を定期的に確認する
+    let poll = move || {
+        spawn_local(async move {
+            let info = match services::stream_api::get_stream_key().await {
+                Ok(StreamKeyResponse { stream_key: Some(key), .. }) => {
+                    services::stream_api::get_stream_info(&key)
+                        .await
+                        .map(|info| info.map(|info| (key, info)))
+                }
+                Ok(_) => Ok(None),
+                Err(e) => Err(e),
+            };
+            match info {
+                Ok(info) => set_stream_info.set(info),
+                Err(e) => log::error!("Stream info error: {}", e),
+            }
+        });
+    };
+    poll();
+    if let Ok(handle) = set_interval_with_handle(poll, Duration::from_millis(STREAM_INFO_POLL_MS)) {
+        on_cleanup(move || handle.clear());
+    }
+
+    // 配信中のストリームキーが変わったときだけプレイヤーを作り直す
+    let live_key = Memo::new(move |_| stream_info.get().map(|(key, _)| key));
+    Effect::new(move |_| match live_key.get() {
+        Some(key) => {
+            if !init_live_player("video-preview", &key) {
+                log::error!("Failed to start the player");
+            }
+        }
+        None => destroy_live_player(),
+    });
+    on_cleanup(destroy_live_player);
+
     view! {
         <div class="w-full h-full flex flex-col items-center justify-center relative">
             <video
                 id="video-preview"
-                class="w-full h-full object-contain hidden"
+                class=move || if live_key.get().is_some() {
+                    "w-full h-full object-contain"
+                } else {
+                    "w-full h-full object-contain hidden"
+                }
                 autoplay=true
                 muted=true
             ></video>
 
-            <div class="absolute inset-0 flex flex-col items-center justify-center">
-                <div class="absolute inset-0 bg-gradient-to-br from-zinc-800 to-zinc-900 opacity-50"></div>
-                <div class="relative z-10 text-center space-y-4">
-                    <div class="text-6xl">"📹"</div>
-                    <div class="text-zinc-400 font-medium">"ストリーム待機中"</div>
-                    <p class="text-zinc-600 text-sm">"OBSから配信を開始してください"</p>
-                </div>
-            </div>
+            {move || match stream_info.get() {
+                Some((_, info)) => view! {
+                    <div class="absolute top-4 left-4 z-10 px-3 py-1 rounded-full bg-red-600/90 text-white text-sm font-semibold shadow">
+                        {format!("🔴 接続中: {}", info.summary())}
+                    </div>
+                }.into_any(),
+                None => view! {
+                    <div class="absolute inset-0 flex flex-col items-center justify-center">
+                        <div class="absolute inset-0 bg-gradient-to-br from-zinc-800 to-zinc-900 opacity-50"></div>
+                        <div class="relative z-10 text-center space-y-4">
+                            <div class="text-6xl">"📹"</div>
+                            <div class="text-zinc-400 font-medium">"ストリーム待機中"</div>
+                            <p class="text-zinc-600 text-sm">"OBSから配信を開始してください"</p>
+                        </div>
+                    </div>
+                }.into_any(),
+            }}
         </div>
     }
 }
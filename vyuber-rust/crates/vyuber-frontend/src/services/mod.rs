@@ -1 +1,2 @@
 pub mod chat_api;
+pub mod stream_api;
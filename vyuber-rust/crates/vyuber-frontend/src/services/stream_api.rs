@@ -0,0 +1,39 @@
+use gloo_net::http::Request;
+use vyuber_shared::stream::{StreamInfo, StreamKeyResponse};
+
+pub async fn get_stream_key() -> Result<StreamKeyResponse, String> {
+    let response = Request::get("/api/stream-key")
+        .send()
+        .await
+        .map_err(|e| format!("Failed to send request: {}", e))?;
+
+    if !response.ok() {
+        return Err(format!("API error: {}", response.status()));
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse response: {}", e))
+}
+
+/// 配信中なら `Some`、オフラインなら `None`
+pub async fn get_stream_info(stream_key: &str) -> Result<Option<StreamInfo>, String> {
+    let response = Request::get(&format!("/api/streams/{}/info", stream_key))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to send request: {}", e))?;
+
+    if response.status() == 404 {
+        return Ok(None);
+    }
+    if !response.ok() {
+        return Err(format!("API error: {}", response.status()));
+    }
+
+    response
+        .json()
+        .await
+        .map(Some)
+        .map_err(|e| format!("Failed to parse response: {}", e))
+}
@@ -13,3 +13,204 @@ pub struct StreamKeyResponse {
     pub server_url: String,
     pub full_url: Option<String>,
 }
+
+/// 配信中のストリームの映像/音声情報（onMetaData とシーケンスヘッダから取得）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamInfo {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub framerate: Option<f64>,
+    /// 映像コーデック名（例: "H.264"）
+    pub video_codec: Option<String>,
+    /// 映像プロファイル（例: "High"）
+    pub video_profile: Option<String>,
+    /// 映像レベル（例: "4.2"）
+    pub video_level: Option<String>,
+    /// 映像ビットレート（kbps、エンコーダ申告値）
+    pub video_bitrate: Option<f64>,
+    /// 音声コーデック名（例: "AAC"）
+    pub audio_codec: Option<String>,
+    /// 音声プロファイル（例: "LC"）
+    pub audio_profile: Option<String>,
+    pub audio_sample_rate: Option<u32>,
+    pub audio_channels: Option<u8>,
+    /// 音声ビットレート（kbps、エンコーダ申告値）
+    pub audio_bitrate: Option<f64>,
+    /// エンコーダ名（例: "obs-output module (libobs version 30.0.0)"）
+    pub encoder: Option<String>,
+}
+
+impl StreamInfo {
+    /// 表示用の要約（例: "1920x1080@60 H.264 High"）
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            let mut resolution = format!("{}x{}", width, height);
+            if let Some(fps) = self.framerate {
+                if fps.fract().abs() < 0.01 {
+                    resolution.push_str(&format!("@{}", fps.round()));
+                } else {
+                    resolution.push_str(&format!("@{:.2}", fps));
+                }
+            }
+            parts.push(resolution);
+        }
+        if let Some(codec) = &self.video_codec {
+            parts.push(codec.clone());
+        }
+        if let Some(profile) = &self.video_profile {
+            parts.push(profile.clone());
+        }
+
+        parts.join(" ")
+    }
+}
+
+/// 配信の受信統計
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamStats {
+    /// 配信開始からの経過秒数
+    pub uptime_secs: f64,
+    /// 受信した総バイト数（映像/音声/スクリプトタグのペイロード）
+    pub bytes_received: u64,
+    pub video: TrackStats,
+    pub audio: TrackStats,
+    /// 映像タグのタイムスタンプから計測したフレームレート
+    pub fps: Option<f64>,
+    pub keyframes: u64,
+    /// 直近のキーフレーム間隔（ミリ秒）
+    pub keyframe_interval_ms: Option<u32>,
+    /// フレーム間隔の飛びから推定した欠落フレーム数
+    pub dropped_frames: u64,
+    /// 映像フレーム間隔のジッタ（ミリ秒）
+    pub jitter_ms: f64,
+}
+
+/// 映像/音声トラックごとの受信統計
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackStats {
+    pub bytes: u64,
+    pub frames: u64,
+    pub bitrate: BitrateWindows,
+    /// タイムスタンプが大きく飛んだ回数
+    pub timestamp_gaps: u64,
+    /// タイムスタンプが巻き戻った回数
+    pub timestamp_regressions: u64,
+}
+
+/// 直近の時間窓ごとの受信ビットレート（kbps）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BitrateWindows {
+    pub last_1s: f64,
+    pub last_5s: f64,
+    pub last_30s: f64,
+}
+
+/// ストリームキーごとのプッシュリレー先（PUT /api/streams/:stream_key/relays）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RelayTargets {
+    /// rtmp://host[:port]/app/stream_name 形式のURL（rtmps:// も可）
+    pub targets: Vec<String>,
+}
+
+/// プッシュリレーの接続状態
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayState {
+    /// 配信待ち
+    #[default]
+    Idle,
+    /// 接続中（ハンドシェイク〜publish）
+    Connecting,
+    /// 転送中
+    Publishing,
+    /// 切断され、再接続を待っている
+    Reconnecting,
+    /// 配信終了により停止した
+    Stopped,
+}
+
+/// プッシュリレー先1つ分の状態
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RelayStatus {
+    pub url: String,
+    pub state: RelayState,
+    /// この配信で送った総バイト数
+    pub bytes_sent: u64,
+    /// この配信での再接続回数
+    pub reconnects: u32,
+    pub last_error: Option<String>,
+}
+
+/// 配信中のストリームキーへ別の配信者がpublishしてきたときの扱い
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PublishPolicy {
+    /// 後から来た配信者を拒否する
+    #[default]
+    Reject,
+    /// 既存の配信者を切断して引き継ぐ（視聴者はそのまま）
+    Takeover,
+}
+
+/// 重複publish時の扱い（GET/PUT /api/streams/:stream_key/publish-policy）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublishPolicySetting {
+    pub policy: PublishPolicy,
+}
+
+/// ストリームキーごとの録画の有効/無効（GET/PUT /api/streams/:stream_key/recording）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordingSetting {
+    pub enabled: bool,
+    /// 録画中のファイル（録画していなければ `None`、PUTでは無視する）
+    #[serde(default)]
+    pub current_file: Option<String>,
+}
+
+/// 録画のMP4変換ジョブの状態
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemuxState {
+    /// 変換待ち
+    #[default]
+    Queued,
+    /// 変換中
+    Running,
+    Completed,
+    Failed,
+}
+
+/// 録画のMP4変換ジョブ1つ分（GET /api/remux-jobs）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RemuxJob {
+    pub stream_key: String,
+    /// 変換元のFLVファイル
+    pub source: String,
+    /// 変換後のMP4ファイル（完了するまで `None`）
+    pub output: Option<String>,
+    pub state: RemuxState,
+    pub error: Option<String>,
+}
+
+/// 録画1本分の情報（GET /api/recordings）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecordingInfo {
+    /// `<ストリームキー>_<開始日時>`
+    pub id: String,
+    pub stream_key: String,
+    /// 録画開始時刻（RFC 3339）
+    pub started_at: String,
+    /// 録画終了時刻（RFC 3339）
+    pub ended_at: String,
+    pub duration_secs: f64,
+    /// FLVとMP4を合わせたファイルサイズ（バイト）
+    pub size: u64,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    /// MP4に変換済みか（ファイルのエンドポイントはMP4があればMP4を返す）
+    pub has_mp4: bool,
+    /// 元のFLVが残っているか
+    pub has_flv: bool,
+}